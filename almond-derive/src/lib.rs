@@ -0,0 +1,143 @@
+//! `#[derive(AlmondCaveats)]`, which implements `almonds::claims::AlmondCaveats`
+//! for structs with named fields.
+//!
+//! Each field maps to a caveat with the same key, whose value is encoded with
+//! `almonds::CaveatValue`. Fields of type `Option<T>` are optional: the
+//! caveat is only added if the field is `Some`, and a missing caveat is
+//! extracted as `None`.
+//!
+//! ```ignore
+//! #[macro_use] extern crate almond_derive;
+//! extern crate almonds;
+//!
+//! use almonds::{Almond, Verifier};
+//! use almonds::claims::AlmondCaveats;
+//!
+//! #[derive(AlmondCaveats)]
+//! struct Claims {
+//!     user: String,
+//!     expires: u64,
+//!     org: Option<String>,
+//! }
+//!
+//! let claims = Claims { user: "erikj".to_owned(), expires: 1500000000, org: None };
+//!
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! claims.add_to(&mut almond);
+//!
+//! let claims = Claims::extract_from(&almond).unwrap();
+//!
+//! // Or, only once the almond has been verified:
+//! let mut v = Verifier::new(&almond, 1, b"access");
+//! v.allow(b"user");
+//! v.allow(b"expires");
+//! let claims: Claims = v.verify_into().unwrap();
+//! ```
+
+extern crate proc_macro;
+extern crate proc_macro2;
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, GenericArgument, PathArguments, Type};
+
+
+#[proc_macro_derive(AlmondCaveats)]
+pub fn derive_almond_caveats(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = syn::parse(input).unwrap();
+
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2, syn::Error> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(input, "AlmondCaveats requires named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(input, "AlmondCaveats can only be derived for structs")),
+    };
+
+    let mut add = Vec::new();
+    let mut extract = Vec::new();
+
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let key = ident.to_string();
+
+        if option_inner(&field.ty).is_some() {
+            add.push(quote! {
+                if let Some(ref value) = self.#ident {
+                    almond.add_caveat_typed(#key.as_bytes(), value.clone());
+                }
+            });
+            extract.push(quote! {
+                #ident: match caveats.claim(#key) {
+                    Ok(value) => value,
+                    Err(err) => return Err(err),
+                }
+            });
+        } else {
+            add.push(quote! {
+                almond.add_caveat_typed(#key.as_bytes(), self.#ident.clone());
+            });
+            extract.push(quote! {
+                #ident: match caveats.claim(#key) {
+                    Ok(Some(value)) => value,
+                    Ok(None) => return Err(::almonds::claims::ClaimsError::Missing {
+                        key: #key.to_owned(),
+                    }),
+                    Err(err) => return Err(err),
+                }
+            });
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::almonds::claims::AlmondCaveats for #name #ty_generics #where_clause {
+            fn add_to(&self, almond: &mut ::almonds::Almond) {
+                #(#add)*
+            }
+
+            fn extract(caveats: &::almonds::claims::CaveatList)
+                -> ::std::result::Result<Self, ::almonds::claims::ClaimsError>
+            {
+                Ok(#name {
+                    #(#extract,)*
+                })
+            }
+        }
+    })
+}
+
+
+/// If the type is `Option<T>`, returns `T`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let path = match *ty {
+        Type::Path(ref path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+
+    let segment = match path.segments.last() {
+        Some(segment) if segment.ident == "Option" => segment,
+        _ => return None,
+    };
+
+    match segment.arguments {
+        PathArguments::AngleBracketed(ref args) => match args.args.first() {
+            Some(&GenericArgument::Type(ref inner)) if args.args.len() == 1 => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
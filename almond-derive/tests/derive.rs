@@ -0,0 +1,66 @@
+#[macro_use] extern crate almond_derive;
+extern crate almonds;
+
+use almonds::{Almond, Verifier};
+use almonds::claims::{AlmondCaveats, ClaimsError};
+
+
+#[derive(AlmondCaveats, Debug, PartialEq)]
+struct Claims {
+    user: String,
+    expires: u64,
+    org: Option<String>,
+    admin: Option<bool>,
+}
+
+
+#[test]
+fn round_trip() {
+    let claims = Claims {
+        user: "erikj".to_owned(),
+        expires: 1500000000,
+        org: Some("matrix".to_owned()),
+        admin: None,
+    };
+
+    let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+    claims.add_to(&mut almond);
+
+    assert_eq!(almond.caveat_str(b"user"), Some("erikj"));
+    assert_eq!(almond.caveat_str(b"expires"), Some("1500000000"));
+    assert_eq!(almond.caveat_str(b"org"), Some("matrix"));
+    assert_eq!(almond.caveat_value(b"admin"), None);
+
+    assert_eq!(Claims::extract_from(&almond), Ok(claims));
+}
+
+#[test]
+fn extract_errors() {
+    let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+    almond.add_caveat(b"user", Some(b"erikj"));
+    assert_eq!(
+        Claims::extract_from(&almond),
+        Err(ClaimsError::Missing { key: "expires".to_owned() })
+    );
+
+    almond.add_caveat(b"expires", Some(b"never"));
+    assert_eq!(
+        Claims::extract_from(&almond),
+        Err(ClaimsError::Invalid { key: "expires".to_owned() })
+    );
+}
+
+#[test]
+fn verify_into() {
+    let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+    almond.add_caveat(b"user", Some(b"erikj"));
+    almond.add_caveat(b"expires", Some(b"1500000000"));
+
+    let mut v = Verifier::new(&almond, 1, b"login");
+    v.allow(b"user");
+    v.satisfies_exact(b"expires", Some(b"1500000000"));
+
+    let claims: Claims = v.verify_into().unwrap();
+    assert_eq!(claims.user, "erikj");
+    assert_eq!(claims.org, None);
+}
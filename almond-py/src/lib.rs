@@ -0,0 +1,214 @@
+//! Python bindings for Almond.
+//!
+//! The module exposes `Almond` and `Verifier` classes mirroring the Rust API.
+//! All keys, types and caveats are `bytes`.
+
+extern crate almonds;
+#[macro_use] extern crate pyo3;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyList};
+
+use almonds::{Almond, AlmondParseError};
+
+
+create_exception!(almonds, InvalidAlmondError, PyValueError);
+create_exception!(almonds, IncorrectHashError, PyValueError);
+
+fn parse_error_to_py(err: AlmondParseError) -> PyErr {
+    match err {
+        AlmondParseError::IncorrectHash
+        | AlmondParseError::ExtendedAfterSeal
+        | AlmondParseError::UnknownKey => {
+            IncorrectHashError::new_err(err.to_string())
+        }
+        AlmondParseError::Io(err) => PyErr::from(err),
+        err => InvalidAlmondError::new_err(err.to_string()),
+    }
+}
+
+
+/// A deserialized almond. See the Rust `Almond` type.
+#[pyclass(name = "Almond")]
+struct PyAlmond {
+    inner: Almond,
+}
+
+#[pymethods]
+impl PyAlmond {
+    /// Create a new almond with the given key, generation and type.
+    #[new]
+    fn new(key: &[u8], generation: u32, almond_type: &[u8]) -> PyAlmond {
+        PyAlmond {
+            inner: Almond::create(key, generation, almond_type.to_vec()),
+        }
+    }
+
+    /// Parse a binary serialized almond, and validate that the hashes match.
+    #[staticmethod]
+    fn parse_and_validate(key: &[u8], input: &[u8]) -> PyResult<PyAlmond> {
+        Almond::parse_and_validate(key, input)
+            .map(|almond| PyAlmond { inner: almond })
+            .map_err(parse_error_to_py)
+    }
+
+    /// Parse a Base64 serialized almond, and validate that the hashes match.
+    #[staticmethod]
+    fn parse_base64_and_validate(key: &[u8], input: &[u8])
+        -> PyResult<PyAlmond>
+    {
+        Almond::parse_base64_and_validate(key, input)
+            .map(|almond| PyAlmond { inner: almond })
+            .map_err(parse_error_to_py)
+    }
+
+    /// Adds a caveat, with an optional value.
+    #[pyo3(signature = (key, value=None))]
+    fn add_caveat(&mut self, key: &[u8], value: Option<&[u8]>) {
+        self.inner.add_caveat(key, value);
+    }
+
+    /// Add a new literal caveat.
+    fn add_literal_caveat(&mut self, caveat: &[u8]) {
+        self.inner.add_literal_caveat(caveat.to_vec());
+    }
+
+    #[getter]
+    fn almond_type<'p>(&self, py: Python<'p>) -> &'p PyBytes {
+        PyBytes::new(py, self.inner.almond_type())
+    }
+
+    #[getter]
+    fn generation(&self) -> u32 {
+        self.inner.generation()
+    }
+
+    #[getter]
+    fn caveats<'p>(&self, py: Python<'p>) -> &'p PyList {
+        PyList::new(
+            py,
+            self.inner.caveats().map(|caveat| PyBytes::new(py, caveat)).collect::<Vec<_>>(),
+        )
+    }
+
+    /// Serialize into a binary blob.
+    fn serialize_binary<'p>(&self, py: Python<'p>) -> &'p PyBytes {
+        PyBytes::new(py, &self.inner.serialize_binary())
+    }
+
+    /// Serialize into Base64.
+    fn serialize_base64(&self) -> String {
+        self.inner.serialize_base64()
+    }
+}
+
+
+enum Rule {
+    Allow(Vec<u8>),
+    Satisfies(Vec<u8>, PyObject),
+    SatisfiesExact(Vec<u8>, Option<Vec<u8>>),
+}
+
+
+/// Checks an almond against a list of predicates. See the Rust `Verifier`
+/// type for the exact semantics.
+///
+/// Rules are recorded and only evaluated when `verify` is called.
+#[pyclass(name = "Verifier")]
+struct PyVerifier {
+    almond: Py<PyAlmond>,
+    generation: u32,
+    almond_type: Vec<u8>,
+    rules: Vec<Rule>,
+}
+
+#[pymethods]
+impl PyVerifier {
+    #[new]
+    fn new(almond: Py<PyAlmond>, generation: u32, almond_type: &[u8])
+        -> PyVerifier
+    {
+        PyVerifier {
+            almond: almond,
+            generation: generation,
+            almond_type: almond_type.to_vec(),
+            rules: Vec::new(),
+        }
+    }
+
+    /// Allow all caveats with the given key, irrespective of their values.
+    fn allow(&mut self, key: &[u8]) {
+        self.rules.push(Rule::Allow(key.to_vec()));
+    }
+
+    /// Calls `predicate(value)` for every caveat with the given key, which
+    /// should return whether the value is acceptable.
+    fn satisfies(&mut self, key: &[u8], predicate: PyObject) {
+        self.rules.push(Rule::Satisfies(key.to_vec(), predicate));
+    }
+
+    /// Accepts caveats with the given key only if their value matches.
+    #[pyo3(signature = (key, value=None))]
+    fn satisfies_exact(&mut self, key: &[u8], value: Option<&[u8]>) {
+        self.rules.push(
+            Rule::SatisfiesExact(key.to_vec(), value.map(|v| v.to_vec()))
+        );
+    }
+
+    /// Returns whether the almond satisfies the given conditions.
+    ///
+    /// Exceptions raised by predicates are propagated.
+    fn verify(&self, py: Python) -> PyResult<bool> {
+        let almond = self.almond.borrow(py);
+        let mut v = almonds::Verifier::new(
+            &almond.inner, self.generation, &self.almond_type
+        );
+
+        let mut error = None;
+
+        for rule in &self.rules {
+            match *rule {
+                Rule::Allow(ref key) => {
+                    v.allow(key);
+                }
+                Rule::Satisfies(ref key, ref predicate) => {
+                    v.satisfies(key, |val| {
+                        if error.is_some() {
+                            return false;
+                        }
+                        let res = predicate.call1(py, (PyBytes::new(py, val),))
+                            .and_then(|res| res.is_true(py));
+                        match res {
+                            Ok(res) => res,
+                            Err(err) => {
+                                error = Some(err);
+                                false
+                            }
+                        }
+                    });
+                }
+                Rule::SatisfiesExact(ref key, ref value) => {
+                    v.satisfies_exact(key, value.as_ref().map(|v| &v[..]));
+                }
+            }
+        }
+
+        match error {
+            Some(err) => Err(err),
+            None => Ok(v.verify()),
+        }
+    }
+}
+
+
+#[pymodule]
+#[pyo3(name = "almonds")]
+fn almond_py(py: Python, m: &PyModule) -> PyResult<()> {
+    try!(m.add_class::<PyAlmond>());
+    try!(m.add_class::<PyVerifier>());
+    try!(m.add("InvalidAlmondError", py.get_type::<InvalidAlmondError>()));
+    try!(m.add("IncorrectHashError", py.get_type::<IncorrectHashError>()));
+    Ok(())
+}
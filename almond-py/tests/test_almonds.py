@@ -0,0 +1,45 @@
+import pytest
+
+import almonds
+
+KEY = b"this_is_a_secret"
+TOKEN = "yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag"
+
+
+def test_create_matches_rust():
+    almond = almonds.Almond(KEY, 1, b"login")
+    almond.add_caveat(b"user", b"erikj")
+    assert almond.serialize_base64() == TOKEN
+
+
+def test_parse_and_verify():
+    almond = almonds.Almond.parse_base64_and_validate(KEY, TOKEN.encode())
+    assert almond.generation == 1
+    assert almond.almond_type == b"login"
+    assert almond.caveats == [b"user erikj"]
+
+    v = almonds.Verifier(almond, 1, b"login")
+    assert not v.verify()
+
+    v.satisfies(b"user", lambda value: value == b"erikj")
+    assert v.verify()
+
+    v.satisfies_exact(b"user", b"noterikj")
+    assert not v.verify()
+
+
+def test_incorrect_hash():
+    with pytest.raises(almonds.IncorrectHashError):
+        almonds.Almond.parse_base64_and_validate(b"wrong", TOKEN.encode())
+
+
+def test_predicate_exception_propagates():
+    almond = almonds.Almond.parse_base64_and_validate(KEY, TOKEN.encode())
+    v = almonds.Verifier(almond, 1, b"login")
+
+    def boom(value):
+        raise RuntimeError("boom")
+
+    v.satisfies(b"user", boom)
+    with pytest.raises(RuntimeError):
+        v.verify()
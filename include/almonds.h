@@ -0,0 +1,87 @@
+/* C interface to the almonds crate.
+ *
+ * Generated from src/ffi.rs with cbindgen (see cbindgen.toml); build the
+ * crate with `--features ffi` to get a library exporting these symbols.
+ */
+
+#ifndef ALMONDS_H
+#define ALMONDS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Status codes returned by the fallible functions. */
+typedef enum AlmondStatus {
+    ALMOND_STATUS_OK = 0,
+    ALMOND_STATUS_NULL_POINTER = 1,
+    ALMOND_STATUS_INVALID_ALMOND = 2,
+    ALMOND_STATUS_INCORRECT_HASH = 3,
+} AlmondStatus;
+
+typedef struct Almond Almond;
+
+typedef struct AlmondVerifier AlmondVerifier;
+
+typedef bool (*AlmondPredicate)(const uint8_t *value,
+                                size_t value_len,
+                                void *user_data);
+
+Almond *almond_create(const uint8_t *key,
+                      size_t key_len,
+                      uint32_t generation,
+                      const uint8_t *almond_type,
+                      size_t almond_type_len);
+
+AlmondStatus almond_add_caveat(Almond *almond,
+                               const uint8_t *key,
+                               size_t key_len,
+                               const uint8_t *value,
+                               size_t value_len);
+
+char *almond_serialize_base64(const Almond *almond);
+
+AlmondStatus almond_parse_validate(const uint8_t *key,
+                                   size_t key_len,
+                                   const uint8_t *input,
+                                   size_t input_len,
+                                   Almond **out);
+
+void almond_free(Almond *almond);
+
+void almond_string_free(char *string);
+
+AlmondVerifier *almond_verifier_new(const Almond *almond,
+                                    uint32_t generation,
+                                    const uint8_t *almond_type,
+                                    size_t almond_type_len);
+
+AlmondStatus almond_verifier_allow(AlmondVerifier *verifier,
+                                   const uint8_t *key,
+                                   size_t key_len);
+
+AlmondStatus almond_verifier_satisfies_exact(AlmondVerifier *verifier,
+                                             const uint8_t *key,
+                                             size_t key_len,
+                                             const uint8_t *value,
+                                             size_t value_len);
+
+AlmondStatus almond_verifier_satisfies(AlmondVerifier *verifier,
+                                       const uint8_t *key,
+                                       size_t key_len,
+                                       AlmondPredicate predicate,
+                                       void *user_data);
+
+bool almond_verifier_verify(const AlmondVerifier *verifier);
+
+void almond_verifier_free(AlmondVerifier *verifier);
+
+#ifdef __cplusplus
+} /* extern "C" */
+#endif
+
+#endif /* ALMONDS_H */
@@ -1,20 +1,97 @@
-use crypto::mac::{Mac, MacResult};
-use crypto::sha2::Sha256;
-use crypto::hmac::Hmac;
+use rand::{OsRng, Rng};
 use rustc_serialize::base64;
-use rustc_serialize::base64::{ToBase64, FromBase64};
+use rustc_serialize::base64::{FromBase64, FromBase64Error, ToBase64};
+use rustc_serialize::hex::{FromHex, FromHexError, ToHex};
+use rustc_serialize::json::{Json, Object};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, BufReader, Read, Write};
+use std::iter;
+use std::slice;
+use std::str::{self, Utf8Error};
+use std::sync::OnceLock;
+use std::time::SystemTime;
 
+use attenuation::AttenuationRecord;
+use base32;
+use base45;
+use binding::{self, CHANNEL_KEY, CONTENT_KEY};
+use cbor;
+use crypt;
+use dictionary::KeyDictionary;
+use ct::{ct_eq, HashTag};
+use format::{self, Caveats, Format, ParseLimits, RawAlmond, RawCaveats};
+use metrics;
+use sha;
+use trace;
+use type_codes::TypeRegistry;
+use net::IpNet;
+use value::CaveatValue;
+use varint;
 
-/// The arbitrary 32 byte array used to seed the initial HMAC.
+
+/// The arbitrary 32 byte array used to seed the initial HMAC, unless a
+/// different seed is given to `Almond::create_with_seed`.
 pub const ALMOND_HASH_SEED : &'static [u8; 32] = b"this_is_a_bit_of_arbitrary_data!";
 
+/// The key of the caveats added by `Almond::add_attenuation_record`.
+pub const ATTENUATION_KEY : &'static [u8] = b"att";
+
+/// The key of the caveats added by `Almond::add_audience`.
+pub const AUDIENCE_KEY : &'static [u8] = b"audience";
+
+/// The key of the caveats added by `Almond::add_ip_restriction`.
+pub const CLIENT_IP_KEY : &'static [u8] = b"client_ip";
+
+/// The key of the caveats added by `Almond::add_epoch`.
+pub const EPOCH_KEY : &'static [u8] = b"epoch";
+
+/// The key of the caveats added by `Almond::add_expiry`.
+pub const EXPIRES_KEY : &'static [u8] = b"expires";
+
+/// The key of the caveats added by `Almond::add_max_caveats`.
+pub const MAX_CAVEATS_KEY : &'static [u8] = b"max_caveats";
+
+/// The key of the caveats added by `Almond::add_not_before`.
+pub const NOT_BEFORE_KEY : &'static [u8] = b"not_before";
+
+/// The key of the caveats added by `Almond::add_scope`.
+pub const SCOPE_KEY : &'static [u8] = b"scope";
+
+/// The key of the caveats added by `Almond::add_token_id`.
+pub const TOKEN_ID_KEY : &'static [u8] = b"id";
+
+/// The key of the caveats added by `Almond::add_single_use`.
+pub const SINGLE_USE_KEY : &'static [u8] = b"single_use";
+
+/// The key of the caveat added by `Almond::seal`.
+///
+/// It starts with a newline, which a v1 caveat can't contain, so it can't be
+/// confused with a caveat of an almond minted before seals existed. Sealed
+/// almonds are therefore always serialized in v2.
+pub const SEALED_KEY : &'static [u8] = b"\nsealed";
+
+/// Hashed in a final round after the caveat added by `Almond::seal`.
+const SEAL_SENTINEL : &'static [u8] = b"\x00almond seal";
+
+/// The version byte of almonds encrypted with `Almond::seal_base64`.
+const ENCRYPTED_VERSION : u8 = 1;
+
+/// The maximum number of bytes `Almond::parse_from_reader` will read.
+///
+/// Base64, Base32, Base45 and hex inputs that decode to more than this are also
+/// rejected.
+pub const MAX_READER_LEN : usize = 64 * 1024;
+
 
 /// A representation of a deserialized Almond.
 ///
 /// Every Almond consists of:
 ///
 /// - A `generation`, which is a simple version to allow upgrading the caveat
-///   formats. This is an 8-bit unsigned integer.
+///   formats. This is a 32-bit unsigned integer, though generations of 255
+///   and above require the v2 format.
 /// - A `type`, which defines what this caveat is used for. For example,
 ///   general access vs. logging in a new client.
 /// - A list of caveats that each *decrease* the level of authorization the
@@ -23,6 +100,9 @@ pub const ALMOND_HASH_SEED : &'static [u8; 32] = b"this_is_a_bit_of_arbitrary_da
 /// The type and caveats are serialized within the almond, so it is suggested
 /// that they are not needlessly verbose.
 ///
+/// Almonds are serialized in the `Format` they were created or parsed with,
+/// see `set_format`.
+///
 /// A caveat is key (with optional value) that decreases the scope of the
 /// Almond. An almond with no caveats grants full authorization to any and
 /// all holders of the Almond. In practice, almost all almonds have at least
@@ -30,27 +110,116 @@ pub const ALMOND_HASH_SEED : &'static [u8; 32] = b"this_is_a_bit_of_arbitrary_da
 ///
 /// The exact format and interpretation of the caveats are application defined.
 ///
+/// Two almonds are equal if they have the same hash, generation, type and
+/// caveats, regardless of their format or key id, like `canonical_bytes`.
+/// The hashes and caveats are compared in constant time, as caveat values
+/// may be secrets such as session ids.
+#[derive(Clone)]
 pub struct Almond {
     hash: [u8; 32],
-    caveats: Vec<Vec<u8>>,
-    generation: u8,
+    /// The caveats, each prefixed with its length as in the v2 format.
+    caveats: Vec<u8>,
+    /// The caveats split into keys and values, computed when first needed.
+    splits: OnceLock<Vec<CaveatSplit>>,
+    generation: u32,
+    key_id: Option<Vec<u8>>,
     almond_type: Vec<u8>,
+    format: Format,
 }
 
 impl Almond {
     /// Create a new Almond with given generation and type.
-    pub fn create(key: &[u8], generation: u8, almond_type: Vec<u8>) -> Almond {
-        let mut almond = Almond {
-            hash: *ALMOND_HASH_SEED,
+    ///
+    /// The almond uses the v1 format, unless the generation or type can't be
+    /// represented in v1.
+    pub fn create(key: &[u8], generation: u32, almond_type: Vec<u8>) -> Almond {
+        Almond::create_with_seed(ALMOND_HASH_SEED, key, generation, almond_type)
+    }
+
+    /// Create a new Almond with given generation and type, using `seed` in
+    /// place of `ALMOND_HASH_SEED`.
+    ///
+    /// Almonds created with different seeds never validate against each
+    /// other, even if the same key is used, so a seed unique to the
+    /// application separates its almonds from those of every other
+    /// application. Use `parse_and_validate_with_seed` to validate them.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let seed = b"example.com access tokens";
+    /// let almond = Almond::create_with_seed(seed, b"secret", 1, b"login".to_vec());
+    /// let serialized = almond.serialize_binary();
+    ///
+    /// assert!(Almond::parse_and_validate_with_seed(seed, b"secret", &serialized).is_ok());
+    /// assert!(Almond::parse_and_validate(b"secret", &serialized).is_err());
+    /// ```
+    pub fn create_with_seed(seed: &[u8], key: &[u8], generation: u32, almond_type: Vec<u8>)
+        -> Almond
+    {
+        let almond = Almond {
+            hash: initial_hash_with_seed(seed, key, generation, &almond_type),
             caveats: Vec::new(),
+            splits: OnceLock::new(),
             generation: generation,
+            key_id: None,
+            format: Format::V1.for_contents(generation, &almond_type, iter::empty()),
             almond_type: almond_type,
         };
+        trace::created(&almond);
+        almond
+    }
 
-        add_to_hash(&mut almond.hash, key);
-        add_to_hash(&mut almond.hash, &[generation]);
-        add_to_hash(&mut almond.hash, &almond.almond_type);
+    /// Create a new Almond with given generation and type, that only
+    /// validates in the given application context.
+    ///
+    /// The context, e.g. the service name and environment, is mixed into the
+    /// hash but not serialized. This means an almond minted for staging
+    /// never validates in production, even if both use the same key. Use
+    /// `parse_and_validate_with_context` to validate them.
+    ///
+    /// This is equivalent to `create_with_seed`, with a seed derived from
+    /// the context.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let almond = Almond::create_with_context(b"secret", b"api staging", 1, b"login".to_vec());
+    /// let serialized = almond.serialize_binary();
+    ///
+    /// assert!(Almond::parse_and_validate_with_context(b"secret", b"api staging", &serialized).is_ok());
+    /// assert!(Almond::parse_and_validate_with_context(b"secret", b"api production", &serialized).is_err());
+    /// ```
+    pub fn create_with_context(key: &[u8], context: &[u8], generation: u32, almond_type: Vec<u8>)
+        -> Almond
+    {
+        Almond::create_with_seed(&context_seed(context), key, generation, almond_type)
+    }
 
+    /// Create a new Almond with given generation and type, that carries
+    /// `key_id` in its header.
+    ///
+    /// The key id lets validators pick the right key, see `key_id`. It is
+    /// only a hint: it is not covered by the hash, so it can't be trusted
+    /// until the almond has been validated. Almonds with a key id always use
+    /// the v2 format, and the key id is not carried by the CBOR or
+    /// MessagePack formats.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let almond = Almond::create_with_key_id(b"secret", b"2017-06".to_vec(), 1, b"login".to_vec());
+    /// let serialized = almond.serialize_binary();
+    ///
+    /// let unverified = Almond::parse_untrusted(&serialized).unwrap();
+    /// assert_eq!(unverified.key_id(), Some(&b"2017-06"[..]));
+    ///
+    /// let almond = unverified.validate(b"secret").unwrap();
+    /// assert_eq!(almond.key_id(), Some(&b"2017-06"[..]));
+    /// ```
+    pub fn create_with_key_id(key: &[u8], key_id: Vec<u8>, generation: u32, almond_type: Vec<u8>)
+        -> Almond
+    {
+        let mut almond = Almond::create(key, generation, almond_type);
+        almond.key_id = Some(key_id);
+        almond.format = Format::V2;
         almond
     }
 
@@ -60,44 +229,347 @@ impl Almond {
     pub fn parse_and_validate(key: &[u8], input: &[u8])
         -> Result<Almond, AlmondParseError>
     {
-        if input.len() < 34 {
-            return Err(AlmondParseError::InvalidAlmond);
-        }
+        Almond::parse_and_validate_with_limits(key, input, &ParseLimits::default())
+    }
+
+    /// Parse a binary serialized Almond, rejecting it if it exceeds
+    /// `limits`, and validate that the hashes match.
+    ///
+    /// `parse_and_validate` uses `ParseLimits::default()`.
+    ///
+    /// ```
+    /// # use almonds::{Almond, AlmondParseError, ParseLimits};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// almond.add_caveat(b"device", Some(b"phone"));
+    ///
+    /// let limits = ParseLimits { max_caveats: 1, ..ParseLimits::default() };
+    /// match Almond::parse_and_validate_with_limits(b"secret", &almond.serialize_binary(), &limits) {
+    ///     Err(AlmondParseError::CaveatLimit { max: 1 }) => {}
+    ///     _ => panic!("almond should exceed the limits"),
+    /// }
+    /// ```
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_and_validate_with_limits(key: &[u8], input: &[u8], limits: &ParseLimits)
+        -> Result<Almond, AlmondParseError>
+    {
+        let _span = trace::parse_span();
 
-        let hash = &input[..32];
-        let generation = input[32];
+        let res = AlmondRef::parse_with_limits(
+            input,
+            limits,
+            |generation, almond_type| initial_hash(key, generation, almond_type),
+        ).map(|a| a.to_almond());
+        trace::parsed(&res);
+        metrics::parsed(&res);
+        res
+    }
 
-        let mut split_it = input[33..].split(|c| *c == b'\n');
+    /// Parse a binary serialized v1 Almond with generation 255, and validate
+    /// that the hashes match.
+    ///
+    /// If the type starts with a version byte the almond looks like v2 or v3,
+    /// so `parse_and_validate` doesn't read it as v1 and rejects it. This is
+    /// only for reading almonds known to have been serialized before v2
+    /// existed, as it can't read v2 almonds. The parsed almond is serialized
+    /// in the v2 format if it would be ambiguous in v1.
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_legacy_v1_and_validate(key: &[u8], input: &[u8])
+        -> Result<Almond, AlmondParseError>
+    {
+        let raw = try!(format::parse_legacy_v1(input));
+        AlmondRef::validate_raw(
+            raw,
+            |generation, almond_type| initial_hash(key, generation, almond_type),
+        ).map(|a| a.to_almond())
+    }
 
-        let almond_type = try!(
-            split_it.next()
-            .ok_or(AlmondParseError::InvalidAlmond)
-        );
+    /// Parse a binary serialized Almond that may use the v3 format produced
+    /// by `serialize_with_dictionary`, and validate that the hashes match.
+    ///
+    /// The dictionary must have the same keys as the one the almond was
+    /// serialized with. The parsed almond uses the v2 format.
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_and_validate_with_dictionary(
+        key: &[u8],
+        input: &[u8],
+        dictionary: &KeyDictionary,
+    ) -> Result<Almond, AlmondParseError> {
+        let expanded = try!(format::expand_dictionary(input, dictionary));
+        Almond::parse_and_validate(key, &expanded)
+    }
+
+    /// Parse a binary serialized Almond whose type may be a code produced by
+    /// `serialize_with_type_code`, and validate that the hashes match.
+    ///
+    /// The parsed almond uses the v2 format if the type was a code.
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_and_validate_with_type_codes(
+        key: &[u8],
+        input: &[u8],
+        types: &TypeRegistry,
+    ) -> Result<Almond, AlmondParseError> {
+        let expanded = try!(format::expand_type_code(input, types));
+        Almond::parse_and_validate(key, &expanded)
+    }
+
+    /// Parse a binary serialized Almond created with `create_with_seed`, and
+    /// validate that the hashes match.
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_and_validate_with_seed(seed: &[u8], key: &[u8], input: &[u8])
+        -> Result<Almond, AlmondParseError>
+    {
+        AlmondRef::parse_with(
+            input,
+            |generation, almond_type| initial_hash_with_seed(seed, key, generation, almond_type),
+        ).map(|a| a.to_almond())
+    }
+
+    /// Parse a binary serialized Almond created with `create_with_context`,
+    /// and validate that the hashes match.
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_and_validate_with_context(key: &[u8], context: &[u8], input: &[u8])
+        -> Result<Almond, AlmondParseError>
+    {
+        Almond::parse_and_validate_with_seed(&context_seed(context), key, input)
+    }
+
+    /// Parse the structure of a binary serialized Almond *without*
+    /// validating the hash.
+    ///
+    /// This allows routing on the type or generation of an almond before the
+    /// key that validates it is known. Nothing in the result can be trusted
+    /// until `UnverifiedAlmond::validate` succeeds.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let mut almond = Almond::create(b"login_secret", 1, b"login".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// let serialized = almond.serialize_binary();
+    ///
+    /// let unverified = Almond::parse_untrusted(&serialized).unwrap();
+    /// let key: &[u8] = match unverified.almond_type() {
+    ///     b"login" => b"login_secret",
+    ///     _ => b"access_secret",
+    /// };
+    ///
+    /// assert!(unverified.validate(key).is_ok());
+    /// ```
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_untrusted(input: &[u8]) -> Result<UnverifiedAlmond, AlmondParseError> {
+        UnverifiedAlmond::parse(input)
+    }
+
+    /// Parse a binary serialized Almond from `reader`, and validate that the
+    /// hashes match.
+    ///
+    /// The reader is read until EOF, and caveats are hashed as they are read
+    /// rather than buffering the entire input first. Inputs longer than
+    /// `MAX_READER_LEN` bytes are rejected.
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_from_reader<R: Read>(key: &[u8], reader: R)
+        -> Result<Almond, AlmondParseError>
+    {
+        let mut reader = BufReader::new(reader.take(MAX_READER_LEN as u64 + 1));
+
+        let (header, mut stream) = try!(format::read_header(&mut reader));
 
-        let mut almond = Almond::create(key, generation, almond_type.to_vec());
+        let mut almond = Almond::create(key, header.generation, header.almond_type);
+        almond.format = header.format;
+        almond.key_id = header.key_id;
 
-        for caveat in split_it {
-            almond.add_literal_caveat(caveat.to_vec());
+        let limits = ParseLimits::default();
+        let mut hasher = CaveatHasher::new(almond.hash);
+        let mut caveat = Vec::new();
+        for index in 0.. {
+            caveat.clear();
+            if !try!(stream.next(&mut reader, &mut caveat)) {
+                break;
+            }
+            try!(format::check_caveat_limits(&limits, index, &caveat));
+            hasher.add(&caveat);
+            format::push_prefixed(&mut almond.caveats, &caveat);
+        }
+        almond.hash = hasher.finish();
+
+        // If we've used up the entire limit then the input was too long.
+        if reader.get_ref().limit() == 0 {
+            return Err(AlmondParseError::TooLong { max: MAX_READER_LEN });
         }
 
         // Always compare hashes using equality operators that are
         // resistent to timing attacks.
-        if MacResult::new(hash) == MacResult::new(almond.hash()) {
+        if ct_eq(&header.hash, &almond.hash) {
             Ok(almond)
         } else {
-            Err(AlmondParseError::IncorrectHash)
+            Err(hash_error(almond.caveats()))
+        }
+    }
+
+    /// Parse and validate a list of binary serialized Almonds with the same
+    /// key.
+    ///
+    /// This is faster than calling `parse_and_validate` on each input, as the
+    /// hash of the key, generation and type is only computed once for each
+    /// distinct generation and type.
+    ///
+    /// *Note: This expects binary serializations rather than base64*
+    pub fn parse_and_validate_batch(key: &[u8], inputs: &[&[u8]])
+        -> Vec<Result<Almond, AlmondParseError>>
+    {
+        let mut prefixes = HashMap::new();
+
+        inputs.iter().map(|input| {
+            AlmondRef::parse_with(input, |generation, almond_type| {
+                *prefixes.entry((generation, almond_type)).or_insert_with(
+                    || initial_hash(key, generation, almond_type)
+                )
+            }).map(|almond| almond.to_almond())
+        }).collect()
+    }
+
+    /// Parse a binary serialized Almond, and validate it against each of
+    /// `keys` in turn, e.g. the new and old keys during a key rotation.
+    ///
+    /// Returns the almond and the index of the first key that validated it.
+    /// The input is only parsed once, but as the key is the first input to
+    /// the hash the caveats are hashed once per key that is tried.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let almond = Almond::create(b"old_secret", 1, b"login".to_vec());
+    /// let serialized = almond.serialize_binary();
+    ///
+    /// let keys: &[&[u8]] = &[b"new_secret", b"old_secret"];
+    /// let (_, idx) = Almond::parse_and_validate_any(keys, &serialized).unwrap();
+    /// assert_eq!(idx, 1);
+    /// ```
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_and_validate_any(keys: &[&[u8]], input: &[u8])
+        -> Result<(Almond, usize), AlmondParseError>
+    {
+        let raw = try!(format::parse(input));
+
+        for (idx, key) in keys.iter().enumerate() {
+            let hash = hash_caveats(
+                initial_hash(key, raw.generation, raw.almond_type),
+                raw.caveats.iter(),
+            );
+
+            // Always compare hashes using equality operators that are
+            // resistent to timing attacks.
+            if ct_eq(raw.hash, &hash) {
+                let almond = Almond {
+                    hash: hash,
+                    caveats: raw.caveats.to_prefixed(),
+                    splits: OnceLock::new(),
+                    generation: raw.generation,
+                    key_id: raw.key_id.map(|k| k.to_vec()),
+                    almond_type: raw.almond_type.to_vec(),
+                    format: raw.format,
+                };
+                return Ok((almond, idx));
+            }
         }
+
+        Err(hash_error(raw.caveats.iter()))
     }
 
     /// Parse a Base64 serialized Almond, and validate that the hashes match.
+    ///
+    /// Both the standard and URL safe alphabets are accepted, with or
+    /// without padding, so this parses the output of `serialize_base64_with`
+    /// for any `Base64Config`.
     pub fn parse_base64_and_validate(key: &[u8], input: &[u8])
         -> Result<Almond, AlmondParseError>
     {
-        let parsed = try!(
-            input.from_base64()
-            .or(Err(AlmondParseError::InvalidAlmond))
-        );
-        Almond::parse_and_validate(key, &parsed)
+        parse_text_and_validate(key, input, |input| {
+            input.from_base64().map_err(AlmondParseError::Base64)
+        })
+    }
+
+    /// Parse a Base32 serialized Almond, see `serialize_base32`, and
+    /// validate that the hashes match.
+    ///
+    /// Both upper and lower case input is accepted.
+    pub fn parse_base32_and_validate(key: &[u8], input: &[u8])
+        -> Result<Almond, AlmondParseError>
+    {
+        parse_text_and_validate(key, input, |input| {
+            base32::decode(input).ok_or(AlmondParseError::Base32)
+        })
+    }
+
+    /// Parse a Base45 serialized Almond, see `serialize_base45`, and
+    /// validate that the hashes match.
+    pub fn parse_base45_and_validate(key: &[u8], input: &[u8])
+        -> Result<Almond, AlmondParseError>
+    {
+        parse_text_and_validate(key, input, |input| {
+            base45::decode(input).ok_or(AlmondParseError::Base45)
+        })
+    }
+
+    /// Decrypt an almond encrypted with `seal_base64`, and validate that the
+    /// hashes match. `secret` is used both to decrypt and validate it.
+    ///
+    /// Returns `AlmondParseError::Decryption` if the input was encrypted with
+    /// a different secret, or has been modified.
+    pub fn open_and_validate(secret: &[u8], input: &[u8])
+        -> Result<Almond, AlmondParseError>
+    {
+        let encrypted = try!(decode_text(input, |input| {
+            input.from_base64().map_err(AlmondParseError::Base64)
+        }));
+
+        let key = crypt::token_key(secret);
+        let decrypted = match encrypted.split_first() {
+            Some((&ENCRYPTED_VERSION, rest)) => crypt::decrypt(&key, &[ENCRYPTED_VERSION], rest),
+            _ => None,
+        };
+
+        match decrypted {
+            Some(decrypted) => Almond::parse_and_validate(secret, &decrypted),
+            None => Err(AlmondParseError::Decryption),
+        }
+    }
+
+    /// Parse a hex serialized Almond, see `serialize_hex`, and validate that
+    /// the hashes match.
+    ///
+    /// Both upper and lower case input is accepted.
+    pub fn parse_hex_and_validate(key: &[u8], input: &[u8])
+        -> Result<Almond, AlmondParseError>
+    {
+        // `FromHex` ignores newlines, which would allow more than one
+        // encoding of the same almond.
+        parse_text_and_validate(key, input, |input| {
+            if let Some(idx) = input.iter().position(|c| *c == b'\n' || *c == b'\r') {
+                let err = FromHexError::InvalidHexCharacter(input[idx] as char, idx);
+                return Err(AlmondParseError::Hex(err));
+            }
+
+            // Invalid UTF-8 is replaced with characters that aren't hex.
+            String::from_utf8_lossy(input).from_hex().map_err(AlmondParseError::Hex)
+        })
+    }
+
+    /// Parse a CBOR serialized Almond, see `serialize_cbor`, and validate
+    /// that the hashes match.
+    pub fn parse_cbor_and_validate(key: &[u8], input: &[u8])
+        -> Result<Almond, AlmondParseError>
+    {
+        let raw = try!(cbor::parse(input));
+        validate_parts(key, raw.hash, raw.generation, raw.almond_type, &raw.caveats)
     }
 
     /// Add a new literal caveat.
@@ -105,148 +577,2939 @@ impl Almond {
     /// The interpretation of the caveat is either `<key>` or `<key> <value>`
     /// depending on if `caveat` has a space or not.
     pub fn add_literal_caveat(&mut self, caveat: Vec<u8>) -> &mut Self {
-        add_to_hash(&mut self.hash, &caveat);
-        self.caveats.push(caveat);
+        self.push_caveat(&[&caveat]);
         self
     }
 
     /// Adds a caveat.
     ///
-    /// The key must not include a space.
+    /// The key must not be empty or include a space, otherwise the almond
+    /// will not round trip through serialization. Use `try_add_caveat` to
+    /// have this checked.
+    ///
+    /// Keys and values may only include newlines in the v2 format. Adding
+    /// one to a v1 almond means it will be serialized as v2 instead.
     pub fn add_caveat(&mut self, key: &[u8], value: Option<&[u8]>) -> &mut Self {
-        let mut caveat = Vec::new();
-        caveat.push_all(key);
-        if let Some(val) = value {
-            caveat.push(b' ');
-            caveat.push_all(val);
+        match value {
+            Some(val) => self.push_caveat(&[key, b" ", val]),
+            None => self.push_caveat(&[key]),
         }
-        add_to_hash(&mut self.hash, &caveat);
-        self.caveats.push(caveat);
         self
     }
 
-    /// Get the type of the Almond
-    pub fn almond_type(&self) -> &[u8] {
-        &self.almond_type
-    }
-
-    /// Get the generation of the Almond
-    pub fn generation(&self) -> u8 {
-        self.generation
+    /// Returns a copy of the almond with the given caveats added, leaving
+    /// this one unchanged.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let almond = Almond::create(b"secret", 1, b"access".to_vec());
+    ///
+    /// let reader = almond.attenuate(&[(b"scope", Some(b"repo:read"))]);
+    /// assert_eq!(reader.caveats().count(), 1);
+    /// assert_eq!(almond.caveats().count(), 0);
+    /// ```
+    pub fn attenuate(&self, caveats: &[(&[u8], Option<&[u8]>)]) -> Almond {
+        self.attenuated_with(|almond| {
+            for &(key, value) in caveats {
+                almond.add_caveat(key, value);
+            }
+        })
     }
 
-    /// Get the *current* caveats of the Almond
-    pub fn caveats(&self) -> &[Vec<u8>] {
-        &self.caveats
+    /// Returns a copy of the almond attenuated by `f`, leaving this one
+    /// unchanged.
+    ///
+    /// This allows a service to keep a broad almond and hand out several
+    /// differently restricted copies of it. `f` may use any of the methods
+    /// that add caveats.
+    ///
+    /// ```
+    /// # use std::time::{Duration, SystemTime};
+    /// # use almonds::Almond;
+    /// let almond = Almond::create(b"secret", 1, b"access".to_vec());
+    ///
+    /// let expiring = almond.attenuated_with(|a| {
+    ///     a.add_caveat(b"user", Some(b"erikj"))
+    ///         .add_expiry(SystemTime::now() + Duration::from_secs(60));
+    /// });
+    /// assert!(expiring != almond);
+    /// assert_eq!(expiring.caveat_str(b"user"), Some("erikj"));
+    /// ```
+    pub fn attenuated_with<F>(&self, f: F) -> Almond
+        where F: FnOnce(&mut Almond)
+    {
+        let mut almond = self.clone();
+        f(&mut almond);
+        almond
     }
 
-    /// Get the *current* hash of the almond.
+    /// Add the caveat made by concatenating `parts` to the hash and the list
+    /// of caveats.
     ///
-    /// # Safety
-    /// Do not compare this directly with other hashes. Always use a specially
-    /// designed constant time comparison function.
-    pub fn hash(&self) -> &[u8; 32] {
-        &self.hash
-    }
+    /// This continues the hash from the serialized hash, rather than the one
+    /// a validator computes, so that caveats added after a seal invalidate
+    /// the almond. See `seal`.
+    fn push_caveat(&mut self, parts: &[&[u8]]) {
+        let len = parts.iter().fold(0, |len, part| len + part.len());
+        varint::encode(len as u64, &mut self.caveats);
 
-    /// Serialize into a binary blob
-    pub fn serialize_binary(&self) -> Vec<u8> {
-        let mut result : Vec<u8> = Vec::new();
+        let start = self.caveats.len();
+        for part in parts {
+            self.caveats.extend_from_slice(part);
+        }
+
+        if let Some(splits) = self.splits.get_mut() {
+            splits.push(CaveatSplit::new(start, &self.caveats[start..]));
+        }
 
-        result.push_all(&self.hash);
-        result.push(self.generation);
-        result.push_all(&self.almond_type);
-        result.push(b'\n');
+        let mut hasher = CaveatHasher::new(self.hash);
+        hasher.add(&self.caveats[start..]);
+        self.hash = hasher.finish();
+    }
 
-        for caveat in &self.caveats {
-            result.push_all(&caveat);
-            result.push(b'\n');
+    /// Seal the almond, so that it can't be attenuated any further.
+    ///
+    /// This adds a `SEALED_KEY` caveat followed by a final hash round, which
+    /// a holder of the serialized almond can't undo. Any caveat added after
+    /// the seal, either with `add_caveat` or by appending to the serialized
+    /// almond, makes the almond fail validation with
+    /// `AlmondParseError::ExtendedAfterSeal`.
+    ///
+    /// This is useful for almonds handed to untrusted intermediaries, which
+    /// shouldn't be able to derive new almonds from them. A `Verifier`
+    /// accepts the seal caveat, and can insist on it with `require_sealed`.
+    ///
+    /// ```
+    /// # use almonds::{Almond, AlmondParseError};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// almond.seal();
+    /// assert!(almond.is_sealed());
+    ///
+    /// let serialized = almond.serialize_binary();
+    /// assert!(Almond::parse_and_validate(b"secret", &serialized).is_ok());
+    ///
+    /// almond.add_caveat(b"device", Some(b"phone"));
+    /// match Almond::parse_and_validate(b"secret", &almond.serialize_binary()) {
+    ///     Err(AlmondParseError::ExtendedAfterSeal) => {}
+    ///     _ => panic!("almond was extended after the seal"),
+    /// }
+    /// ```
+    pub fn seal(&mut self) -> &mut Self {
+        if !self.is_sealed() {
+            self.push_caveat(&[SEALED_KEY]);
         }
+        self
+    }
+
+    /// Returns whether the last caveat is the seal added by `seal`.
+    pub fn is_sealed(&self) -> bool {
+        self.caveats().last() == Some(SEALED_KEY)
+    }
 
-        result.pop();
+    /// Adds a caveat, first checking that it can be safely serialized in the
+    /// almond's format.
+    ///
+    /// ```
+    /// # use almonds::{Almond, CaveatError};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// assert!(almond.try_add_caveat(b"user", Some(b"erikj")).is_ok());
+    /// assert_eq!(
+    ///     almond.try_add_caveat(b"user", Some(b"erikj\nadmin")).err(),
+    ///     Some(CaveatError::InvalidValue)
+    /// );
+    /// assert_eq!(almond.caveats().count(), 1);
+    /// ```
+    pub fn try_add_caveat(&mut self, key: &[u8], value: Option<&[u8]>)
+        -> Result<&mut Self, CaveatError>
+    {
+        try!(check_caveat(self.format, key, value));
+        Ok(self.add_caveat(key, value))
+    }
 
-        result
+    /// Adds a caveat with a string key and value. See `add_caveat`.
+    pub fn add_caveat_str(&mut self, key: &str, value: Option<&str>) -> &mut Self {
+        self.add_caveat(key.as_bytes(), value.map(|v| v.as_bytes()))
     }
 
-    /// Serialize into Base64.
+    /// Adds a caveat whose value is encoded with `CaveatValue`.
     ///
-    /// This is equivalent to Base64 encoding the binary serialization
-    pub fn serialize_base64(&self) -> String {
-        self.to_base64(base64::URL_SAFE)
+    /// ```
+    /// # use almonds::Almond;
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat_typed(b"expires", 1500000000u64);
+    /// almond.add_caveat_typed(b"user", "erikj");
+    ///
+    /// assert_eq!(almond.caveat_typed::<u64>(b"expires"), Some(1500000000));
+    /// assert_eq!(almond.caveat_typed::<&str>(b"user"), Some("erikj"));
+    /// ```
+    pub fn add_caveat_typed<'v, T>(&mut self, key: &[u8], value: T) -> &mut Self
+        where T: CaveatValue<'v>
+    {
+        let mut encoded = Vec::new();
+        value.encode_value(&mut encoded);
+        self.add_caveat(key, Some(&encoded))
     }
-}
 
-impl base64::ToBase64 for Almond {
-    fn to_base64(&self, config: base64::Config) -> String {
-        let serialized = self.serialize_binary();
-        serialized.to_base64(config)
+    /// Adds a caveat whose value is encrypted with a key derived from
+    /// `secret`, the key the almond was created with, so that holders of
+    /// the almond can't read it.
+    ///
+    /// The value is authenticated along with the caveat key, and stored as
+    /// URL safe Base64. Read it with `decrypt_caveat`, or check it with
+    /// `Verifier::satisfies_encrypted`.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_encrypted_caveat(b"secret", b"user_id", b"1234");
+    ///
+    /// assert!(almond.caveat_value(b"user_id") != Some(&b"1234"[..]));
+    /// assert_eq!(almond.decrypt_caveat(b"secret", b"user_id"), Some(b"1234".to_vec()));
+    /// assert_eq!(almond.decrypt_caveat(b"other", b"user_id"), None);
+    /// ```
+    pub fn add_encrypted_caveat(&mut self, secret: &[u8], key: &[u8], plaintext: &[u8])
+        -> &mut Self
+    {
+        let enc_key = crypt::caveat_key(secret, self.generation, &self.almond_type);
+        self.add_caveat(key, Some(&crypt::encrypt_caveat(&enc_key, key, plaintext)))
     }
-}
 
+    /// Decrypt the value of the first caveat with the given key, added with
+    /// `add_encrypted_caveat`.
+    ///
+    /// Returns `None` if there is no such caveat, or it can't be decrypted
+    /// with `secret`.
+    ///
+    /// *Note: This does not check that the caveats are well formed. Use a
+    /// `Verifier` first.*
+    pub fn decrypt_caveat(&self, secret: &[u8], key: &[u8]) -> Option<Vec<u8>> {
+        let enc_key = crypt::caveat_key(secret, self.generation, &self.almond_type);
+        self.caveat_value(key).and_then(|value| crypt::decrypt_caveat(&enc_key, key, value))
+    }
 
-fn add_to_hash(hash: &mut [u8], data: &[u8]) {
-    let hasher = Sha256::new();
-    let mut mac = Hmac::new(hasher, hash);
-    mac.input(data);
-    mac.raw_result(hash);
-}
+    /// Adds a caveat that restricts the almond to the service with the given
+    /// identifier, stored under `AUDIENCE_KEY`. Check it with
+    /// `Verifier::satisfies_audience`.
+    pub fn add_audience(&mut self, audience: &[u8]) -> &mut Self {
+        self.add_caveat(AUDIENCE_KEY, Some(audience))
+    }
 
+    /// Adds a caveat that restricts the almond to the given scope, stored
+    /// under `SCOPE_KEY`. See the `scope` module for the syntax.
+    ///
+    /// Every scope caveat must be satisfied, so adding a narrower scope to an
+    /// almond attenuates it.
+    pub fn add_scope(&mut self, scope: &str) -> &mut Self {
+        self.add_caveat(SCOPE_KEY, Some(scope.as_bytes()))
+    }
 
-quick_error! {
-    /// An error returned when we failed to parse a buffer as an almond.
-    #[derive(Debug)]
-    pub enum AlmondParseError {
-        /// The buffer did not contain a valid almond. 
-        InvalidAlmond {}
+    /// Adds a caveat that restricts the almond to clients in the given
+    /// network, stored under `CLIENT_IP_KEY`. Check it with
+    /// `Verifier::satisfies_client_ip`.
+    ///
+    /// ```
+    /// # use almonds::{Almond, CLIENT_IP_KEY};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_ip_restriction("10.0.0.0/8".parse().unwrap());
+    /// assert_eq!(almond.caveat_str(CLIENT_IP_KEY), Some("10.0.0.0/8"));
+    /// ```
+    pub fn add_ip_restriction(&mut self, network: IpNet) -> &mut Self {
+        self.add_caveat_typed(CLIENT_IP_KEY, network)
+    }
 
-        /// The hash did not match the deserialized Almond.
-        IncorrectHash {}
+    /// Adds a caveat that binds the almond to a request body with the given
+    /// SHA-256 digest, see `binding::content_digest`. Check it with
+    /// `Verifier::satisfies_content`.
+    pub fn add_content_binding(&mut self, digest: &[u8; 32]) -> &mut Self {
+        self.add_caveat(CONTENT_KEY, Some(&binding::encode_digest(digest)))
     }
-}
 
+    /// Adds a caveat that binds the almond to the TLS connection that the
+    /// given keying material was exported from. See the `binding` module.
+    /// Check it with `Verifier::satisfies_channel`.
+    pub fn add_channel_binding(&mut self, exporter: &[u8]) -> &mut Self {
+        let digest = binding::channel_digest(exporter);
+        self.add_caveat(CHANNEL_KEY, Some(&binding::encode_digest(&digest)))
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use test::Bencher;
-    use rustc_serialize::base64::{FromBase64, ToBase64, URL_SAFE};
+    /// Adds a caveat with a random token id, stored under `TOKEN_ID_KEY`,
+    /// and returns the id.
+    ///
+    /// The id allows the almond to be revoked, see the `revocation` module.
+    /// It is 16 random bytes from the operating system, hex encoded.
+    pub fn add_token_id(&mut self) -> Vec<u8> {
+        let token_id = random_id();
+        self.add_caveat(TOKEN_ID_KEY, Some(&token_id));
+        token_id
+    }
 
-    #[test]
-    fn basic_test() {
-        let key = b"this_is_a_secret";
+    /// Adds a caveat that allows the almond to only be used once, and returns
+    /// its random id. The id is stored under `SINGLE_USE_KEY`, with the same
+    /// encoding as `add_token_id`.
+    ///
+    /// Verifiers reject the almond unless they have a `UseStore`, see
+    /// `Verifier::set_use_store`.
+    pub fn add_single_use(&mut self) -> Vec<u8> {
+        let token_id = random_id();
+        self.add_caveat(SINGLE_USE_KEY, Some(&token_id));
+        token_id
+    }
 
-        let mut almond = Almond::create(key, 1, b"login".to_vec());
-        almond.add_caveat(b"user", Some(b"erikj"));
-        let r = almond.to_base64(URL_SAFE);
-        assert_eq!(
-            r,
-            "yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag"
-        );
+    /// Get the token id of the almond, if it has one.
+    pub fn token_id(&self) -> Option<&[u8]> {
+        self.caveat_value(TOKEN_ID_KEY)
+    }
 
-        Almond::parse_and_validate(key, &almond.serialize_binary()).unwrap();
+    /// Adds a caveat that expires the almond at the given time.
+    ///
+    /// The time is stored under `EXPIRES_KEY` as whole seconds since the UNIX
+    /// epoch, see `CaveatValue`. Check it with `Verifier::satisfies_expiry`.
+    pub fn add_expiry(&mut self, expires: SystemTime) -> &mut Self {
+        self.add_caveat_typed(EXPIRES_KEY, expires)
     }
 
-    #[test]
-    fn parse_and_serialize() {
-        let key = b"this_is_a_secret";
+    /// Get the earliest expiry time of the almond, if it has one.
+    ///
+    /// *Note: This does not check that the caveats are well formed. Use a
+    /// `Verifier` first.*
+    pub fn expiry(&self) -> Option<SystemTime> {
+        self.iter_caveats()
+            .filter(|caveat| caveat.key() == EXPIRES_KEY)
+            .filter_map(|caveat| caveat.value().and_then(SystemTime::decode_value))
+            .min()
+    }
 
-        let input = "yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag";
-        let a = Almond::parse_and_validate(key, &input.from_base64().unwrap()).unwrap();
+    /// Adds a caveat that prevents the almond being used before the given
+    /// time.
+    ///
+    /// The time is stored under `NOT_BEFORE_KEY` with the same encoding as
+    /// `add_expiry`. Check it with `Verifier::satisfies_not_before`.
+    pub fn add_not_before(&mut self, not_before: SystemTime) -> &mut Self {
+        self.add_caveat_typed(NOT_BEFORE_KEY, not_before)
+    }
 
-        assert_eq!(a.to_base64(URL_SAFE), input);
+    /// Get the latest not before time of the almond, if it has one.
+    ///
+    /// *Note: This does not check that the caveats are well formed. Use a
+    /// `Verifier` first.*
+    pub fn not_before(&self) -> Option<SystemTime> {
+        self.iter_caveats()
+            .filter(|caveat| caveat.key() == NOT_BEFORE_KEY)
+            .filter_map(|caveat| caveat.value().and_then(SystemTime::decode_value))
+            .max()
     }
 
-    #[bench]
-    fn create(b: &mut Bencher) {
-        let key = b"this_is_a_secret";
-        b.iter(|| {
-            let mut almond = Almond::create(key, 1, b"login".to_vec());
-            almond.add_caveat(b"user", Some(b"erikj"));
-            almond.add_caveat(b"fooo", Some(b"bar"));
-            almond.add_caveat(b"testing", None);
-            almond.add_caveat(b"teeeeeeeeeeeest", None);
-            almond
-        });
+    /// Adds a caveat recording the epoch the almond was minted in.
+    ///
+    /// The epoch is stored under `EPOCH_KEY` as a decimal integer. Bumping
+    /// the minimum epoch passed to `Verifier::satisfies_min_epoch` revokes
+    /// every almond minted in an earlier epoch, without rotating the key.
+    pub fn add_epoch(&mut self, epoch: u64) -> &mut Self {
+        self.add_caveat_typed(EPOCH_KEY, epoch)
+    }
+
+    /// Get the earliest epoch of the almond, if it has one.
+    ///
+    /// *Note: This does not check that the caveats are well formed. Use a
+    /// `Verifier` first.*
+    pub fn epoch(&self) -> Option<u64> {
+        self.iter_caveats()
+            .filter(|caveat| caveat.key() == EPOCH_KEY)
+            .filter_map(|caveat| caveat.value().and_then(u64::decode_value))
+            .min()
+    }
+
+    /// Adds a caveat limiting the total number of caveats the almond may
+    /// have, including this one, stored under `MAX_CAVEATS_KEY`. Check it
+    /// with `Verifier::satisfies_max_caveats`.
+    ///
+    /// This bounds how far the almond can be attenuated, e.g. by a service
+    /// that re-attenuates almonds on behalf of its clients.
+    pub fn add_max_caveats(&mut self, max: u64) -> &mut Self {
+        self.add_caveat_typed(MAX_CAVEATS_KEY, max)
+    }
+
+    /// Get the smallest caveat limit of the almond, if it has one.
+    ///
+    /// *Note: This does not check that the caveats are well formed. Use a
+    /// `Verifier` first.*
+    pub fn max_caveats(&self) -> Option<u64> {
+        self.iter_caveats()
+            .filter(|caveat| caveat.key() == MAX_CAVEATS_KEY)
+            .filter_map(|caveat| caveat.value().and_then(u64::decode_value))
+            .min()
+    }
+
+    /// Adds a caveat recording that `actor` attenuated the almond at
+    /// `timestamp`, stored under `ATTENUATION_KEY`. Check it with
+    /// `Verifier::satisfies_attenuation_records`.
+    ///
+    /// Add this alongside the caveats that restrict the almond, so the
+    /// records show who narrowed it. See the `attenuation` module. The actor
+    /// must not be empty.
+    pub fn add_attenuation_record(&mut self, actor: &str, timestamp: SystemTime) -> &mut Self {
+        self.add_caveat_typed(ATTENUATION_KEY, AttenuationRecord::new(actor, timestamp))
+    }
+
+    /// Get the attenuation records of the almond, in the order they were
+    /// added.
+    ///
+    /// *Note: This does not check that the caveats are well formed, and
+    /// skips malformed records. Use a `Verifier` first.*
+    pub fn attenuation_records(&self) -> Vec<AttenuationRecord> {
+        self.iter_caveats()
+            .filter(|caveat| caveat.key() == ATTENUATION_KEY)
+            .filter_map(|caveat| caveat.value().and_then(AttenuationRecord::decode_value))
+            .collect()
+    }
+
+    /// Get the type of the Almond
+    pub fn almond_type(&self) -> &[u8] {
+        &self.almond_type
+    }
+
+    /// Get the type of the Almond as a string
+    pub fn almond_type_str(&self) -> Result<&str, Utf8Error> {
+        str::from_utf8(&self.almond_type)
+    }
+
+    /// Get the generation of the Almond
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Get the key id carried in the header, see `create_with_key_id`.
+    pub fn key_id(&self) -> Option<&[u8]> {
+        self.key_id.as_ref().map(|k| &k[..])
+    }
+
+    /// Get an iterator over the *current* caveats of the Almond
+    pub fn caveats(&self) -> Caveats {
+        RawCaveats::Prefixed(&self.caveats).iter()
+    }
+
+    /// Get an iterator over the *current* caveats of the Almond, split into
+    /// their keys and values.
+    ///
+    /// The caveats are split the first time this is called, and the result
+    /// is kept for later calls and for every `Verifier` of the almond.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// almond.add_caveat(b"guest", None);
+    ///
+    /// let pairs: Vec<_> = almond.iter_caveats().map(|c| c.pair()).collect();
+    /// assert_eq!(pairs, vec![
+    ///     (&b"user"[..], Some(&b"erikj"[..])),
+    ///     (&b"guest"[..], None),
+    /// ]);
+    /// ```
+    pub fn iter_caveats(&self) -> CaveatIter {
+        let splits = self.splits.get_or_init(|| split_caveats(&self.caveats));
+        CaveatIter { caveats: &self.caveats, splits: splits.iter() }
+    }
+
+    /// Collect the *current* caveats into a map from key to the values of
+    /// every caveat with that key, in order.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"scope", Some(b"read"));
+    /// almond.add_caveat(b"scope", Some(b"write"));
+    /// almond.add_caveat(b"guest", None);
+    ///
+    /// let map = almond.caveat_map();
+    /// assert_eq!(map[&b"scope"[..]], vec![Some(&b"read"[..]), Some(&b"write"[..])]);
+    /// assert_eq!(map[&b"guest"[..]], vec![None]);
+    /// ```
+    pub fn caveat_map(&self) -> BTreeMap<&[u8], Vec<Option<&[u8]>>> {
+        let mut map = BTreeMap::new();
+        for caveat in self.iter_caveats() {
+            map.entry(caveat.key()).or_insert_with(Vec::new).push(caveat.value());
+        }
+        map
+    }
+
+    /// Describe the almond as JSON, for logging and debugging.
+    ///
+    /// Only the first four bytes of the hash are included, so the output
+    /// can't be used to reconstruct the token. Bytes that aren't valid UTF-8
+    /// are replaced, and caveats without a value have a `null` value.
+    ///
+    /// *Note: This is not a wire format, almonds can't be parsed or verified
+    /// from it.*
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    ///
+    /// assert_eq!(
+    ///     almond.to_inspection_json().to_string(),
+    ///     r#"{"caveats":[{"key":"user","value":"erikj"}],"generation":1,"hash_prefix":"45949dbf","type":"access"}"#
+    /// );
+    /// ```
+    pub fn to_inspection_json(&self) -> Json {
+        let caveats = self.iter_caveats().map(|caveat| {
+            let mut object = Object::new();
+            object.insert("key".to_owned(), lossy_json(caveat.key()));
+            object.insert(
+                "value".to_owned(),
+                caveat.value().map(lossy_json).unwrap_or(Json::Null),
+            );
+            Json::Object(object)
+        }).collect();
+
+        let mut object = Object::new();
+        object.insert("generation".to_owned(), Json::U64(self.generation as u64));
+        object.insert("type".to_owned(), lossy_json(&self.almond_type));
+        object.insert("caveats".to_owned(), Json::Array(caveats));
+        object.insert("hash_prefix".to_owned(), Json::String(self.hash[..4].to_hex()));
+        Json::Object(object)
+    }
+
+    /// Get the value of the first caveat with the given key.
+    ///
+    /// Returns `None` if there is no such caveat, or if it has no value.
+    ///
+    /// *Note: This does not check that the caveat is satisfied. Use a
+    /// `Verifier` first.*
+    pub fn caveat_value(&self, key: &[u8]) -> Option<&[u8]> {
+        self.iter_caveats()
+            .find(|caveat| caveat.key() == key)
+            .and_then(|caveat| caveat.value())
+    }
+
+    /// Get the value of the first caveat with the given key as a string.
+    ///
+    /// Returns `None` if the value is missing or is not valid UTF-8.
+    pub fn caveat_str(&self, key: &[u8]) -> Option<&str> {
+        self.caveat_value(key).and_then(|value| str::from_utf8(value).ok())
+    }
+
+    /// Get the value of the first caveat with the given key as a string,
+    /// returning an error if the value is not valid UTF-8.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat_str("user", Some("erikj"));
+    /// almond.add_caveat(b"blob", Some(b"\xff"));
+    ///
+    /// assert_eq!(almond.caveat_str_value("user"), Ok(Some("erikj")));
+    /// assert_eq!(almond.caveat_str_value("missing"), Ok(None));
+    /// assert!(almond.caveat_str_value("blob").is_err());
+    /// ```
+    pub fn caveat_str_value(&self, key: &str) -> Result<Option<&str>, Utf8Error> {
+        match self.caveat_value(key.as_bytes()) {
+            Some(value) => str::from_utf8(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the value of the first caveat with the given key, decoded with
+    /// `CaveatValue`.
+    ///
+    /// Returns `None` if the value is missing or could not be decoded.
+    pub fn caveat_typed<'a, T>(&'a self, key: &[u8]) -> Option<T>
+        where T: CaveatValue<'a>
+    {
+        self.caveat_value(key).and_then(T::decode_value)
+    }
+
+    /// Get the value of the first caveat with the given key as a decimal
+    /// integer.
+    ///
+    /// Returns `None` if the value is missing or is not a valid integer.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// almond.add_caveat(b"expires", Some(b"1500000000"));
+    ///
+    /// assert_eq!(almond.caveat_str(b"user"), Some("erikj"));
+    /// assert_eq!(almond.caveat_u64(b"expires"), Some(1500000000));
+    /// assert_eq!(almond.caveat_u64(b"user"), None);
+    /// ```
+    pub fn caveat_u64(&self, key: &[u8]) -> Option<u64> {
+        self.caveat_typed(key)
+    }
+
+    /// Get the *current* hash of the almond.
+    ///
+    /// The `HashTag` can only be compared in constant time.
+    pub fn hash(&self) -> HashTag {
+        HashTag::from(self.hash)
+    }
+
+    /// Get the format the almond was created or parsed with.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Set the format the almond will be serialized with.
+    ///
+    /// The hash does not depend on the format, so this does not change which
+    /// keys the almond validates against. If the almond can't be represented
+    /// in v1 then it is serialized as v2 regardless.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Format};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// almond.set_format(Format::V2);
+    ///
+    /// let serialized = almond.serialize_binary();
+    /// let parsed = Almond::parse_and_validate(b"secret", &serialized).unwrap();
+    /// assert_eq!(parsed.format(), Format::V2);
+    /// assert!(parsed.caveats().eq(almond.caveats()));
+    /// ```
+    pub fn set_format(&mut self, format: Format) -> &mut Self {
+        self.format = format;
+        self
+    }
+
+    /// The format that will actually be used to serialize the almond.
+    fn wire_format(&self) -> Format {
+        if self.key_id.is_some() {
+            return Format::V2;
+        }
+        self.format.for_contents(self.generation, &self.almond_type, self.caveats())
+    }
+
+    /// Serialize into a binary blob
+    pub fn serialize_binary(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.serialized_len());
+        self.serialize_to_buf(&mut result);
+        result
+    }
+
+    /// Append the binary serialization to the end of `buf`.
+    ///
+    /// This allows callers to reuse buffers when serializing many almonds.
+    pub fn serialize_to_buf(&self, buf: &mut Vec<u8>) {
+        buf.reserve(self.serialized_len());
+
+        // Writing to a `Vec` can't fail.
+        self.serialize_into(buf).expect("failed to write to Vec");
+    }
+
+    /// Write the binary serialization to `writer`.
+    pub fn serialize_into<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        format::write(
+            writer,
+            self.wire_format(),
+            &self.hash,
+            self.generation,
+            self.key_id(),
+            &self.almond_type,
+            self.caveats(),
+        )
+    }
+
+    /// The canonical binary serialization, which is the same for any two
+    /// serializations of the same almond.
+    ///
+    /// Since the hash doesn't depend on the format or key id, an almond can
+    /// be serialized in several ways that all validate. This uses the v1
+    /// format where possible and leaves out the key id, so it is suitable
+    /// for use as a cache key.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Format};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// let canonical = almond.canonical_bytes();
+    ///
+    /// almond.set_format(Format::V2);
+    /// assert!(almond.serialize_binary() != canonical);
+    /// assert_eq!(almond.canonical_bytes(), canonical);
+    /// ```
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let format = Format::V1.for_contents(self.generation, &self.almond_type, self.caveats());
+
+        let mut result = Vec::with_capacity(format::serialized_len(
+            format, self.generation, None, &self.almond_type, self.caveats()
+        ));
+        format::write(
+            &mut result, format, &self.hash, self.generation, None, &self.almond_type, self.caveats()
+        ).expect("failed to write to Vec");
+        result
+    }
+
+    /// Serialize into the v3 format, where the keys of caveats are replaced
+    /// by codes from `dictionary`. See the `dictionary` module.
+    ///
+    /// This ignores the format set with `set_format`.
+    pub fn serialize_with_dictionary(&self, dictionary: &KeyDictionary) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.serialized_len());
+        format::write_with_dictionary(
+            &mut result,
+            dictionary,
+            &self.hash,
+            self.generation,
+            self.key_id(),
+            &self.almond_type,
+            self.caveats(),
+        ).expect("failed to write to Vec");
+        result
+    }
+
+    /// Serialize into the v2 format with the type replaced by its code in
+    /// `types`. See the `type_codes` module.
+    ///
+    /// If the type isn't registered this is the same as `serialize_binary`.
+    pub fn serialize_with_type_code(&self, types: &TypeRegistry) -> Vec<u8> {
+        let code = match types.code(&self.almond_type) {
+            Some(code) => code,
+            None => return self.serialize_binary(),
+        };
+
+        let mut result = Vec::with_capacity(self.serialized_len());
+        format::write_with_type_code(
+            &mut result, &self.hash, self.generation, self.key_id(), code, self.caveats()
+        ).expect("failed to write to Vec");
+        result
+    }
+
+    /// The length in bytes of the binary serialization.
+    ///
+    /// This is computed without serializing the almond.
+    pub fn serialized_len(&self) -> usize {
+        format::serialized_len(
+            self.wire_format(), self.generation, self.key_id(), &self.almond_type, self.caveats()
+        )
+    }
+
+    /// Serialize into Base64.
+    ///
+    /// This is equivalent to Base64 encoding the binary serialization, with
+    /// the URL safe alphabet and no padding.
+    pub fn serialize_base64(&self) -> String {
+        self.serialize_base64_with(Base64Config::URL_SAFE_NO_PAD)
+    }
+
+    /// The length of `serialize_base64`, computed without serializing the
+    /// almond.
+    ///
+    /// This allows checking that a token will fit in, say, a cookie before
+    /// handing it out.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let mut almond = Almond::create(b"secret", 1, b"login".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    ///
+    /// assert!(almond.base64_len() <= 4096);
+    /// assert_eq!(almond.base64_len(), almond.serialize_base64().len());
+    /// ```
+    pub fn base64_len(&self) -> usize {
+        self.base64_len_with(Base64Config::URL_SAFE_NO_PAD)
+    }
+
+    /// The length of `serialize_base64_with` for the given config, computed
+    /// without serializing the almond.
+    pub fn base64_len_with(&self, config: Base64Config) -> usize {
+        let len = self.serialized_len();
+        if config.padded {
+            len.div_ceil(3) * 4
+        } else {
+            (len * 4).div_ceil(3)
+        }
+    }
+
+    /// Serialize into Base64 with the given alphabet and padding.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Base64Config};
+    /// let almond = Almond::create(b"secret", 1, b"login".to_vec());
+    ///
+    /// let encoded = almond.serialize_base64_with(Base64Config::STANDARD);
+    /// assert!(encoded.ends_with("="));
+    /// assert!(Almond::parse_base64_and_validate(b"secret", encoded.as_bytes()).is_ok());
+    /// ```
+    pub fn serialize_base64_with(&self, config: Base64Config) -> String {
+        let char_set = if config.url_safe {
+            base64::CharacterSet::UrlSafe
+        } else {
+            base64::CharacterSet::Standard
+        };
+
+        self.to_base64(base64::Config {
+            char_set: char_set,
+            newline: base64::Newline::LF,
+            pad: config.padded,
+            line_length: None,
+        })
+    }
+
+    /// Serialize into unpadded Base32 (RFC 4648).
+    ///
+    /// This is longer than Base64, but survives channels that don't preserve
+    /// case, such as DNS labels.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let almond = Almond::create(b"secret", 1, b"login".to_vec());
+    ///
+    /// let encoded = almond.serialize_base32().to_lowercase();
+    /// assert!(Almond::parse_base32_and_validate(b"secret", encoded.as_bytes()).is_ok());
+    /// ```
+    pub fn serialize_base32(&self) -> String {
+        base32::encode(&self.serialize_binary())
+    }
+
+    /// Serialize into Base45 (RFC 9285).
+    ///
+    /// Base45 only uses characters of the alphanumeric mode of QR codes,
+    /// which stores them more compactly than the byte mode needed for
+    /// Base64.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let almond = Almond::create(b"secret", 1, b"pairing".to_vec());
+    ///
+    /// let encoded = almond.serialize_base45();
+    /// assert!(Almond::parse_base45_and_validate(b"secret", encoded.as_bytes()).is_ok());
+    /// ```
+    pub fn serialize_base45(&self) -> String {
+        base45::encode(&self.serialize_binary())
+    }
+
+    /// Encrypt the binary serialization with a key derived from `secret`,
+    /// the key the almond was created with, and encode it as URL safe
+    /// Base64.
+    ///
+    /// The result is opaque to holders, who can't read the type or caveats,
+    /// e.g. when caveat keys would reveal details of the service. Holders
+    /// also can't attenuate it. It is decrypted with `open_and_validate`.
+    ///
+    /// This is unrelated to `seal`, which prevents further caveats.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"feature_flag", Some(b"new_billing"));
+    ///
+    /// let encrypted = almond.seal_base64(b"secret");
+    /// assert!(Almond::parse_base64_and_validate(b"secret", encrypted.as_bytes()).is_err());
+    ///
+    /// let opened = Almond::open_and_validate(b"secret", encrypted.as_bytes()).unwrap();
+    /// assert_eq!(opened, almond);
+    /// ```
+    pub fn seal_base64(&self, secret: &[u8]) -> String {
+        let key = crypt::token_key(secret);
+
+        let mut encrypted = vec![ENCRYPTED_VERSION];
+        encrypted.extend_from_slice(&crypt::encrypt(&key, &[ENCRYPTED_VERSION], &self.serialize_binary()));
+        encrypted.to_base64(base64::URL_SAFE)
+    }
+
+    /// Serialize into lowercase hex.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let almond = Almond::create(b"secret", 1, b"login".to_vec());
+    ///
+    /// let encoded = almond.serialize_hex();
+    /// assert!(Almond::parse_hex_and_validate(b"secret", encoded.as_bytes()).is_ok());
+    /// ```
+    pub fn serialize_hex(&self) -> String {
+        self.serialize_binary().to_hex()
+    }
+
+    /// Serialize into deterministic CBOR, as an array of the hash,
+    /// generation, type and caveats.
+    ///
+    /// The hash is the same as for the binary serialization, so the almond
+    /// can be converted between the two without the key.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// let mut almond = Almond::create(b"secret", 1, b"login".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    ///
+    /// let encoded = almond.serialize_cbor();
+    /// let parsed = Almond::parse_cbor_and_validate(b"secret", &encoded).unwrap();
+    /// assert_eq!(parsed.serialize_binary(), almond.serialize_binary());
+    /// ```
+    pub fn serialize_cbor(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        cbor::write(&mut buf, &self.hash, self.generation, &self.almond_type, self.caveats());
+        buf
+    }
+}
+
+/// The alphabet and padding used by `Almond::serialize_base64_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Base64Config {
+    /// Whether to use the URL safe alphabet (`-` and `_`) rather than the
+    /// standard one (`+` and `/`).
+    pub url_safe: bool,
+    /// Whether to pad the output with `=`.
+    pub padded: bool,
+}
+
+impl Base64Config {
+    /// The standard alphabet with padding.
+    pub const STANDARD : Base64Config = Base64Config { url_safe: false, padded: true };
+    /// The standard alphabet without padding.
+    pub const STANDARD_NO_PAD : Base64Config = Base64Config { url_safe: false, padded: false };
+    /// The URL safe alphabet with padding.
+    pub const URL_SAFE : Base64Config = Base64Config { url_safe: true, padded: true };
+    /// The URL safe alphabet without padding, as used by `serialize_base64`.
+    pub const URL_SAFE_NO_PAD : Base64Config = Base64Config { url_safe: true, padded: false };
+}
+
+impl Default for Base64Config {
+    fn default() -> Base64Config {
+        Base64Config::URL_SAFE_NO_PAD
+    }
+}
+
+impl PartialEq for Almond {
+    fn eq(&self, other: &Almond) -> bool {
+        ct_eq(&self.hash, &other.hash)
+            && self.generation == other.generation
+            && self.almond_type == other.almond_type
+            && ct_eq(&self.caveats, &other.caveats)
+    }
+}
+
+impl Eq for Almond {}
+
+/// Prints the generation, type and caveat keys, but not the hash or caveat
+/// values, so that almonds can be logged without leaking credentials.
+///
+/// ```
+/// # use almonds::Almond;
+/// let mut almond = Almond::create(b"secret", 1, b"login".to_vec());
+/// almond.add_caveat(b"user", Some(b"erikj"));
+/// almond.add_caveat(b"admin", None);
+///
+/// assert_eq!(
+///     format!("{:?}", almond),
+///     r#"Almond { generation: 1, almond_type: "login", caveats: [user <5 bytes>, admin], hash: <redacted> }"#
+/// );
+/// ```
+impl fmt::Debug for Almond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let caveats: Vec<_> = self.caveats().map(RedactedCaveat).collect();
+
+        let mut s = f.debug_struct("Almond");
+        s.field("generation", &self.generation);
+        s.field("almond_type", &String::from_utf8_lossy(&self.almond_type));
+        if let Some(ref key_id) = self.key_id {
+            s.field("key_id", &String::from_utf8_lossy(key_id));
+        }
+        s.field("caveats", &caveats);
+        s.field("hash", &Redacted);
+        s.finish()
+    }
+}
+
+/// Formats a caveat as its key and the length of its value.
+struct RedactedCaveat<'a>(&'a [u8]);
+
+impl <'a> fmt::Debug for RedactedCaveat<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (key, value) = split_caveat(self.0);
+        try!(f.write_str(&String::from_utf8_lossy(key)));
+        if let Some(value) = value {
+            try!(write!(f, " <{} bytes>", value.len()));
+        }
+        Ok(())
+    }
+}
+
+struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl base64::ToBase64 for Almond {
+    fn to_base64(&self, config: base64::Config) -> String {
+        let serialized = self.serialize_binary();
+        serialized.to_base64(config)
+    }
+}
+
+
+/// A view of a caveat, split into its key and optional value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Caveat<'a> {
+    raw: &'a [u8],
+    key: &'a [u8],
+    value: Option<&'a [u8]>,
+}
+
+impl <'a> Caveat<'a> {
+    /// Interpret a literal caveat as either `<key>` or `<key> <value>`.
+    pub fn parse(raw: &'a [u8]) -> Caveat<'a> {
+        let (key, value) = split_caveat(raw);
+        Caveat {
+            raw: raw,
+            key: key,
+            value: value,
+        }
+    }
+
+    /// Get the key of the caveat
+    pub fn key(&self) -> &'a [u8] {
+        self.key
+    }
+
+    /// Get the value of the caveat, if it has one
+    pub fn value(&self) -> Option<&'a [u8]> {
+        self.value
+    }
+
+    /// Get the key and value of the caveat as a tuple
+    pub fn pair(&self) -> (&'a [u8], Option<&'a [u8]>) {
+        (self.key, self.value)
+    }
+
+    /// Get the literal caveat
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.raw
+    }
+}
+
+
+/// An iterator over the caveats of an `Almond`, see `Almond::iter_caveats`.
+pub struct CaveatIter<'a> {
+    caveats: &'a [u8],
+    splits: slice::Iter<'a, CaveatSplit>,
+}
+
+impl <'a> Iterator for CaveatIter<'a> {
+    type Item = Caveat<'a>;
+
+    fn next(&mut self) -> Option<Caveat<'a>> {
+        let caveats = self.caveats;
+        self.splits.next().map(|split| split.get(caveats))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.splits.size_hint()
+    }
+}
+
+
+/// Where a caveat, and the space separating its key and value, are in
+/// `Almond::caveats`.
+#[derive(Clone, Copy)]
+struct CaveatSplit {
+    start: usize,
+    end: usize,
+    space: Option<usize>,
+}
+
+impl CaveatSplit {
+    fn new(start: usize, caveat: &[u8]) -> CaveatSplit {
+        CaveatSplit {
+            start: start,
+            end: start + caveat.len(),
+            space: caveat.iter().position(|c| *c == b' ').map(|idx| start + idx),
+        }
+    }
+
+    /// Get the caveat from the buffer it was split from.
+    fn get<'a>(&self, caveats: &'a [u8]) -> Caveat<'a> {
+        let raw = &caveats[self.start..self.end];
+        match self.space {
+            Some(space) => Caveat {
+                raw: raw,
+                key: &caveats[self.start..space],
+                value: Some(&caveats[space + 1..self.end]),
+            },
+            None => Caveat { raw: raw, key: raw, value: None },
+        }
+    }
+}
+
+/// Split every caveat in a buffer of length prefixed caveats.
+fn split_caveats(caveats: &[u8]) -> Vec<CaveatSplit> {
+    let mut splits = Vec::new();
+    let mut start = 0;
+    for caveat in RawCaveats::Prefixed(caveats).iter() {
+        start += varint::encoded_len(caveat.len() as u64);
+        splits.push(CaveatSplit::new(start, caveat));
+        start += caveat.len();
+    }
+    splits
+}
+
+
+/// Mints almonds that share a key, generation and type.
+///
+/// The hash of the key, generation and type is computed once up front, so
+/// each call to `mint` skips the first three HMAC rounds that
+/// `Almond::create` would otherwise perform.
+///
+/// ```
+/// # use almonds::{Almond, MintingKey};
+/// let minting_key = MintingKey::new(b"secret", 1, b"login".to_vec());
+///
+/// let mut almond = minting_key.mint();
+/// almond.add_caveat(b"user", Some(b"erikj"));
+///
+/// let mut expected = Almond::create(b"secret", 1, b"login".to_vec());
+/// expected.add_caveat(b"user", Some(b"erikj"));
+///
+/// assert_eq!(almond.serialize_base64(), expected.serialize_base64());
+/// ```
+pub struct MintingKey {
+    hash: [u8; 32],
+    generation: u32,
+    almond_type: Vec<u8>,
+}
+
+impl MintingKey {
+    /// Precompute the initial hash for the given key, generation and type.
+    pub fn new(key: &[u8], generation: u32, almond_type: Vec<u8>) -> MintingKey {
+        MintingKey {
+            hash: initial_hash(key, generation, &almond_type),
+            generation: generation,
+            almond_type: almond_type,
+        }
+    }
+
+    /// Create a new Almond with no caveats.
+    ///
+    /// This is equivalent to calling `Almond::create` with the same
+    /// arguments as were given to `MintingKey::new`.
+    pub fn mint(&self) -> Almond {
+        Almond {
+            hash: self.hash,
+            caveats: Vec::new(),
+            splits: OnceLock::new(),
+            generation: self.generation,
+            key_id: None,
+            almond_type: self.almond_type.clone(),
+            format: Format::V1.for_contents(self.generation, &self.almond_type, iter::empty()),
+        }
+    }
+
+    /// Get the type of the minted Almonds
+    pub fn almond_type(&self) -> &[u8] {
+        &self.almond_type
+    }
+
+    /// Get the generation of the minted Almonds
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+
+/// A validated Almond that borrows its type and caveats from the buffer it
+/// was parsed from, rather than copying them.
+///
+/// This is useful when verifying a large number of almonds, as parsing does
+/// not allocate. Use `to_almond` to convert it into an owned `Almond`.
+pub struct AlmondRef<'a> {
+    hash: [u8; 32],
+    generation: u32,
+    key_id: Option<&'a [u8]>,
+    almond_type: &'a [u8],
+    caveats: RawCaveats<'a>,
+    format: Format,
+}
+
+impl <'a> AlmondRef<'a> {
+    /// Parse a binary serialized Almond, and validate that the hashes match.
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_and_validate(key: &[u8], input: &'a [u8])
+        -> Result<AlmondRef<'a>, AlmondParseError>
+    {
+        AlmondRef::parse_with(
+            input,
+            |generation, almond_type| initial_hash(key, generation, almond_type),
+        )
+    }
+
+    /// Parse and validate, using `prefix_hash` to compute the hash of the
+    /// key, generation and type.
+    fn parse_with<F>(input: &'a [u8], prefix_hash: F)
+        -> Result<AlmondRef<'a>, AlmondParseError>
+        where F: FnOnce(u32, &'a [u8]) -> [u8; 32]
+    {
+        AlmondRef::parse_with_limits(input, &ParseLimits::default(), prefix_hash)
+    }
+
+    /// Like `parse_with`, but with the given limits.
+    fn parse_with_limits<F>(input: &'a [u8], limits: &ParseLimits, prefix_hash: F)
+        -> Result<AlmondRef<'a>, AlmondParseError>
+        where F: FnOnce(u32, &'a [u8]) -> [u8; 32]
+    {
+        let raw = try!(format::parse_with_limits(input, limits));
+        AlmondRef::validate_raw(raw, prefix_hash)
+    }
+
+    /// Validate the hash of a parsed almond, using `prefix_hash` to compute
+    /// the hash of the key, generation and type.
+    fn validate_raw<F>(raw: RawAlmond<'a>, prefix_hash: F)
+        -> Result<AlmondRef<'a>, AlmondParseError>
+        where F: FnOnce(u32, &'a [u8]) -> [u8; 32]
+    {
+        let mut almond = AlmondRef {
+            hash: prefix_hash(raw.generation, raw.almond_type),
+            generation: raw.generation,
+            key_id: raw.key_id,
+            almond_type: raw.almond_type,
+            caveats: raw.caveats,
+            format: raw.format,
+        };
+
+        almond.hash = hash_caveats(almond.hash, almond.caveats());
+
+        // Always compare hashes using equality operators that are
+        // resistent to timing attacks.
+        if ct_eq(raw.hash, &almond.hash) {
+            Ok(almond)
+        } else {
+            Err(hash_error(almond.caveats()))
+        }
+    }
+
+    /// Get the type of the Almond
+    pub fn almond_type(&self) -> &'a [u8] {
+        self.almond_type
+    }
+
+    /// Get the generation of the Almond
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Get the key id carried in the header of the Almond
+    pub fn key_id(&self) -> Option<&'a [u8]> {
+        self.key_id
+    }
+
+    /// Get the format the Almond was serialized in
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Get an iterator over the caveats of the Almond
+    pub fn caveats(&self) -> Caveats<'a> {
+        self.caveats.iter()
+    }
+
+    /// Get the hash of the almond.
+    ///
+    /// The `HashTag` can only be compared in constant time.
+    pub fn hash(&self) -> HashTag {
+        HashTag::from(self.hash)
+    }
+
+    /// Copy into an owned `Almond`.
+    pub fn to_almond(&self) -> Almond {
+        Almond {
+            hash: self.hash,
+            caveats: self.caveats.to_prefixed(),
+            splits: OnceLock::new(),
+            generation: self.generation,
+            key_id: self.key_id.map(|k| k.to_vec()),
+            almond_type: self.almond_type.to_vec(),
+            format: self.format,
+        }
+    }
+}
+
+
+/// An almond whose structure has been parsed, but whose hash has *not* been
+/// validated.
+///
+/// Returned by `Almond::parse_untrusted`.
+///
+/// Nothing about an unverified almond can be trusted. It is useful when
+/// something about the almond, e.g. its type, needs to be known before it
+/// can be validated, or for generic code that converts with `TryFrom`. Use
+/// `validate` to turn it into an `Almond`.
+///
+/// The caveats can't be read until then, so that they can't be acted on by
+/// mistake. Only the header, which is needed to pick a key, is exposed.
+///
+/// Converting from `&[u8]` expects a binary serialization, while converting
+/// from `&str` expects any of the Base64 variants.
+///
+/// ```
+/// # use almonds::UnverifiedAlmond;
+/// use std::convert::TryFrom;
+///
+/// let token = "yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag";
+///
+/// let unverified = UnverifiedAlmond::try_from(token).unwrap();
+/// assert_eq!(unverified.almond_type(), b"login");
+///
+/// let almond = unverified.validate(b"this_is_a_secret").unwrap();
+/// assert_eq!(almond.generation(), 1);
+/// ```
+pub struct UnverifiedAlmond {
+    hash: [u8; 32],
+    /// The caveats, each prefixed with its length as in the v2 format.
+    caveats: Vec<u8>,
+    generation: u32,
+    key_id: Option<Vec<u8>>,
+    almond_type: Vec<u8>,
+    format: Format,
+}
+
+impl UnverifiedAlmond {
+    /// Parse the structure of a binary serialized Almond, *without*
+    /// validating the hash.
+    pub fn parse(input: &[u8]) -> Result<UnverifiedAlmond, AlmondParseError> {
+        let raw = try!(format::parse(input));
+
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(raw.hash);
+
+        Ok(UnverifiedAlmond {
+            hash: hash,
+            caveats: raw.caveats.to_prefixed(),
+            generation: raw.generation,
+            key_id: raw.key_id.map(|k| k.to_vec()),
+            almond_type: raw.almond_type.to_vec(),
+            format: raw.format,
+        })
+    }
+
+    /// Validate that the hash matches, using `key`.
+    pub fn validate(self, key: &[u8]) -> Result<Almond, AlmondParseError> {
+        let hash = hash_caveats(
+            initial_hash(key, self.generation, &self.almond_type),
+            self.caveats(),
+        );
+
+        // Always compare hashes using equality operators that are
+        // resistent to timing attacks.
+        if !ct_eq(&self.hash, &hash) {
+            return Err(hash_error(self.caveats()));
+        }
+
+        Ok(Almond {
+            hash: hash,
+            caveats: self.caveats,
+            splits: OnceLock::new(),
+            generation: self.generation,
+            key_id: self.key_id,
+            almond_type: self.almond_type,
+            format: self.format,
+        })
+    }
+
+    /// Get the unverified type of the Almond
+    pub fn almond_type(&self) -> &[u8] {
+        &self.almond_type
+    }
+
+    /// Get the unverified generation of the Almond
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Get the key id carried in the header of the Almond, which can be
+    /// used to pick the key to validate with.
+    pub fn key_id(&self) -> Option<&[u8]> {
+        self.key_id.as_ref().map(|k| &k[..])
+    }
+
+    /// Get the format the Almond was serialized in
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Get the unverified hash of the Almond.
+    ///
+    /// The `HashTag` can only be compared in constant time.
+    pub fn hash(&self) -> HashTag {
+        HashTag::from(self.hash)
+    }
+
+    /// Get the number of caveats of the Almond.
+    ///
+    /// The caveats themselves are only available once it is validated.
+    pub fn caveat_count(&self) -> usize {
+        self.caveats().count()
+    }
+
+    fn caveats(&self) -> Caveats {
+        RawCaveats::Prefixed(&self.caveats).iter()
+    }
+}
+
+impl <'a> TryFrom<&'a [u8]> for UnverifiedAlmond {
+    type Error = AlmondParseError;
+
+    fn try_from(input: &'a [u8]) -> Result<UnverifiedAlmond, AlmondParseError> {
+        UnverifiedAlmond::parse(input)
+    }
+}
+
+impl <'a> TryFrom<&'a str> for UnverifiedAlmond {
+    type Error = AlmondParseError;
+
+    fn try_from(input: &'a str) -> Result<UnverifiedAlmond, AlmondParseError> {
+        let parsed = try!(decode_text(input.as_bytes(), |input| {
+            input.from_base64().map_err(AlmondParseError::Base64)
+        }));
+        UnverifiedAlmond::parse(&parsed)
+    }
+}
+
+
+/// Check that a caveat with the given key and value survives a round trip
+/// through the given serialization format.
+pub fn check_caveat(format: Format, key: &[u8], value: Option<&[u8]>)
+    -> Result<(), CaveatError>
+{
+    if key.is_empty() {
+        return Err(CaveatError::EmptyKey);
+    }
+
+    if key.contains(&b' ') || (format == Format::V1 && key.contains(&b'\n')) {
+        return Err(CaveatError::InvalidKey);
+    }
+
+    if format == Format::V1 && value.map_or(false, |value| value.contains(&b'\n')) {
+        return Err(CaveatError::InvalidValue);
+    }
+
+    Ok(())
+}
+
+/// Decode a text serialized almond with `decode`, and validate that the
+/// hashes match.
+fn parse_text_and_validate<F>(key: &[u8], input: &[u8], decode: F)
+    -> Result<Almond, AlmondParseError>
+    where F: FnOnce(&[u8]) -> Result<Vec<u8>, AlmondParseError>
+{
+    let parsed = try!(decode_text(input, decode));
+
+    Almond::parse_and_validate(key, &parsed)
+}
+
+/// Rebuild an almond from its decoded parts, and validate that the hashes
+/// match.
+pub fn validate_parts<C: AsRef<[u8]>>(
+    key: &[u8],
+    hash: &[u8],
+    generation: u32,
+    almond_type: &[u8],
+    caveats: &[C],
+) -> Result<Almond, AlmondParseError> {
+    let limits = ParseLimits::default();
+    for (index, caveat) in caveats.iter().enumerate() {
+        try!(format::check_caveat_limits(&limits, index, caveat.as_ref()));
+    }
+
+    let mut almond = Almond::create(key, generation, almond_type.to_vec());
+    almond.hash = hash_caveats(almond.hash, caveats.iter().map(|c| c.as_ref()));
+    for caveat in caveats {
+        format::push_prefixed(&mut almond.caveats, caveat.as_ref());
+    }
+
+    // Always compare hashes using equality operators that are
+    // resistent to timing attacks.
+    if ct_eq(hash, &almond.hash) {
+        Ok(almond)
+    } else {
+        Err(hash_error(caveats.iter().map(|c| c.as_ref())))
+    }
+}
+
+/// Decode a text serialized almond with `decode`, enforcing the length
+/// limits.
+pub fn decode_text<F>(input: &[u8], decode: F) -> Result<Vec<u8>, AlmondParseError>
+    where F: FnOnce(&[u8]) -> Result<Vec<u8>, AlmondParseError>
+{
+    // None of the text encodings are more than twice as long as the binary,
+    // so this rejects long inputs before decoding them.
+    if input.len() > 2 * MAX_READER_LEN {
+        return Err(AlmondParseError::TooLong { max: 2 * MAX_READER_LEN });
+    }
+
+    let parsed = try!(decode(input));
+    if parsed.len() > MAX_READER_LEN {
+        return Err(AlmondParseError::TooLong { max: MAX_READER_LEN });
+    }
+
+    Ok(parsed)
+}
+
+fn lossy_json(bytes: &[u8]) -> Json {
+    Json::String(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Generate a random hex encoded id.
+fn random_id() -> Vec<u8> {
+    let mut rng = OsRng::new().expect("failed to access the OS random number generator");
+
+    let mut id = [0u8; 16];
+    rng.fill_bytes(&mut id);
+    id.to_hex().into_bytes()
+}
+
+/// Split a caveat into its key and optional value.
+pub fn split_caveat(caveat: &[u8]) -> (&[u8], Option<&[u8]>) {
+    match caveat.iter().position(|c| *c == b' ') {
+        Some(idx) => (&caveat[..idx], Some(&caveat[idx + 1..])),
+        None => (caveat, None),
+    }
+}
+
+fn initial_hash(key: &[u8], generation: u32, almond_type: &[u8]) -> [u8; 32] {
+    initial_hash_with_seed(ALMOND_HASH_SEED, key, generation, almond_type)
+}
+
+/// Derive the seed used for almonds created in the given context.
+fn context_seed(context: &[u8]) -> [u8; 32] {
+    let mut seed = *ALMOND_HASH_SEED;
+    add_to_hash(&mut seed, context);
+    seed
+}
+
+fn initial_hash_with_seed(seed: &[u8], key: &[u8], generation: u32, almond_type: &[u8])
+    -> [u8; 32]
+{
+    // The seed may be any length, so the first round can't reuse
+    // `add_to_hash`.
+    let mut hash = sha::hmac_sha256(seed, key);
+
+    let mut encoded = Vec::with_capacity(varint::MAX_LEN);
+    format::encode_generation(generation, &mut encoded);
+    add_to_hash(&mut hash, &encoded);
+    add_to_hash(&mut hash, almond_type);
+    hash
+}
+
+fn add_to_hash(hash: &mut [u8; 32], data: &[u8]) {
+    *hash = sha::hmac_sha256(hash, data);
+}
+
+/// Computes the hash of a list of caveats, as a validator does.
+///
+/// If the last caveat is a seal, the result is hashed once more with
+/// `SEAL_SENTINEL`. A seal anywhere else is hashed like any other caveat, so
+/// continuing the chain from a sealed hash doesn't give a valid almond.
+struct CaveatHasher {
+    hash: [u8; 32],
+    sealed: bool,
+}
+
+impl CaveatHasher {
+    fn new(hash: [u8; 32]) -> CaveatHasher {
+        CaveatHasher { hash: hash, sealed: false }
+    }
+
+    fn add(&mut self, caveat: &[u8]) {
+        add_to_hash(&mut self.hash, caveat);
+        self.sealed = caveat == SEALED_KEY;
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        if self.sealed {
+            add_to_hash(&mut self.hash, SEAL_SENTINEL);
+        }
+        self.hash
+    }
+}
+
+fn hash_caveats<'c, I>(hash: [u8; 32], caveats: I) -> [u8; 32]
+    where I: IntoIterator<Item = &'c [u8]>
+{
+    let mut hasher = CaveatHasher::new(hash);
+    for caveat in caveats {
+        hasher.add(caveat);
+    }
+    hasher.finish()
+}
+
+/// The error for an almond whose hash didn't match, distinguishing almonds
+/// with caveats added after a seal.
+fn hash_error<'c, I>(caveats: I) -> AlmondParseError
+    where I: IntoIterator<Item = &'c [u8]>
+{
+    let mut caveats = caveats.into_iter().peekable();
+    while let Some(caveat) = caveats.next() {
+        if caveat == SEALED_KEY && caveats.peek().is_some() {
+            return AlmondParseError::ExtendedAfterSeal;
+        }
+    }
+    AlmondParseError::IncorrectHash
+}
+
+
+quick_error! {
+    /// An error returned when we failed to parse a buffer as an almond.
+    #[derive(Debug)]
+    pub enum AlmondParseError {
+        /// The buffer did not contain a valid almond, for a reason not
+        /// covered by the other variants.
+        InvalidAlmond {
+            display("input is not a valid almond")
+        }
+
+        /// The input was not valid Base64.
+        Base64(err: FromBase64Error) {
+            from()
+            cause(err)
+            display("input is not valid Base64: {}", err)
+        }
+
+        /// The input was not valid Base32.
+        Base32 {
+            display("input is not valid unpadded Base32")
+        }
+
+        /// The input was not valid Base45.
+        Base45 {
+            display("input is not valid Base45")
+        }
+
+        /// The input was not valid hex.
+        Hex(err: FromHexError) {
+            from()
+            cause(err)
+            display("input is not valid hex: {}", err)
+        }
+
+        /// The input was shorter than the smallest possible almond.
+        TooShort { len: usize } {
+            display("input is {} bytes, which is too short to be an almond", len)
+        }
+
+        /// The input ended part way through the almond.
+        Truncated {
+            display("input ended part way through the almond")
+        }
+
+        /// The input was longer than the limit.
+        TooLong { max: usize } {
+            display("input is longer than the limit of {} bytes", max)
+        }
+
+        /// The almond uses a version of the v2 format that isn't supported.
+        UnsupportedVersion { version: u8 } {
+            display("almond format version {} is not supported", version)
+        }
+
+        /// The almond has flags set that aren't supported.
+        UnknownFlags { flags: u8 } {
+            display("almond has unsupported flags {:#04x}", flags)
+        }
+
+        /// The extended generation was malformed or out of range.
+        InvalidGeneration {
+            display("almond generation is malformed or out of range")
+        }
+
+        /// The length prefixed key id was truncated.
+        InvalidKeyId {
+            display("almond key id is truncated")
+        }
+
+        /// The length prefixed type was missing or truncated.
+        MissingType {
+            display("almond type is missing or truncated")
+        }
+
+        /// The caveat with the given index was malformed or truncated.
+        InvalidCaveat { index: usize } {
+            display("caveat {} of the almond is malformed or truncated", index)
+        }
+
+        /// The v1 caveat with the given index was empty, which isn't a
+        /// canonical serialization.
+        EmptyCaveat { index: usize } {
+            display("caveat {} of the almond is empty", index)
+        }
+
+        /// The almond claimed more caveats than it could contain.
+        TooManyCaveats { count: u64 } {
+            display("almond claims {} caveats, more than the input could contain", count)
+        }
+
+        /// The almond has more caveats than the limit, see `ParseLimits`.
+        CaveatLimit { max: usize } {
+            display("almond has more than the limit of {} caveats", max)
+        }
+
+        /// The caveat with the given index is longer than the limit, see
+        /// `ParseLimits`.
+        CaveatTooLong { index: usize, max: usize } {
+            display("caveat {} of the almond is longer than the limit of {} bytes", index, max)
+        }
+
+        /// The type code of the almond isn't in the `TypeRegistry`.
+        UnknownTypeCode { code: u64 } {
+            display("almond type code {} is not registered", code)
+        }
+
+        /// The compressed body of the almond couldn't be decompressed.
+        InvalidCompression {
+            display("almond body is not valid DEFLATE data")
+        }
+
+        /// The hash did not match, because caveats were added after the
+        /// almond was sealed, see `Almond::seal`.
+        ExtendedAfterSeal {
+            display("almond was extended after being sealed")
+        }
+
+        /// The hash did not match the deserialized Almond.
+        IncorrectHash {
+            display("almond hash did not match, it was minted with a different key or modified")
+        }
+
+        /// An almond encrypted with `Almond::seal_base64` could not be
+        /// decrypted.
+        Decryption {
+            display("almond could not be decrypted")
+        }
+
+        /// No key was found for the generation and type of the almond.
+        UnknownKey {
+            display("no key is known for the generation and type of the almond")
+        }
+
+        /// An IO error occurred while reading the almond.
+        Io(err: io::Error) {
+            from()
+            cause(err)
+            display("I/O error while reading the almond: {}", err)
+        }
+    }
+}
+
+impl AlmondParseError {
+    /// A short name for the kind of error, suitable for use as a metrics
+    /// label.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            AlmondParseError::InvalidAlmond => "invalid_almond",
+            AlmondParseError::Base64(_) => "base64",
+            AlmondParseError::Base32 => "base32",
+            AlmondParseError::Base45 => "base45",
+            AlmondParseError::Hex(_) => "hex",
+            AlmondParseError::TooShort { .. } => "too_short",
+            AlmondParseError::Truncated => "truncated",
+            AlmondParseError::TooLong { .. } => "too_long",
+            AlmondParseError::UnsupportedVersion { .. } => "unsupported_version",
+            AlmondParseError::UnknownFlags { .. } => "unknown_flags",
+            AlmondParseError::InvalidGeneration => "invalid_generation",
+            AlmondParseError::InvalidKeyId => "invalid_key_id",
+            AlmondParseError::MissingType => "missing_type",
+            AlmondParseError::InvalidCaveat { .. } => "invalid_caveat",
+            AlmondParseError::EmptyCaveat { .. } => "empty_caveat",
+            AlmondParseError::TooManyCaveats { .. } => "too_many_caveats",
+            AlmondParseError::CaveatLimit { .. } => "caveat_limit",
+            AlmondParseError::CaveatTooLong { .. } => "caveat_too_long",
+            AlmondParseError::UnknownTypeCode { .. } => "unknown_type_code",
+            AlmondParseError::InvalidCompression => "invalid_compression",
+            AlmondParseError::ExtendedAfterSeal => "extended_after_seal",
+            AlmondParseError::IncorrectHash => "incorrect_hash",
+            AlmondParseError::Decryption => "decryption",
+            AlmondParseError::UnknownKey => "unknown_key",
+            AlmondParseError::Io(_) => "io",
+        }
+    }
+}
+
+
+quick_error! {
+    /// An error returned when a caveat can't be safely added to an almond.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum CaveatError {
+        /// The key was empty, which would be parsed as a leading space.
+        EmptyKey {
+            display("caveat key is empty")
+        }
+
+        /// The key contained a space or newline.
+        InvalidKey {
+            display("caveat key contains a space or newline")
+        }
+
+        /// The value contained a newline.
+        InvalidValue {
+            display("caveat value contains a newline")
+        }
+
+        /// The value of a subject caveat was empty, see
+        /// `ScopedBuilder::subject`.
+        EmptyValue {
+            display("caveat value is empty")
+        }
+
+        /// The value was longer than allowed, see
+        /// `AlmondBuilder::max_value_len`.
+        ValueTooLong { len: usize, max: usize } {
+            display("caveat value is {} bytes, longer than the maximum of {}", len, max)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test::Bencher;
+    use rustc_serialize::base64::{FromBase64, ToBase64, URL_SAFE};
+
+    use Verifier;
+
+    #[test]
+    fn basic_test() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let r = almond.to_base64(URL_SAFE);
+        assert_eq!(
+            r,
+            "yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag"
+        );
+
+        Almond::parse_and_validate(key, &almond.serialize_binary()).unwrap();
+    }
+
+    #[test]
+    fn parse_and_serialize() {
+        let key = b"this_is_a_secret";
+
+        let input = "yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag";
+        let a = Almond::parse_and_validate(key, &input.from_base64().unwrap()).unwrap();
+
+        assert_eq!(a.to_base64(URL_SAFE), input);
+    }
+
+    #[test]
+    fn serialize_to_buf_and_writer() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"guest", None);
+
+        let expected = almond.serialize_binary();
+        assert_eq!(almond.serialized_len(), expected.len());
+
+        let mut buf = b"prefix".to_vec();
+        almond.serialize_to_buf(&mut buf);
+        assert_eq!(&buf[..6], b"prefix");
+        assert_eq!(&buf[6..], &expected[..]);
+
+        let mut written = Vec::new();
+        almond.serialize_into(&mut written).unwrap();
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn parse_from_reader() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"guest", None);
+        let serialized = almond.serialize_binary();
+
+        let a = Almond::parse_from_reader(key, &serialized[..]).unwrap();
+        assert!(a.caveats().eq(almond.caveats()));
+        assert_eq!(a.serialize_binary(), serialized);
+
+        assert!(Almond::parse_from_reader(b"wrong", &serialized[..]).is_err());
+        assert!(Almond::parse_from_reader(key, &serialized[..20]).is_err());
+    }
+
+    #[test]
+    fn parse_from_reader_too_long() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"data", Some(&[b'a'; MAX_READER_LEN]));
+        let serialized = almond.serialize_binary();
+
+        assert!(Almond::parse_and_validate(key, &serialized).is_err());
+        assert!(Almond::parse_from_reader(key, &serialized[..]).is_err());
+
+        let limits = ParseLimits {
+            max_len: 2 * MAX_READER_LEN,
+            max_caveats: 1,
+            max_caveat_len: 2 * MAX_READER_LEN,
+        };
+        assert!(Almond::parse_and_validate_with_limits(key, &serialized, &limits).is_ok());
+    }
+
+    #[test]
+    fn inspection_json() {
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        almond.add_caveat(b"admin", None);
+        almond.add_caveat(b"bytes", Some(b"\xff"));
+
+        let json = almond.to_inspection_json();
+        let caveats = json["caveats"].as_array().unwrap();
+        assert!(caveats[0]["value"].is_null());
+        assert_eq!(caveats[1]["value"].as_string(), Some("\u{fffd}"));
+        assert_eq!(json["hash_prefix"].as_string().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn base32() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let encoded = almond.serialize_base32();
+        assert_eq!(
+            encoded,
+            "ZMSM2YOPQIAXJVMSAXSDM6L4YHJTGBJTEAPSFUKKKXVY3V7ZKMGQC3DPM5UW4CTVONSXEIDFOJUWW2Q"
+        );
+
+        let parsed = Almond::parse_base32_and_validate(key, encoded.as_bytes()).unwrap();
+        assert_eq!(parsed.serialize_binary(), almond.serialize_binary());
+
+        let lower = encoded.to_lowercase();
+        assert!(Almond::parse_base32_and_validate(key, lower.as_bytes()).is_ok());
+
+        match Almond::parse_base32_and_validate(key, b"not base32!") {
+            Err(AlmondParseError::Base32) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn base45() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"pairing".to_vec());
+        almond.add_caveat(b"device", Some(b"phone"));
+
+        let encoded = almond.serialize_base45();
+        assert!(encoded.len() < almond.serialize_base32().len());
+
+        let parsed = Almond::parse_base45_and_validate(key, encoded.as_bytes()).unwrap();
+        assert_eq!(parsed.serialize_binary(), almond.serialize_binary());
+
+        match Almond::parse_base45_and_validate(key, encoded.to_lowercase().as_bytes()) {
+            Err(AlmondParseError::Base45) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn base64_configs() {
+        let key = b"this_is_a_secret";
+
+        // The encodings differ in both alphabet and padding.
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let standard = "yyTNYc+CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag";
+        let url_safe = "yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag";
+
+        let expected = [
+            (Base64Config::STANDARD, format!("{}==", standard)),
+            (Base64Config::STANDARD_NO_PAD, standard.to_owned()),
+            (Base64Config::URL_SAFE, format!("{}==", url_safe)),
+            (Base64Config::URL_SAFE_NO_PAD, url_safe.to_owned()),
+        ];
+
+        for &(config, ref encoded) in &expected {
+            assert_eq!(&almond.serialize_base64_with(config), encoded);
+            assert_eq!(almond.base64_len_with(config), encoded.len());
+            assert!(Almond::parse_base64_and_validate(key, encoded.as_bytes()).is_ok());
+        }
+
+        assert_eq!(almond.serialize_base64(), url_safe);
+    }
+
+    #[test]
+    fn hex() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let encoded = almond.serialize_hex();
+        assert_eq!(
+            encoded,
+            "cb24cd61cf820174d59205e436797cc1d3330533201f22d14a55eb8dd7f9530d\
+             016c6f67696e0a75736572206572696b6a"
+        );
+
+        let parsed = Almond::parse_hex_and_validate(key, encoded.as_bytes()).unwrap();
+        assert_eq!(parsed.serialize_binary(), almond.serialize_binary());
+
+        let upper = encoded.to_uppercase();
+        assert!(Almond::parse_hex_and_validate(key, upper.as_bytes()).is_ok());
+
+        let wrapped = format!("{}\n{}", &encoded[..10], &encoded[10..]);
+        for input in &[&wrapped[..], &encoded[1..], "not hex"] {
+            match Almond::parse_hex_and_validate(key, input.as_bytes()) {
+                Err(AlmondParseError::Hex(_)) => {}
+                res => panic!("unexpected result: {:?}", res.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn text_too_long() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"data", Some(&[b'a'; MAX_READER_LEN]));
+
+        match Almond::parse_base64_and_validate(key, almond.serialize_base64().as_bytes()) {
+            Err(AlmondParseError::TooLong { max: MAX_READER_LEN }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        match Almond::parse_hex_and_validate(key, almond.serialize_hex().as_bytes()) {
+            Err(AlmondParseError::TooLong { max }) if max == 2 * MAX_READER_LEN => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn cbor() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"note", Some(b"multi\nline"));
+
+        let encoded = almond.serialize_cbor();
+        let parsed = Almond::parse_cbor_and_validate(key, &encoded).unwrap();
+        assert_eq!(parsed.serialize_binary(), almond.serialize_binary());
+        assert_eq!(parsed.serialize_cbor(), encoded);
+
+        match Almond::parse_cbor_and_validate(b"wrong_key", &encoded) {
+            Err(AlmondParseError::IncorrectHash) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        match Almond::parse_cbor_and_validate(key, &almond.serialize_binary()) {
+            Err(AlmondParseError::InvalidAlmond) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn parse_and_validate_batch() {
+        let key = b"this_is_a_secret";
+
+        let mut login = Almond::create(key, 1, b"login".to_vec());
+        login.add_caveat(b"user", Some(b"erikj"));
+        let login = login.serialize_binary();
+
+        let mut access = Almond::create(key, 2, b"access".to_vec());
+        access.add_caveat(b"user", Some(b"erikj"));
+        let access = access.serialize_binary();
+
+        let wrong_key = Almond::create(b"wrong", 1, b"login".to_vec())
+            .serialize_binary();
+
+        let results = Almond::parse_and_validate_batch(
+            key, &[&login, &access, &wrong_key, b"short", &login],
+        );
+
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0].as_ref().unwrap().serialize_binary(), login);
+        assert_eq!(results[1].as_ref().unwrap().serialize_binary(), access);
+        assert!(results[2].is_err());
+        assert!(results[3].is_err());
+        assert_eq!(results[4].as_ref().unwrap().serialize_binary(), login);
+    }
+
+    #[test]
+    fn caveat_values() {
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"guest", None);
+        almond.add_caveat(b"expires", Some(b"1500000000"));
+        almond.add_caveat(b"expires", Some(b"1600000000"));
+        almond.add_caveat(b"bad", Some(b"\xff"));
+
+        assert_eq!(almond.caveat_value(b"user"), Some(&b"erikj"[..]));
+        assert_eq!(almond.caveat_value(b"guest"), None);
+        assert_eq!(almond.caveat_value(b"missing"), None);
+        assert_eq!(almond.caveat_u64(b"expires"), Some(1500000000));
+        assert_eq!(almond.caveat_str(b"bad"), None);
+    }
+
+    #[test]
+    fn try_add_caveat() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.try_add_caveat(b"user", Some(b"erikj")).unwrap();
+        almond.try_add_caveat(b"guest", None).unwrap();
+        almond.try_add_caveat(b"name", Some(b"Erik Johnston")).unwrap();
+
+        assert_eq!(almond.try_add_caveat(b"", Some(b"x")).err(), Some(CaveatError::EmptyKey));
+        assert_eq!(almond.try_add_caveat(b"a b", None).err(), Some(CaveatError::InvalidKey));
+        assert_eq!(almond.try_add_caveat(b"a\nb", None).err(), Some(CaveatError::InvalidKey));
+        assert_eq!(
+            almond.try_add_caveat(b"user", Some(b"x\nadmin")).err(),
+            Some(CaveatError::InvalidValue)
+        );
+
+        let parsed = Almond::parse_and_validate(key, &almond.serialize_binary()).unwrap();
+        assert!(parsed.caveats().eq(almond.caveats()));
+    }
+
+    #[test]
+    fn parse_and_validate_ref() {
+        let key = b"this_is_a_secret";
+
+        let input = "yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag"
+            .from_base64().unwrap();
+        let a = AlmondRef::parse_and_validate(key, &input).unwrap();
+
+        assert_eq!(a.generation(), 1);
+        assert_eq!(a.almond_type(), b"login");
+        assert_eq!(a.caveats().collect::<Vec<_>>(), vec![&b"user erikj"[..]]);
+        assert_eq!(a.to_almond().to_base64(URL_SAFE), input.to_base64(URL_SAFE));
+    }
+
+    #[test]
+    fn parse_ref_no_caveats() {
+        let key = b"this_is_a_secret";
+
+        let almond = Almond::create(key, 1, b"login".to_vec());
+        let serialized = almond.serialize_binary();
+        let a = AlmondRef::parse_and_validate(key, &serialized).unwrap();
+
+        assert_eq!(a.almond_type(), b"login");
+        assert_eq!(a.caveats().count(), 0);
+
+        assert!(AlmondRef::parse_and_validate(b"wrong", &serialized).is_err());
+    }
+
+    #[test]
+    fn v2_format() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let v1 = almond.serialize_binary();
+        assert_eq!(almond.format(), Format::V1);
+
+        almond.set_format(Format::V2);
+        almond.try_add_caveat(b"data", Some(b"multi\nline")).unwrap();
+        almond.try_add_caveat(b"multi\nline", None).unwrap();
+        assert_eq!(almond.try_add_caveat(b"a b", None).err(), Some(CaveatError::InvalidKey));
+
+        let v2 = almond.serialize_binary();
+        assert_eq!(v2.len(), almond.serialized_len());
+        assert_eq!(v2[32], 0xFF);
+
+        let parsed = Almond::parse_and_validate(key, &v2).unwrap();
+        assert_eq!(parsed.format(), Format::V2);
+        assert!(parsed.caveats().eq(almond.caveats()));
+        assert_eq!(parsed.caveat_value(b"data"), Some(&b"multi\nline"[..]));
+
+        let parsed = AlmondRef::parse_and_validate(key, &v2).unwrap();
+        assert_eq!(parsed.format(), Format::V2);
+        assert_eq!(parsed.caveats().count(), 3);
+
+        let parsed = Almond::parse_from_reader(key, &v2[..]).unwrap();
+        assert_eq!(parsed.format(), Format::V2);
+        assert!(parsed.caveats().eq(almond.caveats()));
+
+        // The hash doesn't depend on the format.
+        let mut parsed = Almond::parse_and_validate(key, &v1).unwrap();
+        parsed.set_format(Format::V2);
+        let reserialized = parsed.serialize_binary();
+        let mut parsed = Almond::parse_and_validate(key, &reserialized).unwrap();
+        parsed.set_format(Format::V1);
+        assert_eq!(parsed.serialize_binary(), v1);
+
+        assert!(Almond::parse_and_validate(b"wrong", &v2).is_err());
+        assert!(Almond::parse_and_validate(key, &v2[..v2.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn v1_falls_back_to_v2() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"data", Some(b"multi\nline"));
+        assert_eq!(almond.format(), Format::V1);
+
+        let serialized = almond.serialize_binary();
+        let parsed = Almond::parse_and_validate(key, &serialized).unwrap();
+        assert_eq!(parsed.format(), Format::V2);
+        assert!(parsed.caveats().eq(almond.caveats()));
+
+        // With generation 255 the type can't start with a version byte.
+        let almond = Almond::create(key, 255, b"\x02login".to_vec());
+        assert_eq!(almond.format(), Format::V2);
+        let parsed = Almond::parse_and_validate(key, &almond.serialize_binary()).unwrap();
+        assert_eq!(parsed.generation(), 255);
+        assert_eq!(parsed.almond_type(), b"\x02login");
+    }
+
+    #[test]
+    fn generation_boundary() {
+        let key = b"this_is_a_secret";
+
+        // Generations up to 255 are a single byte, and fit in v1.
+        for &(generation, format) in &[(255, Format::V1), (256, Format::V2)] {
+            let mut almond = Almond::create(key, generation, b"login".to_vec());
+            almond.add_caveat(b"user", Some(b"erikj"));
+            assert_eq!(almond.format(), format);
+
+            let serialized = almond.serialize_binary();
+            let parsed = Almond::parse_and_validate(key, &serialized).unwrap();
+            assert_eq!(parsed.generation(), generation);
+            assert_eq!(parsed.format(), format);
+            assert_eq!(parsed.serialize_binary(), serialized);
+
+            let read = Almond::parse_from_reader(key, &serialized[..]).unwrap();
+            assert_eq!(read.generation(), generation);
+            assert_eq!(read.format(), format);
+        }
+    }
+
+    #[test]
+    fn legacy_v1_generation_255() {
+        let key = b"this_is_a_secret";
+
+        // Serialized in v1 with generation 255, before v2 existed.
+        let serialized = "dt5hqDYhv2HIcKynoaHejVgm1JWR5LNKXDUZFwFZIlL_bG9naW4KdXNlciBlcmlrag"
+            .from_base64()
+            .unwrap();
+        let parsed = Almond::parse_and_validate(key, &serialized).unwrap();
+        assert_eq!(parsed.generation(), 255);
+        assert_eq!(parsed.format(), Format::V1);
+        assert_eq!(parsed.serialize_binary(), serialized);
+
+        // Both are read by the legacy path. The second has a type starting
+        // with a version byte, so is also well formed as v2, with generation
+        // 5 and type `a`, and can only be read by the legacy path.
+        for b64 in &[
+            "dt5hqDYhv2HIcKynoaHejVgm1JWR5LNKXDUZFwFZIlL_bG9naW4KdXNlciBlcmlrag",
+            "x78-gyo_bf089dPyQUyh9e_YcJpt0HUAbjea_QS2sgv_AgAFAWEKdXNlciBlcmlrag",
+        ] {
+            let serialized = b64.from_base64().unwrap();
+
+            let parsed = Almond::parse_legacy_v1_and_validate(key, &serialized).unwrap();
+            assert_eq!(parsed.generation(), 255);
+            assert_eq!(parsed.caveat_str(b"user"), Some("erikj"));
+
+            // It is reserialized unambiguously.
+            let reserialized = Almond::parse_and_validate(key, &parsed.serialize_binary()).unwrap();
+            assert_eq!(reserialized, parsed);
+
+            match Almond::parse_legacy_v1_and_validate(b"other", &serialized) {
+                Err(AlmondParseError::IncorrectHash) => {}
+                res => panic!("unexpected result: {:?}", res.map(|_| ())),
+            }
+        }
+
+        let serialized = "x78-gyo_bf089dPyQUyh9e_YcJpt0HUAbjea_QS2sgv_AgAFAWEKdXNlciBlcmlrag"
+            .from_base64()
+            .unwrap();
+        assert!(Almond::parse_and_validate(key, &serialized).is_err());
+        let parsed = Almond::parse_legacy_v1_and_validate(key, &serialized).unwrap();
+        assert_eq!(Almond::parse_untrusted(&parsed.serialize_binary()).unwrap().format(), Format::V2);
+
+        // v2 almonds can't be read this way, but other v1 almonds can.
+        let mut almond = Almond::create(key, 255, b"login".to_vec());
+        almond.set_format(Format::V2);
+        assert!(Almond::parse_legacy_v1_and_validate(key, &almond.serialize_binary()).is_err());
+        let almond = Almond::create(key, 1, b"login".to_vec());
+        assert!(Almond::parse_legacy_v1_and_validate(key, &almond.serialize_binary()).is_ok());
+    }
+
+    #[test]
+    fn extended_generation() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 70000, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        assert_eq!(almond.format(), Format::V2);
+
+        let serialized = almond.serialize_binary();
+        assert_eq!(serialized.len(), almond.serialized_len());
+
+        let parsed = Almond::parse_and_validate(key, &serialized).unwrap();
+        assert_eq!(parsed.generation(), 70000);
+        assert!(parsed.caveats().eq(almond.caveats()));
+
+        let parsed = Almond::parse_from_reader(key, &serialized[..]).unwrap();
+        assert_eq!(parsed.generation(), 70000);
+
+        let batch = Almond::parse_and_validate_batch(key, &[&serialized]);
+        assert_eq!(batch[0].as_ref().unwrap().generation(), 70000);
+
+        // Small generations hash the same as they always have.
+        let mut v1 = Almond::create(key, 1, b"login".to_vec());
+        v1.add_caveat(b"user", Some(b"erikj"));
+        assert_eq!(
+            v1.serialize_base64(),
+            "yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag"
+        );
+        assert!(v1.hash() != almond.hash());
+    }
+
+    #[test]
+    fn parse_and_validate_any() {
+        let mut almond = Almond::create(b"old_secret", 300, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let serialized = almond.serialize_binary();
+
+        let keys: &[&[u8]] = &[b"new_secret", b"old_secret", b"old_secret"];
+        let (parsed, idx) = Almond::parse_and_validate_any(keys, &serialized).unwrap();
+        assert_eq!(idx, 1);
+        assert_eq!(parsed.serialize_binary(), serialized);
+
+        match Almond::parse_and_validate_any(&keys[..1], &serialized) {
+            Err(AlmondParseError::IncorrectHash) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        match Almond::parse_and_validate_any(&[], &serialized) {
+            Err(AlmondParseError::IncorrectHash) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn key_id() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create_with_key_id(key, b"k1".to_vec(), 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        assert_eq!(almond.format(), Format::V2);
+        let serialized = almond.serialize_binary();
+        assert_eq!(serialized.len(), almond.serialized_len());
+
+        let parsed = AlmondRef::parse_and_validate(key, &serialized).unwrap();
+        assert_eq!(parsed.key_id(), Some(&b"k1"[..]));
+        assert_eq!(parsed.to_almond().serialize_binary(), serialized);
+
+        let parsed = Almond::parse_from_reader(key, &serialized[..]).unwrap();
+        assert_eq!(parsed.key_id(), Some(&b"k1"[..]));
+
+        // The key id isn't covered by the hash.
+        let mut plain = Almond::create(key, 1, b"login".to_vec());
+        plain.add_caveat(b"user", Some(b"erikj"));
+        assert_eq!(plain.hash(), almond.hash());
+        assert_eq!(plain.key_id(), None);
+    }
+
+    #[test]
+    fn seal() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        assert!(!almond.is_sealed());
+        almond.seal();
+        assert!(almond.is_sealed());
+
+        // Sealing twice is a no-op.
+        let hash = almond.hash();
+        almond.seal();
+        assert_eq!(almond.hash(), hash);
+        assert_eq!(almond.caveats().count(), 2);
+
+        // All of the ways of validating accept the sealed almond.
+        let serialized = almond.serialize_binary();
+        assert!(Almond::parse_and_validate(key, &serialized).unwrap().is_sealed());
+        assert!(Almond::parse_from_reader(key, &serialized[..]).is_ok());
+        assert!(Almond::parse_and_validate_any(&[key], &serialized).is_ok());
+        assert!(AlmondRef::parse_and_validate(key, &serialized).is_ok());
+        assert!(Almond::parse_untrusted(&serialized).unwrap().validate(key).is_ok());
+        assert!(Almond::parse_cbor_and_validate(key, &almond.serialize_cbor()).is_ok());
+
+        // A holder extending the serialized almond continues the hash from
+        // the sealed hash, which a validator doesn't.
+        let mut extended = Almond::parse_and_validate(key, &serialized).unwrap();
+        extended.add_caveat(b"device", Some(b"phone"));
+        let serialized = extended.serialize_binary();
+
+        match Almond::parse_and_validate(key, &serialized) {
+            Err(AlmondParseError::ExtendedAfterSeal) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+        match Almond::parse_from_reader(key, &serialized[..]) {
+            Err(AlmondParseError::ExtendedAfterSeal) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+        match AlmondRef::parse_and_validate(key, &serialized) {
+            Err(AlmondParseError::ExtendedAfterSeal) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+        match Almond::parse_untrusted(&serialized).unwrap().validate(key) {
+            Err(AlmondParseError::ExtendedAfterSeal) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        // Sealing again doesn't help.
+        extended.seal();
+        match Almond::parse_and_validate(key, &extended.serialize_binary()) {
+            Err(AlmondParseError::ExtendedAfterSeal) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn seal_keeps_existing_almonds() {
+        let key = b"this_is_a_secret";
+
+        // A v1 almond ending in a bare `sealed` caveat, minted before seals
+        // existed, is hashed like any other almond.
+        let mut hash = initial_hash(key, 1, b"login");
+        add_to_hash(&mut hash, b"user erikj");
+        add_to_hash(&mut hash, b"sealed");
+
+        let mut serialized = hash.to_vec();
+        serialized.extend_from_slice(b"\x01login\nuser erikj\nsealed");
+
+        let almond = Almond::parse_and_validate(key, &serialized).unwrap();
+        assert_eq!(almond.format(), Format::V1);
+        assert!(!almond.is_sealed());
+        assert_eq!(almond.serialize_binary(), serialized);
+
+        // It can still be attenuated.
+        let mut extended = almond.clone();
+        extended.add_caveat(b"device", Some(b"phone"));
+        assert!(Almond::parse_and_validate(key, &extended.serialize_binary()).is_ok());
+
+        // Sealing it for real switches to v2.
+        let mut sealed = almond;
+        sealed.seal();
+        assert!(sealed.is_sealed());
+        let serialized = sealed.serialize_binary();
+        assert_eq!(Almond::parse_untrusted(&serialized).unwrap().format(), Format::V2);
+        assert!(Almond::parse_and_validate(key, &serialized).unwrap().is_sealed());
+    }
+
+    #[test]
+    fn lengths() {
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+
+        // Cover every remainder of the length modulo 3, in both formats.
+        for value in &[&b""[..], b"a", b"ab", b"abc", b"a\nb"] {
+            almond.add_caveat(b"user", Some(value));
+
+            for &format in &[Format::V1, Format::V2] {
+                almond.set_format(format);
+                assert_eq!(almond.serialized_len(), almond.serialize_binary().len());
+
+                for &config in &[Base64Config::STANDARD, Base64Config::URL_SAFE_NO_PAD] {
+                    assert_eq!(almond.base64_len_with(config), almond.serialize_base64_with(config).len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dictionary() {
+        let key = b"this_is_a_secret";
+
+        let mut dictionary = KeyDictionary::new();
+        dictionary.add(b"device".to_vec()).unwrap();
+
+        let mut almond = Almond::create_with_key_id(key, b"k1".to_vec(), 300, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"device", Some(b"phone"));
+        almond.add_caveat(b"other", Some(b"a\nb"));
+        almond.add_caveat(b"sealed", None);
+
+        let compressed = almond.serialize_with_dictionary(&dictionary);
+        assert!(compressed.len() < almond.serialize_binary().len());
+
+        let parsed = Almond::parse_and_validate_with_dictionary(key, &compressed, &dictionary)
+            .unwrap();
+        assert!(parsed.caveats().eq(almond.caveats()));
+        assert_eq!(parsed.key_id(), Some(&b"k1"[..]));
+        assert_eq!(parsed.generation(), 300);
+        assert_eq!(parsed.serialize_with_dictionary(&dictionary), compressed);
+
+        // Other formats are accepted too.
+        let serialized = almond.serialize_binary();
+        assert!(Almond::parse_and_validate_with_dictionary(key, &serialized, &dictionary).is_ok());
+
+        // Without the application keys the device caveat can't be decoded.
+        match Almond::parse_and_validate_with_dictionary(key, &compressed, &KeyDictionary::new()) {
+            Err(AlmondParseError::InvalidCaveat { index: 1 }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+        match Almond::parse_and_validate(key, &compressed) {
+            Err(AlmondParseError::UnsupportedVersion { version: 3 }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn type_codes() {
+        let key = b"this_is_a_secret";
+
+        let mut types = TypeRegistry::new();
+        types.register(300, b"login".to_vec()).unwrap();
+
+        let mut almond = Almond::create_with_key_id(key, b"k1".to_vec(), 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let serialized = almond.serialize_with_type_code(&types);
+        assert_eq!(serialized.len(), almond.serialized_len() - 4);
+
+        let parsed = Almond::parse_and_validate_with_type_codes(key, &serialized, &types).unwrap();
+        assert_eq!(parsed.almond_type(), b"login");
+        assert_eq!(parsed.key_id(), Some(&b"k1"[..]));
+        assert!(parsed.caveats().eq(almond.caveats()));
+        assert_eq!(parsed.serialize_with_type_code(&types), serialized);
+
+        // Unregistered types are serialized as normal.
+        let other = Almond::create(key, 1, b"other".to_vec());
+        assert_eq!(other.serialize_with_type_code(&types), other.serialize_binary());
+        assert!(
+            Almond::parse_and_validate_with_type_codes(key, &other.serialize_binary(), &types)
+                .is_ok()
+        );
+
+        match Almond::parse_and_validate_with_type_codes(key, &serialized, &TypeRegistry::new()) {
+            Err(AlmondParseError::UnknownTypeCode { code: 300 }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+        match Almond::parse_and_validate(key, &serialized) {
+            Err(AlmondParseError::UnknownFlags { flags }) if flags == format::FLAG_TYPE_CODE => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn caveat_storage() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"admin", None);
+        almond.add_literal_caveat(b"data a\nb".to_vec());
+        almond.seal();
+
+        assert_eq!(almond.caveats.len(), 34);
+        assert_eq!(&almond.caveats[..11], b"\x0auser erikj");
+        assert_eq!(
+            almond.caveats().collect::<Vec<_>>(),
+            vec![&b"user erikj"[..], b"admin", b"data a\nb", SEALED_KEY]
+        );
+        assert!(almond.is_sealed());
+
+        // The v2 serialization ends with the stored caveats.
+        let serialized = almond.serialize_binary();
+        assert!(serialized.ends_with(&almond.caveats));
+
+        let parsed = Almond::parse_and_validate(key, &serialized).unwrap();
+        assert_eq!(parsed.caveats, almond.caveats);
+
+        let mut v1 = Almond::create(key, 1, b"login".to_vec());
+        v1.add_caveat(b"user", Some(b"erikj"));
+        let parsed = Almond::parse_and_validate(key, &v1.serialize_binary()).unwrap();
+        assert_eq!(parsed.format(), Format::V1);
+        assert_eq!(parsed.caveats, v1.caveats);
+    }
+
+    #[test]
+    fn cached_splits() {
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        assert!(almond.splits.get().is_none());
+
+        let pairs: Vec<_> = almond.iter_caveats().map(|c| c.pair()).collect();
+        assert_eq!(pairs, vec![(&b"user"[..], Some(&b"erikj"[..]))]);
+        assert_eq!(almond.splits.get().map(|s| s.len()), Some(1));
+
+        // Adding caveats extends the cached splits.
+        almond.add_caveat(b"admin", None);
+        almond.add_caveat(b"note", Some(b"a b"));
+        let expected = vec![
+            (&b"user"[..], Some(&b"erikj"[..])),
+            (&b"admin"[..], None),
+            (&b"note"[..], Some(&b"a b"[..])),
+        ];
+        let pairs: Vec<_> = almond.iter_caveats().map(|c| c.pair()).collect();
+        assert_eq!(pairs, expected);
+
+        let cloned = almond.clone();
+        let pairs: Vec<_> = cloned.iter_caveats().map(|c| c.pair()).collect();
+        assert_eq!(pairs, expected);
+
+        let uncached: Vec<_> = almond.caveats().map(|c| Caveat::parse(c).pair()).collect();
+        assert_eq!(uncached, expected);
+    }
+
+    #[test]
+    fn clone_eq() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let mut copy = almond.clone();
+        assert!(copy == almond);
+
+        // The format isn't part of the almond's identity.
+        copy.set_format(Format::V2);
+        assert!(copy == almond);
+
+        copy.add_caveat(b"device", Some(b"phone"));
+        assert!(copy != almond);
+
+        let mut other_key = Almond::create(b"other", 1, b"login".to_vec());
+        other_key.add_caveat(b"user", Some(b"erikj"));
+        assert!(other_key != almond);
+
+        let parsed = Almond::parse_and_validate(key, &almond.serialize_binary()).unwrap();
+        assert!(parsed == almond);
+    }
+
+    #[test]
+    fn attenuate() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let original = almond.serialize_binary();
+
+        let reader = almond.attenuate(&[(b"scope", Some(b"repo:read")), (b"device", None)]);
+        let writer = almond.attenuated_with(|a| { a.add_scope("repo:write"); });
+        assert_eq!(almond.serialize_binary(), original);
+
+        let mut expected = almond.clone();
+        expected.add_caveat(b"scope", Some(b"repo:read")).add_caveat(b"device", None);
+        assert_eq!(reader, expected);
+        assert!(writer != reader);
+
+        for &(ref attenuated, count) in &[(reader, 3), (writer, 2)] {
+            let parsed = Almond::parse_and_validate(key, &attenuated.serialize_binary()).unwrap();
+            assert_eq!(parsed.caveats().count(), count);
+        }
+    }
+
+    #[test]
+    fn encrypted_caveats() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_encrypted_caveat(key, b"user_id", b"1234");
+        almond.set_format(Format::V1);
+
+        let parsed = Almond::parse_and_validate(key, &almond.serialize_binary()).unwrap();
+        assert_eq!(parsed.decrypt_caveat(key, b"user_id"), Some(b"1234".to_vec()));
+        assert_eq!(parsed.decrypt_caveat(key, b"other_id"), None);
+
+        // The value is bound to its caveat key, and to the almond's type.
+        let value = almond.caveat_value(b"user_id").unwrap().to_vec();
+        let mut moved = Almond::create(key, 1, b"login".to_vec());
+        moved.add_caveat(b"other_id", Some(&value));
+        assert_eq!(moved.decrypt_caveat(key, b"other_id"), None);
+
+        let mut retyped = Almond::create(key, 1, b"other".to_vec());
+        retyped.add_caveat(b"user_id", Some(&value));
+        assert_eq!(retyped.decrypt_caveat(key, b"user_id"), None);
+
+        {
+            let mut v = Verifier::new(&almond, 1, b"login");
+            v.satisfies_encrypted(b"other", b"user_id", |_| true);
+            assert!(!v.verify());
+        }
+
+        almond.add_caveat(b"user_id", Some(b"1234"));
+        {
+            let mut v = Verifier::new(&almond, 1, b"login");
+            v.satisfies_encrypted(key, b"user_id", |user_id| user_id == b"1234");
+            assert!(!v.verify());
+        }
+    }
+
+    #[test]
+    fn encrypted_almonds() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create_with_key_id(key, b"k1".to_vec(), 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let encrypted = almond.seal_base64(key);
+        assert!(!encrypted.contains('='));
+        assert!(encrypted != almond.seal_base64(key));
+
+        let opened = Almond::open_and_validate(key, encrypted.as_bytes()).unwrap();
+        assert_eq!(opened, almond);
+        assert_eq!(opened.key_id(), Some(&b"k1"[..]));
+
+        match Almond::open_and_validate(b"other", encrypted.as_bytes()) {
+            Err(AlmondParseError::Decryption) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        // Unencrypted almonds aren't accepted.
+        match Almond::open_and_validate(key, almond.serialize_base64().as_bytes()) {
+            Err(AlmondParseError::Decryption) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        let mut modified = encrypted.from_base64().unwrap();
+        let last = modified.len() - 1;
+        modified[last] ^= 1;
+        match Almond::open_and_validate(key, modified.to_base64(URL_SAFE).as_bytes()) {
+            Err(AlmondParseError::Decryption) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn debug_redacts() {
+        let mut almond = Almond::create_with_key_id(
+            b"this_is_a_secret", b"k1".to_vec(), 1, b"login".to_vec()
+        );
+        almond.add_caveat(b"password", Some(b"hunter2"));
+
+        let debug = format!("{:?}", almond);
+        assert!(debug.contains("password <7 bytes>"));
+        assert!(debug.contains("key_id: \"k1\""));
+        assert!(!debug.contains("hunter2"));
+        assert!(!debug.contains(&almond.hash().as_bytes().to_hex()));
+
+        // Panicking with an almond doesn't leak it either.
+        let res: Result<(), Almond> = Err(almond);
+        let debug = format!("{:?}", res);
+        assert!(!debug.contains("hunter2"));
+    }
+
+    #[test]
+    fn canonical() {
+        let key = b"this_is_a_secret";
+
+        let create = || {
+            let mut almond = Almond::create(key, 1, b"login".to_vec());
+            almond.add_caveat(b"user", Some(b"erikj"));
+            almond
+        };
+
+        let almond = create();
+
+        let mut with_empty = create();
+        with_empty.add_literal_caveat(Vec::new());
+
+        let mut with_newline = create();
+        with_newline.add_caveat(b"data", Some(b"a\nb"));
+
+        let mut v2 = create();
+        v2.set_format(Format::V2);
+
+        let with_key_id = Almond::create_with_key_id(key, b"k1".to_vec(), 1, b"login".to_vec());
+
+        for a in &[&almond, &with_empty, &with_newline, &v2, &with_key_id] {
+            let serialized = a.serialize_binary();
+            let parsed = Almond::parse_and_validate(key, &serialized).unwrap();
+            assert_eq!(parsed.serialize_binary(), serialized);
+
+            let canonical = a.canonical_bytes();
+            assert_eq!(Almond::parse_and_validate(key, &canonical).unwrap().canonical_bytes(), canonical);
+        }
+
+        assert_eq!(v2.canonical_bytes(), almond.serialize_binary());
+        assert_eq!(with_empty.serialize_binary()[32], format::EXTENDED_HEADER);
+
+        // A trailing newline would add an empty caveat.
+        let mut trailing = almond.serialize_binary();
+        trailing.push(b'\n');
+        match Almond::parse_and_validate(key, &trailing) {
+            Err(AlmondParseError::EmptyCaveat { index: 1 }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+        match Almond::parse_from_reader(key, &trailing[..]) {
+            Err(AlmondParseError::EmptyCaveat { index: 1 }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn parse_limits() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"device", Some(b"phone"));
+        let serialized = almond.serialize_binary();
+
+        // The defaults accept ordinary almonds.
+        let defaults = ParseLimits::default();
+        assert!(Almond::parse_and_validate_with_limits(key, &serialized, &defaults).is_ok());
+
+        let limits = ParseLimits { max_caveats: 1, ..defaults };
+        match Almond::parse_and_validate_with_limits(key, &serialized, &limits) {
+            Err(AlmondParseError::CaveatLimit { max: 1 }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        let limits = ParseLimits { max_caveat_len: 10, ..defaults };
+        match Almond::parse_and_validate_with_limits(key, &serialized, &limits) {
+            Err(AlmondParseError::CaveatTooLong { index: 1, max: 10 }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        let limits = ParseLimits { max_len: serialized.len() - 1, ..defaults };
+        match Almond::parse_and_validate_with_limits(key, &serialized, &limits) {
+            Err(AlmondParseError::TooLong { max }) if max == serialized.len() - 1 => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        // The other parsers apply the default limits.
+        let mut large = Almond::create(key, 1, b"login".to_vec());
+        for _ in 0..defaults.max_caveats + 1 {
+            large.add_caveat(b"a", None);
+        }
+        let serialized = large.serialize_binary();
+        match Almond::parse_and_validate(key, &serialized) {
+            Err(AlmondParseError::CaveatLimit { .. }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+        match Almond::parse_from_reader(key, &serialized[..]) {
+            Err(AlmondParseError::CaveatLimit { .. }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+        match Almond::parse_cbor_and_validate(key, &large.serialize_cbor()) {
+            Err(AlmondParseError::CaveatLimit { .. }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn seed() {
+        let key = b"this_is_a_secret";
+
+        // The default seed is the same as giving it explicitly.
+        let mut almond = Almond::create_with_seed(ALMOND_HASH_SEED, key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        assert_eq!(
+            almond.serialize_base64(),
+            "yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag"
+        );
+
+        let mut seeded = Almond::create_with_seed(b"app", key, 1, b"login".to_vec());
+        seeded.add_caveat(b"user", Some(b"erikj"));
+        let serialized = seeded.serialize_binary();
+
+        let parsed = Almond::parse_and_validate_with_seed(b"app", key, &serialized).unwrap();
+        assert!(parsed.caveats().eq(seeded.caveats()));
+
+        for &seed in &[&b"other"[..], &ALMOND_HASH_SEED[..]] {
+            match Almond::parse_and_validate_with_seed(seed, key, &serialized) {
+                Err(AlmondParseError::IncorrectHash) => {}
+                res => panic!("unexpected result: {:?}", res.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn context() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create_with_context(key, b"staging", 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let serialized = almond.serialize_binary();
+
+        let parsed = Almond::parse_and_validate_with_context(key, b"staging", &serialized).unwrap();
+        assert!(parsed.caveats().eq(almond.caveats()));
+
+        // Neither a different context nor no context validates, including
+        // the empty context.
+        for &context in &[&b"production"[..], &b""[..]] {
+            match Almond::parse_and_validate_with_context(key, context, &serialized) {
+                Err(AlmondParseError::IncorrectHash) => {}
+                res => panic!("unexpected result: {:?}", res.map(|_| ())),
+            }
+        }
+        assert!(Almond::parse_and_validate(key, &serialized).is_err());
+
+        let plain = Almond::create(key, 1, b"login".to_vec()).serialize_binary();
+        assert!(Almond::parse_and_validate_with_context(key, b"", &plain).is_err());
+    }
+
+    #[test]
+    fn parse_error_display() {
+        let err = Almond::parse_and_validate(b"this_is_a_secret", b"short").err().unwrap();
+        assert_eq!(err.to_string(), "input is 5 bytes, which is too short to be an almond");
+
+        let err = Almond::parse_base64_and_validate(b"this_is_a_secret", b"a!").err().unwrap();
+        assert!(err.to_string().starts_with("input is not valid Base64: "));
+    }
+
+    #[test]
+    fn unverified() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let serialized = almond.serialize_binary();
+
+        let unverified = UnverifiedAlmond::try_from(&serialized[..]).unwrap();
+        assert_eq!(unverified.almond_type(), b"login");
+        assert_eq!(unverified.generation(), 1);
+        assert_eq!(unverified.caveat_count(), 1);
+
+        // Parsing succeeds with any key, but validation doesn't.
+        match UnverifiedAlmond::parse(&serialized).unwrap().validate(b"wrong_key") {
+            Err(AlmondParseError::IncorrectHash) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        let padded = almond.serialize_base64_with(Base64Config::STANDARD);
+        let validated = UnverifiedAlmond::try_from(&padded[..]).unwrap().validate(key).unwrap();
+        assert_eq!(validated.serialize_binary(), serialized);
+
+        let untrusted = Almond::parse_untrusted(&serialized).unwrap();
+        assert_eq!(untrusted.format(), Format::V1);
+        assert_eq!(untrusted.hash(), almond.hash());
+
+        assert!(UnverifiedAlmond::try_from(&serialized[..20]).is_err());
+        assert!(UnverifiedAlmond::try_from("not base64!").is_err());
+    }
+
+    #[bench]
+    fn create(b: &mut Bencher) {
+        let key = b"this_is_a_secret";
+        b.iter(|| {
+            let mut almond = Almond::create(key, 1, b"login".to_vec());
+            almond.add_caveat(b"user", Some(b"erikj"));
+            almond.add_caveat(b"fooo", Some(b"bar"));
+            almond.add_caveat(b"testing", None);
+            almond.add_caveat(b"teeeeeeeeeeeest", None);
+            almond
+        });
+    }
+
+    #[bench]
+    fn mint(b: &mut Bencher) {
+        let minting_key = MintingKey::new(b"this_is_a_secret", 1, b"login".to_vec());
+        b.iter(|| {
+            let mut almond = minting_key.mint();
+            almond.add_caveat(b"user", Some(b"erikj"));
+            almond.add_caveat(b"fooo", Some(b"bar"));
+            almond.add_caveat(b"testing", None);
+            almond.add_caveat(b"teeeeeeeeeeeest", None);
+            almond
+        });
     }
 
     #[bench]
@@ -267,4 +3530,23 @@ mod tests {
             almond.unwrap();
         });
     }
+
+    #[bench]
+    fn parse_ref(b: &mut Bencher) {
+        let key = b"this_is_a_secret";
+
+        let b64 = concat!(
+            "Rv31sT9t5d31LHPeBFjPewo0TJ1ARbDok7vOWBVNSM4BbG9naW4KdXN",
+            "lciBlcmlragpmb29vIGJhcgp0ZXN0aW5nCnRlZWVlZWVlZWVlZWVzdA",
+        );
+        let parsed = b64.from_base64().unwrap();
+
+        b.iter(|| {
+            let almond = AlmondRef::parse_and_validate(
+                key,
+                &parsed,
+            );
+            almond.unwrap();
+        });
+    }
 }
@@ -0,0 +1,275 @@
+//! Validation and verification that need an asynchronous lookup, enabled by
+//! the `async` feature.
+//!
+//! The futures are runtime agnostic, so can be awaited on any executor.
+//!
+//! ```
+//! # extern crate almonds;
+//! # extern crate futures;
+//! # use futures::executor::block_on;
+//! # use futures::future;
+//! # use almonds::Almond;
+//! # use almonds::asynchronous::AsyncVerifier;
+//! # fn main() {
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_caveat(b"group", Some(b"admins"));
+//! almond.add_caveat(b"user", Some(b"erikj"));
+//!
+//! let mut v = AsyncVerifier::new(&almond, 1, b"access");
+//! v.allow(b"user");
+//! v.satisfies_async(b"group", |group| future::ready(group == b"admins"));
+//! assert!(block_on(v.verify()));
+//! # }
+//! ```
+
+use futures::future::{self, Future, FutureExt, LocalBoxFuture};
+
+use std::ops::{Deref, DerefMut};
+
+use {format, Almond, AlmondParseError, Caveat, Verifier, VerifyError};
+
+
+/// Looks up the key that almonds of a given generation and type were minted
+/// with, e.g. by fetching it from a secrets manager.
+///
+/// Keys are fetched every time an almond is validated, so implementations
+/// should cache them if the lookup is expensive.
+///
+/// ```
+/// # extern crate almonds;
+/// # extern crate futures;
+/// # use futures::executor::block_on;
+/// # use futures::future::{self, FutureExt, LocalBoxFuture};
+/// # use almonds::Almond;
+/// # use almonds::asynchronous::AsyncKeyProvider;
+/// struct StaticKeys;
+///
+/// impl AsyncKeyProvider for StaticKeys {
+///     fn key<'a>(&'a self, generation: u32, _almond_type: &'a [u8])
+///         -> LocalBoxFuture<'a, Option<Vec<u8>>>
+///     {
+///         let key = if generation == 1 { Some(b"secret".to_vec()) } else { None };
+///         future::ready(key).boxed_local()
+///     }
+/// }
+///
+/// # fn main() {
+/// let almond = Almond::create(b"secret", 1, b"access".to_vec());
+/// let input = almond.serialize_binary();
+///
+/// let parsed = block_on(Almond::parse_and_validate_async(&StaticKeys, &input));
+/// assert!(parsed.is_ok());
+/// # }
+/// ```
+pub trait AsyncKeyProvider {
+    /// Fetch the key for the given generation and type, resolving to `None`
+    /// if there isn't one.
+    fn key<'a>(&'a self, generation: u32, almond_type: &'a [u8])
+        -> LocalBoxFuture<'a, Option<Vec<u8>>>;
+}
+
+impl <'p, P: AsyncKeyProvider + ?Sized> AsyncKeyProvider for &'p P {
+    fn key<'a>(&'a self, generation: u32, almond_type: &'a [u8])
+        -> LocalBoxFuture<'a, Option<Vec<u8>>>
+    {
+        (**self).key(generation, almond_type)
+    }
+}
+
+
+impl Almond {
+    /// Parse a binary serialized Almond, and validate that the hashes match
+    /// using the key fetched from `provider`.
+    ///
+    /// Resolves to `AlmondParseError::UnknownKey` if the provider has no key
+    /// for the almond's generation and type.
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_and_validate_async<'a, P>(provider: &'a P, input: &'a [u8])
+        -> LocalBoxFuture<'a, Result<Almond, AlmondParseError>>
+        where P: AsyncKeyProvider + ?Sized
+    {
+        let raw = match format::parse(input) {
+            Ok(raw) => raw,
+            Err(err) => return future::ready(Err(err)).boxed_local(),
+        };
+
+        provider.key(raw.generation, raw.almond_type).map(move |key| {
+            match key {
+                Some(key) => Almond::parse_and_validate(&key, input),
+                None => Err(AlmondParseError::UnknownKey),
+            }
+        }).boxed_local()
+    }
+}
+
+
+/// A `Verifier` that also accepts asynchronous predicates.
+///
+/// Synchronous predicates are added through the underlying `Verifier`, which
+/// this dereferences to.
+pub struct AsyncVerifier<'a> {
+    verifier: Verifier<'a>,
+    caveats: Vec<Caveat<'a>>,
+    pending: Vec<(Vec<u8>, Vec<LocalBoxFuture<'a, bool>>)>,
+}
+
+impl <'a> AsyncVerifier<'a> {
+    /// Create a new instance to verify the given almond.
+    pub fn new(almond: &'a Almond, generation: u32, almond_type: &[u8])
+        -> AsyncVerifier<'a>
+    {
+        AsyncVerifier {
+            verifier: Verifier::new(almond, generation, almond_type),
+            caveats: almond.iter_caveats().collect(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Like `Verifier::satisfies`, but `predicate` returns a future.
+    ///
+    /// `predicate` is invoked immediately for every caveat with the given
+    /// key, and the futures are then awaited concurrently by `verify`.
+    /// Rejects the caveat if the key matches but has no value.
+    pub fn satisfies_async<F, Fut>(&mut self, key: &[u8], predicate: F) -> &mut Self
+        where F: Fn(&'a [u8]) -> Fut,
+              Fut: Future<Output = bool> + 'a
+    {
+        let futures = self.caveats.iter()
+            .filter(|caveat| caveat.key() == key)
+            .filter_map(|caveat| caveat.value())
+            .map(|value| predicate(value).boxed_local())
+            .collect();
+
+        self.pending.push((key.to_vec(), futures));
+        self
+    }
+
+    /// Returns a future resolving to whether the almond satisfies the
+    /// synchronous and asynchronous predicates, see `Verifier::verify`.
+    pub fn verify(self) -> LocalBoxFuture<'a, bool> {
+        self.verify_detailed().map(|res| res.is_ok()).boxed_local()
+    }
+
+    /// Like `verify`, but on failure resolves to the reason the almond was
+    /// rejected.
+    pub fn verify_detailed(self) -> LocalBoxFuture<'a, Result<(), VerifyError>> {
+        let AsyncVerifier { mut verifier, pending, .. } = self;
+
+        let lookups = pending.into_iter().map(|(key, futures)| {
+            future::join_all(futures).map(move |results| (key, results))
+        });
+
+        future::join_all(lookups).map(move |outcomes| {
+            for (key, results) in outcomes {
+                // The verifier visits the caveats in the same order as
+                // `satisfies_async` did.
+                let mut results = results.into_iter();
+                verifier.satisfies(&key, |_| results.next().unwrap_or(false));
+            }
+
+            verifier.verify_detailed()
+        }).boxed_local()
+    }
+}
+
+impl <'a> Deref for AsyncVerifier<'a> {
+    type Target = Verifier<'a>;
+
+    fn deref(&self) -> &Verifier<'a> {
+        &self.verifier
+    }
+}
+
+impl <'a> DerefMut for AsyncVerifier<'a> {
+    fn deref_mut(&mut self) -> &mut Verifier<'a> {
+        &mut self.verifier
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{AsyncKeyProvider, AsyncVerifier};
+    use {Almond, AlmondParseError, VerifyError};
+
+    use futures::executor::block_on;
+    use futures::future::{self, FutureExt, LocalBoxFuture};
+
+    use std::collections::HashMap;
+
+    impl AsyncKeyProvider for HashMap<u32, Vec<u8>> {
+        fn key<'a>(&'a self, generation: u32, _almond_type: &'a [u8])
+            -> LocalBoxFuture<'a, Option<Vec<u8>>>
+        {
+            future::ready(self.get(&generation).cloned()).boxed_local()
+        }
+    }
+
+    #[test]
+    fn parse_and_validate_async() {
+        let mut keys = HashMap::new();
+        keys.insert(1, b"this_is_a_secret".to_vec());
+        keys.insert(2, b"this_is_another_secret".to_vec());
+
+        let mut almond = Almond::create(b"this_is_another_secret", 2, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let input = almond.serialize_binary();
+
+        let parsed = block_on(Almond::parse_and_validate_async(&keys, &input)).unwrap();
+        assert_eq!(parsed.serialize_binary(), input);
+
+        let almond = Almond::create(b"this_is_a_secret", 2, b"login".to_vec());
+        match block_on(Almond::parse_and_validate_async(&keys, &almond.serialize_binary())) {
+            Err(AlmondParseError::IncorrectHash) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        let almond = Almond::create(b"this_is_a_secret", 3, b"login".to_vec());
+        match block_on(Almond::parse_and_validate_async(&keys, &almond.serialize_binary())) {
+            Err(AlmondParseError::UnknownKey) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        match block_on(Almond::parse_and_validate_async(&keys, b"invalid")) {
+            Err(AlmondParseError::TooShort { len: 7 }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn satisfies_async() {
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"access".to_vec());
+        almond.add_caveat(b"group", Some(b"admins"));
+        almond.add_caveat(b"group", Some(b"staff"));
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let groups: &[&[u8]] = &[b"admins", b"staff"];
+
+        let mut v = AsyncVerifier::new(&almond, 1, b"access");
+        v.allow(b"user");
+        v.satisfies_async(b"group", |group| future::ready(groups.contains(&group)));
+        assert!(block_on(v.verify()));
+
+        let mut v = AsyncVerifier::new(&almond, 1, b"access");
+        v.allow(b"user");
+        v.satisfies_async(b"group", |group| future::ready(group == b"staff"));
+        assert_eq!(
+            block_on(v.verify_detailed()),
+            Err(VerifyError::Caveats {
+                unmatched: vec![],
+                rejected: vec![b"group".to_vec()],
+            })
+        );
+
+        let mut v = AsyncVerifier::new(&almond, 1, b"access");
+        v.satisfies_async(b"group", |_| future::ready(true));
+        assert_eq!(
+            block_on(v.verify_detailed()),
+            Err(VerifyError::Caveats {
+                unmatched: vec![b"user".to_vec()],
+                rejected: vec![],
+            })
+        );
+    }
+}
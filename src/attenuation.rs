@@ -0,0 +1,134 @@
+//! Records of who attenuated an almond and when.
+//!
+//! When a holder restricts a delegated almond, they can also add an
+//! `ATTENUATION_KEY` caveat naming themselves and the time, with
+//! `Almond::add_attenuation_record`. As caveats can't be removed, the
+//! records form a chain that shows who narrowed the almond, in order.
+//!
+//! ```
+//! # use std::time::{Duration, UNIX_EPOCH};
+//! # use almonds::{Almond, Verifier};
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_attenuation_record("billing", UNIX_EPOCH + Duration::from_secs(1500000000));
+//! almond.add_caveat(b"scope", Some(b"invoices:read"));
+//!
+//! let mut v = Verifier::new(&almond, 1, b"access");
+//! v.allow(b"scope");
+//! v.satisfies_attenuation_records();
+//! assert!(v.verify());
+//!
+//! let chain = v.attenuation_chain();
+//! assert_eq!(chain.len(), 1);
+//! assert_eq!(chain[0].actor, "billing");
+//! ```
+
+use std::str;
+use std::time::SystemTime;
+
+use value::CaveatValue;
+
+
+/// An attenuation record, stored as `<actor> <timestamp>` where the
+/// timestamp is encoded as for `SystemTime`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttenuationRecord {
+    /// Who attenuated the almond. This is never empty.
+    pub actor: String,
+    /// When the almond was attenuated, to the second.
+    pub timestamp: SystemTime,
+}
+
+impl AttenuationRecord {
+    /// Create a new record.
+    pub fn new(actor: &str, timestamp: SystemTime) -> AttenuationRecord {
+        AttenuationRecord {
+            actor: actor.to_owned(),
+            timestamp: timestamp,
+        }
+    }
+}
+
+impl <'a> CaveatValue<'a> for AttenuationRecord {
+    fn encode_value(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.actor.as_bytes());
+        buf.push(b' ');
+        self.timestamp.encode_value(buf);
+    }
+
+    /// The actor may contain spaces, as the timestamp can't.
+    fn decode_value(value: &'a [u8]) -> Option<AttenuationRecord> {
+        let idx = match value.iter().rposition(|c| *c == b' ') {
+            Some(idx) if idx > 0 => idx,
+            _ => return None,
+        };
+
+        let actor = match str::from_utf8(&value[..idx]) {
+            Ok(actor) => actor,
+            Err(_) => return None,
+        };
+        SystemTime::decode_value(&value[idx + 1..]).map(|timestamp| {
+            AttenuationRecord::new(actor, timestamp)
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::*;
+    use value::{encode, CaveatValue};
+    use {Almond, Verifier, ATTENUATION_KEY};
+
+    #[test]
+    fn records() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+
+        let record = AttenuationRecord::new("billing service", at(1500000000));
+        assert_eq!(encode(&record), b"billing service 1500000000");
+        assert_eq!(AttenuationRecord::decode_value(b"billing service 1500000000"), Some(record));
+
+        assert_eq!(AttenuationRecord::decode_value(b" 1500000000"), None);
+        assert_eq!(AttenuationRecord::decode_value(b"billing"), None);
+        assert_eq!(AttenuationRecord::decode_value(b"billing soon"), None);
+        assert_eq!(AttenuationRecord::decode_value(b"\xff 1500000000"), None);
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"access".to_vec());
+        almond.add_attenuation_record("alice", at(1500000000));
+        almond.add_caveat(b"scope", Some(b"repo:read"));
+        almond.add_attenuation_record("bob", at(1500000100));
+
+        let records = almond.attenuation_records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].actor, "alice");
+        assert_eq!(records[1], AttenuationRecord::new("bob", at(1500000100)));
+
+        {
+            let mut v = Verifier::new(&almond, 1, b"access");
+            v.allow(b"scope");
+            v.satisfies_attenuation_records();
+            assert!(v.verify());
+            assert_eq!(v.attenuation_chain(), records);
+        }
+
+        // Records from the future are rejected, as are malformed ones.
+        let mut future = almond.clone();
+        future.add_attenuation_record("mallory", SystemTime::now() + Duration::from_secs(3600));
+        {
+            let mut v = Verifier::new(&future, 1, b"access");
+            v.allow(b"scope");
+            v.satisfies_attenuation_records();
+            assert!(!v.verify());
+        }
+
+        almond.add_caveat(ATTENUATION_KEY, Some(b"mallory"));
+        {
+            let mut v = Verifier::new(&almond, 1, b"access");
+            v.allow(b"scope");
+            v.satisfies_attenuation_records();
+            assert!(!v.verify());
+            assert_eq!(v.attenuation_chain().len(), 2);
+        }
+    }
+}
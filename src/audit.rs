@@ -0,0 +1,180 @@
+//! Structured records of verification, for security audit logs.
+//!
+//! ```
+//! # use almonds::{Almond, Verifier};
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_caveat(b"user", Some(b"erikj"));
+//! almond.add_caveat(b"admin", None);
+//!
+//! let mut v = Verifier::new(&almond, 1, b"access");
+//! v.satisfies_exact(b"user", Some(b"erikj"));
+//!
+//! let record = v.verify_with_audit();
+//! assert!(!record.accepted());
+//! assert_eq!(record.caveats_checked, 2);
+//! assert_eq!(record.failed_caveat, Some(b"admin".to_vec()));
+//! assert_eq!(record.to_json()["outcome"].as_string(), Some("caveats"));
+//! ```
+
+use rustc_serialize::json::{Json, Object};
+
+use VerifyError;
+
+
+/// Whether an almond was accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditOutcome {
+    /// The almond was accepted.
+    Accepted,
+    /// The almond was rejected for the given reason.
+    Rejected(VerifyError),
+}
+
+
+/// A record of verifying an almond, returned by
+/// `Verifier::verify_with_audit`.
+///
+/// The record contains caveat keys but never their values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// The type of the almond.
+    pub almond_type: Vec<u8>,
+    /// The generation of the almond.
+    pub generation: u32,
+    /// The number of caveats the almond had.
+    pub caveats_checked: usize,
+    /// Whether the almond was accepted.
+    pub outcome: AuditOutcome,
+    /// The key of the first caveat responsible for the almond being
+    /// rejected, if any.
+    pub failed_caveat: Option<Vec<u8>>,
+}
+
+impl AuditRecord {
+    /// Create the record for the result of verifying an almond.
+    pub fn new(
+        almond_type: &[u8],
+        generation: u32,
+        caveats_checked: usize,
+        result: Result<(), VerifyError>,
+    ) -> AuditRecord {
+        let (outcome, failed_caveat) = match result {
+            Ok(()) => (AuditOutcome::Accepted, None),
+            Err(err) => {
+                let failed_caveat = failed_caveat(&err);
+                (AuditOutcome::Rejected(err), failed_caveat)
+            }
+        };
+
+        AuditRecord {
+            almond_type: almond_type.to_vec(),
+            generation: generation,
+            caveats_checked: caveats_checked,
+            outcome: outcome,
+            failed_caveat: failed_caveat,
+        }
+    }
+
+    /// Whether the almond was accepted.
+    pub fn accepted(&self) -> bool {
+        self.outcome == AuditOutcome::Accepted
+    }
+
+    /// Convert the record to JSON, for logging.
+    ///
+    /// `outcome` is `"accepted"` or the `VerifyError::kind` of the reason
+    /// the almond was rejected, and `reason` describes the reason.
+    pub fn to_json(&self) -> Json {
+        let mut object = Object::new();
+        object.insert("type".to_owned(), lossy_json(&self.almond_type));
+        object.insert("generation".to_owned(), Json::U64(self.generation as u64));
+        object.insert("caveats_checked".to_owned(), Json::U64(self.caveats_checked as u64));
+
+        match self.outcome {
+            AuditOutcome::Accepted => {
+                object.insert("outcome".to_owned(), Json::String("accepted".to_owned()));
+            }
+            AuditOutcome::Rejected(ref err) => {
+                object.insert("outcome".to_owned(), Json::String(err.kind().to_owned()));
+                object.insert("reason".to_owned(), Json::String(err.to_string()));
+            }
+        }
+
+        object.insert(
+            "failed_caveat".to_owned(),
+            self.failed_caveat.as_ref().map(|k| lossy_json(k)).unwrap_or(Json::Null),
+        );
+        Json::Object(object)
+    }
+}
+
+/// Get the key of the first caveat responsible for `err`.
+fn failed_caveat(err: &VerifyError) -> Option<Vec<u8>> {
+    match *err {
+        VerifyError::MissingCaveats { ref keys }
+        | VerifyError::ForbiddenCaveats { ref keys }
+        | VerifyError::DuplicateCaveats { ref keys } => keys.first().cloned(),
+        VerifyError::Caveats { ref unmatched, ref rejected } => {
+            rejected.first().or(unmatched.first()).cloned()
+        }
+        _ => None,
+    }
+}
+
+fn lossy_json(bytes: &[u8]) -> Json {
+    Json::String(String::from_utf8_lossy(bytes).into_owned())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+
+    use super::*;
+    use {Almond, Verifier, VerifyError};
+
+    #[test]
+    fn records() {
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"access".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        {
+            let mut v = Verifier::new(&almond, 1, b"access");
+            v.satisfies_exact(b"user", Some(b"erikj"));
+
+            let record = v.verify_with_audit();
+            assert!(record.accepted());
+            assert_eq!(record.almond_type, b"access");
+            assert_eq!(record.generation, 1);
+            assert_eq!(record.caveats_checked, 1);
+            assert_eq!(record.failed_caveat, None);
+
+            let json = record.to_json();
+            assert_eq!(json["outcome"].as_string(), Some("accepted"));
+            assert_eq!(json["failed_caveat"], Json::Null);
+        }
+
+        {
+            let mut v = Verifier::new(&almond, 1, b"access");
+            v.satisfies_exact(b"user", Some(b"bob"));
+
+            let record = v.verify_with_audit();
+            assert_eq!(record.outcome, AuditOutcome::Rejected(VerifyError::Caveats {
+                unmatched: vec![],
+                rejected: vec![b"user".to_vec()],
+            }));
+            assert_eq!(record.failed_caveat, Some(b"user".to_vec()));
+
+            // Values never appear in the record.
+            let json = record.to_json().to_string();
+            assert!(!json.contains("erikj"));
+        }
+
+        {
+            let v = Verifier::new(&almond, 2, b"access");
+            let record = v.verify_with_audit();
+            assert_eq!(record.to_json()["outcome"].as_string(), Some("wrong_generation"));
+            assert_eq!(record.failed_caveat, None);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Base32 (RFC 4648) without padding.
+
+const ALPHABET : &'static [u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+
+/// Encode `data` using the uppercase alphabet.
+pub fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() * 8).div_ceil(5));
+
+    for chunk in data.chunks(5) {
+        let mut block = [0u8; 5];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let bits = block.iter().fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for idx in 0..chars {
+            let value = (bits >> (35 - 5 * idx)) & 0x1F;
+            out.push(ALPHABET[value as usize] as char);
+        }
+    }
+
+    out
+}
+
+/// Decode `input`, accepting either case.
+///
+/// Returns `None` if the input contains padding or other invalid characters,
+/// has an impossible length, or has non-zero trailing bits.
+pub fn decode(input: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 5 / 8);
+
+    for chunk in input.chunks(8) {
+        let len = match chunk.len() {
+            2 => 1,
+            4 => 2,
+            5 => 3,
+            7 => 4,
+            8 => 5,
+            _ => return None,
+        };
+
+        let mut bits = 0u64;
+        for (idx, c) in chunk.iter().enumerate() {
+            let value = match *c {
+                c if b'A' <= c && c <= b'Z' => c - b'A',
+                c if b'a' <= c && c <= b'z' => c - b'a',
+                c if b'2' <= c && c <= b'7' => c - b'2' + 26,
+                _ => return None,
+            };
+            bits |= (value as u64) << (35 - 5 * idx);
+        }
+
+        // The unused low bits of the final character must be zero.
+        if bits & ((1 << (40 - 8 * len)) - 1) != 0 {
+            return None;
+        }
+
+        for idx in 0..len {
+            out.push((bits >> (32 - 8 * idx)) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc_4648_vectors() {
+        let vectors: &[(&[u8], &str)] = &[
+            (b"", ""),
+            (b"f", "MY"),
+            (b"fo", "MZXQ"),
+            (b"foo", "MZXW6"),
+            (b"foob", "MZXW6YQ"),
+            (b"fooba", "MZXW6YTB"),
+            (b"foobar", "MZXW6YTBOI"),
+        ];
+
+        for &(data, encoded) in vectors {
+            assert_eq!(encode(data), encoded);
+            assert_eq!(decode(encoded.as_bytes()), Some(data.to_vec()));
+            assert_eq!(decode(encoded.to_lowercase().as_bytes()), Some(data.to_vec()));
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decode(b"MY======"), None);
+        assert_eq!(decode(b"M"), None);
+        assert_eq!(decode(b"MZX"), None);
+        assert_eq!(decode(b"MZ"), None);
+        assert_eq!(decode(b"M1"), None);
+    }
+}
@@ -0,0 +1,97 @@
+//! Base45 (RFC 9285), whose alphabet is the QR code alphanumeric mode.
+
+const ALPHABET : &'static [u8; 45] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+
+/// Encode `data`.
+pub fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() * 3).div_ceil(2));
+
+    for chunk in data.chunks(2) {
+        let (mut value, chars) = match *chunk {
+            [a, b] => ((a as usize) << 8 | b as usize, 3),
+            [a] => (a as usize, 2),
+            _ => unreachable!(),
+        };
+
+        for _ in 0..chars {
+            out.push(ALPHABET[value % 45] as char);
+            value /= 45;
+        }
+    }
+
+    out
+}
+
+/// Decode `input`.
+///
+/// Returns `None` if the input contains lowercase or other invalid
+/// characters, has an impossible length, or encodes a value that doesn't
+/// fit.
+pub fn decode(input: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 2 / 3);
+
+    for chunk in input.chunks(3) {
+        if chunk.len() == 1 {
+            return None;
+        }
+
+        let mut value = 0usize;
+        for c in chunk.iter().rev() {
+            let digit = match ALPHABET.iter().position(|a| a == c) {
+                Some(digit) => digit,
+                None => return None,
+            };
+            value = value * 45 + digit;
+        }
+
+        if chunk.len() == 3 {
+            if value > 0xFFFF {
+                return None;
+            }
+            out.push((value >> 8) as u8);
+            out.push(value as u8);
+        } else {
+            if value > 0xFF {
+                return None;
+            }
+            out.push(value as u8);
+        }
+    }
+
+    Some(out)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc_9285_vectors() {
+        let vectors: &[(&[u8], &str)] = &[
+            (b"", ""),
+            (b"AB", "BB8"),
+            (b"Hello!!", "%69 VD92EX0"),
+            (b"base-45", "UJCLQE7W581"),
+            (b"ietf!", "QED8WEX0"),
+        ];
+
+        for &(data, encoded) in vectors {
+            assert_eq!(encode(data), encoded);
+            assert_eq!(decode(encoded.as_bytes()), Some(data.to_vec()));
+        }
+
+        let all: Vec<u8> = (0..256).map(|b| b as u8).collect();
+        assert_eq!(decode(encode(&all).as_bytes()), Some(all));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decode(b"GGW"), None);
+        assert_eq!(decode(b"::"), None);
+        assert_eq!(decode(b"BB8A"), None);
+        assert_eq!(decode(b"bb8"), None);
+        assert_eq!(decode(b"BB#"), None);
+    }
+}
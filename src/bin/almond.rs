@@ -0,0 +1,317 @@
+//! Command line tool for minting, inspecting, verifying and attenuating
+//! almonds. Build with `--features cli`.
+//!
+//! The secret is read from the `ALMOND_SECRET` environment variable by
+//! default, see `almond --help` for other options.
+
+extern crate almonds;
+extern crate getopts;
+extern crate rustc_serialize;
+
+use std::convert::TryFrom;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+use getopts::{Matches, Options};
+use rustc_serialize::hex::ToHex;
+
+use almonds::{Almond, UnverifiedAlmond, Verifier};
+
+
+const DEFAULT_SECRET_ENV: &'static str = "ALMOND_SECRET";
+
+const USAGE: &'static str = "\
+Usage:
+    almond mint --generation N --type TYPE [--caveat CAVEAT]...
+    almond inspect [TOKEN]
+    almond verify --generation N --type TYPE [--allow KEY]... [--exact CAVEAT]... [TOKEN]
+    almond attenuate --caveat CAVEAT... [TOKEN]
+
+Caveats are given as `<key>` or `<key> <value>`. If TOKEN is omitted it is
+read from stdin. `inspect` only shows the caveats of a token once its hash
+has been checked with the secret.";
+
+
+/// Write a line of output, turning errors into the message to report.
+macro_rules! out {
+    ($out:expr, $($arg:tt)*) => (
+        try!(writeln!($out, $($arg)*).map_err(|e| e.to_string()))
+    )
+}
+
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let stdout = io::stdout();
+    match run(args, &mut stdout.lock()) {
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "almond: {}", err);
+            process::exit(2);
+        }
+    }
+}
+
+fn options() -> Options {
+    let mut opts = Options::new();
+    opts.optopt("", "secret-env", "environment variable holding the secret", "VAR");
+    opts.optopt("", "secret-file", "file holding the secret", "PATH");
+    opts.optopt("g", "generation", "almond generation", "N");
+    opts.optopt("t", "type", "almond type", "TYPE");
+    opts.optmulti("c", "caveat", "caveat to add", "CAVEAT");
+    opts.optmulti("", "allow", "allow caveats with the given key", "KEY");
+    opts.optmulti("", "exact", "require the caveat to match exactly", "CAVEAT");
+    opts.optflag("h", "help", "print this help");
+    opts
+}
+
+fn run(args: Vec<String>, out: &mut Write) -> Result<i32, String> {
+    let opts = options();
+    let matches = try!(opts.parse(&args).map_err(|e| e.to_string()));
+
+    if matches.opt_present("help") || matches.free.is_empty() {
+        out!(out, "{}", opts.usage(USAGE));
+        return Ok(0);
+    }
+
+    let command = matches.free[0].clone();
+    let token = matches.free.get(1).cloned();
+
+    match &command[..] {
+        "mint" => mint(&matches, out),
+        "inspect" => inspect(&matches, token, out),
+        "verify" => verify(&matches, token, out),
+        "attenuate" => attenuate(&matches, token, out),
+        _ => Err(format!("unknown command `{}`", command)),
+    }
+}
+
+fn mint(matches: &Matches, out: &mut Write) -> Result<i32, String> {
+    let secret = try!(read_secret(matches));
+    let generation = try!(generation(matches));
+    let almond_type = try!(required(matches, "type"));
+
+    let mut almond = Almond::create(
+        &secret, generation, almond_type.into_bytes()
+    );
+    try!(add_caveats(&mut almond, matches));
+
+    out!(out, "{}", almond.serialize_base64());
+    Ok(0)
+}
+
+fn inspect(matches: &Matches, token: Option<String>, out: &mut Write)
+    -> Result<i32, String>
+{
+    let token = try!(read_token(token));
+    let almond = try!(
+        UnverifiedAlmond::try_from(&token[..]).map_err(|e| e.to_string())
+    );
+
+    out!(out, "hash:       {}", almond.hash().as_bytes().to_hex());
+    out!(out, "format:     {:?}", almond.format());
+    out!(out, "generation: {}", almond.generation());
+    if let Some(key_id) = almond.key_id() {
+        out!(out, "key id:     {}", String::from_utf8_lossy(key_id));
+    }
+    out!(out, "type:       {}", String::from_utf8_lossy(almond.almond_type()));
+
+    let count = almond.caveat_count();
+    match read_secret(matches) {
+        Ok(secret) => match almond.validate(&secret) {
+            Ok(almond) => {
+                for caveat in almond.caveats() {
+                    out!(out, "caveat:     {}", String::from_utf8_lossy(caveat));
+                }
+                out!(out, "valid:      yes");
+            }
+            Err(err) => {
+                out!(out, "caveats:    {} (not shown, as the hash is invalid)", count);
+                out!(out, "valid:      no ({})", err);
+            }
+        },
+        Err(_) => out!(out, "caveats:    {} (not shown without a secret)", count),
+    }
+
+    Ok(0)
+}
+
+fn verify(matches: &Matches, token: Option<String>, out: &mut Write)
+    -> Result<i32, String>
+{
+    let secret = try!(read_secret(matches));
+    let generation = try!(generation(matches));
+    let almond_type = try!(required(matches, "type"));
+    let token = try!(read_token(token));
+
+    let almond = try!(
+        Almond::parse_base64_and_validate(&secret, token.as_bytes())
+            .map_err(|e| e.to_string())
+    );
+
+    let mut v = Verifier::new(&almond, generation, almond_type.as_bytes());
+    for key in matches.opt_strs("allow") {
+        v.allow(key.as_bytes());
+    }
+    for caveat in matches.opt_strs("exact") {
+        let (key, value) = split_caveat(&caveat);
+        v.satisfies_exact(key.as_bytes(), value.map(|v| v.as_bytes()));
+    }
+
+    if v.verify() {
+        out!(out, "ok");
+        Ok(0)
+    } else {
+        out!(out, "rejected");
+        Ok(1)
+    }
+}
+
+fn attenuate(matches: &Matches, token: Option<String>, out: &mut Write)
+    -> Result<i32, String>
+{
+    let secret = try!(read_secret(matches));
+    let token = try!(read_token(token));
+
+    let mut almond = try!(
+        Almond::parse_base64_and_validate(&secret, token.as_bytes())
+            .map_err(|e| e.to_string())
+    );
+    try!(add_caveats(&mut almond, matches));
+
+    out!(out, "{}", almond.serialize_base64());
+    Ok(0)
+}
+
+/// Split a caveat given as `<key>` or `<key> <value>`.
+fn split_caveat(caveat: &str) -> (&str, Option<&str>) {
+    let mut it = caveat.splitn(2, ' ');
+    (it.next().unwrap_or(""), it.next())
+}
+
+/// Add the `--caveat` options to the almond, rejecting any that wouldn't
+/// round trip through serialization.
+fn add_caveats(almond: &mut Almond, matches: &Matches) -> Result<(), String> {
+    for caveat in matches.opt_strs("caveat") {
+        let (key, value) = split_caveat(&caveat);
+        try!(
+            almond.try_add_caveat(key.as_bytes(), value.map(|v| v.as_bytes()))
+                .map_err(|e| format!("invalid caveat `{}`: {}", caveat, e))
+        );
+    }
+    Ok(())
+}
+
+fn required(matches: &Matches, name: &str) -> Result<String, String> {
+    matches.opt_str(name).ok_or(format!("missing --{}", name))
+}
+
+fn generation(matches: &Matches) -> Result<u32, String> {
+    let generation = try!(required(matches, "generation"));
+    generation.parse().map_err(|_| format!("invalid generation `{}`", generation))
+}
+
+fn read_secret(matches: &Matches) -> Result<Vec<u8>, String> {
+    if let Some(path) = matches.opt_str("secret-file") {
+        let mut secret = Vec::new();
+        try!(
+            File::open(&path)
+                .and_then(|mut f| f.read_to_end(&mut secret))
+                .map_err(|e| format!("failed to read {}: {}", path, e))
+        );
+        while secret.last() == Some(&b'\n') || secret.last() == Some(&b'\r') {
+            secret.pop();
+        }
+        return Ok(secret);
+    }
+
+    let var = matches.opt_str("secret-env")
+        .unwrap_or(DEFAULT_SECRET_ENV.to_owned());
+    env::var(&var)
+        .map(|s| s.into_bytes())
+        .map_err(|_| format!("secret not found in ${}", var))
+}
+
+fn read_token(token: Option<String>) -> Result<String, String> {
+    match token {
+        Some(token) => Ok(token),
+        None => {
+            let mut token = String::new();
+            try!(
+                io::stdin().read_to_string(&mut token)
+                    .map_err(|e| e.to_string())
+            );
+            Ok(token.trim().to_owned())
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::str;
+
+    use super::run;
+
+    const SECRET_ENV: &'static str = "ALMOND_CLI_TEST_SECRET";
+
+    /// Run the tool with the test secret, returning the exit code and output.
+    fn almond(args: &[&str]) -> Result<(i32, String), String> {
+        env::set_var(SECRET_ENV, "this_is_a_secret");
+
+        let mut args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        args.push("--secret-env".to_owned());
+        args.push(SECRET_ENV.to_owned());
+
+        let mut out = Vec::new();
+        let code = try!(run(args, &mut out));
+        Ok((code, str::from_utf8(&out).unwrap().to_owned()))
+    }
+
+    #[test]
+    fn round_trip() {
+        let (code, token) = almond(
+            &["mint", "-g", "1", "-t", "login", "-c", "user erikj", "-c", "admin"]
+        ).unwrap();
+        assert_eq!(code, 0);
+        let token = token.trim();
+
+        let (code, inspected) = almond(&["inspect", token]).unwrap();
+        assert_eq!(code, 0);
+        assert!(inspected.contains("type:       login\n"));
+        assert!(inspected.contains("caveat:     user erikj\n"));
+        assert!(inspected.contains("caveat:     admin\n"));
+        assert!(inspected.contains("valid:      yes\n"));
+
+        let verify = ["verify", "-g", "1", "-t", "login", "--allow", "admin"];
+        let (code, out) = almond(&[&verify[..], &["--exact", "user erikj", token]].concat())
+            .unwrap();
+        assert_eq!((code, &out[..]), (0, "ok\n"));
+        let (code, out) = almond(&[&verify[..], &["--exact", "user bob", token]].concat())
+            .unwrap();
+        assert_eq!((code, &out[..]), (1, "rejected\n"));
+
+        let (code, attenuated) = almond(&["attenuate", "-c", "device phone", token]).unwrap();
+        assert_eq!(code, 0);
+        let (_, inspected) = almond(&["inspect", attenuated.trim()]).unwrap();
+        assert!(inspected.contains("caveat:     device phone\n"));
+    }
+
+    #[test]
+    fn invalid_caveats() {
+        let err = almond(&["mint", "-g", "1", "-t", "login", "-c", ""]).unwrap_err();
+        assert_eq!(err, "invalid caveat ``: caveat key is empty");
+        let err = almond(&["mint", "-g", "1", "-t", "login", "-c", " erikj"]).unwrap_err();
+        assert_eq!(err, "invalid caveat ` erikj`: caveat key is empty");
+        let err = almond(&["mint", "-g", "1", "-t", "login", "-c", "user erik\nj"]).unwrap_err();
+        assert_eq!(err, "invalid caveat `user erik\nj`: caveat value contains a newline");
+
+        let (_, token) = almond(&["mint", "-g", "1", "-t", "login"]).unwrap();
+        let err = almond(&["attenuate", "-c", "", token.trim()]).unwrap_err();
+        assert_eq!(err, "invalid caveat ``: caveat key is empty");
+    }
+}
@@ -0,0 +1,55 @@
+//! Caveats binding an almond to a request body or a TLS connection.
+//!
+//! Bindings are stored as the lowercase hex encoding of a SHA-256 digest:
+//!
+//! - `CONTENT_KEY` holds the digest of the request body, so e.g. an upload
+//!   token can only be used to upload specific content.
+//! - `CHANNEL_KEY` holds the digest of keying material exported from the TLS
+//!   connection (RFC 5705, or RFC 8446 for TLS 1.3), so a stolen almond can't
+//!   be replayed over another connection. Both sides must export with
+//!   `CHANNEL_EXPORTER_LABEL`, no context, and a length of 32 bytes.
+
+use rustc_serialize::hex::ToHex;
+
+use sha;
+
+
+/// The key of the caveats added by `Almond::add_content_binding`.
+pub const CONTENT_KEY : &'static [u8] = b"content_sha256";
+
+/// The key of the caveats added by `Almond::add_channel_binding`.
+pub const CHANNEL_KEY : &'static [u8] = b"channel_sha256";
+
+/// The label to use when exporting keying material for a channel binding.
+pub const CHANNEL_EXPORTER_LABEL : &'static str = "EXPORTER-almond-channel-binding";
+
+
+/// Compute the SHA-256 digest of a request body.
+pub fn content_digest(body: &[u8]) -> [u8; 32] {
+    sha::sha256(body)
+}
+
+/// Compute the digest of keying material exported from a TLS connection.
+pub fn channel_digest(exporter: &[u8]) -> [u8; 32] {
+    content_digest(exporter)
+}
+
+/// Encode a digest as a caveat value.
+pub fn encode_digest(digest: &[u8; 32]) -> Vec<u8> {
+    digest.to_hex().into_bytes()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digest() {
+        let digest = content_digest(b"abc");
+        assert_eq!(
+            encode_digest(&digest),
+            b"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_vec()
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use almond::check_caveat;
+use {Almond, CaveatError, CaveatValue, Format};
+
+use std::marker::PhantomData;
+
+
+/// The default maximum length of a caveat value, see
+/// `AlmondBuilder::max_value_len`.
+pub const DEFAULT_MAX_VALUE_LEN : usize = 1024;
+
+
+/// Builds an almond, checking each caveat as it is added.
+///
+/// `Almond::add_caveat` accepts any key and value, so e.g. a key containing
+/// a space produces an almond whose caveat is parsed back differently. The
+/// builder instead rejects empty keys, keys containing spaces or newlines,
+/// values containing newlines and overly long values when the caveat is
+/// added, so every almond it builds round trips in either format.
+///
+/// ```
+/// # use almonds::{AlmondBuilder, CaveatError};
+/// let mut builder = AlmondBuilder::new(1, b"access".to_vec());
+/// builder.caveat(b"user", Some(b"erikj")).unwrap();
+/// builder.typed_caveat(b"expires", 1500000000u64).unwrap();
+///
+/// assert_eq!(builder.caveat(b"is admin", None).err(), Some(CaveatError::InvalidKey));
+///
+/// let almond = builder.build(b"secret");
+/// assert_eq!(almond.caveats().count(), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AlmondBuilder {
+    generation: u32,
+    almond_type: Vec<u8>,
+    caveats: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    max_value_len: usize,
+}
+
+impl AlmondBuilder {
+    /// Create a new builder for almonds with the given generation and type.
+    pub fn new(generation: u32, almond_type: Vec<u8>) -> AlmondBuilder {
+        AlmondBuilder {
+            generation: generation,
+            almond_type: almond_type,
+            caveats: Vec::new(),
+            max_value_len: DEFAULT_MAX_VALUE_LEN,
+        }
+    }
+
+    /// Set the maximum length of caveat values. Defaults to
+    /// `DEFAULT_MAX_VALUE_LEN`.
+    pub fn max_value_len(&mut self, max: usize) -> &mut Self {
+        self.max_value_len = max;
+        self
+    }
+
+    /// Add a caveat, checking that it is well formed.
+    pub fn caveat(&mut self, key: &[u8], value: Option<&[u8]>)
+        -> Result<&mut Self, CaveatError>
+    {
+        try!(check_caveat(Format::V1, key, value));
+
+        if let Some(value) = value {
+            if value.len() > self.max_value_len {
+                return Err(CaveatError::ValueTooLong {
+                    len: value.len(),
+                    max: self.max_value_len,
+                });
+            }
+        }
+
+        self.caveats.push((key.to_vec(), value.map(|v| v.to_vec())));
+        Ok(self)
+    }
+
+    /// Add a caveat whose value is encoded with `CaveatValue`, checking that
+    /// it is well formed.
+    pub fn typed_caveat<'v, T>(&mut self, key: &[u8], value: T)
+        -> Result<&mut Self, CaveatError>
+        where T: CaveatValue<'v>
+    {
+        let mut encoded = Vec::new();
+        value.encode_value(&mut encoded);
+        self.caveat(key, Some(&encoded))
+    }
+
+    /// Get the number of caveats added so far.
+    pub fn len(&self) -> usize {
+        self.caveats.len()
+    }
+
+    /// Returns whether no caveats have been added.
+    pub fn is_empty(&self) -> bool {
+        self.caveats.is_empty()
+    }
+
+    /// Create the almond with `key`.
+    pub fn build(&self, key: &[u8]) -> Almond {
+        let mut almond = Almond::create(key, self.generation, self.almond_type.clone());
+        for &(ref caveat_key, ref value) in &self.caveats {
+            almond.add_caveat(caveat_key, value.as_ref().map(|v| &v[..]));
+        }
+        almond
+    }
+}
+
+
+/// The state of a `ScopedBuilder` before a subject caveat has been added.
+#[derive(Debug, Clone, Copy)]
+pub enum NoSubject {}
+
+/// The state of a `ScopedBuilder` after a subject caveat has been added.
+#[derive(Debug, Clone, Copy)]
+pub enum HasSubject {}
+
+
+/// Like `AlmondBuilder`, but `build` is only available once a caveat
+/// scoping the almond to an identity, e.g. a user, has been added with
+/// `subject`.
+///
+/// This prevents accidentally minting an almond that grants everything its
+/// type allows to whoever holds it.
+///
+/// ```
+/// # use almonds::ScopedBuilder;
+/// let almond = ScopedBuilder::new(1, b"access".to_vec())
+///     .typed_caveat(b"expires", 1500000000u64).unwrap()
+///     .subject(b"user", b"erikj").unwrap()
+///     .build(b"secret");
+/// assert_eq!(almond.caveat_str(b"user"), Some("erikj"));
+/// ```
+///
+/// Without a subject, it doesn't compile:
+///
+/// ```compile_fail
+/// # use almonds::ScopedBuilder;
+/// let almond = ScopedBuilder::new(1, b"access".to_vec())
+///     .typed_caveat(b"expires", 1500000000u64).unwrap()
+///     .build(b"secret");
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScopedBuilder<S> {
+    inner: AlmondBuilder,
+    state: PhantomData<S>,
+}
+
+impl ScopedBuilder<NoSubject> {
+    /// Create a new builder for almonds with the given generation and type.
+    pub fn new(generation: u32, almond_type: Vec<u8>) -> ScopedBuilder<NoSubject> {
+        ScopedBuilder {
+            inner: AlmondBuilder::new(generation, almond_type),
+            state: PhantomData,
+        }
+    }
+}
+
+impl <S> ScopedBuilder<S> {
+    /// See `AlmondBuilder::max_value_len`.
+    pub fn max_value_len(mut self, max: usize) -> ScopedBuilder<S> {
+        self.inner.max_value_len(max);
+        self
+    }
+
+    /// See `AlmondBuilder::caveat`.
+    pub fn caveat(mut self, key: &[u8], value: Option<&[u8]>)
+        -> Result<ScopedBuilder<S>, CaveatError>
+    {
+        try!(self.inner.caveat(key, value));
+        Ok(self)
+    }
+
+    /// See `AlmondBuilder::typed_caveat`.
+    pub fn typed_caveat<'v, T>(mut self, key: &[u8], value: T)
+        -> Result<ScopedBuilder<S>, CaveatError>
+        where T: CaveatValue<'v>
+    {
+        try!(self.inner.typed_caveat(key, value));
+        Ok(self)
+    }
+
+    /// Add a caveat scoping the almond to the identity `value`, e.g.
+    /// `subject(b"user", b"erikj")`. This allows the almond to be built.
+    ///
+    /// The value must not be empty.
+    pub fn subject(mut self, key: &[u8], value: &[u8])
+        -> Result<ScopedBuilder<HasSubject>, CaveatError>
+    {
+        if value.is_empty() {
+            return Err(CaveatError::EmptyValue);
+        }
+        try!(self.inner.caveat(key, Some(value)));
+        Ok(ScopedBuilder {
+            inner: self.inner,
+            state: PhantomData,
+        })
+    }
+}
+
+impl ScopedBuilder<HasSubject> {
+    /// Create the almond with `key`.
+    pub fn build(&self, key: &[u8]) -> Almond {
+        self.inner.build(key)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{AlmondBuilder, ScopedBuilder};
+    use {Almond, CaveatError};
+
+    #[test]
+    fn build() {
+        let key = b"this_is_a_secret";
+
+        let mut builder = AlmondBuilder::new(1, b"login".to_vec());
+        builder.caveat(b"user", Some(b"erikj")).unwrap()
+            .caveat(b"guest", None).unwrap()
+            .typed_caveat(b"uploads", 10u64).unwrap();
+        assert_eq!(builder.len(), 3);
+
+        let mut expected = Almond::create(key, 1, b"login".to_vec());
+        expected.add_caveat(b"user", Some(b"erikj"));
+        expected.add_caveat(b"guest", None);
+        expected.add_caveat(b"uploads", Some(b"10"));
+        assert_eq!(builder.build(key).serialize_base64(), expected.serialize_base64());
+    }
+
+    #[test]
+    fn invalid_caveats() {
+        let mut builder = AlmondBuilder::new(1, b"login".to_vec());
+        builder.max_value_len(4);
+
+        assert_eq!(builder.caveat(b"", None).err(), Some(CaveatError::EmptyKey));
+        assert_eq!(builder.caveat(b"a b", None).err(), Some(CaveatError::InvalidKey));
+        assert_eq!(builder.caveat(b"a\nb", None).err(), Some(CaveatError::InvalidKey));
+        assert_eq!(builder.caveat(b"a", Some(b"b\nc")).err(), Some(CaveatError::InvalidValue));
+        assert_eq!(
+            builder.caveat(b"a", Some(b"12345")).err(),
+            Some(CaveatError::ValueTooLong { len: 5, max: 4 })
+        );
+        assert!(builder.caveat(b"a", Some(b"1234")).is_ok());
+        assert!(builder.caveat(b"a", Some(b"b c")).is_ok());
+
+        assert_eq!(builder.len(), 2);
+    }
+
+    #[test]
+    fn scoped() {
+        let key = b"this_is_a_secret";
+
+        let builder = ScopedBuilder::new(1, b"login".to_vec())
+            .caveat(b"guest", None).unwrap();
+        assert_eq!(builder.clone().subject(b"user", b"").err(), Some(CaveatError::EmptyValue));
+        assert_eq!(
+            builder.clone().subject(b"user", b"erikj\nadmin").err(),
+            Some(CaveatError::InvalidValue)
+        );
+
+        let almond = builder.subject(b"user", b"erikj").unwrap()
+            .caveat(b"device", Some(b"phone")).unwrap()
+            .build(key);
+
+        let mut expected = Almond::create(key, 1, b"login".to_vec());
+        expected.add_caveat(b"guest", None);
+        expected.add_caveat(b"user", Some(b"erikj"));
+        expected.add_caveat(b"device", Some(b"phone"));
+        assert_eq!(almond.serialize_base64(), expected.serialize_base64());
+    }
+}
@@ -0,0 +1,233 @@
+//! A deterministic CBOR (RFC 8949) encoding of almonds.
+//!
+//! An almond is encoded as a four element array:
+//!
+//! ```text
+//! [hash: bytes, generation: uint, type: bytes, caveats: [* bytes]]
+//! ```
+//!
+//! Only definite lengths and the shortest encoding of each length and
+//! integer are used, so every almond has exactly one encoding. Anything else
+//! is rejected when parsing.
+
+use AlmondParseError;
+
+
+const UINT : u8 = 0;
+const BYTES : u8 = 2;
+const ARRAY : u8 = 4;
+
+
+/// The fields of a CBOR encoded almond.
+pub struct RawCbor<'a> {
+    pub hash: &'a [u8],
+    pub generation: u32,
+    pub almond_type: &'a [u8],
+    pub caveats: Vec<&'a [u8]>,
+}
+
+
+/// Append the encoding of an almond to `buf`.
+pub fn write<'c, I>(
+    buf: &mut Vec<u8>,
+    hash: &[u8],
+    generation: u32,
+    almond_type: &[u8],
+    caveats: I,
+) where I: Iterator<Item = &'c [u8]> + Clone {
+    write_head(buf, ARRAY, 4);
+    write_bytes(buf, hash);
+    write_head(buf, UINT, generation as u64);
+    write_bytes(buf, almond_type);
+
+    write_head(buf, ARRAY, caveats.clone().count() as u64);
+    for caveat in caveats {
+        write_bytes(buf, caveat);
+    }
+}
+
+/// Parse an encoded almond. This does not validate the hash.
+pub fn parse(input: &[u8]) -> Result<RawCbor, AlmondParseError> {
+    let mut rest = input;
+
+    if try!(read_head(&mut rest, ARRAY)) != 4 {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+
+    let hash = try!(read_bytes(&mut rest));
+    if hash.len() != 32 {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+
+    let generation = try!(read_head(&mut rest, UINT));
+    if generation > u32::max_value() as u64 {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+
+    let almond_type = try!(read_bytes(&mut rest));
+
+    // Each caveat takes at least one byte, which bounds the allocation.
+    let count = try!(read_head(&mut rest, ARRAY));
+    if count > rest.len() as u64 {
+        return Err(AlmondParseError::TooManyCaveats { count: count });
+    }
+
+    let mut caveats = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        caveats.push(try!(read_bytes(&mut rest)));
+    }
+
+    if !rest.is_empty() {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+
+    Ok(RawCbor {
+        hash: hash,
+        generation: generation as u32,
+        almond_type: almond_type,
+        caveats: caveats,
+    })
+}
+
+
+fn write_head(buf: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+
+    if value < 24 {
+        buf.push(major | value as u8);
+    } else if value <= 0xFF {
+        buf.push(major | 24);
+        buf.push(value as u8);
+    } else if value <= 0xFFFF {
+        buf.push(major | 25);
+        write_be(buf, value, 2);
+    } else if value <= 0xFFFF_FFFF {
+        buf.push(major | 26);
+        write_be(buf, value, 4);
+    } else {
+        buf.push(major | 27);
+        write_be(buf, value, 8);
+    }
+}
+
+fn write_be(buf: &mut Vec<u8>, value: u64, len: usize) {
+    for idx in (0..len).rev() {
+        buf.push((value >> (8 * idx)) as u8);
+    }
+}
+
+fn write_bytes(buf: &mut Vec<u8>, data: &[u8]) {
+    write_head(buf, BYTES, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+/// Read the head of an item of the given major type, returning its argument.
+fn read_head(input: &mut &[u8], major: u8) -> Result<u64, AlmondParseError> {
+    let initial = match input.first() {
+        Some(initial) => *initial,
+        None => return Err(AlmondParseError::InvalidAlmond),
+    };
+
+    if initial >> 5 != major {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+
+    let (len, min) = match initial & 0x1F {
+        info if info < 24 => {
+            *input = &input[1..];
+            return Ok(info as u64);
+        }
+        24 => (1, 24),
+        25 => (2, 0x100),
+        26 => (4, 0x1_0000),
+        27 => (8, 0x1_0000_0000),
+        // Reserved values and indefinite lengths.
+        _ => return Err(AlmondParseError::InvalidAlmond),
+    };
+
+    if input.len() < 1 + len {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+
+    let value = input[1..1 + len].iter().fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+    *input = &input[1 + len..];
+
+    if value < min {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+
+    Ok(value)
+}
+
+fn read_bytes<'a>(input: &mut &'a [u8]) -> Result<&'a [u8], AlmondParseError> {
+    let len = try!(read_head(input, BYTES));
+    if len > input.len() as u64 {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+
+    let (data, rest) = input.split_at(len as usize);
+    *input = rest;
+    Ok(data)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let hash = [7u8; 32];
+        let caveats: &[&[u8]] = &[b"user erikj", b"admin"];
+
+        let mut buf = Vec::new();
+        write(&mut buf, &hash, 1000, b"login", caveats.iter().cloned());
+
+        let mut expected = vec![0x84, 0x58, 0x20];
+        expected.extend_from_slice(&hash);
+        expected.extend_from_slice(b"\x19\x03\xe8\x45login\x82\x4auser erikj\x45admin");
+        assert_eq!(buf, expected);
+
+        let raw = parse(&buf).unwrap();
+        assert_eq!(raw.hash, &hash[..]);
+        assert_eq!(raw.generation, 1000);
+        assert_eq!(raw.almond_type, b"login");
+        assert_eq!(raw.caveats, caveats.to_vec());
+    }
+
+    #[test]
+    fn invalid() {
+        let mut valid = Vec::new();
+        write(&mut valid, &[0u8; 32], 1, b"login", [&b"admin"[..]].iter().cloned());
+        assert!(parse(&valid).is_ok());
+
+        let mut trailing = valid.clone();
+        trailing.push(0);
+
+        let mut long_generation = vec![0x84, 0x58, 0x20];
+        long_generation.extend_from_slice(&[0u8; 32]);
+        long_generation.extend_from_slice(b"\x18\x01\x45login\x80");
+
+        let mut indefinite = vec![0x84, 0x58, 0x20];
+        indefinite.extend_from_slice(&[0u8; 32]);
+        indefinite.extend_from_slice(b"\x01\x45login\x9f\xff");
+
+        let mut big_generation = vec![0x84, 0x58, 0x20];
+        big_generation.extend_from_slice(&[0u8; 32]);
+        big_generation.extend_from_slice(b"\x1b\x00\x00\x00\x01\x00\x00\x00\x00\x45login\x80");
+
+        let inputs: Vec<&[u8]> = vec![
+            b"",
+            b"\x83",
+            &valid[..valid.len() - 1],
+            &trailing,
+            &long_generation,
+            &indefinite,
+            &big_generation,
+        ];
+
+        for input in inputs {
+            assert!(parse(input).is_err(), "{:?}", input);
+        }
+    }
+}
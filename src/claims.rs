@@ -0,0 +1,199 @@
+//! A typed claims layer over caveats.
+//!
+//! A struct implementing `AlmondCaveats` maps each of its fields to a caveat
+//! with the same key, encoded with `CaveatValue`. Fields of type `Option<T>`
+//! are optional caveats. The trait is usually derived with the
+//! `#[derive(AlmondCaveats)]` macro from the `almond-derive` crate:
+//!
+//! ```ignore
+//! #[macro_use] extern crate almond_derive;
+//! extern crate almonds;
+//!
+//! use almonds::claims::AlmondCaveats;
+//!
+//! #[derive(AlmondCaveats)]
+//! struct Claims {
+//!     user: String,
+//!     expires: u64,
+//!     org: Option<String>,
+//! }
+//! ```
+//!
+//! *Note: `extract_from` does not check that the caveats are satisfied.
+//! Prefer `Verifier::verify_into`, which only extracts the claims once the
+//! almond has been verified.*
+
+use {Almond, CaveatValue};
+
+
+quick_error! {
+    /// An error returned when claims can't be extracted from an almond.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum ClaimsError {
+        /// A required caveat is missing, or has no value.
+        Missing { key: String } {
+            display("caveat {:?} is missing", key)
+        }
+
+        /// The value of a caveat couldn't be decoded.
+        Invalid { key: String } {
+            display("caveat {:?} has an invalid value", key)
+        }
+
+        /// The almond has more than one caveat with the key, e.g. because it
+        /// was attenuated, so there isn't a single value for the claim.
+        Duplicate { key: String } {
+            display("caveat {:?} appears more than once", key)
+        }
+    }
+}
+
+
+/// A type that can be added to and extracted from the caveats of an almond.
+pub trait AlmondCaveats: Sized {
+    /// Add a caveat for each claim to the almond.
+    fn add_to(&self, almond: &mut Almond);
+
+    /// Extract the claims from a list of caveats.
+    fn extract(caveats: &CaveatList) -> Result<Self, ClaimsError>;
+
+    /// Extract the claims from the caveats of the almond.
+    fn extract_from(almond: &Almond) -> Result<Self, ClaimsError> {
+        Self::extract(&CaveatList::from(almond))
+    }
+}
+
+/// No claims, for when only the caveats need to be verified.
+impl AlmondCaveats for () {
+    fn add_to(&self, _almond: &mut Almond) {}
+
+    fn extract(_caveats: &CaveatList) -> Result<(), ClaimsError> {
+        Ok(())
+    }
+}
+
+
+/// The keys and values of a list of caveats, from which claims are
+/// extracted.
+#[derive(Default)]
+pub struct CaveatList<'c> {
+    caveats: Vec<(&'c [u8], Option<&'c [u8]>)>,
+}
+
+impl <'c> CaveatList<'c> {
+    /// Create a new, empty list.
+    pub fn new() -> CaveatList<'c> {
+        CaveatList { caveats: Vec::new() }
+    }
+
+    /// Add a caveat to the list.
+    pub fn push(&mut self, key: &'c [u8], value: Option<&'c [u8]>) {
+        self.caveats.push((key, value));
+    }
+
+    /// Get the value of the caveat with the given key, if there is one.
+    ///
+    /// Unlike `Almond::caveat_typed`, this distinguishes values that can't be
+    /// decoded from missing values, and rejects keys that appear more than
+    /// once.
+    ///
+    /// ```
+    /// # use almonds::Almond;
+    /// use almonds::claims::CaveatList;
+    ///
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// almond.add_caveat(b"expires", Some(b"soon"));
+    ///
+    /// let caveats = CaveatList::from(&almond);
+    /// assert_eq!(caveats.claim::<&str>("user"), Ok(Some("erikj")));
+    /// assert_eq!(caveats.claim::<&str>("org"), Ok(None));
+    /// assert!(caveats.claim::<u64>("expires").is_err());
+    /// ```
+    pub fn claim<T>(&self, key: &str) -> Result<Option<T>, ClaimsError>
+        where T: CaveatValue<'c>
+    {
+        let mut values = self.caveats.iter()
+            .filter(|&&(k, _)| k == key.as_bytes())
+            .map(|&(_, value)| value);
+
+        let value = match values.next() {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        if values.next().is_some() {
+            return Err(ClaimsError::Duplicate { key: key.to_owned() });
+        }
+
+        match value {
+            Some(value) => match T::decode_value(value) {
+                Some(value) => Ok(Some(value)),
+                None => Err(ClaimsError::Invalid { key: key.to_owned() }),
+            },
+            None => Err(ClaimsError::Missing { key: key.to_owned() }),
+        }
+    }
+}
+
+impl <'c> From<&'c Almond> for CaveatList<'c> {
+    fn from(almond: &'c Almond) -> CaveatList<'c> {
+        CaveatList { caveats: almond.iter_caveats().map(|c| c.pair()).collect() }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Almond;
+
+    struct Claims {
+        user: String,
+        expires: u64,
+        org: Option<String>,
+    }
+
+    impl AlmondCaveats for Claims {
+        fn add_to(&self, almond: &mut Almond) {
+            almond.add_caveat_typed(b"user", &self.user[..]);
+            almond.add_caveat_typed(b"expires", self.expires);
+            if let Some(ref org) = self.org {
+                almond.add_caveat_typed(b"org", &org[..]);
+            }
+        }
+
+        fn extract(caveats: &CaveatList) -> Result<Claims, ClaimsError> {
+            let missing = |key: &str| ClaimsError::Missing { key: key.to_owned() };
+            Ok(Claims {
+                user: try!(try!(caveats.claim("user")).ok_or(missing("user"))),
+                expires: try!(try!(caveats.claim("expires")).ok_or(missing("expires"))),
+                org: try!(caveats.claim("org")),
+            })
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let claims = Claims { user: "erikj".to_owned(), expires: 1500000000, org: None };
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        claims.add_to(&mut almond);
+
+        let extracted = Claims::extract_from(&almond).unwrap();
+        assert_eq!(extracted.user, "erikj");
+        assert_eq!(extracted.expires, 1500000000);
+        assert_eq!(extracted.org, None);
+
+        almond.add_caveat(b"user", Some(b"bob"));
+        match Claims::extract_from(&almond) {
+            Err(ClaimsError::Duplicate { ref key }) if key == "user" => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+
+        let almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        match Claims::extract_from(&almond) {
+            Err(ClaimsError::Missing { ref key }) if key == "user" => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Sources of the current time for time based caveats.
+//!
+//! A `Verifier` uses the `SystemClock` by default. Tests can instead use a
+//! `MockClock` to check behaviour at a fixed point in time:
+//!
+//! ```
+//! # use std::time::{Duration, UNIX_EPOCH};
+//! # use almonds::{Almond, Verifier};
+//! # use almonds::clock::MockClock;
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_expiry(UNIX_EPOCH + Duration::from_secs(1500000000));
+//!
+//! let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1400000000));
+//!
+//! let mut v = Verifier::new(&almond, 1, b"access");
+//! v.set_clock(&clock);
+//! v.satisfies_expiry();
+//! assert!(v.verify());
+//! ```
+
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+
+/// A source of the current time.
+pub trait Clock {
+    /// Get the current time.
+    fn now(&self) -> SystemTime;
+}
+
+impl <'c, C: Clock + ?Sized> Clock for &'c C {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+}
+
+
+/// A clock that uses the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+
+/// A clock that only changes when told to, for use in tests.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<SystemTime>,
+}
+
+impl MockClock {
+    /// Create a clock set to the given time.
+    pub fn new(now: SystemTime) -> MockClock {
+        MockClock { now: Mutex::new(now) }
+    }
+
+    /// Set the current time.
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    /// Move the current time forward.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn mock_clock() {
+        let clock = MockClock::new(UNIX_EPOCH);
+        assert_eq!(clock.now(), UNIX_EPOCH);
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!((&clock).now(), UNIX_EPOCH + Duration::from_secs(10));
+
+        clock.set(UNIX_EPOCH + Duration::from_secs(5));
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_secs(5));
+    }
+}
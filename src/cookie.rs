@@ -0,0 +1,279 @@
+//! Helpers for storing almonds in cookies, e.g. as session cookies.
+//!
+//! Almonds are encoded with the URL safe Base64 alphabet without padding,
+//! which needs no escaping in a cookie value.
+//!
+//! ```
+//! # use almonds::Almond;
+//! use almonds::cookie::{from_cookie_header, to_cookie, CookieAttributes};
+//!
+//! let mut almond = Almond::create(b"secret", 1, b"session".to_vec());
+//! almond.add_caveat(b"user", Some(b"erikj"));
+//!
+//! let set_cookie = to_cookie("session", &almond, &CookieAttributes::default()).unwrap();
+//! assert!(set_cookie.ends_with("; Path=/; Secure; HttpOnly; SameSite=Lax"));
+//!
+//! // The browser sends back `name=value` pairs.
+//! let header = format!("theme=dark; {}", set_cookie.split(';').next().unwrap());
+//! let parsed = from_cookie_header(b"secret", &header, "session").unwrap();
+//! assert_eq!(parsed, almond);
+//! ```
+
+use std::fmt::Write;
+
+use {Almond, AlmondParseError, Base64Config};
+
+
+/// The maximum length of a `Set-Cookie` header produced by `to_cookie`.
+/// Browsers are only required to store cookies up to this long.
+pub const MAX_COOKIE_LEN : usize = 4096;
+
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    /// Only sent with requests from the same site.
+    Strict,
+    /// Also sent when navigating to the site from another one.
+    Lax,
+    /// Sent with all requests. Requires `secure`.
+    None,
+}
+
+
+/// The attributes of a cookie.
+///
+/// The defaults are `Path=/`, `Secure`, `HttpOnly` and `SameSite=Lax`, with
+/// no `Domain` or `Max-Age`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookieAttributes {
+    /// The `Path` attribute.
+    pub path: Option<String>,
+    /// The `Domain` attribute.
+    pub domain: Option<String>,
+    /// The `Max-Age` attribute in seconds, e.g. the lifetime of the almond.
+    /// Without one the cookie is deleted when the browser is closed.
+    pub max_age: Option<u64>,
+    /// Whether the cookie is only sent over HTTPS.
+    pub secure: bool,
+    /// Whether the cookie is hidden from scripts.
+    pub http_only: bool,
+    /// The `SameSite` attribute.
+    pub same_site: SameSite,
+}
+
+impl Default for CookieAttributes {
+    fn default() -> CookieAttributes {
+        CookieAttributes {
+            path: Some("/".to_owned()),
+            domain: None,
+            max_age: None,
+            secure: true,
+            http_only: true,
+            same_site: SameSite::Lax,
+        }
+    }
+}
+
+
+quick_error! {
+    /// An error returned by the cookie helpers.
+    #[derive(Debug)]
+    pub enum CookieError {
+        /// The cookie would be longer than `MAX_COOKIE_LEN`.
+        TooLong { len: usize } {
+            display("cookie is {} bytes, longer than {}", len, MAX_COOKIE_LEN)
+        }
+
+        /// The name is empty or contains characters not allowed in cookie
+        /// names.
+        InvalidName {
+            display("invalid cookie name")
+        }
+
+        /// The path or domain contains characters not allowed in cookie
+        /// attributes.
+        InvalidAttribute {
+            display("invalid cookie attribute")
+        }
+
+        /// `SameSite=None` was used without `secure`, which browsers reject.
+        InsecureSameSite {
+            display("SameSite=None requires Secure")
+        }
+
+        /// The header has no cookie with the name.
+        Missing {
+            display("no cookie with the name")
+        }
+
+        /// None of the cookies with the name held a valid almond. This is the
+        /// error for the first of them.
+        Parse(err: AlmondParseError) {
+            from()
+            display("invalid almond: {}", err)
+            cause(err)
+        }
+    }
+}
+
+
+/// Serialize an almond as the value of a `Set-Cookie` header.
+pub fn to_cookie(name: &str, almond: &Almond, attributes: &CookieAttributes)
+    -> Result<String, CookieError>
+{
+    if name.is_empty() || !name.bytes().all(is_token_char) {
+        return Err(CookieError::InvalidName);
+    }
+    if attributes.same_site == SameSite::None && !attributes.secure {
+        return Err(CookieError::InsecureSameSite);
+    }
+
+    let mut cookie = format!(
+        "{}={}", name, almond.serialize_base64_with(Base64Config::URL_SAFE_NO_PAD)
+    );
+
+    // Writing to a `String` can't fail.
+    if let Some(ref path) = attributes.path {
+        try!(check_attribute(path));
+        write!(cookie, "; Path={}", path).unwrap();
+    }
+    if let Some(ref domain) = attributes.domain {
+        try!(check_attribute(domain));
+        write!(cookie, "; Domain={}", domain).unwrap();
+    }
+    if let Some(max_age) = attributes.max_age {
+        write!(cookie, "; Max-Age={}", max_age).unwrap();
+    }
+    if attributes.secure {
+        cookie.push_str("; Secure");
+    }
+    if attributes.http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    cookie.push_str(match attributes.same_site {
+        SameSite::Strict => "; SameSite=Strict",
+        SameSite::Lax => "; SameSite=Lax",
+        SameSite::None => "; SameSite=None",
+    });
+
+    if cookie.len() > MAX_COOKIE_LEN {
+        return Err(CookieError::TooLong { len: cookie.len() });
+    }
+
+    Ok(cookie)
+}
+
+/// Find the almond in the cookie called `name` in a `Cookie` header, and
+/// validate it with `key`.
+///
+/// A header can have several cookies with the same name, e.g. if one was
+/// set for a parent domain. The first that validates is returned.
+pub fn from_cookie_header(key: &[u8], header: &str, name: &str)
+    -> Result<Almond, CookieError>
+{
+    let mut first_err = None;
+
+    for pair in header.split(';') {
+        let (cookie_name, value) = match pair.find('=') {
+            Some(idx) => (pair[..idx].trim(), &pair[idx + 1..]),
+            None => continue,
+        };
+        if cookie_name != name {
+            continue;
+        }
+
+        let value = value.trim().trim_matches('"');
+        match Almond::parse_base64_and_validate(key, value.as_bytes()) {
+            Ok(almond) => return Ok(almond),
+            Err(err) => if first_err.is_none() {
+                first_err = Some(err);
+            },
+        }
+    }
+
+    Err(first_err.map(CookieError::Parse).unwrap_or(CookieError::Missing))
+}
+
+
+/// Whether the byte is allowed in a cookie name, i.e. is a `token`
+/// character in RFC 2616.
+fn is_token_char(c: u8) -> bool {
+    c > 0x20 && c < 0x7f && !b"()<>@,;:\\\"/[]?={}".contains(&c)
+}
+
+fn check_attribute(value: &str) -> Result<(), CookieError> {
+    if value.bytes().all(|c| c >= 0x20 && c < 0x7f && c != b';') {
+        Ok(())
+    } else {
+        Err(CookieError::InvalidAttribute)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Almond, AlmondParseError};
+
+    #[test]
+    fn cookies() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"session".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let value = almond.serialize_base64();
+
+        let attributes = CookieAttributes {
+            path: None,
+            domain: Some("example.com".to_owned()),
+            max_age: Some(3600),
+            secure: true,
+            http_only: false,
+            same_site: SameSite::None,
+        };
+        assert_eq!(
+            to_cookie("sid", &almond, &attributes).unwrap(),
+            format!("sid={}; Domain=example.com; Max-Age=3600; Secure; SameSite=None", value)
+        );
+
+        let insecure = CookieAttributes { secure: false, ..attributes.clone() };
+        match to_cookie("sid", &almond, &insecure) {
+            Err(CookieError::InsecureSameSite) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let invalid = CookieAttributes { domain: Some("a; b".to_owned()), ..attributes };
+        match to_cookie("sid", &almond, &invalid) {
+            Err(CookieError::InvalidAttribute) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let defaults = CookieAttributes::default();
+        for name in &["", "s id", "s;id"] {
+            match to_cookie(name, &almond, &defaults) {
+                Err(CookieError::InvalidName) => {}
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+
+        let mut large = almond.clone();
+        large.add_caveat(b"data", Some(&[b'a'; MAX_COOKIE_LEN]));
+        match to_cookie("sid", &large, &defaults) {
+            Err(CookieError::TooLong { len }) => assert!(len > MAX_COOKIE_LEN),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let other = Almond::create(b"other", 1, b"session".to_vec()).serialize_base64();
+        let header = format!("sid={}; theme=dark;sid=\"{}\" ", other, value);
+        assert_eq!(from_cookie_header(key, &header, "sid").unwrap(), almond);
+
+        match from_cookie_header(key, "theme=dark", "sid") {
+            Err(CookieError::Missing) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match from_cookie_header(key, &format!("sid={}", other), "sid") {
+            Err(CookieError::Parse(AlmondParseError::IncorrectHash)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
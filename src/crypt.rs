@@ -0,0 +1,135 @@
+//! Authenticated encryption, for encrypted caveats and encrypted almonds.
+//!
+//! Values are encrypted with ChaCha20-Poly1305, with a random 24 byte nonce
+//! per value. Like XChaCha20, the first 16 bytes of the nonce derive a
+//! one-off key, here with HMAC-SHA256, and the last 8 bytes are the
+//! ChaCha20 nonce. This makes random nonces safe for any number of values.
+
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use rand::{OsRng, Rng};
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
+
+use sha;
+
+
+/// The length of the nonce prepended to the ciphertext.
+pub const NONCE_LEN : usize = 24;
+
+/// The length of the tag appended to the ciphertext.
+pub const TAG_LEN : usize = 16;
+
+
+/// Derive a key for a purpose, described by `info`, from a secret.
+pub fn derive_key(secret: &[u8], info: &[u8]) -> [u8; 32] {
+    sha::hmac_sha256(secret, info)
+}
+
+/// Derive the key used for encrypted caveats of almonds with the given
+/// generation and type.
+///
+/// The generation is encoded at a fixed width, so that it can't run into
+/// the type.
+pub fn caveat_key(secret: &[u8], generation: u32, almond_type: &[u8]) -> [u8; 32] {
+    let mut info = b"almond caveat encryption\x00".to_vec();
+    info.extend_from_slice(&generation.to_be_bytes());
+    info.extend_from_slice(almond_type);
+    derive_key(secret, &info)
+}
+
+/// Derive the key used to encrypt whole almonds, see
+/// `Almond::seal_base64`.
+pub fn token_key(secret: &[u8]) -> [u8; 32] {
+    derive_key(secret, b"almond token encryption\x00")
+}
+
+/// Encrypt the value of the caveat with the given key, as URL safe Base64.
+pub fn encrypt_caveat(key: &[u8; 32], caveat_key: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    encrypt(key, caveat_key, plaintext).to_base64(base64::URL_SAFE).into_bytes()
+}
+
+/// Decrypt the output of `encrypt_caveat`.
+pub fn decrypt_caveat(key: &[u8; 32], caveat_key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+    value.from_base64().ok().and_then(|sealed| decrypt(key, caveat_key, &sealed))
+}
+
+/// Encrypt `plaintext`, returning the nonce, ciphertext and tag.
+///
+/// `aad` is authenticated but not encrypted, and must be given again to
+/// decrypt.
+pub fn encrypt(key: &[u8; 32], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut rng = OsRng::new().expect("failed to access the OS random number generator");
+
+    let mut out = vec![0u8; NONCE_LEN + plaintext.len() + TAG_LEN];
+    rng.fill_bytes(&mut out[..NONCE_LEN]);
+
+    let (nonce, rest) = out.split_at_mut(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at_mut(plaintext.len());
+    cipher(key, nonce, aad).encrypt(plaintext, ciphertext, tag);
+
+    out
+}
+
+/// Decrypt the output of `encrypt`, returning `None` if it was encrypted
+/// with a different key or `aad`, or has been modified.
+pub fn decrypt(key: &[u8; 32], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return None;
+    }
+
+    let (nonce, rest) = sealed.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+
+    let mut plaintext = vec![0u8; ciphertext.len()];
+    if cipher(key, nonce, aad).decrypt(ciphertext, &mut plaintext, tag) {
+        Some(plaintext)
+    } else {
+        None
+    }
+}
+
+fn cipher(key: &[u8; 32], nonce: &[u8], aad: &[u8]) -> ChaCha20Poly1305 {
+    let subkey = sha::hmac_sha256(key, &nonce[..16]);
+    ChaCha20Poly1305::new(&subkey, &nonce[16..], aad)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let key = derive_key(b"this_is_a_secret", b"test");
+        let other = derive_key(b"this_is_a_secret", b"other");
+
+        let sealed = encrypt(&key, b"aad", b"hello");
+        assert_eq!(sealed.len(), NONCE_LEN + 5 + TAG_LEN);
+        assert_eq!(decrypt(&key, b"aad", &sealed), Some(b"hello".to_vec()));
+
+        // Nonces are random, so the same plaintext encrypts differently.
+        assert!(encrypt(&key, b"aad", b"hello") != sealed);
+
+        assert_eq!(decrypt(&other, b"aad", &sealed), None);
+        assert_eq!(decrypt(&key, b"other", &sealed), None);
+        assert_eq!(decrypt(&key, b"aad", &sealed[..NONCE_LEN + TAG_LEN - 1]), None);
+
+        let mut modified = sealed.clone();
+        modified[NONCE_LEN] ^= 1;
+        assert_eq!(decrypt(&key, b"aad", &modified), None);
+
+        let empty = encrypt(&key, b"", b"");
+        assert_eq!(decrypt(&key, b"", &empty), Some(vec![]));
+    }
+
+    #[test]
+    fn caveat_keys() {
+        let secret = b"this_is_a_secret";
+        let key = caveat_key(secret, 258, b"abc");
+
+        assert_eq!(caveat_key(secret, 258, b"abc"), key);
+        assert!(caveat_key(secret, 130, b"\x02abc") != key);
+        assert!(caveat_key(secret, 258, b"abd") != key);
+        assert!(caveat_key(b"other", 258, b"abc") != key);
+    }
+}
@@ -0,0 +1,86 @@
+//! Constant time comparisons.
+
+use std::fmt;
+
+use crypto::util::fixed_time_eq;
+
+
+/// Compare two byte strings in time that depends only on their lengths.
+///
+/// Use this rather than `==` when comparing serialized almonds, hashes, or
+/// any other secret values, so that the comparison doesn't reveal how many
+/// leading bytes matched.
+///
+/// ```
+/// # use almonds::ct_eq;
+/// assert!(ct_eq(b"secret", b"secret"));
+/// assert!(!ct_eq(b"secret", b"secreT"));
+/// assert!(!ct_eq(b"secret", b"secrets"));
+/// assert!(ct_eq(b"", b""));
+/// ```
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    // `fixed_time_eq` reads the first byte even if the slices are empty.
+    a.len() == b.len() && (a.is_empty() || fixed_time_eq(a, b))
+}
+
+
+/// The hash of an almond, which can only be compared in constant time.
+///
+/// ```
+/// # use almonds::Almond;
+/// let almond = Almond::create(b"secret", 1, b"login".to_vec());
+/// let parsed = Almond::parse_and_validate(b"secret", &almond.serialize_binary()).unwrap();
+/// assert!(parsed.hash() == almond.hash());
+/// ```
+#[derive(Clone, Copy)]
+pub struct HashTag {
+    bytes: [u8; 32],
+}
+
+impl HashTag {
+    /// Get the bytes of the hash, e.g. to serialize it.
+    ///
+    /// Do not compare these directly with other hashes, as that may leak
+    /// timing information. Compare `HashTag`s or use `ct_eq` instead.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.bytes
+    }
+}
+
+impl From<[u8; 32]> for HashTag {
+    fn from(bytes: [u8; 32]) -> HashTag {
+        HashTag { bytes: bytes }
+    }
+}
+
+impl PartialEq for HashTag {
+    fn eq(&self, other: &HashTag) -> bool {
+        ct_eq(&self.bytes, &other.bytes)
+    }
+}
+
+impl Eq for HashTag {}
+
+impl fmt::Debug for HashTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("HashTag(<redacted>)")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_tag() {
+        let a = HashTag::from([1u8; 32]);
+        let mut bytes = [1u8; 32];
+        bytes[31] = 2;
+
+        assert_eq!(a, HashTag::from([1u8; 32]));
+        assert!(a != HashTag::from(bytes));
+        assert_eq!(a.as_bytes(), &[1u8; 32]);
+        assert_eq!(format!("{:?}", a), "HashTag(<redacted>)");
+    }
+}
@@ -0,0 +1,156 @@
+//! Compression of the caveats of v2 almonds, enabled by the `deflate`
+//! feature.
+//!
+//! Almonds with many similar caveats, e.g. lots of `scope` caveats, can be
+//! serialized with the caveats compressed with DEFLATE. This is marked by
+//! the `FLAG_DEFLATE` flag in the v2 header, and as the hash covers the
+//! uncompressed caveats a compressed almond validates with the same key.
+//!
+//! ```
+//! # use almonds::Almond;
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! for repo in 0..20 {
+//!     almond.add_caveat(b"scope", Some(format!("repo:erikj/project{}:read", repo).as_bytes()));
+//! }
+//!
+//! let compressed = almond.serialize_deflated();
+//! assert!(compressed.len() < almond.serialize_binary().len());
+//!
+//! let parsed = Almond::parse_deflated_and_validate(b"secret", &compressed).unwrap();
+//! assert!(parsed.caveats().eq(almond.caveats()));
+//! ```
+
+use std::io::Read;
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use almond::MAX_READER_LEN;
+use format::{self, FLAG_DEFLATE};
+use {Almond, AlmondParseError};
+
+
+impl Almond {
+    /// Serialize into the v2 format with the caveats compressed.
+    ///
+    /// This ignores the format set with `set_format`. The output is only
+    /// accepted by `parse_deflated_and_validate`.
+    pub fn serialize_deflated(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        // Writing to a `Vec` can't fail.
+        format::write_compressed_header(
+            &mut buf, self.hash().as_bytes(), self.generation(), self.key_id(), self.almond_type()
+        ).expect("failed to write to Vec");
+
+        let mut encoder = DeflateEncoder::new(buf, Compression::best());
+        format::write_caveats(&mut encoder, self.caveats()).expect("failed to write to Vec");
+        encoder.finish().expect("failed to write to Vec")
+    }
+
+    /// Parse a binary serialized Almond whose caveats may be compressed, and
+    /// validate that the hashes match.
+    ///
+    /// Uncompressed almonds are parsed as with `parse_and_validate`. The
+    /// decompressed almond is limited to `MAX_READER_LEN` bytes.
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_deflated_and_validate(key: &[u8], input: &[u8])
+        -> Result<Almond, AlmondParseError>
+    {
+        if input.len() > MAX_READER_LEN {
+            return Err(AlmondParseError::TooLong { max: MAX_READER_LEN });
+        }
+
+        let header_len = match try!(format::compressed_header_len(input)) {
+            Some(header_len) => header_len,
+            None => return Almond::parse_and_validate(key, input),
+        };
+
+        let mut expanded = input[..header_len].to_vec();
+        expanded[34] &= !FLAG_DEFLATE;
+
+        // Stop decompressing as soon as the output is too long, rather than
+        // trusting the input.
+        let limit = (MAX_READER_LEN + 1 - header_len) as u64;
+        let mut decoder = DeflateDecoder::new(&input[header_len..]).take(limit);
+        try!(
+            decoder.read_to_end(&mut expanded).map_err(|_| AlmondParseError::InvalidCompression)
+        );
+        if expanded.len() > MAX_READER_LEN {
+            return Err(AlmondParseError::TooLong { max: MAX_READER_LEN });
+        }
+
+        Almond::parse_and_validate(key, &expanded)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::DeflateEncoder;
+
+    use almond::MAX_READER_LEN;
+    use format;
+    use {Almond, AlmondParseError};
+
+    #[test]
+    fn round_trip() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create_with_key_id(key, b"k1".to_vec(), 300, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"data", Some(b"a\nb"));
+
+        let compressed = almond.serialize_deflated();
+        let parsed = Almond::parse_deflated_and_validate(key, &compressed).unwrap();
+        assert!(parsed.caveats().eq(almond.caveats()));
+        assert_eq!(parsed.key_id(), Some(&b"k1"[..]));
+        assert_eq!(parsed.generation(), 300);
+        assert_eq!(parsed.serialize_binary(), almond.serialize_binary());
+
+        // Uncompressed almonds are accepted, but the other parsers don't
+        // accept compressed ones.
+        let serialized = almond.serialize_binary();
+        assert!(Almond::parse_deflated_and_validate(key, &serialized).is_ok());
+        match Almond::parse_and_validate(key, &compressed) {
+            Err(AlmondParseError::UnknownFlags { flags }) if flags == format::FLAG_DEFLATE => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let key = b"this_is_a_secret";
+
+        let almond = Almond::create(key, 1, b"login".to_vec());
+        let compressed = almond.serialize_deflated();
+        let header_len = format::compressed_header_len(&compressed).unwrap().unwrap();
+
+        let mut corrupt = compressed[..header_len].to_vec();
+        corrupt.extend_from_slice(b"\xff\xff\xff\xff");
+        match Almond::parse_deflated_and_validate(key, &corrupt) {
+            Err(AlmondParseError::InvalidCompression) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        // A small input that decompresses to more than the limit.
+        let mut encoder = DeflateEncoder::new(compressed[..header_len].to_vec(), Compression::best());
+        let caveat = [0u8; 1024];
+        for _ in 0..(MAX_READER_LEN / caveat.len() + 1) {
+            encoder.write_all(&[0x80, 0x08]).unwrap();
+            encoder.write_all(&caveat).unwrap();
+        }
+        let bomb = encoder.finish().unwrap();
+        assert!(bomb.len() < MAX_READER_LEN);
+
+        match Almond::parse_deflated_and_validate(key, &bomb) {
+            Err(AlmondParseError::TooLong { .. }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+}
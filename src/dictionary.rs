@@ -0,0 +1,235 @@
+//! Dictionary compression of caveat keys.
+//!
+//! The v3 format is the v2 format with the key of each caveat replaced by a
+//! single byte code from a `KeyDictionary`, so `expires 1500000000` costs
+//! eight bytes less. The dictionary starts with the keys used by this crate,
+//! e.g. `user`, `expires`, `scope` and `audience`, and applications can add
+//! their own.
+//!
+//! The hash covers the caveats rather than their encoding, so a v3 almond
+//! validates with the same key as its v1 or v2 serialization. The validator
+//! must use the same dictionary as the minter, with the same application
+//! keys added in the same order.
+//!
+//! ```
+//! # use almonds::Almond;
+//! use almonds::dictionary::KeyDictionary;
+//!
+//! let mut dictionary = KeyDictionary::new();
+//! dictionary.add(b"device".to_vec()).unwrap();
+//!
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_caveat(b"user", Some(b"erikj"));
+//! almond.add_caveat(b"device", Some(b"phone"));
+//!
+//! let compressed = almond.serialize_with_dictionary(&dictionary);
+//! assert!(compressed.len() < almond.serialize_binary().len());
+//!
+//! let parsed = Almond::parse_and_validate_with_dictionary(
+//!     b"secret", &compressed, &dictionary
+//! ).unwrap();
+//! assert!(parsed.caveats().eq(almond.caveats()));
+//! ```
+
+use almond::{check_caveat, CaveatError, AUDIENCE_KEY, CLIENT_IP_KEY, EPOCH_KEY, EXPIRES_KEY,
+             MAX_CAVEATS_KEY, NOT_BEFORE_KEY, SCOPE_KEY, SEALED_KEY, SINGLE_USE_KEY,
+             TOKEN_ID_KEY};
+use format::Format;
+
+
+/// The code of caveats that are written out in full.
+pub const LITERAL_CODE : u8 = 0;
+
+/// The code of the first key added with `KeyDictionary::add`. Codes below
+/// this are reserved for keys known to this crate.
+pub const FIRST_APP_CODE : u8 = 128;
+
+/// The keys known to this crate, in code order starting from 1.
+///
+/// Keys may be appended, but never reordered or removed, as that would
+/// change the meaning of existing tokens.
+const BUILTIN_KEYS : &'static [&'static [u8]] = &[
+    b"user",
+    EXPIRES_KEY,
+    NOT_BEFORE_KEY,
+    SCOPE_KEY,
+    AUDIENCE_KEY,
+    TOKEN_ID_KEY,
+    CLIENT_IP_KEY,
+    EPOCH_KEY,
+    SINGLE_USE_KEY,
+    // The key of `Almond::seal` before it was changed to `SEALED_KEY`.
+    b"sealed",
+    MAX_CAVEATS_KEY,
+    SEALED_KEY,
+];
+
+
+quick_error! {
+    /// An error returned when a key can't be added to a `KeyDictionary`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum DictionaryError {
+        /// All of the application codes are in use.
+        Full {
+            display("dictionary has no codes left")
+        }
+
+        /// The key is already in the dictionary.
+        Duplicate { key: Vec<u8> } {
+            display("{:?} is already in the dictionary", String::from_utf8_lossy(key))
+        }
+
+        /// The key isn't a valid caveat key.
+        InvalidKey(err: CaveatError) {
+            from()
+            cause(err)
+            display("invalid dictionary key: {}", err)
+        }
+    }
+}
+
+
+/// The keys that have single byte codes in the v3 format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyDictionary {
+    keys: Vec<Vec<u8>>,
+}
+
+impl KeyDictionary {
+    /// Create a dictionary with only the keys known to this crate.
+    pub fn new() -> KeyDictionary {
+        KeyDictionary { keys: Vec::new() }
+    }
+
+    /// Add an application key, which gets the next free code starting from
+    /// `FIRST_APP_CODE`.
+    pub fn add(&mut self, key: Vec<u8>) -> Result<&mut Self, DictionaryError> {
+        try!(check_caveat(Format::V1, &key, None));
+
+        if self.code(&key).is_some() {
+            return Err(DictionaryError::Duplicate { key: key });
+        }
+        if self.keys.len() > (u8::max_value() - FIRST_APP_CODE) as usize {
+            return Err(DictionaryError::Full);
+        }
+
+        self.keys.push(key);
+        Ok(self)
+    }
+
+    /// Get the code of a key, if it is in the dictionary.
+    pub fn code(&self, key: &[u8]) -> Option<u8> {
+        if let Some(idx) = BUILTIN_KEYS.iter().position(|k| *k == key) {
+            return Some(idx as u8 + 1);
+        }
+        self.keys.iter().position(|k| &k[..] == key).map(|idx| idx as u8 + FIRST_APP_CODE)
+    }
+
+    /// Get the key with the given code.
+    pub fn key(&self, code: u8) -> Option<&[u8]> {
+        if code == LITERAL_CODE {
+            None
+        } else if code < FIRST_APP_CODE {
+            BUILTIN_KEYS.get(code as usize - 1).map(|k| *k)
+        } else {
+            self.keys.get((code - FIRST_APP_CODE) as usize).map(|k| &k[..])
+        }
+    }
+
+    /// Split a caveat into its code and the bytes following the key, which
+    /// are the whole caveat if its key isn't in the dictionary.
+    pub fn encode<'c>(&self, caveat: &'c [u8]) -> (u8, &'c [u8]) {
+        let key_len = caveat.iter().position(|c| *c == b' ').unwrap_or(caveat.len());
+
+        match self.code(&caveat[..key_len]) {
+            Some(code) => (code, &caveat[key_len..]),
+            None => (LITERAL_CODE, caveat),
+        }
+    }
+
+    /// Append the caveat encoded by `encode` to `buf`.
+    ///
+    /// Returns `None` if the code is unknown, or the caveat isn't the one
+    /// `encode` would have produced.
+    pub fn decode(&self, code: u8, rest: &[u8], buf: &mut Vec<u8>) -> Option<()> {
+        if code == LITERAL_CODE {
+            if self.encode(rest).0 != LITERAL_CODE {
+                return None;
+            }
+            buf.extend_from_slice(rest);
+            return Some(());
+        }
+
+        let key = match self.key(code) {
+            Some(key) => key,
+            None => return None,
+        };
+        if !rest.is_empty() && rest[0] != b' ' {
+            return None;
+        }
+
+        buf.extend_from_slice(key);
+        buf.extend_from_slice(rest);
+        Some(())
+    }
+}
+
+impl Default for KeyDictionary {
+    fn default() -> KeyDictionary {
+        KeyDictionary::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use almond::CaveatError;
+
+    #[test]
+    fn codes() {
+        let mut dictionary = KeyDictionary::new();
+        dictionary.add(b"device".to_vec()).unwrap();
+
+        assert_eq!(dictionary.code(b"user"), Some(1));
+        assert_eq!(dictionary.code(b"expires"), Some(2));
+        assert_eq!(dictionary.code(b"device"), Some(FIRST_APP_CODE));
+        assert_eq!(dictionary.code(b"other"), None);
+        assert_eq!(dictionary.key(FIRST_APP_CODE), Some(&b"device"[..]));
+        assert_eq!(dictionary.key(FIRST_APP_CODE + 1), None);
+        assert_eq!(dictionary.key(LITERAL_CODE), None);
+
+        assert_eq!(dictionary.encode(b"user erikj"), (1, &b" erikj"[..]));
+        assert_eq!(dictionary.encode(b"user"), (1, &b""[..]));
+        assert_eq!(dictionary.encode(b"username x"), (LITERAL_CODE, &b"username x"[..]));
+
+        let mut buf = Vec::new();
+        assert_eq!(dictionary.decode(1, b" erikj", &mut buf), Some(()));
+        assert_eq!(buf, b"user erikj");
+
+        // Only the encoding `encode` produces is accepted.
+        assert_eq!(dictionary.decode(1, b"name x", &mut Vec::new()), None);
+        assert_eq!(dictionary.decode(LITERAL_CODE, b"user erikj", &mut Vec::new()), None);
+        assert_eq!(dictionary.decode(FIRST_APP_CODE + 1, b"", &mut Vec::new()), None);
+    }
+
+    #[test]
+    fn add() {
+        let mut dictionary = KeyDictionary::new();
+
+        assert_eq!(
+            dictionary.add(b"user".to_vec()).err(),
+            Some(DictionaryError::Duplicate { key: b"user".to_vec() })
+        );
+        assert_eq!(
+            dictionary.add(b"a b".to_vec()).err(),
+            Some(DictionaryError::InvalidKey(CaveatError::InvalidKey))
+        );
+
+        for i in 0..(256 - FIRST_APP_CODE as usize) {
+            dictionary.add(format!("key{}", i).into_bytes()).unwrap();
+        }
+        assert_eq!(dictionary.code(b"key127"), Some(255));
+        assert_eq!(dictionary.add(b"more".to_vec()).err(), Some(DictionaryError::Full));
+    }
+}
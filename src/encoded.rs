@@ -0,0 +1,134 @@
+use rustc_serialize::base64::FromBase64;
+
+use std::fmt;
+use std::str::FromStr;
+
+use almond::decode_text;
+use ct::ct_eq;
+use format;
+use {Almond, AlmondParseError};
+
+
+/// A Base64 encoded almond that has been checked to be well formed, but not
+/// validated.
+///
+/// This allows tokens to be passed around as command line arguments, config
+/// values or headers with the standard conversions, and validated when they
+/// are used.
+///
+/// Equality compares the decoded almonds in constant time, so tokens with
+/// different padding or alphabets are equal.
+///
+/// ```
+/// # use almonds::{Almond, EncodedAlmond};
+/// let token: EncodedAlmond = "yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag"
+///     .parse()
+///     .unwrap();
+///
+/// let almond = token.validate(b"this_is_a_secret").unwrap();
+/// assert_eq!(almond.serialize_base64(), token.to_string());
+/// ```
+#[derive(Clone)]
+pub struct EncodedAlmond {
+    encoded: String,
+    binary: Vec<u8>,
+}
+
+impl EncodedAlmond {
+    /// Get the encoded almond as given.
+    pub fn as_str(&self) -> &str {
+        &self.encoded
+    }
+
+    /// Validate the almond with `key`.
+    pub fn validate(&self, key: &[u8]) -> Result<Almond, AlmondParseError> {
+        Almond::parse_and_validate(key, &self.binary)
+    }
+}
+
+impl FromStr for EncodedAlmond {
+    type Err = AlmondParseError;
+
+    /// Checks that the string is a well formed Base64 almond. This does
+    /// *not* validate the hash.
+    fn from_str(s: &str) -> Result<EncodedAlmond, AlmondParseError> {
+        let binary = try!(decode_text(s.as_bytes(), |input| {
+            input.from_base64().map_err(AlmondParseError::Base64)
+        }));
+        try!(format::parse(&binary));
+
+        Ok(EncodedAlmond {
+            encoded: s.to_owned(),
+            binary: binary,
+        })
+    }
+}
+
+impl <'a> From<&'a Almond> for EncodedAlmond {
+    fn from(almond: &'a Almond) -> EncodedAlmond {
+        EncodedAlmond {
+            encoded: almond.serialize_base64(),
+            binary: almond.serialize_binary(),
+        }
+    }
+}
+
+impl fmt::Display for EncodedAlmond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.encoded)
+    }
+}
+
+impl fmt::Debug for EncodedAlmond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EncodedAlmond({:?})", self.encoded)
+    }
+}
+
+impl AsRef<str> for EncodedAlmond {
+    fn as_ref(&self) -> &str {
+        &self.encoded
+    }
+}
+
+impl PartialEq for EncodedAlmond {
+    fn eq(&self, other: &EncodedAlmond) -> bool {
+        ct_eq(&self.binary, &other.binary)
+    }
+}
+
+impl Eq for EncodedAlmond {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::EncodedAlmond;
+    use {Almond, AlmondParseError, Base64Config};
+
+    #[test]
+    fn parse() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let padded = almond.serialize_base64_with(Base64Config::STANDARD);
+        let encoded: EncodedAlmond = padded.parse().unwrap();
+        assert_eq!(encoded.as_str(), padded);
+        assert_eq!(encoded, EncodedAlmond::from(&almond));
+
+        // Parsing doesn't validate the hash
+        assert!(encoded.validate(key).is_ok());
+        match encoded.validate(b"wrong_key") {
+            Err(AlmondParseError::IncorrectHash) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        let mut other = Almond::create(key, 1, b"login".to_vec());
+        other.add_caveat(b"user", Some(b"bob"));
+        assert!(encoded != EncodedAlmond::from(&other));
+
+        assert!("not base64!".parse::<EncodedAlmond>().is_err());
+        assert!("AAAA".parse::<EncodedAlmond>().is_err());
+    }
+}
@@ -0,0 +1,348 @@
+//! A small expression language for describing caveat checks, so that
+//! policies can be loaded from configuration.
+//!
+//! An expression is one or more clauses joined by `&&`. Each clause compares
+//! the value of every caveat with a key against an operand:
+//!
+//! ```text
+//! expires > @now && user == ctx.user && scope startswith "repo:"
+//! ```
+//!
+//! The operators are `==`, `!=`, `startswith`, and the numeric comparisons
+//! `<`, `<=`, `>` and `>=`. Operands are string literals, unsigned integers,
+//! `@now` for the current unix time in seconds, or `ctx.<name>` for a value
+//! set with `Verifier::set_context_value`.
+//!
+//! As with the rest of the `Verifier`, a caveat is accepted if it passes
+//! every clause with its key, and caveats with keys that no clause mentions
+//! are left unmatched. Numeric comparisons reject values that aren't
+//! canonically encoded integers, as decoded by `CaveatValue`, and clauses
+//! referring to a missing context value reject.
+//!
+//! ```
+//! # use almonds::{Almond, Verifier};
+//! use almonds::expr::Expression;
+//!
+//! let expr: Expression = "user == ctx.user && uploads <= 10".parse().unwrap();
+//!
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_caveat(b"user", Some(b"erikj"));
+//! almond.add_caveat(b"uploads", Some(b"5"));
+//!
+//! let mut v = Verifier::new(&almond, 1, b"access");
+//! v.register(Box::new(&expr));
+//! v.set_context_value("user", "erikj");
+//! assert!(v.verify());
+//! ```
+
+use std::str::{self, FromStr};
+use std::time::UNIX_EPOCH;
+
+use registry::{CaveatVerifier, VerifyContext};
+use value::CaveatValue;
+
+
+quick_error! {
+    /// An error returned when an expression can't be parsed.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum ExprError {
+        /// The expression ended part way through a clause.
+        UnexpectedEnd {
+            display("expression ended unexpectedly")
+        }
+
+        /// A token appeared where it isn't allowed.
+        UnexpectedToken { token: String } {
+            display("unexpected {:?} in expression", token)
+        }
+
+        /// A string literal wasn't closed.
+        UnterminatedString {
+            display("unterminated string in expression")
+        }
+
+        /// An operand started with `@` but isn't a known variable.
+        UnknownVariable { name: String } {
+            display("unknown variable {:?} in expression", name)
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    StartsWith,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Literal(String),
+    Now,
+    Context(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Clause {
+    key: Vec<u8>,
+    op: Op,
+    operand: Operand,
+}
+
+impl Clause {
+    fn check(&self, value: Option<&[u8]>, ctx: &VerifyContext) -> bool {
+        let value = match value {
+            Some(value) => value,
+            None => return false,
+        };
+
+        let now;
+        let operand = match self.operand {
+            Operand::Literal(ref literal) => &literal[..],
+            Operand::Now => {
+                let secs = ctx.now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                now = secs.to_string();
+                &now[..]
+            }
+            Operand::Context(ref name) => match ctx.get(name) {
+                Some(operand) => operand,
+                None => return false,
+            },
+        };
+
+        match self.op {
+            Op::Eq => value == operand.as_bytes(),
+            Op::Ne => value != operand.as_bytes(),
+            Op::StartsWith => value.starts_with(operand.as_bytes()),
+            op => {
+                let value = u64::decode_value(value);
+                let operand = u64::decode_value(operand.as_bytes());
+
+                match (value, operand) {
+                    (Some(value), Some(operand)) => match op {
+                        Op::Lt => value < operand,
+                        Op::Le => value <= operand,
+                        Op::Gt => value > operand,
+                        _ => value >= operand,
+                    },
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+
+/// A parsed expression, which checks caveats when registered with
+/// `Verifier::register`. See the module documentation for the syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    clauses: Vec<Clause>,
+}
+
+impl FromStr for Expression {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Expression, ExprError> {
+        let mut tokens = try!(tokenize(s)).into_iter();
+        let mut clauses = Vec::new();
+
+        loop {
+            let key = match tokens.next() {
+                Some(Token::Word(key)) => key.into_bytes(),
+                Some(token) => return Err(token.unexpected()),
+                None => return Err(ExprError::UnexpectedEnd),
+            };
+
+            let op = match tokens.next() {
+                Some(Token::Word(ref op)) => match &op[..] {
+                    "==" => Op::Eq,
+                    "!=" => Op::Ne,
+                    "<" => Op::Lt,
+                    "<=" => Op::Le,
+                    ">" => Op::Gt,
+                    ">=" => Op::Ge,
+                    "startswith" => Op::StartsWith,
+                    _ => return Err(ExprError::UnexpectedToken { token: op.clone() }),
+                },
+                Some(token) => return Err(token.unexpected()),
+                None => return Err(ExprError::UnexpectedEnd),
+            };
+
+            let operand = match tokens.next() {
+                Some(Token::Str(literal)) => Operand::Literal(literal),
+                Some(Token::Word(word)) => try!(parse_operand(word)),
+                None => return Err(ExprError::UnexpectedEnd),
+            };
+
+            clauses.push(Clause { key: key, op: op, operand: operand });
+
+            match tokens.next() {
+                Some(Token::Word(ref and)) if and == "&&" => {}
+                Some(token) => return Err(token.unexpected()),
+                None => break,
+            }
+        }
+
+        Ok(Expression { clauses: clauses })
+    }
+}
+
+impl CaveatVerifier for Expression {
+    fn check(&self, key: &[u8], value: Option<&[u8]>, ctx: &VerifyContext) -> Option<bool> {
+        let mut res = None;
+        for clause in self.clauses.iter().filter(|c| c.key == key) {
+            if !clause.check(value, ctx) {
+                return Some(false);
+            }
+            res = Some(true);
+        }
+        res
+    }
+}
+
+
+#[derive(Debug)]
+enum Token {
+    Word(String),
+    Str(String),
+}
+
+impl Token {
+    fn unexpected(self) -> ExprError {
+        let token = match self {
+            Token::Word(word) => word,
+            Token::Str(literal) => format!("{:?}", literal),
+        };
+        ExprError::UnexpectedToken { token: token }
+    }
+}
+
+/// Split the expression into whitespace separated words and string literals.
+fn tokenize(s: &str) -> Result<Vec<Token>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+
+            let mut literal = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c) => literal.push(c),
+                        None => return Err(ExprError::UnterminatedString),
+                    },
+                    Some(c) => literal.push(c),
+                    None => return Err(ExprError::UnterminatedString),
+                }
+            }
+            tokens.push(Token::Str(literal));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_operand(word: String) -> Result<Operand, ExprError> {
+    if word == "@now" {
+        Ok(Operand::Now)
+    } else if word.starts_with('@') {
+        Err(ExprError::UnknownVariable { name: word })
+    } else if word.starts_with("ctx.") && word.len() > 4 {
+        Ok(Operand::Context(word[4..].to_owned()))
+    } else if word.parse::<u64>().is_ok() {
+        Ok(Operand::Literal(word))
+    } else {
+        Err(ExprError::UnexpectedToken { token: word })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use registry::{CaveatVerifier, VerifyContext};
+
+    use std::collections::BTreeMap;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn check(expr: &str, key: &[u8], value: Option<&[u8]>) -> Option<bool> {
+        let mut values = BTreeMap::new();
+        values.insert("user".to_owned(), "erikj".to_owned());
+        values.insert("max".to_owned(), "+7".to_owned());
+        let ctx = VerifyContext::new(UNIX_EPOCH + Duration::from_secs(1000), &values);
+
+        let expr: Expression = expr.parse().unwrap();
+        expr.check(key, value, &ctx)
+    }
+
+    #[test]
+    fn clauses() {
+        assert_eq!(check("user == ctx.user", b"user", Some(b"erikj")), Some(true));
+        assert_eq!(check("user == ctx.user", b"user", Some(b"bob")), Some(false));
+        assert_eq!(check("user == ctx.user", b"user", None), Some(false));
+        assert_eq!(check("user == ctx.user", b"other", Some(b"erikj")), None);
+        assert_eq!(check("user != \"bob\"", b"user", Some(b"erikj")), Some(true));
+        assert_eq!(check("user == ctx.missing", b"user", Some(b"erikj")), Some(false));
+
+        assert_eq!(check("expires > @now", b"expires", Some(b"1001")), Some(true));
+        assert_eq!(check("expires > @now", b"expires", Some(b"1000")), Some(false));
+        assert_eq!(check("expires >= @now", b"expires", Some(b"1000")), Some(true));
+        assert_eq!(check("uploads < 10", b"uploads", Some(b"9")), Some(true));
+        assert_eq!(check("uploads <= 10", b"uploads", Some(b"ten")), Some(false));
+
+        // Only the canonical encoding of integers is accepted, as with
+        // `CaveatValue`.
+        assert_eq!(check("uploads <= 10", b"uploads", Some(b"+5")), Some(false));
+        assert_eq!(check("uploads <= 10", b"uploads", Some(b"007")), Some(false));
+        assert_eq!(check("uploads <= 10", b"uploads", Some(b"0")), Some(true));
+        assert_eq!(check("uploads <= ctx.max", b"uploads", Some(b"5")), Some(false));
+
+        let scope = "scope startswith \"repo:\"";
+        assert_eq!(check(scope, b"scope", Some(b"repo:read")), Some(true));
+        assert_eq!(check(scope, b"scope", Some(b"user:read")), Some(false));
+
+        let both = "n > 1 && n < 5 && user == \"a \\\"quoted\\\" name\"";
+        assert_eq!(check(both, b"n", Some(b"3")), Some(true));
+        assert_eq!(check(both, b"n", Some(b"5")), Some(false));
+        assert_eq!(check(both, b"user", Some(b"a \"quoted\" name")), Some(true));
+    }
+
+    #[test]
+    fn invalid() {
+        let cases = vec![
+            ("", ExprError::UnexpectedEnd),
+            ("user ==", ExprError::UnexpectedEnd),
+            ("user == \"erikj", ExprError::UnterminatedString),
+            ("user = \"erikj\"", ExprError::UnexpectedToken { token: "=".to_owned() }),
+            ("user == erikj", ExprError::UnexpectedToken { token: "erikj".to_owned() }),
+            ("a == 1 || b == 2", ExprError::UnexpectedToken { token: "||".to_owned() }),
+            ("a == 1 &&", ExprError::UnexpectedEnd),
+            ("a == @then", ExprError::UnknownVariable { name: "@then".to_owned() }),
+        ];
+
+        for (expr, err) in cases {
+            assert_eq!(expr.parse::<Expression>(), Err(err), "{}", expr);
+        }
+    }
+}
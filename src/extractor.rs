@@ -0,0 +1,286 @@
+//! An actix-web extractor, enabled by the `actix-web` feature.
+//!
+//! A handler taking a `VerifiedAlmond` only runs for requests with an
+//! almond in a `Authorization: Bearer <token>` header that is valid for the
+//! key from the `AlmondConfig` in the app data, and accepted by its policy.
+//! Otherwise the response is a `401`, or `403` if the almond was valid but
+//! rejected, with a JSON body giving the reason, e.g.
+//! `{"error":"caveats","reason":"..."}`.
+//!
+//! ```
+//! # extern crate almonds;
+//! # extern crate actix_web;
+//! # use actix_web::FromRequest;
+//! # use actix_web::test::TestRequest;
+//! use almonds::{Almond, VerifiedAlmond, VerifierPolicy};
+//! use almonds::extractor::AlmondConfig;
+//!
+//! fn profile(almond: VerifiedAlmond) -> String {
+//!     format!("hello {}", almond.caveat_str(b"user").unwrap())
+//! }
+//!
+//! # fn main() {
+//! let mut policy = VerifierPolicy::new(1, b"access".to_vec());
+//! policy.allow(b"user");
+//! let config = AlmondConfig::new(b"secret", policy);
+//! // App::new().app_data(config).route("/profile", web::get().to(profile))
+//!
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_caveat(b"user", Some(b"erikj"));
+//! let req = TestRequest::default()
+//!     .app_data(config)
+//!     .insert_header(("Authorization", format!("Bearer {}", almond.serialize_base64())))
+//!     .to_http_request();
+//!
+//! let verified = VerifiedAlmond::extract(&req).into_inner().unwrap();
+//! assert_eq!(profile(verified), "hello erikj");
+//! # }
+//! ```
+
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError};
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{self, ContentType};
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::json::{Json, Object};
+
+use std::future::{self, Ready};
+use std::sync::Arc;
+
+use http::bearer_token;
+use verified::from_validated;
+use {AlmondParseError, UnverifiedAlmond, VerifiedAlmond, VerifierPolicy, VerifyError};
+
+
+/// Looks up the key that almonds of a given generation and type were minted
+/// with.
+///
+/// This is implemented for closures, e.g. to select the key by generation
+/// during a key rotation.
+pub trait KeyProvider: Send + Sync {
+    /// Get the key for the given generation and type, if there is one.
+    fn key(&self, generation: u32, almond_type: &[u8]) -> Option<Vec<u8>>;
+}
+
+impl <F> KeyProvider for F
+    where F: Fn(u32, &[u8]) -> Option<Vec<u8>> + Send + Sync
+{
+    fn key(&self, generation: u32, almond_type: &[u8]) -> Option<Vec<u8>> {
+        self(generation, almond_type)
+    }
+}
+
+struct StaticKey(Vec<u8>);
+
+impl KeyProvider for StaticKey {
+    fn key(&self, _generation: u32, _almond_type: &[u8]) -> Option<Vec<u8>> {
+        Some(self.0.clone())
+    }
+}
+
+
+struct Config {
+    keys: Box<KeyProvider>,
+    policy: VerifierPolicy,
+}
+
+/// The keys and policy used by the `VerifiedAlmond` extractor, which must be
+/// added to the app data.
+#[derive(Clone)]
+pub struct AlmondConfig {
+    config: Arc<Config>,
+}
+
+impl AlmondConfig {
+    /// Validate almonds with `key` and check them against `policy`.
+    pub fn new(key: &[u8], policy: VerifierPolicy) -> AlmondConfig {
+        AlmondConfig::with_key_provider(StaticKey(key.to_vec()), policy)
+    }
+
+    /// Validate almonds with the key from `keys` and check them against
+    /// `policy`. Almonds without a key are rejected with
+    /// `AlmondParseError::UnknownKey`.
+    pub fn with_key_provider<K>(keys: K, policy: VerifierPolicy) -> AlmondConfig
+        where K: KeyProvider + 'static
+    {
+        AlmondConfig {
+            config: Arc::new(Config {
+                keys: Box::new(keys),
+                policy: policy,
+            }),
+        }
+    }
+
+    /// Validate and verify a Base64 encoded almond.
+    pub fn verify(&self, token: &[u8]) -> Result<VerifiedAlmond, ExtractError> {
+        let input = try!(token.from_base64().map_err(AlmondParseError::Base64));
+        let unverified = try!(UnverifiedAlmond::parse(&input));
+
+        let key = try!(
+            self.config.keys.key(unverified.generation(), unverified.almond_type())
+                .ok_or(AlmondParseError::UnknownKey)
+        );
+        let almond = try!(unverified.validate(&key));
+        try!(self.config.policy.check_detailed(&almond));
+
+        Ok(from_validated(almond))
+    }
+}
+
+
+quick_error! {
+    /// The reason the `VerifiedAlmond` extractor failed, which is also the
+    /// error response.
+    #[derive(Debug)]
+    pub enum ExtractError {
+        /// There is no `AlmondConfig` in the app data.
+        NotConfigured {
+            display("no AlmondConfig in the app data")
+        }
+
+        /// The request has no bearer token.
+        MissingToken {
+            display("missing bearer token")
+        }
+
+        /// The token could not be parsed or its hash did not match.
+        Parse(err: AlmondParseError) {
+            from()
+            display("invalid almond: {}", err)
+            cause(err)
+        }
+
+        /// The almond was valid but was rejected by the policy.
+        Rejected(err: VerifyError) {
+            from()
+            display("almond rejected: {}", err)
+            cause(err)
+        }
+    }
+}
+
+impl ExtractError {
+    /// A short, stable name for the error, used as the `error` of the
+    /// response. See `AlmondParseError::kind` and `VerifyError::kind`.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            ExtractError::NotConfigured => "not_configured",
+            ExtractError::MissingToken => "missing_token",
+            ExtractError::Parse(ref err) => err.kind(),
+            ExtractError::Rejected(ref err) => err.kind(),
+        }
+    }
+}
+
+impl ResponseError for ExtractError {
+    fn status_code(&self) -> StatusCode {
+        match *self {
+            ExtractError::NotConfigured => StatusCode::INTERNAL_SERVER_ERROR,
+            ExtractError::Rejected(_) => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut object = Object::new();
+        object.insert("error".to_owned(), Json::String(self.kind().to_owned()));
+        object.insert("reason".to_owned(), Json::String(self.to_string()));
+
+        let mut res = HttpResponse::build(self.status_code());
+        res.content_type(ContentType::json());
+        if self.status_code() == StatusCode::UNAUTHORIZED {
+            res.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        res.body(Json::Object(object).to_string())
+    }
+}
+
+
+impl FromRequest for VerifiedAlmond {
+    type Error = ExtractError;
+    type Future = Ready<Result<VerifiedAlmond, ExtractError>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        future::ready(extract(req))
+    }
+}
+
+fn extract(req: &HttpRequest) -> Result<VerifiedAlmond, ExtractError> {
+    let config = try!(req.app_data::<AlmondConfig>().ok_or(ExtractError::NotConfigured));
+    let token = try!(
+        req.headers().get(header::AUTHORIZATION)
+            .and_then(|value| bearer_token(value.as_bytes()))
+            .ok_or(ExtractError::MissingToken)
+    );
+
+    config.verify(token)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{FromRequest, ResponseError};
+    use actix_web::body::MessageBody;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    use super::*;
+    use {Almond, AlmondParseError, VerifiedAlmond, VerifierPolicy, VerifyError};
+
+    fn extract(config: Option<AlmondConfig>, authorization: Option<String>)
+        -> Result<VerifiedAlmond, ExtractError>
+    {
+        let mut req = TestRequest::default();
+        if let Some(config) = config {
+            req = req.app_data(config);
+        }
+        if let Some(authorization) = authorization {
+            req = req.insert_header(("Authorization", authorization));
+        }
+
+        VerifiedAlmond::extract(&req.to_http_request()).into_inner()
+    }
+
+    #[test]
+    fn extractor() {
+        let mut policy = VerifierPolicy::new(1, b"access".to_vec());
+        policy.allow(b"user");
+        let config = AlmondConfig::with_key_provider(|generation, _: &[u8]| {
+            if generation == 1 { Some(b"this_is_a_secret".to_vec()) } else { None }
+        }, policy);
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"access".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let bearer = format!("Bearer {}", almond.serialize_base64());
+
+        let verified = extract(Some(config.clone()), Some(bearer.clone())).unwrap();
+        assert_eq!(*verified, almond);
+
+        let err = extract(Some(config.clone()), None).unwrap_err();
+        assert_eq!(err.kind(), "missing_token");
+        assert_eq!(err.status_code(), StatusCode::UNAUTHORIZED);
+
+        let other = Almond::create(b"this_is_a_secret", 2, b"access".to_vec());
+        match extract(Some(config.clone()), Some(format!("Bearer {}", other.serialize_base64()))) {
+            Err(ExtractError::Parse(AlmondParseError::UnknownKey)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        almond.add_caveat(b"admin", None);
+        let err = extract(Some(config), Some(format!("Bearer {}", almond.serialize_base64())))
+            .unwrap_err();
+        match err {
+            ExtractError::Rejected(VerifyError::Caveats { .. }) => {}
+            ref err => panic!("unexpected error: {:?}", err),
+        }
+
+        let res = err.error_response();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let body = res.into_body().try_into_bytes().unwrap();
+        let json = Json::from_str(::std::str::from_utf8(&body).unwrap()).unwrap();
+        assert_eq!(json["error"].as_string(), Some("caveats"));
+
+        let err = extract(None, Some(bearer)).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,432 @@
+//! A C compatible interface to Almond, enabled by the `ffi` feature.
+//!
+//! All functions take byte strings as a pointer and a length. Pointers may
+//! be null only if the corresponding length is zero, except where noted.
+//!
+//! Objects returned by this module are owned by the caller and must be
+//! released with the matching `*_free` function. A verifier borrows the
+//! almond it was created with, so the almond must outlive the verifier.
+//!
+//! The corresponding C header can be found in `include/almonds.h`.
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use std::slice;
+
+use {Almond, AlmondParseError, Verifier};
+
+
+/// Status codes returned by the fallible functions in this module.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlmondStatus {
+    /// The call succeeded.
+    Ok = 0,
+    /// A required pointer argument was null.
+    NullPointer = 1,
+    /// The input did not contain a valid almond.
+    InvalidAlmond = 2,
+    /// The hash did not match the deserialized almond.
+    IncorrectHash = 3,
+}
+
+impl From<AlmondParseError> for AlmondStatus {
+    fn from(err: AlmondParseError) -> AlmondStatus {
+        match err {
+            AlmondParseError::IncorrectHash
+            | AlmondParseError::ExtendedAfterSeal
+            | AlmondParseError::UnknownKey => {
+                AlmondStatus::IncorrectHash
+            }
+            _ => AlmondStatus::InvalidAlmond,
+        }
+    }
+}
+
+/// The predicate type used by `almond_verifier_satisfies`.
+///
+/// It is called with the caveat value and the `user_data` pointer given to
+/// `almond_verifier_satisfies`, and should return whether the value is
+/// acceptable.
+pub type AlmondPredicate = extern "C" fn(
+    value: *const u8, value_len: usize, user_data: *mut c_void
+) -> bool;
+
+
+unsafe fn byte_slice<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if ptr.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr, len)
+    }
+}
+
+
+/// Create a new almond with the given key, generation and type.
+///
+/// Returns null if `key` or `almond_type` is null with a non-zero length.
+///
+/// # Safety
+///
+/// `key` and `almond_type` must each be null or point to the given number
+/// of readable bytes. The bytes are copied, so they only need to be valid for
+/// the duration of the call.
+#[no_mangle]
+pub unsafe extern "C" fn almond_create(
+    key: *const u8, key_len: usize,
+    generation: u32,
+    almond_type: *const u8, almond_type_len: usize,
+) -> *mut Almond {
+    if (key.is_null() && key_len != 0)
+        || (almond_type.is_null() && almond_type_len != 0)
+    {
+        return ptr::null_mut();
+    }
+
+    let almond = Almond::create(
+        byte_slice(key, key_len),
+        generation,
+        byte_slice(almond_type, almond_type_len).to_vec(),
+    );
+
+    Box::into_raw(Box::new(almond))
+}
+
+/// Add a caveat to the almond. If `value` is null then the caveat will not
+/// have a value.
+///
+/// # Safety
+///
+/// `almond` must be null or a live almond returned by this module, which no
+/// verifier is borrowing. `key` and `value` must each be null or point to the
+/// given number of readable bytes, which are copied.
+#[no_mangle]
+pub unsafe extern "C" fn almond_add_caveat(
+    almond: *mut Almond,
+    key: *const u8, key_len: usize,
+    value: *const u8, value_len: usize,
+) -> AlmondStatus {
+    if almond.is_null() || (key.is_null() && key_len != 0) {
+        return AlmondStatus::NullPointer;
+    }
+
+    let value = if value.is_null() {
+        None
+    } else {
+        Some(byte_slice(value, value_len))
+    };
+
+    (*almond).add_caveat(byte_slice(key, key_len), value);
+
+    AlmondStatus::Ok
+}
+
+/// Serialize the almond into a NUL terminated Base64 string.
+///
+/// The returned string must be freed with `almond_string_free`. Returns null
+/// if `almond` is null.
+///
+/// # Safety
+///
+/// `almond` must be null or a live almond returned by this module.
+#[no_mangle]
+pub unsafe extern "C" fn almond_serialize_base64(
+    almond: *const Almond
+) -> *mut c_char {
+    if almond.is_null() {
+        return ptr::null_mut();
+    }
+
+    // Base64 never contains a NUL byte.
+    let encoded = CString::new((*almond).serialize_base64())
+        .expect("base64 contained a NUL byte");
+
+    encoded.into_raw()
+}
+
+/// Parse a Base64 serialized almond and validate its hash.
+///
+/// On success `*out` is set to the parsed almond, which must be freed with
+/// `almond_free`. On failure `*out` is left untouched.
+///
+/// # Safety
+///
+/// `key` and `input` must each be null or point to the given number of
+/// readable bytes, which are only read during the call. `out` must be null or
+/// valid for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn almond_parse_validate(
+    key: *const u8, key_len: usize,
+    input: *const u8, input_len: usize,
+    out: *mut *mut Almond,
+) -> AlmondStatus {
+    if out.is_null()
+        || (key.is_null() && key_len != 0)
+        || (input.is_null() && input_len != 0)
+    {
+        return AlmondStatus::NullPointer;
+    }
+
+    match Almond::parse_base64_and_validate(
+        byte_slice(key, key_len), byte_slice(input, input_len)
+    ) {
+        Ok(almond) => {
+            *out = Box::into_raw(Box::new(almond));
+            AlmondStatus::Ok
+        }
+        Err(err) => AlmondStatus::from(err),
+    }
+}
+
+/// Free an almond returned by `almond_create` or `almond_parse_validate`.
+///
+/// # Safety
+///
+/// `almond` must be null or an almond returned by this module that hasn't
+/// been freed, and no verifier may still be borrowing it. It must not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn almond_free(almond: *mut Almond) {
+    if !almond.is_null() {
+        drop(Box::from_raw(almond));
+    }
+}
+
+/// Free a string returned by `almond_serialize_base64`.
+///
+/// # Safety
+///
+/// `string` must be null or a string returned by `almond_serialize_base64`
+/// that hasn't been freed. It must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn almond_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Create a new verifier for the given almond.
+///
+/// The almond must not be freed or modified until the verifier has been
+/// freed. Returns null if `almond` is null.
+///
+/// # Safety
+///
+/// `almond` must be null or a live almond returned by this module, which
+/// must outlive the returned verifier. `almond_type` must be null or point to
+/// the given number of readable bytes, which are copied.
+#[no_mangle]
+pub unsafe extern "C" fn almond_verifier_new(
+    almond: *const Almond,
+    generation: u32,
+    almond_type: *const u8, almond_type_len: usize,
+) -> *mut Verifier<'static> {
+    if almond.is_null() || (almond_type.is_null() && almond_type_len != 0) {
+        return ptr::null_mut();
+    }
+
+    let verifier = Verifier::new(
+        &*almond, generation, byte_slice(almond_type, almond_type_len)
+    );
+
+    Box::into_raw(Box::new(verifier))
+}
+
+/// See `Verifier::allow`.
+///
+/// # Safety
+///
+/// `verifier` must be null or a live verifier returned by
+/// `almond_verifier_new`. `key` must be null or point to the given number of
+/// readable bytes, which are copied.
+#[no_mangle]
+pub unsafe extern "C" fn almond_verifier_allow(
+    verifier: *mut Verifier<'static>,
+    key: *const u8, key_len: usize,
+) -> AlmondStatus {
+    if verifier.is_null() || (key.is_null() && key_len != 0) {
+        return AlmondStatus::NullPointer;
+    }
+
+    (*verifier).allow(byte_slice(key, key_len));
+
+    AlmondStatus::Ok
+}
+
+/// See `Verifier::satisfies_exact`. If `value` is null then only caveats
+/// without a value are accepted.
+///
+/// # Safety
+///
+/// `verifier` must be null or a live verifier returned by
+/// `almond_verifier_new`. `key` and `value` must each be null or point to the
+/// given number of readable bytes, which are copied.
+#[no_mangle]
+pub unsafe extern "C" fn almond_verifier_satisfies_exact(
+    verifier: *mut Verifier<'static>,
+    key: *const u8, key_len: usize,
+    value: *const u8, value_len: usize,
+) -> AlmondStatus {
+    if verifier.is_null() || (key.is_null() && key_len != 0) {
+        return AlmondStatus::NullPointer;
+    }
+
+    let value = if value.is_null() {
+        None
+    } else {
+        Some(byte_slice(value, value_len))
+    };
+
+    (*verifier).satisfies_exact(byte_slice(key, key_len), value);
+
+    AlmondStatus::Ok
+}
+
+/// See `Verifier::satisfies`. The `predicate` is invoked synchronously and
+/// `user_data` is passed through to it unchanged.
+///
+/// # Safety
+///
+/// `verifier` must be null or a live verifier returned by
+/// `almond_verifier_new`. `key` must be null or point to the given number of
+/// readable bytes, which are copied. `predicate` must be safe to call with
+/// `user_data` whenever the verifier is used. The value passed to it is only
+/// valid for the duration of that call.
+#[no_mangle]
+pub unsafe extern "C" fn almond_verifier_satisfies(
+    verifier: *mut Verifier<'static>,
+    key: *const u8, key_len: usize,
+    predicate: Option<AlmondPredicate>,
+    user_data: *mut c_void,
+) -> AlmondStatus {
+    let predicate = match predicate {
+        Some(predicate) => predicate,
+        None => return AlmondStatus::NullPointer,
+    };
+
+    if verifier.is_null() || (key.is_null() && key_len != 0) {
+        return AlmondStatus::NullPointer;
+    }
+
+    (*verifier).satisfies(
+        byte_slice(key, key_len),
+        |val| predicate(val.as_ptr(), val.len(), user_data),
+    );
+
+    AlmondStatus::Ok
+}
+
+/// See `Verifier::verify`. Returns false if `verifier` is null.
+///
+/// # Safety
+///
+/// `verifier` must be null or a live verifier returned by
+/// `almond_verifier_new`, whose almond hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn almond_verifier_verify(
+    verifier: *const Verifier<'static>
+) -> bool {
+    if verifier.is_null() {
+        return false;
+    }
+
+    (*verifier).verify()
+}
+
+/// Free a verifier returned by `almond_verifier_new`.
+///
+/// # Safety
+///
+/// `verifier` must be null or a verifier returned by `almond_verifier_new`
+/// that hasn't been freed. It must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn almond_verifier_free(verifier: *mut Verifier<'static>) {
+    if !verifier.is_null() {
+        drop(Box::from_raw(verifier));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::CStr;
+    use std::os::raw::c_void;
+    use std::ptr;
+
+    const KEY: &'static [u8] = b"this_is_a_secret";
+
+    extern "C" fn is_erikj(
+        value: *const u8, value_len: usize, _: *mut c_void
+    ) -> bool {
+        let value = unsafe { super::byte_slice(value, value_len) };
+        value == b"erikj"
+    }
+
+    #[test]
+    fn create_and_serialize() {
+        unsafe {
+            let almond = almond_create(KEY.as_ptr(), KEY.len(), 1, b"login".as_ptr(), 5);
+            assert!(!almond.is_null());
+
+            let status = almond_add_caveat(
+                almond, b"user".as_ptr(), 4, b"erikj".as_ptr(), 5
+            );
+            assert_eq!(status, AlmondStatus::Ok);
+
+            let encoded = almond_serialize_base64(almond);
+            assert_eq!(
+                CStr::from_ptr(encoded).to_str().unwrap(),
+                "yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag"
+            );
+
+            almond_string_free(encoded);
+            almond_free(almond);
+        }
+    }
+
+    #[test]
+    fn parse_and_verify() {
+        let input = b"yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag";
+
+        unsafe {
+            let mut almond = ptr::null_mut();
+            let status = almond_parse_validate(
+                KEY.as_ptr(), KEY.len(), input.as_ptr(), input.len(), &mut almond
+            );
+            assert_eq!(status, AlmondStatus::Ok);
+
+            let verifier = almond_verifier_new(almond, 1, b"login".as_ptr(), 5);
+            assert!(!almond_verifier_verify(verifier));
+
+            almond_verifier_satisfies(
+                verifier, b"user".as_ptr(), 4, Some(is_erikj), ptr::null_mut()
+            );
+            assert!(almond_verifier_verify(verifier));
+
+            almond_verifier_satisfies_exact(
+                verifier, b"user".as_ptr(), 4, b"noterikj".as_ptr(), 8
+            );
+            assert!(!almond_verifier_verify(verifier));
+
+            almond_verifier_free(verifier);
+            almond_free(almond);
+        }
+    }
+
+    #[test]
+    fn parse_wrong_key() {
+        let input = b"yyTNYc-CAXTVkgXkNnl8wdMzBTMgHyLRSlXrjdf5Uw0BbG9naW4KdXNlciBlcmlrag";
+
+        unsafe {
+            let mut almond = ptr::null_mut();
+            let status = almond_parse_validate(
+                b"wrong".as_ptr(), 5, input.as_ptr(), input.len(), &mut almond
+            );
+            assert_eq!(status, AlmondStatus::IncorrectHash);
+            assert!(almond.is_null());
+        }
+    }
+}
@@ -0,0 +1,1160 @@
+//! The binary wire formats.
+//!
+//! Every serialized almond starts with the 32 byte hash. What follows depends
+//! on the format:
+//!
+//! - **v1**: `<generation> <type> ("\n" <caveat>)*`, where the generation is a
+//!   single byte. The type and caveats can't contain newlines, and caveats
+//!   can't be empty. With generation 255 the type can't start with a
+//!   version byte, as the almond would then look like v2 or v3. Almonds like
+//!   that serialized before v2 existed can only be read with
+//!   `parse_legacy_v1`.
+//! - **v2**: `0xFF <version = 2> <flags> <generation> [<len> <key id>]
+//!   <len> <type> (<len> <caveat>)*`, where each `<len>` is an unsigned
+//!   LEB128 varint. Types and caveats may contain arbitrary bytes. The
+//!   generation is a single byte, unless the `FLAG_EXTENDED_GENERATION` flag
+//!   is set in which case it is a varint. The key id is only present if the
+//!   `FLAG_KEY_ID` flag is set. Unknown flags are rejected.
+//!
+//!   The `FLAG_TYPE_CODE` flag replaces the type with a varint code from a
+//!   `TypeRegistry`. It is only accepted by
+//!   `Almond::parse_and_validate_with_type_codes`.
+//!
+//!   With the `deflate` feature the `FLAG_DEFLATE` flag may also be set, in
+//!   which case everything after the type is compressed with DEFLATE. It is
+//!   only accepted by `Almond::parse_deflated_and_validate`.
+//! - **v3**: the v2 format with version 3, where each caveat is
+//!   `<len> <code> <rest>`. The caveat is the key with the given code in a
+//!   `KeyDictionary` followed by `<rest>`, or just `<rest>` if the code is
+//!   zero. This is only produced and accepted when a dictionary is given,
+//!   see the `dictionary` module.
+//!
+//! The hash only covers the key, generation, type and caveats, so the same
+//! almond validates in either format. In particular the key id is *not*
+//! covered, it is only a hint for picking the key to validate with.
+//!
+//! Parsing only accepts the canonical serialization in each format, so
+//! serializing a parsed almond always gives back the input. In v1 that means
+//! rejecting empty caveat lines, including a trailing newline, and in v2
+//! varints must be minimally encoded.
+
+use std::io::{self, BufRead, Read};
+use std::slice::Split;
+
+use std::borrow::Cow;
+
+use almond::{AlmondParseError, MAX_READER_LEN};
+use dictionary::KeyDictionary;
+use type_codes::TypeRegistry;
+use varint;
+
+
+/// The byte that, in place of a v1 generation, marks an extended header if
+/// it is followed by a version byte.
+pub const EXTENDED_HEADER: u8 = 0xFF;
+
+const VERSION_2: u8 = 2;
+
+const VERSION_3: u8 = 3;
+
+/// Set in the v2 flags byte when the generation is encoded as a varint.
+const FLAG_EXTENDED_GENERATION: u8 = 0x01;
+
+/// Set in the v2 flags byte when a key id follows the generation.
+const FLAG_KEY_ID: u8 = 0x02;
+
+/// Set in the v2 flags byte when the caveats are compressed. This isn't in
+/// `KNOWN_FLAGS`, as the other parsers can't handle it.
+#[cfg(feature = "deflate")]
+pub const FLAG_DEFLATE: u8 = 0x04;
+
+/// Set in the v2 flags byte when the type is replaced by a code from a
+/// `TypeRegistry`. This isn't in `KNOWN_FLAGS` either.
+pub const FLAG_TYPE_CODE: u8 = 0x08;
+
+const KNOWN_FLAGS: u8 = FLAG_EXTENDED_GENERATION | FLAG_KEY_ID;
+
+
+/// Limits on the size of almonds accepted when parsing.
+///
+/// Every caveat is hashed when an almond is validated, so without limits a
+/// large input split into many tiny caveats costs a lot to reject. The
+/// limits are checked before any hashing. `ParseLimits::default()` is used
+/// by `Almond::parse_and_validate` and the other parsing functions, and
+/// `Almond::parse_and_validate_with_limits` takes custom limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// The maximum length of the binary serialization, in bytes. Defaults to
+    /// `MAX_READER_LEN`.
+    pub max_len: usize,
+    /// The maximum number of caveats. Defaults to 1024.
+    pub max_caveats: usize,
+    /// The maximum length of a single caveat, in bytes. Defaults to 4096.
+    pub max_caveat_len: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> ParseLimits {
+        ParseLimits {
+            max_len: MAX_READER_LEN,
+            max_caveats: 1024,
+            max_caveat_len: 4096,
+        }
+    }
+}
+
+/// Check the caveat with the given index against the limits.
+pub fn check_caveat_limits(limits: &ParseLimits, index: usize, caveat: &[u8])
+    -> Result<(), AlmondParseError>
+{
+    if index >= limits.max_caveats {
+        return Err(AlmondParseError::CaveatLimit { max: limits.max_caveats });
+    }
+    if caveat.len() > limits.max_caveat_len {
+        return Err(AlmondParseError::CaveatTooLong {
+            index: index,
+            max: limits.max_caveat_len,
+        });
+    }
+    Ok(())
+}
+
+
+/// The binary wire format of an almond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// The original newline delimited format.
+    V1,
+    /// A length prefixed format that can carry arbitrary bytes.
+    V2,
+}
+
+impl Format {
+    /// Returns the format that should be used to serialize an almond with
+    /// the given contents, preferring `self`.
+    ///
+    /// This is `self` unless it is `V1` and the contents can't be represented
+    /// in v1, in which case `V2` is used.
+    pub fn for_contents<'c, I>(self, generation: u32, almond_type: &[u8], caveats: I)
+        -> Format
+        where I: IntoIterator<Item = &'c [u8]>
+    {
+        if self == Format::V1
+            && (generation > 0xFF
+                || (generation == EXTENDED_HEADER as u32
+                    && almond_type.first().map_or(false, |b| is_version(*b)))
+                || almond_type.contains(&b'\n')
+                || caveats.into_iter().any(|c| c.is_empty() || c.contains(&b'\n')))
+        {
+            Format::V2
+        } else {
+            self
+        }
+    }
+}
+
+
+/// Append the bytes that represent `generation` in the hash to `buf`.
+///
+/// Generations that fit in a byte are a single byte, for compatibility with
+/// v1, and larger generations are a varint. As a varint of a value above 255
+/// is always at least two bytes the two can't collide.
+pub fn encode_generation(generation: u32, buf: &mut Vec<u8>) {
+    if generation <= 0xFF {
+        buf.push(generation as u8);
+    } else {
+        varint::encode(generation as u64, buf);
+    }
+}
+
+/// The length of the serialization of an almond in the given format.
+pub fn serialized_len(
+    format: Format,
+    generation: u32,
+    key_id: Option<&[u8]>,
+    almond_type: &[u8],
+    caveats: Caveats,
+) -> usize {
+    match format {
+        Format::V1 => {
+            caveats.fold(
+                32 + 1 + almond_type.len(),
+                |len, caveat| len + 1 + caveat.len()
+            )
+        }
+        Format::V2 => {
+            let header_len = 32 + 3 + generation_len(generation)
+                + key_id.map_or(0, prefixed_len)
+                + prefixed_len(almond_type);
+
+            match caveats.inner {
+                CaveatsInner::Prefixed(data) => header_len + data.len(),
+                _ => caveats.fold(header_len, |len, caveat| len + prefixed_len(caveat)),
+            }
+        }
+    }
+}
+
+fn generation_len(generation: u32) -> usize {
+    if generation <= 0xFF {
+        1
+    } else {
+        varint::encoded_len(generation as u64)
+    }
+}
+
+fn prefixed_len(data: &[u8]) -> usize {
+    varint::encoded_len(data.len() as u64) + data.len()
+}
+
+/// Write the serialization of an almond in the given format.
+///
+/// A key id can only be written in the v2 format.
+pub fn write<W: io::Write>(
+    writer: &mut W,
+    format: Format,
+    hash: &[u8; 32],
+    generation: u32,
+    key_id: Option<&[u8]>,
+    almond_type: &[u8],
+    caveats: Caveats,
+) -> io::Result<()> {
+    try!(writer.write_all(hash));
+
+    match format {
+        Format::V1 => {
+            debug_assert!(key_id.is_none(), "key ids can't be written in v1");
+
+            try!(writer.write_all(&[generation as u8]));
+            try!(writer.write_all(almond_type));
+
+            for caveat in caveats {
+                try!(writer.write_all(b"\n"));
+                try!(writer.write_all(caveat));
+            }
+        }
+        Format::V2 => {
+            try!(write_extended_header(writer, VERSION_2, 0, generation, key_id));
+            try!(write_prefixed(writer, almond_type));
+            try!(write_caveats(writer, caveats));
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the v3 serialization of an almond, compressing the caveat keys
+/// with `dictionary`.
+pub fn write_with_dictionary<W: io::Write>(
+    writer: &mut W,
+    dictionary: &KeyDictionary,
+    hash: &[u8; 32],
+    generation: u32,
+    key_id: Option<&[u8]>,
+    almond_type: &[u8],
+    caveats: Caveats,
+) -> io::Result<()> {
+    try!(writer.write_all(hash));
+    try!(write_extended_header(writer, VERSION_3, 0, generation, key_id));
+    try!(write_prefixed(writer, almond_type));
+
+    let mut len = [0u8; varint::MAX_LEN];
+    for caveat in caveats {
+        let (code, rest) = dictionary.encode(caveat);
+
+        let len_len = varint::encode_to_slice(1 + rest.len() as u64, &mut len);
+        try!(writer.write_all(&len[..len_len]));
+        try!(writer.write_all(&[code]));
+        try!(writer.write_all(rest));
+    }
+
+    Ok(())
+}
+
+/// Write the hash and v2 header of an almond whose caveats are compressed.
+///
+/// The caveats, as written by `write_caveats`, should follow compressed with
+/// DEFLATE.
+#[cfg(feature = "deflate")]
+pub fn write_compressed_header<W: io::Write>(
+    writer: &mut W,
+    hash: &[u8; 32],
+    generation: u32,
+    key_id: Option<&[u8]>,
+    almond_type: &[u8],
+) -> io::Result<()> {
+    try!(writer.write_all(hash));
+    try!(write_extended_header(writer, VERSION_2, FLAG_DEFLATE, generation, key_id));
+    write_prefixed(writer, almond_type)
+}
+
+/// Write the v2 serialization of an almond, with the type replaced by
+/// `type_code`.
+pub fn write_with_type_code<W: io::Write>(
+    writer: &mut W,
+    hash: &[u8; 32],
+    generation: u32,
+    key_id: Option<&[u8]>,
+    type_code: u16,
+    caveats: Caveats,
+) -> io::Result<()> {
+    try!(writer.write_all(hash));
+    try!(write_extended_header(writer, VERSION_2, FLAG_TYPE_CODE, generation, key_id));
+
+    let mut code = [0u8; varint::MAX_LEN];
+    let code_len = varint::encode_to_slice(type_code as u64, &mut code);
+    try!(writer.write_all(&code[..code_len]));
+
+    write_caveats(writer, caveats)
+}
+
+/// Write the caveats in the v2 format.
+pub fn write_caveats<W: io::Write>(writer: &mut W, caveats: Caveats) -> io::Result<()> {
+    // Caveats that are already in the v2 format can be copied as they are.
+    if let CaveatsInner::Prefixed(data) = caveats.inner {
+        return writer.write_all(data);
+    }
+
+    for caveat in caveats {
+        try!(write_prefixed(writer, caveat));
+    }
+    Ok(())
+}
+
+/// Append `data` to `buf` prefixed with its length, as in the v2 format.
+pub fn push_prefixed(buf: &mut Vec<u8>, data: &[u8]) {
+    varint::encode(data.len() as u64, buf);
+    buf.extend_from_slice(data);
+}
+
+/// Write everything between the hash and the type in the v2 and v3
+/// formats.
+fn write_extended_header<W: io::Write>(
+    writer: &mut W,
+    version: u8,
+    extra_flags: u8,
+    generation: u32,
+    key_id: Option<&[u8]>,
+) -> io::Result<()> {
+    let mut flags = extra_flags;
+    if generation > 0xFF {
+        flags |= FLAG_EXTENDED_GENERATION;
+    }
+    if key_id.is_some() {
+        flags |= FLAG_KEY_ID;
+    }
+
+    try!(writer.write_all(&[EXTENDED_HEADER, version, flags]));
+    if generation <= 0xFF {
+        try!(writer.write_all(&[generation as u8]));
+    } else {
+        let mut encoded = [0u8; varint::MAX_LEN];
+        let len = varint::encode_to_slice(generation as u64, &mut encoded);
+        try!(writer.write_all(&encoded[..len]));
+    }
+    if let Some(key_id) = key_id {
+        try!(write_prefixed(writer, key_id));
+    }
+    Ok(())
+}
+
+/// Convert a v2 serialization whose type is a code into the plain v2
+/// serialization, by looking up the code in `types`. Other serializations
+/// are returned unchanged.
+pub fn expand_type_code<'a>(input: &'a [u8], types: &TypeRegistry)
+    -> Result<Cow<'a, [u8]>, AlmondParseError>
+{
+    if input.len() < 37 || !is_extended(input) || input[34] & FLAG_TYPE_CODE == 0 {
+        return Ok(Cow::Borrowed(input));
+    }
+    if input.len() > MAX_READER_LEN {
+        return Err(AlmondParseError::TooLong { max: MAX_READER_LEN });
+    }
+    try!(check_header(input[33], input[34] & !FLAG_TYPE_CODE));
+
+    let (generation, key_id, rest) = try!(split_generation_and_key_id(&input[33..]));
+    let (code, read) = try!(varint::decode(rest).ok_or(AlmondParseError::MissingType));
+    let almond_type = try!(
+        if code <= u16::max_value() as u64 { types.name(code as u16) } else { None }
+            .ok_or(AlmondParseError::UnknownTypeCode { code: code })
+    );
+
+    let mut expanded = input[..32].to_vec();
+    try!(write_extended_header(&mut expanded, VERSION_2, 0, generation, key_id)
+        .and_then(|_| write_prefixed(&mut expanded, almond_type))
+        .map_err(AlmondParseError::Io));
+    expanded.extend_from_slice(&rest[read..]);
+    Ok(Cow::Owned(expanded))
+}
+
+/// Convert a v3 serialization into v2 by expanding the caveat keys with
+/// `dictionary`. Other serializations are returned unchanged.
+///
+/// The output is limited to `MAX_READER_LEN` bytes.
+pub fn expand_dictionary<'a>(input: &'a [u8], dictionary: &KeyDictionary)
+    -> Result<Cow<'a, [u8]>, AlmondParseError>
+{
+    if input.len() < 34 || input[32] != EXTENDED_HEADER || input[33] != VERSION_3 {
+        return Ok(Cow::Borrowed(input));
+    }
+    if input.len() > MAX_READER_LEN {
+        return Err(AlmondParseError::TooLong { max: MAX_READER_LEN });
+    }
+
+    // The v3 header is the v2 header with a different version, so parse the
+    // structure as v2 and then decode the caveats.
+    let mut v2 = input.to_vec();
+    v2[33] = VERSION_2;
+    let raw = try!(parse_structure(&v2));
+
+    let mut caveats = Vec::new();
+    let mut caveat = Vec::new();
+    let mut total = 0;
+    for (index, encoded) in raw.caveats.iter().enumerate() {
+        if encoded.is_empty() {
+            return Err(AlmondParseError::InvalidCaveat { index: index });
+        }
+
+        caveat.clear();
+        try!(
+            dictionary.decode(encoded[0], &encoded[1..], &mut caveat)
+                .ok_or(AlmondParseError::InvalidCaveat { index: index })
+        );
+
+        total += caveat.len();
+        if total > MAX_READER_LEN {
+            return Err(AlmondParseError::TooLong { max: MAX_READER_LEN });
+        }
+        push_prefixed(&mut caveats, &caveat);
+    }
+
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(raw.hash);
+
+    let caveats = RawCaveats::Prefixed(&caveats);
+    let mut expanded = Vec::with_capacity(serialized_len(
+        Format::V2, raw.generation, raw.key_id, raw.almond_type, caveats.iter()
+    ));
+    write(
+        &mut expanded, Format::V2, &hash, raw.generation, raw.key_id, raw.almond_type,
+        caveats.iter()
+    ).expect("failed to write to Vec");
+    Ok(Cow::Owned(expanded))
+}
+
+fn write_prefixed<W: io::Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut len = [0u8; varint::MAX_LEN];
+    let len_len = varint::encode_to_slice(data.len() as u64, &mut len);
+    try!(writer.write_all(&len[..len_len]));
+    writer.write_all(data)
+}
+
+
+/// The structure of a serialized almond, before the hash is checked.
+pub struct RawAlmond<'a> {
+    pub hash: &'a [u8],
+    pub format: Format,
+    pub generation: u32,
+    pub key_id: Option<&'a [u8]>,
+    pub almond_type: &'a [u8],
+    pub caveats: RawCaveats<'a>,
+}
+
+/// The serialized caveats of an almond.
+#[derive(Clone, Copy)]
+pub enum RawCaveats<'a> {
+    /// v1 caveats, or `None` if there are no caveats.
+    Lines(Option<&'a [u8]>),
+    /// v2 caveats, which have already been checked to be well formed.
+    Prefixed(&'a [u8]),
+}
+
+impl <'a> RawCaveats<'a> {
+    /// Get an iterator over the caveats.
+    pub fn iter(&self) -> Caveats<'a> {
+        match *self {
+            RawCaveats::Lines(lines) => Caveats {
+                inner: CaveatsInner::Lines(
+                    lines.map(|c| c.split(is_newline as fn(&u8) -> bool))
+                ),
+            },
+            RawCaveats::Prefixed(data) => Caveats {
+                inner: CaveatsInner::Prefixed(data),
+            },
+        }
+    }
+
+    /// Copy the caveats into a buffer in the v2 format, as stored by
+    /// `Almond`.
+    pub fn to_prefixed(&self) -> Vec<u8> {
+        match *self {
+            RawCaveats::Lines(_) => {
+                let mut buf = Vec::new();
+                for caveat in self.iter() {
+                    push_prefixed(&mut buf, caveat);
+                }
+                buf
+            }
+            RawCaveats::Prefixed(data) => data.to_vec(),
+        }
+    }
+}
+
+
+/// An iterator over the caveats of an almond.
+#[derive(Clone)]
+pub struct Caveats<'a> {
+    inner: CaveatsInner<'a>,
+}
+
+#[derive(Clone)]
+enum CaveatsInner<'a> {
+    Lines(Option<Split<'a, u8, fn(&u8) -> bool>>),
+    Prefixed(&'a [u8]),
+}
+
+impl <'a> Iterator for Caveats<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        match self.inner {
+            CaveatsInner::Lines(ref mut it) => it.as_mut().and_then(|it| it.next()),
+            CaveatsInner::Prefixed(ref mut data) => {
+                if data.is_empty() {
+                    return None;
+                }
+
+                // We checked the caveats were well formed when parsing.
+                let (caveat, rest) = split_prefixed(data)
+                    .expect("invalid caveat length prefix");
+                *data = rest;
+                Some(caveat)
+            }
+        }
+    }
+}
+
+
+/// Parse the structure of a serialized almond, with the default limits.
+pub fn parse(input: &[u8]) -> Result<RawAlmond, AlmondParseError> {
+    parse_with_limits(input, &ParseLimits::default())
+}
+
+/// Parse the structure of a serialized almond.
+pub fn parse_with_limits<'a>(input: &'a [u8], limits: &ParseLimits)
+    -> Result<RawAlmond<'a>, AlmondParseError>
+{
+    if input.len() > limits.max_len {
+        return Err(AlmondParseError::TooLong { max: limits.max_len });
+    }
+
+    let raw = try!(parse_structure(input));
+    try!(check_limits(&raw, limits));
+    Ok(raw)
+}
+
+/// Parse the structure of a v1 almond with generation 255, with the default
+/// limits.
+///
+/// Those whose type starts with a version byte look like v2 or v3, so
+/// `parse` doesn't read them as v1. This is the only way to read them, and
+/// should only be used for almonds known to have been serialized in v1
+/// before v2 existed. Input with any other generation byte is parsed as by
+/// `parse`.
+pub fn parse_legacy_v1(input: &[u8]) -> Result<RawAlmond, AlmondParseError> {
+    let limits = ParseLimits::default();
+    if input.len() > limits.max_len {
+        return Err(AlmondParseError::TooLong { max: limits.max_len });
+    }
+    if input.len() < 34 {
+        return Err(AlmondParseError::TooShort { len: input.len() });
+    }
+    if input[32] != EXTENDED_HEADER {
+        return parse_with_limits(input, &limits);
+    }
+
+    let raw = try!(parse_v1_structure(input));
+    try!(check_limits(&raw, &limits));
+    Ok(raw)
+}
+
+fn check_limits(raw: &RawAlmond, limits: &ParseLimits) -> Result<(), AlmondParseError> {
+    for (index, caveat) in raw.caveats.iter().enumerate() {
+        try!(check_caveat_limits(limits, index, caveat));
+    }
+    Ok(())
+}
+
+fn parse_structure(input: &[u8]) -> Result<RawAlmond, AlmondParseError> {
+    if input.len() < 34 {
+        return Err(AlmondParseError::TooShort { len: input.len() });
+    }
+
+    if !is_extended(input) {
+        return parse_v1_structure(input);
+    }
+
+    let hash = &input[..32];
+    let header = &input[33..];
+    if header.len() < 4 {
+        return Err(AlmondParseError::TooShort { len: input.len() });
+    }
+    try!(check_header(header[0], header[1]));
+
+    let (generation, key_id, almond_type, caveats) = try!(split_header(header));
+
+    // Check all the caveats are well formed up front, so that iterating over
+    // them can't fail.
+    let mut rest = caveats;
+    let mut index = 0;
+    while !rest.is_empty() {
+        let (_, next) = try!(
+            split_prefixed(rest).ok_or(AlmondParseError::InvalidCaveat { index: index })
+        );
+        rest = next;
+        index += 1;
+    }
+
+    Ok(RawAlmond {
+        hash: hash,
+        format: Format::V2,
+        generation: generation,
+        key_id: key_id,
+        almond_type: almond_type,
+        caveats: RawCaveats::Prefixed(caveats),
+    })
+}
+
+/// Parse input of at least 34 bytes as v1, whatever its generation.
+fn parse_v1_structure(input: &[u8]) -> Result<RawAlmond, AlmondParseError> {
+    let body = &input[33..];
+
+    let (almond_type, caveats) = match body.iter().position(|c| *c == b'\n') {
+        Some(idx) => (&body[..idx], Some(&body[idx + 1..])),
+        None => (body, None),
+    };
+
+    if let Some(caveats) = caveats {
+        if let Some(index) = caveats.split(is_newline).position(|c| c.is_empty()) {
+            return Err(AlmondParseError::EmptyCaveat { index: index });
+        }
+    }
+
+    Ok(RawAlmond {
+        hash: &input[..32],
+        format: Format::V1,
+        generation: input[32] as u32,
+        key_id: None,
+        almond_type: almond_type,
+        caveats: RawCaveats::Lines(caveats),
+    })
+}
+
+/// If `input` is a v2 serialization with compressed caveats, return the
+/// length of the hash and header preceding them.
+#[cfg(feature = "deflate")]
+pub fn compressed_header_len(input: &[u8]) -> Result<Option<usize>, AlmondParseError> {
+    if input.len() < 37 || !is_extended(input) || input[34] & FLAG_DEFLATE == 0 {
+        return Ok(None);
+    }
+    try!(check_header(input[33], input[34] & !FLAG_DEFLATE));
+
+    let (_, _, _, caveats) = try!(split_header(&input[33..]));
+    Ok(Some(input.len() - caveats.len()))
+}
+
+/// Split a v2 header, which has at least four bytes and has been checked
+/// with `check_header`, into the generation, key id, type and the remaining
+/// data.
+fn split_header(header: &[u8])
+    -> Result<(u32, Option<&[u8]>, &[u8], &[u8]), AlmondParseError>
+{
+    let (generation, key_id, rest) = try!(split_generation_and_key_id(header));
+
+    let (almond_type, rest) = try!(
+        split_prefixed(rest).ok_or(AlmondParseError::MissingType)
+    );
+
+    Ok((generation, key_id, almond_type, rest))
+}
+
+/// Like `split_header`, but stop before the type.
+fn split_generation_and_key_id(header: &[u8])
+    -> Result<(u32, Option<&[u8]>, &[u8]), AlmondParseError>
+{
+    let (generation, rest) = if header[1] & FLAG_EXTENDED_GENERATION != 0 {
+        let (generation, read) = try!(
+            varint::decode(&header[2..])
+                .and_then(|(g, read)| to_generation(g).map(|g| (g, read)))
+                .ok_or(AlmondParseError::InvalidGeneration)
+        );
+        (generation, &header[2 + read..])
+    } else {
+        (header[2] as u32, &header[3..])
+    };
+
+    let (key_id, rest) = if header[1] & FLAG_KEY_ID != 0 {
+        let (key_id, rest) = try!(
+            split_prefixed(rest).ok_or(AlmondParseError::InvalidKeyId)
+        );
+        (Some(key_id), rest)
+    } else {
+        (None, rest)
+    };
+
+    Ok((generation, key_id, rest))
+}
+
+/// Check the version and flags of a v2 header are supported.
+fn check_header(version: u8, flags: u8) -> Result<(), AlmondParseError> {
+    if version != VERSION_2 {
+        return Err(AlmondParseError::UnsupportedVersion { version: version });
+    }
+
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(AlmondParseError::UnknownFlags { flags: flags & !KNOWN_FLAGS });
+    }
+
+    Ok(())
+}
+
+/// Split a length prefixed field off the front of `data`, returning the
+/// field and the remaining data.
+fn split_prefixed(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, read) = match varint::decode(data) {
+        Some(res) => res,
+        None => return None,
+    };
+
+    let rest = &data[read..];
+    if (rest.len() as u64) < len {
+        return None;
+    }
+
+    let len = len as usize;
+    Some((&rest[..len], &rest[len..]))
+}
+
+/// Whether `input`, of at least 34 bytes, starts with the hash followed by
+/// an extended header, rather than being v1 with generation 255.
+fn is_extended(input: &[u8]) -> bool {
+    input[32] == EXTENDED_HEADER && is_version(input[33])
+}
+
+/// Whether `byte`, following `EXTENDED_HEADER`, is the version of an
+/// extended header.
+fn is_version(byte: u8) -> bool {
+    byte == VERSION_2 || byte == VERSION_3
+}
+
+/// Convert a decoded extended generation, which must not fit in a byte.
+fn to_generation(generation: u64) -> Option<u32> {
+    if generation > 0xFF && generation <= u32::max_value() as u64 {
+        Some(generation as u32)
+    } else {
+        None
+    }
+}
+
+fn is_newline(c: &u8) -> bool {
+    *c == b'\n'
+}
+
+
+/// The header of an almond read by `read_header`.
+pub struct StreamHeader {
+    pub hash: [u8; 32],
+    pub format: Format,
+    pub generation: u32,
+    pub key_id: Option<Vec<u8>>,
+    pub almond_type: Vec<u8>,
+}
+
+/// Reads the caveats following a `StreamHeader`.
+pub struct CaveatStream {
+    format: Format,
+    more: bool,
+    index: usize,
+}
+
+/// Read the header of a serialized almond from `reader`.
+pub fn read_header<R: BufRead>(reader: &mut R)
+    -> Result<(StreamHeader, CaveatStream), AlmondParseError>
+{
+    let mut hash = [0u8; 32];
+    try!(read_exact(reader, &mut hash));
+
+    let mut generation = [0u8; 1];
+    try!(read_exact(reader, &mut generation));
+
+    let mut almond_type = Vec::new();
+
+    let extended = generation[0] == EXTENDED_HEADER
+        && try!(reader.fill_buf()).first().map_or(false, |b| is_version(*b));
+
+    if !extended {
+        let more = try!(read_line(reader, &mut almond_type));
+
+        let header = StreamHeader {
+            hash: hash,
+            format: Format::V1,
+            generation: generation[0] as u32,
+            key_id: None,
+            almond_type: almond_type,
+        };
+        return Ok((header, CaveatStream { format: Format::V1, more: more, index: 0 }));
+    }
+
+    let mut header = [0u8; 2];
+    try!(read_exact(reader, &mut header));
+    try!(check_header(header[0], header[1]));
+
+    let generation = if header[1] & FLAG_EXTENDED_GENERATION != 0 {
+        try!(
+            to_generation(try!(read_varint(reader)))
+                .ok_or(AlmondParseError::InvalidGeneration)
+        )
+    } else {
+        try!(read_exact(reader, &mut generation));
+        generation[0] as u32
+    };
+
+    let key_id = if header[1] & FLAG_KEY_ID != 0 {
+        let mut key_id = Vec::new();
+        try!(read_prefixed(reader, &mut key_id));
+        Some(key_id)
+    } else {
+        None
+    };
+
+    try!(read_prefixed(reader, &mut almond_type));
+
+    let header = StreamHeader {
+        hash: hash,
+        format: Format::V2,
+        generation: generation,
+        key_id: key_id,
+        almond_type: almond_type,
+    };
+    Ok((header, CaveatStream { format: Format::V2, more: true, index: 0 }))
+}
+
+impl CaveatStream {
+    /// Read the next caveat into `buf`, returning false if there are no more
+    /// caveats.
+    pub fn next<R: BufRead>(&mut self, reader: &mut R, buf: &mut Vec<u8>)
+        -> Result<bool, AlmondParseError>
+    {
+        if !self.more {
+            return Ok(false);
+        }
+
+        match self.format {
+            Format::V1 => {
+                let start = buf.len();
+                self.more = try!(read_line(reader, buf));
+                if buf.len() == start {
+                    return Err(AlmondParseError::EmptyCaveat { index: self.index });
+                }
+                self.index += 1;
+                Ok(true)
+            }
+            Format::V2 => {
+                if try!(reader.fill_buf()).is_empty() {
+                    self.more = false;
+                    return Ok(false);
+                }
+                try!(read_prefixed(reader, buf));
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Fill `buf` from the reader, treating EOF as a truncated almond.
+fn read_exact<R: BufRead>(reader: &mut R, buf: &mut [u8])
+    -> Result<(), AlmondParseError>
+{
+    reader.read_exact(buf).map_err(|err| {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            AlmondParseError::Truncated
+        } else {
+            AlmondParseError::Io(err)
+        }
+    })
+}
+
+/// Read up to the next newline or EOF, returning whether a newline was found.
+fn read_line<R: BufRead>(reader: &mut R, buf: &mut Vec<u8>)
+    -> Result<bool, AlmondParseError>
+{
+    try!(reader.read_until(b'\n', buf));
+
+    if buf.last() == Some(&b'\n') {
+        buf.pop();
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Read a varint from the reader.
+fn read_varint<R: BufRead>(reader: &mut R) -> Result<u64, AlmondParseError> {
+    let mut buf = Vec::with_capacity(varint::MAX_LEN);
+    loop {
+        let mut byte = [0u8; 1];
+        try!(read_exact(reader, &mut byte));
+        buf.push(byte[0]);
+
+        if byte[0] & 0x80 == 0 || buf.len() == varint::MAX_LEN {
+            break;
+        }
+    }
+
+    varint::decode(&buf)
+        .map(|(value, _)| value)
+        .ok_or(AlmondParseError::InvalidAlmond)
+}
+
+/// Read a length prefixed field into `buf`.
+fn read_prefixed<R: BufRead>(reader: &mut R, buf: &mut Vec<u8>)
+    -> Result<(), AlmondParseError>
+{
+    let len = try!(read_varint(reader));
+
+    let read = try!(reader.take(len).read_to_end(buf));
+    if (read as u64) < len {
+        return Err(AlmondParseError::Truncated);
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefixed(caveats: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for caveat in caveats {
+            push_prefixed(&mut buf, caveat);
+        }
+        buf
+    }
+
+    fn iter(caveats: &[u8]) -> Caveats {
+        RawCaveats::Prefixed(caveats).iter()
+    }
+
+    #[test]
+    fn v2_round_trip() {
+        let hash = [7u8; 32];
+        let caveats = prefixed(&[b"user erikj".to_vec(), b"data a\nb".to_vec(), Vec::new()]);
+
+        let mut buf = Vec::new();
+        write(&mut buf, Format::V2, &hash, 3, None, b"login", iter(&caveats)).unwrap();
+        assert_eq!(buf.len(), serialized_len(Format::V2, 3, None, b"login", iter(&caveats)));
+        assert_eq!(&buf[32..37], &[0xFF, 2, 0, 3, 5]);
+
+        let raw = parse(&buf).unwrap();
+        assert_eq!(raw.hash, &hash[..]);
+        assert_eq!(raw.format, Format::V2);
+        assert_eq!(raw.generation, 3);
+        assert_eq!(raw.almond_type, b"login");
+        assert_eq!(raw.caveats.iter().collect::<Vec<_>>(), vec![
+            &b"user erikj"[..], &b"data a\nb"[..], &b""[..],
+        ]);
+    }
+
+    #[test]
+    fn v1_empty_caveats() {
+        let mut buf = vec![7u8; 32];
+        buf.push(1);
+        buf.extend(b"login\nuser erikj".iter());
+        assert!(parse(&buf).is_ok());
+
+        let cases: Vec<(&[u8], usize)> = vec![
+            (b"login\n", 0),
+            (b"login\nuser erikj\n", 1),
+            (b"login\n\nuser erikj", 0),
+        ];
+
+        for (body, index) in cases {
+            let mut input = buf[..33].to_vec();
+            input.extend(body.iter());
+
+            match parse(&input) {
+                Err(AlmondParseError::EmptyCaveat { index: i }) if i == index => {}
+                res => panic!("unexpected result: {:?}", res.err()),
+            }
+
+            let mut reader = &input[..];
+            let (_, mut stream) = read_header(&mut reader).unwrap();
+            let mut res = Ok(true);
+            while let Ok(true) = res {
+                res = stream.next(&mut reader, &mut Vec::new());
+            }
+            match res {
+                Err(AlmondParseError::EmptyCaveat { index: i }) if i == index => {}
+                res => panic!("unexpected result: {:?}", res.err()),
+            }
+        }
+    }
+
+    #[test]
+    fn v3_round_trip() {
+        let hash = [7u8; 32];
+        let caveats = prefixed(&[
+            b"user erikj".to_vec(), b"device phone".to_vec(), b"scope".to_vec()
+        ]);
+        let dictionary = KeyDictionary::new();
+
+        let mut buf = Vec::new();
+        write_with_dictionary(&mut buf, &dictionary, &hash, 3, None, b"login", iter(&caveats)).unwrap();
+        assert_eq!(&buf[32..43], &[0xFF, 3, 0, 3, 5, b'l', b'o', b'g', b'i', b'n', 7]);
+        assert_eq!(&buf[43..50], &[1, b' ', b'e', b'r', b'i', b'k', b'j']);
+
+        // Plain v3 isn't accepted without a dictionary.
+        match parse(&buf) {
+            Err(AlmondParseError::UnsupportedVersion { version: 3 }) => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+
+        let expanded = expand_dictionary(&buf, &dictionary).unwrap();
+        let mut v2 = Vec::new();
+        write(&mut v2, Format::V2, &hash, 3, None, b"login", iter(&caveats)).unwrap();
+        assert_eq!(&expanded[..], &v2[..]);
+
+        // Other formats are passed through.
+        assert_eq!(&expand_dictionary(&v2, &dictionary).unwrap()[..], &v2[..]);
+
+        // Caveats need at least a code, and must be canonically encoded.
+        let mut invalid = buf[..42].to_vec();
+        invalid.extend_from_slice(&[0]);
+        match expand_dictionary(&invalid, &dictionary) {
+            Err(AlmondParseError::InvalidCaveat { index: 0 }) => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+
+        let mut invalid = buf[..42].to_vec();
+        invalid.extend_from_slice(&[5, 0, b'u', b's', b'e', b'r']);
+        match expand_dictionary(&invalid, &dictionary) {
+            Err(AlmondParseError::InvalidCaveat { index: 0 }) => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+    }
+
+    #[test]
+    fn v2_invalid() {
+        let hash = [7u8; 32];
+        let caveats = prefixed(&[b"user erikj".to_vec()]);
+
+        let mut buf = Vec::new();
+        write(&mut buf, Format::V2, &hash, 3, None, b"login", iter(&caveats)).unwrap();
+
+        // Truncated caveat
+        match parse(&buf[..buf.len() - 1]) {
+            Err(AlmondParseError::InvalidCaveat { index: 0 }) => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+
+        // Unknown version
+        let mut unknown = buf.clone();
+        unknown[33] = 3;
+        match parse(&unknown) {
+            Err(AlmondParseError::UnsupportedVersion { version: 3 }) => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+
+        // Unknown flags
+        let mut flags = buf.clone();
+        flags[34] = 0x81;
+        match parse(&flags) {
+            Err(AlmondParseError::UnknownFlags { flags: 0x80 }) => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+
+        // Extended generations must not fit in a byte
+        let mut extended = buf.clone();
+        extended[34] = FLAG_EXTENDED_GENERATION;
+        match parse(&extended) {
+            Err(AlmondParseError::InvalidGeneration) => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+
+        match parse(&buf[..33]) {
+            Err(AlmondParseError::TooShort { len: 33 }) => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+    }
+
+    #[test]
+    fn extended_generation() {
+        let hash = [7u8; 32];
+        let caveats = prefixed(&[b"user erikj".to_vec()]);
+
+        let mut buf = Vec::new();
+        write(&mut buf, Format::V2, &hash, 300, None, b"login", iter(&caveats)).unwrap();
+        assert_eq!(buf.len(), serialized_len(Format::V2, 300, None, b"login", iter(&caveats)));
+        assert_eq!(&buf[32..38], &[0xFF, 2, FLAG_EXTENDED_GENERATION, 0xAC, 0x02, 5]);
+
+        let raw = parse(&buf).unwrap();
+        assert_eq!(raw.generation, 300);
+        assert_eq!(raw.almond_type, b"login");
+        assert_eq!(raw.caveats.iter().count(), 1);
+
+        let mut reader = &buf[..];
+        let (header, _) = read_header(&mut reader).unwrap();
+        assert_eq!(header.generation, 300);
+        assert_eq!(header.almond_type, b"login");
+
+        let mut hashed = Vec::new();
+        encode_generation(3, &mut hashed);
+        encode_generation(300, &mut hashed);
+        assert_eq!(hashed, vec![3, 0xAC, 0x02]);
+    }
+
+    #[test]
+    fn key_id() {
+        let hash = [7u8; 32];
+        let caveats = prefixed(&[b"user erikj".to_vec()]);
+
+        let mut buf = Vec::new();
+        write(&mut buf, Format::V2, &hash, 3, Some(&b"k1"[..]), b"login", iter(&caveats)).unwrap();
+        assert_eq!(buf.len(), serialized_len(Format::V2, 3, Some(&b"k1"[..]), b"login", iter(&caveats)));
+        assert_eq!(&buf[32..40], &[0xFF, 2, FLAG_KEY_ID, 3, 2, b'k', b'1', 5]);
+
+        let raw = parse(&buf).unwrap();
+        assert_eq!(raw.key_id, Some(&b"k1"[..]));
+        assert_eq!(raw.almond_type, b"login");
+        assert_eq!(raw.caveats.iter().count(), 1);
+
+        let mut reader = &buf[..];
+        let (header, _) = read_header(&mut reader).unwrap();
+        assert_eq!(header.key_id, Some(b"k1".to_vec()));
+        assert_eq!(header.almond_type, b"login");
+
+        // The key id length runs past the end of the input.
+        match parse(&buf[..38]) {
+            Err(AlmondParseError::InvalidKeyId) => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+    }
+
+    #[test]
+    fn for_contents() {
+        let plain = || vec![&b"user erikj"[..]];
+        let newline = vec![&b"user erik\nj"[..]];
+
+        assert_eq!(Format::V1.for_contents(1, b"login", plain()), Format::V1);
+        assert_eq!(Format::V1.for_contents(1, b"login", newline), Format::V2);
+        assert_eq!(Format::V1.for_contents(1, b"login", vec![&b""[..]]), Format::V2);
+        assert_eq!(Format::V1.for_contents(255, b"login", plain()), Format::V1);
+        assert_eq!(Format::V1.for_contents(255, b"\x02login", plain()), Format::V2);
+        assert_eq!(Format::V1.for_contents(255, b"\x03login", plain()), Format::V2);
+        assert_eq!(Format::V1.for_contents(256, b"login", plain()), Format::V2);
+        assert_eq!(Format::V1.for_contents(1, b"log\nin", plain()), Format::V2);
+        assert_eq!(Format::V2.for_contents(1, b"login", plain()), Format::V2);
+    }
+}
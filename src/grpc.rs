@@ -0,0 +1,141 @@
+//! Helpers for sending almonds in gRPC metadata, e.g. for service to service
+//! authentication.
+//!
+//! Almonds are sent in binary form under the `x-almond-bin` key. gRPC
+//! requires the values of keys ending in `-bin` to be Base64 encoded on the
+//! wire, which `to_metadata_value` and `from_metadata_value` handle for
+//! libraries exposing the raw header values.
+//!
+//! With the `tonic` feature, `insert_metadata` and `from_metadata` read and
+//! write a tonic `MetadataMap` directly.
+//!
+//! ```
+//! # use almonds::Almond;
+//! use almonds::grpc::{from_metadata_value, to_metadata_value, METADATA_KEY};
+//!
+//! let almond = Almond::create(b"secret", 1, b"service".to_vec());
+//!
+//! let value = to_metadata_value(&almond);
+//! assert_eq!(METADATA_KEY, "x-almond-bin");
+//! assert_eq!(from_metadata_value(b"secret", value.as_bytes()).unwrap(), almond);
+//! ```
+
+use {Almond, AlmondParseError, Base64Config};
+
+#[cfg(feature = "tonic")]
+use tonic::Status;
+#[cfg(feature = "tonic")]
+use tonic::metadata::{MetadataMap, MetadataValue};
+
+
+/// The metadata key almonds are sent under.
+pub const METADATA_KEY : &'static str = "x-almond-bin";
+
+
+/// Encode an almond as the raw value of a `-bin` metadata key.
+///
+/// This is the standard Base64 alphabet without padding, as gRPC
+/// implementations send.
+pub fn to_metadata_value(almond: &Almond) -> String {
+    almond.serialize_base64_with(Base64Config::STANDARD_NO_PAD)
+}
+
+/// Parse the raw value of a `-bin` metadata key, and validate that the hashes
+/// match.
+///
+/// gRPC implementations must accept values with or without padding, and so
+/// does this.
+pub fn from_metadata_value(key: &[u8], value: &[u8]) -> Result<Almond, AlmondParseError> {
+    Almond::parse_base64_and_validate(key, value)
+}
+
+
+quick_error! {
+    /// An error returned by `from_metadata`.
+    #[derive(Debug)]
+    pub enum MetadataError {
+        /// The metadata has no almond.
+        Missing {
+            display("no {} metadata", METADATA_KEY)
+        }
+
+        /// The almond could not be parsed or its hash did not match.
+        Parse(err: AlmondParseError) {
+            from()
+            display("invalid almond: {}", err)
+            cause(err)
+        }
+    }
+}
+
+
+/// Add the almond to tonic metadata, replacing any already there.
+#[cfg(feature = "tonic")]
+pub fn insert_metadata(metadata: &mut MetadataMap, almond: &Almond) {
+    metadata.insert_bin(METADATA_KEY, MetadataValue::from_bytes(&almond.serialize_binary()));
+}
+
+/// Get the almond from tonic metadata, and validate that the hashes match.
+#[cfg(feature = "tonic")]
+pub fn from_metadata(key: &[u8], metadata: &MetadataMap) -> Result<Almond, MetadataError> {
+    let value = try!(metadata.get_bin(METADATA_KEY).ok_or(MetadataError::Missing));
+    Ok(try!(from_metadata_value(key, value.as_encoded_bytes())))
+}
+
+/// Converts to an `UNAUTHENTICATED` status, so services can use `try!` on
+/// the result of `from_metadata`.
+#[cfg(feature = "tonic")]
+impl From<MetadataError> for Status {
+    fn from(err: MetadataError) -> Status {
+        Status::unauthenticated(err.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Almond, AlmondParseError};
+
+    #[test]
+    fn metadata_values() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"service".to_vec());
+        almond.add_caveat(b"caller", Some(b"billing"));
+
+        let value = to_metadata_value(&almond);
+        assert!(!value.contains('='));
+        assert!(value.bytes().all(|c| c != b'-' && c != b'_'));
+        assert_eq!(from_metadata_value(key, value.as_bytes()).unwrap(), almond);
+
+        let padded = almond.serialize_base64_with(Base64Config::STANDARD);
+        assert_eq!(from_metadata_value(key, padded.as_bytes()).unwrap(), almond);
+
+        match from_metadata_value(b"other", value.as_bytes()) {
+            Err(AlmondParseError::IncorrectHash) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[cfg(feature = "tonic")]
+    #[test]
+    fn tonic_metadata() {
+        use tonic::Code;
+        use tonic::metadata::MetadataMap;
+
+        let key = b"this_is_a_secret";
+        let almond = Almond::create(key, 1, b"service".to_vec());
+
+        let mut metadata = MetadataMap::new();
+        match from_metadata(key, &metadata) {
+            Err(MetadataError::Missing) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        insert_metadata(&mut metadata, &almond);
+        assert_eq!(from_metadata(key, &metadata).unwrap(), almond);
+
+        let status = Status::from(from_metadata(b"other", &metadata).unwrap_err());
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+}
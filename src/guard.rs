@@ -0,0 +1,294 @@
+//! A Rocket request guard, enabled by the `rocket` feature.
+//!
+//! A handler taking a `Verified<P>` only runs for requests with an almond in
+//! a `Authorization: Bearer <token>` header that is valid for the
+//! `AlmondKey` managed by Rocket and accepted by the policy of the
+//! `PolicyMarker` `P`. The claims of the marker are extracted from the
+//! almond.
+//!
+//! ```ignore
+//! use almonds::VerifierPolicy;
+//! use almonds::guard::{AlmondKey, PolicyMarker, Verified};
+//!
+//! #[derive(AlmondCaveats)]
+//! struct User {
+//!     user: String,
+//! }
+//!
+//! struct Access;
+//!
+//! impl PolicyMarker for Access {
+//!     type Claims = User;
+//!
+//!     fn policy() -> VerifierPolicy {
+//!         let mut policy = VerifierPolicy::new(1, b"access".to_vec());
+//!         policy.allow(b"user");
+//!         policy
+//!     }
+//! }
+//!
+//! #[get("/profile")]
+//! fn profile(almond: Verified<Access>) -> String {
+//!     format!("hello {}", almond.claims().user)
+//! }
+//!
+//! rocket::build()
+//!     .manage(AlmondKey::new(b"secret"))
+//!     .mount("/", routes![profile]);
+//! ```
+
+use rocket_crate::http::Status;
+use rocket_crate::outcome::Outcome;
+use rocket_crate::request::{self, FromRequest, Request};
+
+use std::future::{self, Future};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::pin::Pin;
+
+use claims::AlmondCaveats;
+use http::bearer_token;
+use verified::from_validated;
+use {Almond, AlmondParseError, VerifiedAlmond, VerifierPolicy, VerifyError};
+
+
+/// Names the policy and claims required by a `Verified` guard.
+pub trait PolicyMarker: 'static {
+    /// The claims extracted from accepted almonds, `()` for none.
+    type Claims: AlmondCaveats + Send;
+
+    /// Build the policy almonds must satisfy. This is called for every
+    /// request.
+    fn policy() -> VerifierPolicy;
+}
+
+
+/// The key almonds are validated with, which must be managed by Rocket.
+pub struct AlmondKey {
+    key: Vec<u8>,
+}
+
+impl AlmondKey {
+    /// Create a new instance with the given key.
+    pub fn new(key: &[u8]) -> AlmondKey {
+        AlmondKey { key: key.to_vec() }
+    }
+}
+
+
+quick_error! {
+    /// The reason a `Verified` guard failed.
+    #[derive(Debug)]
+    pub enum GuardError {
+        /// No `AlmondKey` is managed by Rocket.
+        NotConfigured {
+            display("no AlmondKey is managed")
+        }
+
+        /// The request has no bearer token.
+        MissingToken {
+            display("missing bearer token")
+        }
+
+        /// The token could not be parsed or its hash did not match.
+        Parse(err: AlmondParseError) {
+            from()
+            display("invalid almond: {}", err)
+            cause(err)
+        }
+
+        /// The almond was valid but was rejected by the policy, or its
+        /// claims could not be extracted.
+        Rejected(err: VerifyError) {
+            from()
+            display("almond rejected: {}", err)
+            cause(err)
+        }
+    }
+}
+
+impl GuardError {
+    /// The status to respond with: `500` if no key is managed, `403` if the
+    /// almond was rejected and `401` otherwise.
+    pub fn status(&self) -> Status {
+        match *self {
+            GuardError::NotConfigured => Status::InternalServerError,
+            GuardError::Rejected(_) => Status::Forbidden,
+            _ => Status::Unauthorized,
+        }
+    }
+}
+
+
+/// A verified almond and its claims, see the module documentation.
+///
+/// This dereferences to the `VerifiedAlmond`.
+pub struct Verified<P: PolicyMarker> {
+    almond: VerifiedAlmond,
+    claims: P::Claims,
+    marker: PhantomData<fn() -> P>,
+}
+
+impl <P: PolicyMarker> Verified<P> {
+    /// Validate and verify the token from a bearer `Authorization` header.
+    pub fn from_token(key: &AlmondKey, token: &[u8]) -> Result<Verified<P>, GuardError> {
+        let almond = try!(Almond::parse_base64_and_validate(&key.key, token));
+        try!(P::policy().check_detailed(&almond));
+
+        let claims = try!(P::Claims::extract_from(&almond).map_err(VerifyError::from));
+
+        Ok(Verified {
+            almond: from_validated(almond),
+            claims: claims,
+            marker: PhantomData,
+        })
+    }
+
+    /// Get the claims extracted from the almond.
+    pub fn claims(&self) -> &P::Claims {
+        &self.claims
+    }
+
+    /// Get the almond.
+    pub fn almond(&self) -> &VerifiedAlmond {
+        &self.almond
+    }
+
+    /// Split into the almond and its claims.
+    pub fn into_parts(self) -> (VerifiedAlmond, P::Claims) {
+        (self.almond, self.claims)
+    }
+
+    fn from_request_sync(req: &Request) -> Result<Verified<P>, GuardError> {
+        let key = try!(req.rocket().state::<AlmondKey>().ok_or(GuardError::NotConfigured));
+        let token = try!(
+            req.headers().get_one("Authorization")
+                .and_then(|value| bearer_token(value.as_bytes()))
+                .ok_or(GuardError::MissingToken)
+        );
+
+        Verified::from_token(key, token)
+    }
+}
+
+impl <P: PolicyMarker> Deref for Verified<P> {
+    type Target = VerifiedAlmond;
+
+    fn deref(&self) -> &VerifiedAlmond {
+        &self.almond
+    }
+}
+
+// This is what `#[rocket::async_trait]` expands to, as `async fn` isn't
+// available in this edition.
+impl <'r, P: PolicyMarker> FromRequest<'r> for Verified<P> {
+    type Error = GuardError;
+
+    fn from_request<'l, 'a>(req: &'r Request<'l>)
+        -> Pin<Box<Future<Output = request::Outcome<Verified<P>, GuardError>> + Send + 'a>>
+        where 'r: 'a, 'l: 'a, Self: 'a
+    {
+        let outcome = match Verified::from_request_sync(req) {
+            Ok(verified) => Outcome::Success(verified),
+            Err(err) => Outcome::Error((err.status(), err)),
+        };
+        Box::pin(future::ready(outcome))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rocket_crate::{self, Build, Rocket};
+    use rocket_crate::http::Header;
+    use rocket_crate::local::blocking::Client;
+    use rocket_crate::outcome::Outcome;
+    use rocket_crate::request::FromRequest;
+
+    use super::*;
+    use claims::{AlmondCaveats, CaveatList, ClaimsError};
+    use {Almond, VerifierPolicy, VerifyError};
+
+    struct User(String);
+
+    impl AlmondCaveats for User {
+        fn add_to(&self, almond: &mut Almond) {
+            almond.add_caveat(b"user", Some(self.0.as_bytes()));
+        }
+
+        fn extract(caveats: &CaveatList) -> Result<User, ClaimsError> {
+            match caveats.claim::<String>("user") {
+                Ok(Some(user)) => Ok(User(user)),
+                Ok(None) => Err(ClaimsError::Missing { key: "user".to_owned() }),
+                Err(err) => Err(err),
+            }
+        }
+    }
+
+    struct Access;
+
+    impl PolicyMarker for Access {
+        type Claims = User;
+
+        fn policy() -> VerifierPolicy {
+            let mut policy = VerifierPolicy::new(1, b"access".to_vec());
+            policy.allow(b"user");
+            policy
+        }
+    }
+
+    fn guard(rocket: Rocket<Build>, authorization: Option<String>)
+        -> Result<String, (u16, GuardError)>
+    {
+        let client = Client::untracked(rocket).unwrap();
+        let mut req = client.get("/");
+        if let Some(authorization) = authorization {
+            req.add_header(Header::new("Authorization", authorization));
+        }
+
+        match rocket_crate::execute(Verified::<Access>::from_request(req.inner())) {
+            Outcome::Success(verified) => Ok(verified.claims().0.clone()),
+            Outcome::Error((status, err)) => Err((status.code, err)),
+            Outcome::Forward(_) => panic!("unexpected forward"),
+        }
+    }
+
+    #[test]
+    fn request_guard() {
+        let key = b"this_is_a_secret";
+        let rocket = || rocket_crate::build().manage(AlmondKey::new(key));
+
+        let mut almond = Almond::create(key, 1, b"access".to_vec());
+        User("erikj".to_owned()).add_to(&mut almond);
+        let bearer = format!("Bearer {}", almond.serialize_base64());
+
+        assert_eq!(guard(rocket(), Some(bearer.clone())).unwrap(), "erikj");
+
+        match guard(rocket(), None) {
+            Err((401, GuardError::MissingToken)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match guard(rocket(), Some("Bearer invalid".to_owned())) {
+            Err((401, GuardError::Parse(_))) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let other = Almond::create(key, 1, b"access".to_vec());
+        match guard(rocket(), Some(format!("Bearer {}", other.serialize_base64()))) {
+            Err((403, GuardError::Rejected(VerifyError::Claims(_)))) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        almond.add_caveat(b"admin", None);
+        match guard(rocket(), Some(format!("Bearer {}", almond.serialize_base64()))) {
+            Err((403, GuardError::Rejected(VerifyError::Caveats { .. }))) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match guard(rocket_crate::build(), Some(bearer)) {
+            Err((500, GuardError::NotConfigured)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Caveats confining an almond to a single HTTP request.
+//!
+//! This allows short lived, presigned URL style tokens that can only be used
+//! for e.g. `GET /downloads/report.pdf` on a given host.
+//!
+//! ```
+//! # use almonds::{Almond, Verifier};
+//! # use almonds::http::{confine_to_request, RequestInfo};
+//! let request = RequestInfo::new("GET", "/downloads/report.pdf", Some("files.example.com"));
+//!
+//! let mut almond = Almond::create(b"secret", 1, b"download".to_vec());
+//! confine_to_request(&mut almond, &request);
+//!
+//! let mut v = Verifier::new(&almond, 1, b"download");
+//! v.satisfies_http_request(&request);
+//! assert!(v.verify());
+//!
+//! let other = RequestInfo::new("DELETE", "/downloads/report.pdf", Some("files.example.com"));
+//! let mut v = Verifier::new(&almond, 1, b"download");
+//! v.satisfies_http_request(&other);
+//! assert!(!v.verify());
+//! ```
+
+use Almond;
+
+
+/// The key of caveats restricting the request method.
+pub const METHOD_KEY : &'static [u8] = b"http_method";
+
+/// The key of caveats restricting the request path.
+pub const PATH_KEY : &'static [u8] = b"http_path";
+
+/// The key of caveats restricting the request host.
+pub const HOST_KEY : &'static [u8] = b"http_host";
+
+
+/// The parts of an HTTP request that can be restricted by caveats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestInfo<'r> {
+    /// The request method, e.g. `GET`. Compared case sensitively.
+    pub method: &'r str,
+    /// The request path, excluding the query string. Compared exactly.
+    pub path: &'r str,
+    /// The host the request was sent to, if known. Compared case
+    /// insensitively.
+    pub host: Option<&'r str>,
+}
+
+impl <'r> RequestInfo<'r> {
+    /// Create a new `RequestInfo`.
+    pub fn new(method: &'r str, path: &'r str, host: Option<&'r str>) -> RequestInfo<'r> {
+        RequestInfo {
+            method: method,
+            path: path,
+            host: host,
+        }
+    }
+}
+
+
+/// Add caveats confining the almond to requests with the same method, path
+/// and, if given, host as `request`.
+pub fn confine_to_request(almond: &mut Almond, request: &RequestInfo) {
+    almond.add_caveat(METHOD_KEY, Some(request.method.as_bytes()));
+    almond.add_caveat(PATH_KEY, Some(request.path.as_bytes()));
+
+    if let Some(host) = request.host {
+        almond.add_caveat(HOST_KEY, Some(host.to_lowercase().as_bytes()));
+    }
+}
+
+
+/// Get the token from the value of a `Authorization: Bearer <token>`
+/// header. The scheme is matched case insensitively.
+///
+/// ```
+/// # use almonds::http::bearer_token;
+/// assert_eq!(bearer_token(b"Bearer AQAB"), Some(&b"AQAB"[..]));
+/// assert_eq!(bearer_token(b"Basic AQAB"), None);
+/// ```
+pub fn bearer_token(header: &[u8]) -> Option<&[u8]> {
+    let idx = match header.iter().position(|c| *c == b' ') {
+        Some(idx) => idx,
+        None => return None,
+    };
+    if !header[..idx].eq_ignore_ascii_case(b"bearer") {
+        return None;
+    }
+
+    let token = header[idx + 1..].trim_ascii();
+    if token.is_empty() { None } else { Some(token) }
+}
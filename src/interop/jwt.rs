@@ -0,0 +1,218 @@
+//! Conversion between almond caveats and JWT claim sets, enabled by the `jwt`
+//! feature.
+//!
+//! This allows middleware that only understands JWTs to be fed during a
+//! migration. Only the claim set is produced, signing it is left to the JWT
+//! library.
+//!
+//! Caveats map to claims of the same name, except for the registered claims:
+//!
+//! | Caveat key       | Claim |
+//! |------------------|-------|
+//! | `EXPIRES_KEY`    | `exp` |
+//! | `NOT_BEFORE_KEY` | `nbf` |
+//! | `AUDIENCE_KEY`   | `aud` |
+//! | `TOKEN_ID_KEY`   | `jti` |
+//!
+//! `exp` and `nbf` are numbers, caveats without a value are `true`, and all
+//! other values are strings. A key with several caveats becomes an array.
+//!
+//! ```
+//! # use almonds::Almond;
+//! # use almonds::interop::jwt::{from_claims, to_claims};
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_caveat(b"expires", Some(b"1500000000"));
+//! almond.add_caveat(b"user", Some(b"erikj"));
+//!
+//! let claims = to_claims(&almond).unwrap();
+//! assert_eq!(claims["user"].as_string(), Some("erikj"));
+//! assert_eq!(claims["exp"].as_u64(), Some(1500000000));
+//!
+//! let minted = from_claims(b"secret", 1, b"access".to_vec(), &claims, &["user", "exp"]).unwrap();
+//! assert_eq!(minted.serialize_base64(), almond.serialize_base64());
+//! ```
+
+use rustc_serialize::json::{Json, Object};
+
+use std::str;
+
+use {Almond, CaveatError, AUDIENCE_KEY, EXPIRES_KEY, NOT_BEFORE_KEY, TOKEN_ID_KEY};
+
+
+/// Caveat keys that are renamed to registered claims, and whether their
+/// values are numbers.
+const REGISTERED : &'static [(&'static [u8], &'static str, bool)] = &[
+    (EXPIRES_KEY, "exp", true),
+    (NOT_BEFORE_KEY, "nbf", true),
+    (AUDIENCE_KEY, "aud", false),
+    (TOKEN_ID_KEY, "jti", false),
+];
+
+
+quick_error! {
+    /// An error returned when converting between caveats and claims.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum JwtError {
+        /// The key or value of a caveat wasn't valid UTF-8, or a numeric
+        /// caveat wasn't a number.
+        InvalidCaveat { key: Vec<u8> } {
+            display("caveat {:?} can't be represented as a claim", String::from_utf8_lossy(key))
+        }
+
+        /// The claim wasn't in the list of allowed claims.
+        UnexpectedClaim { name: String } {
+            display("claim {:?} is not allowed", name)
+        }
+
+        /// The claim's value can't be represented as a caveat.
+        InvalidClaim { name: String } {
+            display("claim {:?} can't be represented as a caveat", name)
+        }
+
+        /// The claim can't be added as a caveat.
+        Caveat(err: CaveatError) {
+            from()
+            cause(err)
+        }
+    }
+}
+
+
+/// Render the caveats of the almond as a JWT claim set.
+pub fn to_claims(almond: &Almond) -> Result<Object, JwtError> {
+    let mut claims = Object::new();
+
+    for caveat in almond.iter_caveats() {
+        let invalid = || JwtError::InvalidCaveat { key: caveat.key().to_vec() };
+
+        let (name, numeric) = match REGISTERED.iter().find(|r| r.0 == caveat.key()) {
+            Some(&(_, name, numeric)) => (name.to_owned(), numeric),
+            None => (try!(str::from_utf8(caveat.key()).map_err(|_| invalid())).to_owned(), false),
+        };
+
+        let value = match caveat.value() {
+            None => Json::Boolean(true),
+            Some(value) => {
+                let value = try!(str::from_utf8(value).map_err(|_| invalid()));
+                if numeric {
+                    Json::U64(try!(value.parse().map_err(|_| invalid())))
+                } else {
+                    Json::String(value.to_owned())
+                }
+            }
+        };
+
+        let existing = claims.remove(&name);
+        let value = match existing {
+            None => value,
+            Some(Json::Array(mut values)) => { values.push(value); Json::Array(values) }
+            Some(previous) => Json::Array(vec![previous, value]),
+        };
+        claims.insert(name, value);
+    }
+
+    Ok(claims)
+}
+
+/// Mint an almond with a caveat for each claim in the claim set.
+///
+/// Every claim must be listed in `allowed`. Other claims are rejected rather
+/// than dropped, as dropping a restrictive claim would widen the almond.
+///
+/// Caveats are added in order of claim name.
+pub fn from_claims(
+    key: &[u8],
+    generation: u32,
+    almond_type: Vec<u8>,
+    claims: &Object,
+    allowed: &[&str],
+) -> Result<Almond, JwtError> {
+    let mut almond = Almond::create(key, generation, almond_type);
+
+    for (name, value) in claims {
+        if !allowed.contains(&&name[..]) {
+            return Err(JwtError::UnexpectedClaim { name: name.clone() });
+        }
+
+        let caveat_key = match REGISTERED.iter().find(|r| r.1 == name) {
+            Some(&(caveat_key, _, _)) => caveat_key,
+            None => name.as_bytes(),
+        };
+
+        let values = match *value {
+            Json::Array(ref values) => values.clone(),
+            ref value => vec![value.clone()],
+        };
+
+        for value in values {
+            let value = match value {
+                Json::Boolean(true) => None,
+                Json::String(value) => Some(value),
+                Json::U64(value) => Some(value.to_string()),
+                _ => return Err(JwtError::InvalidClaim { name: name.clone() }),
+            };
+
+            try!(almond.try_add_caveat(caveat_key, value.as_ref().map(|v| v.as_bytes())));
+        }
+    }
+
+    Ok(almond)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Almond;
+
+    use rustc_serialize::json::{Json, Object};
+
+    fn mint(claims: &Object, allowed: &[&str]) -> Result<Almond, JwtError> {
+        from_claims(b"this_is_a_secret", 1, b"access".to_vec(), claims, allowed)
+    }
+
+    #[test]
+    fn to_claims_and_back() {
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"access".to_vec());
+        almond.add_caveat(b"id", Some(b"abc"));
+        almond.add_caveat(b"group", Some(b"admins"));
+        almond.add_caveat(b"group", Some(b"staff"));
+        almond.add_caveat(b"admin", None);
+
+        let claims = to_claims(&almond).unwrap();
+        assert_eq!(
+            Json::Object(claims.clone()).to_string(),
+            r#"{"admin":true,"group":["admins","staff"],"jti":"abc"}"#
+        );
+
+        let minted = mint(&claims, &["admin", "group", "jti"]).unwrap();
+        assert_eq!(minted.caveat_map(), almond.caveat_map());
+
+        assert_eq!(
+            mint(&claims, &["group", "jti"]).err(),
+            Some(JwtError::UnexpectedClaim { name: "admin".to_owned() })
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"access".to_vec());
+        almond.add_caveat(b"expires", Some(b"soon"));
+        assert_eq!(
+            to_claims(&almond).err(),
+            Some(JwtError::InvalidCaveat { key: b"expires".to_vec() })
+        );
+
+        let claims = Json::from_str(r#"{"user":{"name":"erikj"}}"#).unwrap();
+        assert_eq!(
+            mint(claims.as_object().unwrap(), &["user"]).err(),
+            Some(JwtError::InvalidClaim { name: "user".to_owned() })
+        );
+
+        let claims = Json::from_str(r#"{"user name":"erikj"}"#).unwrap();
+        assert_eq!(
+            mint(claims.as_object().unwrap(), &["user name"]).err(),
+            Some(JwtError::Caveat(CaveatError::InvalidKey))
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! Conversion to and from the libmacaroons v2 binary format.
+//!
+//! The generation and type of the almond are mapped into the macaroon's
+//! identifier as `<generation> <type>`, e.g. `1 login`, and each caveat
+//! becomes a first party caveat with the literal caveat as its identifier.
+//!
+//! Macaroons are signed differently to almonds, so both conversions need the
+//! key and produce a freshly signed token. The almond key is used directly as
+//! the macaroon root key.
+//!
+//! ```
+//! # use almonds::Almond;
+//! # use almonds::interop::macaroon::{from_macaroon_v2, to_macaroon_v2};
+//! let mut almond = Almond::create(b"secret", 1, b"login".to_vec());
+//! almond.add_caveat(b"user", Some(b"erikj"));
+//!
+//! let macaroon = to_macaroon_v2(&almond, b"secret");
+//! let converted = from_macaroon_v2(b"secret", &macaroon).unwrap();
+//! assert_eq!(converted.serialize_base64(), almond.serialize_base64());
+//! ```
+
+use std::str;
+
+use sha;
+use {ct_eq, varint, Almond, AlmondParseError};
+
+
+/// The key used by libmacaroons to derive the signing key from the root key.
+const KEY_GENERATOR : &'static [u8] = b"macaroons-key-generator";
+
+const VERSION : u8 = 2;
+
+const EOS : u8 = 0;
+const LOCATION : u8 = 1;
+const IDENTIFIER : u8 = 2;
+const SIGNATURE : u8 = 6;
+
+
+/// Serialize the almond as a v2 binary macaroon signed with `key`, which
+/// should be the key the almond was minted with.
+pub fn to_macaroon_v2(almond: &Almond, key: &[u8]) -> Vec<u8> {
+    let identifier = identifier(almond.generation(), almond.almond_type());
+
+    let mut signature = hmac(&hmac(KEY_GENERATOR, key), &identifier);
+
+    let mut buf = vec![VERSION];
+    write_field(&mut buf, IDENTIFIER, &identifier);
+    buf.push(EOS);
+
+    for caveat in almond.caveats() {
+        write_field(&mut buf, IDENTIFIER, caveat);
+        buf.push(EOS);
+        signature = hmac(&signature, caveat);
+    }
+
+    buf.push(EOS);
+    write_field(&mut buf, SIGNATURE, &signature);
+    buf
+}
+
+/// Parse a v2 binary macaroon, validate its signature with `key` and convert
+/// it into an almond minted with the same key.
+///
+/// Macaroons with third party caveats, or whose identifier isn't of the form
+/// `<generation> <type>`, are rejected as invalid.
+pub fn from_macaroon_v2(key: &[u8], input: &[u8]) -> Result<Almond, AlmondParseError> {
+    let macaroon = try!(parse(input));
+    let (generation, almond_type) = try!(
+        parse_identifier(macaroon.identifier).ok_or(AlmondParseError::InvalidAlmond)
+    );
+
+    let mut signature = hmac(&hmac(KEY_GENERATOR, key), macaroon.identifier);
+    for caveat in &macaroon.caveats {
+        signature = hmac(&signature, caveat);
+    }
+
+    // Always compare hashes using equality operators that are
+    // resistent to timing attacks.
+    if !ct_eq(macaroon.signature, &signature) {
+        return Err(AlmondParseError::IncorrectHash);
+    }
+
+    let mut almond = Almond::create(key, generation, almond_type.to_vec());
+    for caveat in macaroon.caveats {
+        almond.add_literal_caveat(caveat.to_vec());
+    }
+    Ok(almond)
+}
+
+
+struct RawMacaroon<'a> {
+    identifier: &'a [u8],
+    caveats: Vec<&'a [u8]>,
+    signature: &'a [u8],
+}
+
+fn parse(input: &[u8]) -> Result<RawMacaroon, AlmondParseError> {
+    if input.first() != Some(&VERSION) {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+    let mut rest = &input[1..];
+
+    let identifier = try!(read_packet(&mut rest));
+
+    let mut caveats = Vec::new();
+    loop {
+        if rest.first() == Some(&EOS) {
+            rest = &rest[1..];
+            break;
+        }
+
+        caveats.push(try!(read_packet(&mut rest)));
+    }
+
+    let (tag, signature) = try!(read_field(&mut rest));
+    if tag != SIGNATURE || signature.len() != 32 || !rest.is_empty() {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+
+    Ok(RawMacaroon {
+        identifier: identifier,
+        caveats: caveats,
+        signature: signature,
+    })
+}
+
+/// Read an optional location and an identifier, terminated by an EOS.
+fn read_packet<'a>(input: &mut &'a [u8]) -> Result<&'a [u8], AlmondParseError> {
+    let (mut tag, mut data) = try!(read_field(input));
+    if tag == LOCATION {
+        let (next_tag, next_data) = try!(read_field(input));
+        tag = next_tag;
+        data = next_data;
+    }
+
+    if tag != IDENTIFIER {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+
+    // Third party caveats have a verification id (`VID`) before the EOS,
+    // which almonds can't represent.
+    match try!(read_field(input)) {
+        (EOS, _) => Ok(data),
+        _ => Err(AlmondParseError::InvalidAlmond),
+    }
+}
+
+fn read_field<'a>(input: &mut &'a [u8]) -> Result<(u8, &'a [u8]), AlmondParseError> {
+    let tag = match input.first() {
+        Some(tag) => *tag,
+        None => return Err(AlmondParseError::InvalidAlmond),
+    };
+
+    if tag == EOS {
+        *input = &input[1..];
+        return Ok((EOS, &[]));
+    }
+
+    let (len, read) = try!(varint::decode(&input[1..]).ok_or(AlmondParseError::InvalidAlmond));
+    let start = 1 + read;
+    if ((input.len() - start) as u64) < len {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+
+    let end = start + len as usize;
+    let data = &input[start..end];
+    *input = &input[end..];
+    Ok((tag, data))
+}
+
+fn write_field(buf: &mut Vec<u8>, tag: u8, data: &[u8]) {
+    buf.push(tag);
+    varint::encode(data.len() as u64, buf);
+    buf.extend_from_slice(data);
+}
+
+fn identifier(generation: u32, almond_type: &[u8]) -> Vec<u8> {
+    let mut identifier = generation.to_string().into_bytes();
+    identifier.push(b' ');
+    identifier.extend_from_slice(almond_type);
+    identifier
+}
+
+fn parse_identifier(identifier: &[u8]) -> Option<(u32, &[u8])> {
+    let idx = match identifier.iter().position(|c| *c == b' ') {
+        Some(idx) => idx,
+        None => return None,
+    };
+    let generation = &identifier[..idx];
+
+    // Only accept the canonical encoding of the generation.
+    if generation.is_empty()
+        || !generation.iter().all(|c| b'0' <= *c && *c <= b'9')
+        || (generation.len() > 1 && generation[0] == b'0')
+    {
+        return None;
+    }
+
+    str::from_utf8(generation).ok()
+        .and_then(|generation| generation.parse().ok())
+        .map(|generation| (generation, &identifier[idx + 1..]))
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    sha::hmac_sha256(key, data)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Almond;
+
+    use rustc_serialize::hex::ToHex;
+
+    #[test]
+    fn libmacaroons_signature() {
+        // The example from the libmacaroons README.
+        let key = b"this is our super secret key; only we should know it";
+        let signature = hmac(&hmac(KEY_GENERATOR, key), b"we used our secret key");
+        assert_eq!(
+            signature.to_hex(),
+            "e3d9e02908526c4c0039ae15114115d97fdd68bf2ba379b342aaf0f617d0552f"
+        );
+
+        let signature = hmac(&signature, b"account = 3735928559");
+        assert_eq!(
+            signature.to_hex(),
+            "1efe4763f290dbce0c1d08477367e11f4eee456a64933cf662d79772dbb82128"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 300, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"admin", None);
+
+        let macaroon = to_macaroon_v2(&almond, key);
+        assert_eq!(&macaroon[..13], &b"\x02\x02\x09300 login\x00"[..]);
+
+        let converted = from_macaroon_v2(key, &macaroon).unwrap();
+        assert_eq!(converted.generation(), 300);
+        assert_eq!(converted.almond_type(), b"login");
+        assert!(converted.caveats().eq(almond.caveats()));
+        assert_eq!(converted.hash(), almond.hash());
+
+        match from_macaroon_v2(b"wrong_key", &macaroon) {
+            Err(AlmondParseError::IncorrectHash) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let key = b"this_is_a_secret";
+        let signature = [0u8; 32];
+
+        let mut location = vec![2, 1, 4];
+        location.extend_from_slice(b"here");
+        location.extend_from_slice(b"\x02\x071 login\x00\x00\x06\x20");
+        location.extend_from_slice(&signature);
+        match from_macaroon_v2(key, &location) {
+            Err(AlmondParseError::IncorrectHash) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        let inputs: &[&[u8]] = &[
+            b"",
+            b"\x01",
+            // Identifier isn't `<generation> <type>`
+            b"\x02\x02\x05login\x00\x00\x06\x20",
+            b"\x02\x02\x0801 login\x00\x00\x06\x20",
+            // Third party caveat
+            b"\x02\x02\x071 login\x00\x02\x01a\x04\x01b\x00\x00\x06\x20",
+            // Truncated
+            b"\x02\x02\x071 log",
+        ];
+
+        for input in inputs {
+            let mut input = input.to_vec();
+            if input.ends_with(b"\x06\x20") {
+                input.extend_from_slice(&signature);
+            }
+            match from_macaroon_v2(key, &input) {
+                Err(AlmondParseError::InvalidAlmond) => {}
+                res => panic!("unexpected result for {:?}: {:?}", input, res.map(|_| ())),
+            }
+        }
+    }
+}
@@ -0,0 +1,6 @@
+//! Conversions between almonds and other token formats, to allow migrating
+//! between them gradually.
+
+pub mod macaroon;
+#[cfg(feature = "jwt")]
+pub mod jwt;
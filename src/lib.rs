@@ -46,16 +46,102 @@
 //! ```
 
 
-#![feature(vec_push_all)]
 #![feature(test)]
 
 extern crate crypto;
+extern crate rand;
 extern crate rustc_serialize;
 extern crate test;
 #[macro_use] extern crate quick_error;
+#[cfg(feature = "parallel")] extern crate rayon;
+#[cfg(feature = "glob")] extern crate glob;
+#[cfg(feature = "regex")] extern crate regex;
+#[cfg(any(feature = "async", feature = "hyper"))] extern crate futures;
+#[cfg(feature = "msgpack")] extern crate rmp;
+#[cfg(feature = "deflate")] extern crate flate2;
+#[cfg(feature = "tracing")] #[macro_use] extern crate tracing;
+#[cfg(feature = "fast-sha2")] extern crate sha2;
+#[cfg(feature = "fast-sha2")] extern crate hmac;
+#[cfg(feature = "hyper")] extern crate http as http_types;
+#[cfg(feature = "hyper")] extern crate tower_layer;
+#[cfg(feature = "hyper")] extern crate tower_service;
+#[cfg(feature = "rocket")] extern crate rocket as rocket_crate;
+#[cfg(feature = "actix-web")] extern crate actix_web;
+#[cfg(feature = "tonic")] extern crate tonic;
+
+#[macro_use]
+mod macros;
 
 mod almond;
+mod base32;
+mod base45;
+mod builder;
+mod cbor;
+mod crypt;
+mod ct;
+#[cfg(feature = "deflate")]
+mod deflate;
+mod encoded;
+mod format;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+mod policy;
+mod sha;
+mod trace;
+mod varint;
+mod verified;
 mod verifier;
 
-pub use almond::{Almond, ALMOND_HASH_SEED, AlmondParseError};
-pub use verifier::Verifier;
+pub mod attenuation;
+pub mod audit;
+pub mod binding;
+pub mod claims;
+pub mod clock;
+pub mod cookie;
+pub mod dictionary;
+pub mod expr;
+pub mod grpc;
+pub mod http;
+pub mod interop;
+pub mod metrics;
+pub mod namespace;
+pub mod net;
+pub mod predicate;
+pub mod registry;
+pub mod revocation;
+pub mod scope;
+pub mod state;
+pub mod type_codes;
+pub mod value;
+
+#[cfg(feature = "async")]
+pub mod asynchronous;
+#[cfg(feature = "actix-web")]
+pub mod extractor;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "rocket")]
+pub mod guard;
+#[cfg(feature = "hyper")]
+pub mod middleware;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+#[cfg(feature = "regex")]
+pub mod regex_cache;
+
+pub use almond::{Almond, AlmondRef, Base64Config, Caveat, CaveatIter, MintingKey,
+                 UnverifiedAlmond,
+                 ALMOND_HASH_SEED, ATTENUATION_KEY, AUDIENCE_KEY, CLIENT_IP_KEY, EPOCH_KEY, EXPIRES_KEY,
+                 MAX_CAVEATS_KEY, MAX_READER_LEN, NOT_BEFORE_KEY, SCOPE_KEY, SEALED_KEY, SINGLE_USE_KEY,
+                 TOKEN_ID_KEY,
+                 AlmondParseError, CaveatError};
+pub use builder::{AlmondBuilder, HasSubject, NoSubject, ScopedBuilder,
+                  DEFAULT_MAX_VALUE_LEN};
+pub use clock::Clock;
+pub use ct::{ct_eq, HashTag};
+pub use encoded::EncodedAlmond;
+pub use format::{Caveats, Format, ParseLimits};
+pub use policy::{PolicyError, VerifierPolicy};
+pub use value::CaveatValue;
+pub use verified::{verify_token, TokenError, VerifiedAlmond};
+pub use verifier::{Verifier, VerifyError};
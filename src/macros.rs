@@ -0,0 +1,88 @@
+/// Create an almond and add caveats to it in one expression.
+///
+/// Caveats are given as `key: value`, where the value is any `CaveatValue`,
+/// or as just `key` for a caveat without a value. Keys are identifiers or
+/// string literals.
+///
+/// ```
+/// #[macro_use] extern crate almonds;
+/// # use almonds::Almond;
+///
+/// # fn main() {
+/// let ts = 1500000000u64;
+///
+/// let almond = almond!(b"secret", gen: 1, type: b"login", {
+///     user: "erikj",
+///     expires: ts,
+///     "limit.uploads": 10u64,
+///     guest,
+/// });
+///
+/// let mut expected = Almond::create(b"secret", 1, b"login".to_vec());
+/// expected.add_caveat(b"user", Some(b"erikj"));
+/// expected.add_caveat(b"expires", Some(b"1500000000"));
+/// expected.add_caveat(b"limit.uploads", Some(b"10"));
+/// expected.add_caveat(b"guest", None);
+/// assert_eq!(almond.serialize_base64(), expected.serialize_base64());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! almond {
+    ($key:expr, gen: $generation:expr, type: $almond_type:expr) => {
+        $crate::Almond::create($key, $generation, $almond_type.to_vec())
+    };
+    ($key:expr, gen: $generation:expr, type: $almond_type:expr, { $($caveats:tt)* }) => {{
+        let mut almond = $crate::Almond::create($key, $generation, $almond_type.to_vec());
+        almond!(@caveats almond, $($caveats)*);
+        almond
+    }};
+
+    (@caveats $almond:ident, ) => {};
+    (@caveats $almond:ident, $key:tt : $value:expr, $($rest:tt)*) => {
+        $almond.add_caveat_typed(almond!(@key $key).as_bytes(), $value);
+        almond!(@caveats $almond, $($rest)*);
+    };
+    (@caveats $almond:ident, $key:tt : $value:expr) => {
+        $almond.add_caveat_typed(almond!(@key $key).as_bytes(), $value);
+    };
+    (@caveats $almond:ident, $key:tt, $($rest:tt)*) => {
+        $almond.add_caveat(almond!(@key $key).as_bytes(), None);
+        almond!(@caveats $almond, $($rest)*);
+    };
+    (@caveats $almond:ident, $key:tt) => {
+        $almond.add_caveat(almond!(@key $key).as_bytes(), None);
+    };
+
+    (@key $key:ident) => { stringify!($key) };
+    (@key $key:expr) => { $key };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use Almond;
+
+    #[test]
+    fn almond_macro() {
+        let key = b"this_is_a_secret";
+
+        let almond = almond!(key, gen: 1, type: b"login", {
+            user: "erikj",
+            admin,
+            "limit.uploads": 10u64
+        });
+
+        let mut expected = Almond::create(key, 1, b"login".to_vec());
+        expected.add_caveat(b"user", Some(b"erikj"));
+        expected.add_caveat(b"admin", None);
+        expected.add_caveat(b"limit.uploads", Some(b"10"));
+        assert_eq!(almond.serialize_base64(), expected.serialize_base64());
+
+        let bare = almond!(key, gen: 2, type: b"login".to_vec());
+        assert_eq!(bare.generation(), 2);
+        assert_eq!(bare.iter_caveats().count(), 0);
+
+        let empty = almond!(key, gen: 1, type: b"login", {});
+        assert_eq!(empty.iter_caveats().count(), 0);
+    }
+}
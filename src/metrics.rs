@@ -0,0 +1,157 @@
+//! Hooks for counting parse and verification failures, e.g. to export them
+//! as Prometheus counters.
+//!
+//! An `Observer` can be set globally with `set_global_observer`, or for a
+//! single verifier with `Verifier::observe`. Use `AlmondParseError::kind`
+//! and `VerifyError::kind` as low cardinality labels.
+//!
+//! ```
+//! # use almonds::{Almond, AlmondParseError, Verifier, VerifyError};
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use almonds::metrics::Observer;
+//!
+//! #[derive(Default)]
+//! struct Counters {
+//!     rejected: AtomicUsize,
+//! }
+//!
+//! impl Observer for Counters {
+//!     fn on_verify_rejected(&self, err: &VerifyError) {
+//!         assert_eq!(err.kind(), "caveats");
+//!         self.rejected.fetch_add(1, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! let counters = Counters::default();
+//!
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_caveat(b"user", Some(b"erikj"));
+//!
+//! let mut v = Verifier::new(&almond, 1, b"access");
+//! v.observe(&counters);
+//! assert!(!v.verify());
+//! assert_eq!(counters.rejected.load(Ordering::Relaxed), 1);
+//! ```
+
+use std::sync::OnceLock;
+
+use {AlmondParseError, VerifyError};
+
+
+/// Notified of the results of parsing and verifying almonds.
+///
+/// All of the methods default to doing nothing.
+pub trait Observer {
+    /// Called when `Almond::parse_and_validate`, or one of the text parsers
+    /// that use it, succeeds.
+    fn on_parse_ok(&self) {}
+
+    /// Called when `Almond::parse_and_validate`, or one of the text parsers
+    /// that use it, fails.
+    fn on_parse_err(&self, _err: &AlmondParseError) {}
+
+    /// Called when a `Verifier` rejects an almond.
+    fn on_verify_rejected(&self, _err: &VerifyError) {}
+}
+
+impl <'a, O: Observer + ?Sized> Observer for &'a O {
+    fn on_parse_ok(&self) {
+        (**self).on_parse_ok()
+    }
+
+    fn on_parse_err(&self, err: &AlmondParseError) {
+        (**self).on_parse_err(err)
+    }
+
+    fn on_verify_rejected(&self, err: &VerifyError) {
+        (**self).on_verify_rejected(err)
+    }
+}
+
+
+quick_error! {
+    /// Returned by `set_global_observer` if an observer was already set.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum SetObserverError {
+        AlreadySet {
+            display("a global observer has already been set")
+        }
+    }
+}
+
+
+static GLOBAL: OnceLock<&'static (dyn Observer + Sync)> = OnceLock::new();
+
+/// Set the observer used for parsing, and for verifiers without their own
+/// observer. This can only be done once.
+///
+/// A boxed observer can be leaked to make it `'static`.
+pub fn set_global_observer(observer: &'static (dyn Observer + Sync))
+    -> Result<(), SetObserverError>
+{
+    GLOBAL.set(observer).map_err(|_| SetObserverError::AlreadySet)
+}
+
+/// Get the global observer, if one has been set.
+pub fn global_observer() -> Option<&'static (dyn Observer + Sync)> {
+    GLOBAL.get().cloned()
+}
+
+/// Notify the global observer of the result of parsing an almond.
+pub fn parsed<T>(res: &Result<T, AlmondParseError>) {
+    if let Some(observer) = global_observer() {
+        match *res {
+            Ok(_) => observer.on_parse_ok(),
+            Err(ref err) => observer.on_parse_err(err),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::{self, ThreadId};
+
+    use super::*;
+    use {Almond, AlmondParseError, ParseLimits};
+
+    /// Counts the parse errors on a single thread.
+    struct Counter {
+        thread: ThreadId,
+        errors: AtomicUsize,
+    }
+
+    impl Observer for Counter {
+        fn on_parse_err(&self, _err: &AlmondParseError) {
+            if thread::current().id() == self.thread {
+                self.errors.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[test]
+    fn global() {
+        // The observer stays set for the other tests in the binary, which may
+        // run in parallel on other threads, so only count this thread.
+        let counter: &'static Counter = Box::leak(Box::new(Counter {
+            thread: thread::current().id(),
+            errors: AtomicUsize::new(0),
+        }));
+        set_global_observer(counter).unwrap();
+        assert_eq!(set_global_observer(counter), Err(SetObserverError::AlreadySet));
+
+        let almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        assert!(Almond::parse_and_validate(b"other", &almond.serialize_binary()).is_err());
+        assert!(Almond::parse_and_validate(b"this_is_a_secret", &almond.serialize_binary()).is_ok());
+
+        let limits = ParseLimits { max_len: 32, ..ParseLimits::default() };
+        assert!(
+            Almond::parse_and_validate_with_limits(
+                b"this_is_a_secret", &almond.serialize_binary(), &limits
+            ).is_err()
+        );
+
+        assert_eq!(counter.errors.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,257 @@
+//! A tower-style middleware for hyper servers, enabled by the `hyper`
+//! feature.
+//!
+//! `AlmondLayer` takes the almond from a `Authorization: Bearer <token>`
+//! header, validates it with a key and checks it against a
+//! `VerifierPolicy`. Accepted requests are passed on with a
+//! `VerifiedAlmond` in their extensions. Other requests get a `401
+//! Unauthorized` response, whose `WWW-Authenticate` header gives the reason
+//! as an `error_description`, e.g. `incorrect_hash` or `caveats`. See
+//! `AlmondParseError::kind` and `VerifyError::kind`.
+//!
+//! ```
+//! # extern crate almonds;
+//! # extern crate futures;
+//! # extern crate http;
+//! # extern crate tower_layer;
+//! # extern crate tower_service;
+//! # use std::task::{Context, Poll};
+//! # use futures::executor::block_on;
+//! # use futures::future::{self, Ready};
+//! # use http::{Request, Response, StatusCode};
+//! # use tower_layer::Layer;
+//! # use tower_service::Service;
+//! use almonds::{Almond, VerifiedAlmond, VerifierPolicy};
+//! use almonds::middleware::AlmondLayer;
+//!
+//! struct Handler;
+//!
+//! impl Service<Request<()>> for Handler {
+//!     type Response = Response<String>;
+//!     type Error = ();
+//!     type Future = Ready<Result<Response<String>, ()>>;
+//!
+//!     fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), ()>> {
+//!         Poll::Ready(Ok(()))
+//!     }
+//!
+//!     fn call(&mut self, req: Request<()>) -> Self::Future {
+//!         let almond = req.extensions().get::<VerifiedAlmond>().unwrap();
+//!         future::ok(Response::new(almond.caveat_str(b"user").unwrap().to_owned()))
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let mut policy = VerifierPolicy::new(1, b"access".to_vec());
+//! policy.allow(b"user");
+//! let mut service = AlmondLayer::new(b"secret", policy).layer(Handler);
+//!
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_caveat(b"user", Some(b"erikj"));
+//! let req = Request::builder()
+//!     .header("Authorization", format!("Bearer {}", almond.serialize_base64()))
+//!     .body(())
+//!     .unwrap();
+//! assert_eq!(block_on(service.call(req)).unwrap().body(), "erikj");
+//!
+//! let res = block_on(service.call(Request::new(()))).unwrap();
+//! assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+//! # }
+//! ```
+
+use futures::future::{self, Either, Ready};
+use http_types::{header, HeaderValue, Request, Response, StatusCode};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::bearer_token;
+use verified::from_validated;
+use {Almond, VerifiedAlmond, VerifierPolicy};
+
+
+/// The reason given when a request has no bearer token.
+pub const MISSING_TOKEN : &'static str = "missing_token";
+
+
+struct Config {
+    key: Vec<u8>,
+    policy: VerifierPolicy,
+}
+
+
+/// Wraps a service with `AlmondService`, see the module documentation.
+#[derive(Clone)]
+pub struct AlmondLayer {
+    config: Arc<Config>,
+}
+
+impl AlmondLayer {
+    /// Create a layer validating almonds with `key` and checking them
+    /// against `policy`.
+    pub fn new(key: &[u8], policy: VerifierPolicy) -> AlmondLayer {
+        AlmondLayer {
+            config: Arc::new(Config {
+                key: key.to_vec(),
+                policy: policy,
+            }),
+        }
+    }
+}
+
+impl <S> Layer<S> for AlmondLayer {
+    type Service = AlmondService<S>;
+
+    fn layer(&self, inner: S) -> AlmondService<S> {
+        AlmondService {
+            inner: inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+
+/// A service that only passes on requests with an acceptable almond, see
+/// the module documentation.
+#[derive(Clone)]
+pub struct AlmondService<S> {
+    inner: S,
+    config: Arc<Config>,
+}
+
+impl <S> AlmondService<S> {
+    /// Validate and verify the bearer token of the request, returning the
+    /// reason if it isn't acceptable.
+    fn authenticate<B>(&self, req: &Request<B>) -> Result<VerifiedAlmond, &'static str> {
+        let token = try!(
+            req.headers().get(header::AUTHORIZATION)
+                .and_then(|value| bearer_token(value.as_bytes()))
+                .ok_or(MISSING_TOKEN)
+        );
+
+        let almond = try!(
+            Almond::parse_base64_and_validate(&self.config.key, token).map_err(|e| e.kind())
+        );
+        try!(self.config.policy.check_detailed(&almond).map_err(|e| e.kind()));
+
+        Ok(from_validated(almond))
+    }
+}
+
+impl <S, ReqBody, ResBody> Service<Request<ReqBody>> for AlmondService<S>
+    where S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+          ResBody: Default
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Either<S::Future, Ready<Result<Response<ResBody>, S::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        match self.authenticate(&req) {
+            Ok(almond) => {
+                req.extensions_mut().insert(almond);
+                Either::Left(self.inner.call(req))
+            }
+            Err(reason) => Either::Right(future::ok(unauthorized(reason))),
+        }
+    }
+}
+
+
+fn unauthorized<B: Default>(reason: &'static str) -> Response<B> {
+    let challenge = format!(r#"Bearer error="invalid_token", error_description="{}""#, reason);
+
+    let mut res = Response::new(B::default());
+    *res.status_mut() = StatusCode::UNAUTHORIZED;
+    res.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        HeaderValue::from_str(&challenge).expect("reasons are valid header values"),
+    );
+    res
+}
+
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::future::{self, Ready};
+    use http_types::{header, Request, Response, StatusCode};
+    use tower_layer::Layer;
+    use tower_service::Service;
+
+    use std::task::{Context, Poll};
+
+    use super::*;
+    use {Almond, VerifiedAlmond, VerifierPolicy};
+
+    struct Echo;
+
+    impl Service<Request<()>> for Echo {
+        type Response = Response<Vec<u8>>;
+        type Error = ();
+        type Future = Ready<Result<Response<Vec<u8>>, ()>>;
+
+        fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<()>) -> Self::Future {
+            let almond = req.extensions().get::<VerifiedAlmond>().expect("no almond");
+            future::ok(Response::new(almond.caveat_value(b"user").unwrap().to_vec()))
+        }
+    }
+
+    fn request(authorization: Option<&str>) -> Request<()> {
+        let mut req = Request::builder();
+        if let Some(authorization) = authorization {
+            req = req.header(header::AUTHORIZATION, authorization);
+        }
+        req.body(()).unwrap()
+    }
+
+    fn reason(res: &Response<Vec<u8>>) -> String {
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        res.headers()[header::WWW_AUTHENTICATE].to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn layer() {
+        let key = b"this_is_a_secret";
+        let mut policy = VerifierPolicy::new(1, b"access".to_vec());
+        policy.satisfies_exact(b"user", Some(b"erikj"));
+        let mut service = AlmondLayer::new(key, policy).layer(Echo);
+
+        let mut almond = Almond::create(key, 1, b"access".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let token = almond.serialize_base64();
+
+        let res = block_on(service.call(request(Some(&format!("bearer  {} ", token))))).unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body(), b"erikj");
+
+        let res = block_on(service.call(request(None))).unwrap();
+        assert!(reason(&res).contains(r#"error_description="missing_token""#));
+
+        let res = block_on(service.call(request(Some(&format!("Basic {}", token))))).unwrap();
+        assert!(reason(&res).contains(r#"error_description="missing_token""#));
+
+        let res = block_on(service.call(request(Some("Bearer invalid")))).unwrap();
+        assert!(res.body().is_empty());
+        reason(&res);
+
+        let other = Almond::create(b"other", 1, b"access".to_vec()).serialize_base64();
+        let res = block_on(service.call(request(Some(&format!("Bearer {}", other))))).unwrap();
+        assert!(reason(&res).contains(r#"error_description="incorrect_hash""#));
+
+        almond.add_caveat(b"admin", None);
+        let res = block_on(service.call(request(Some(&format!("Bearer {}", almond.serialize_base64())))))
+            .unwrap();
+        assert!(reason(&res).contains(r#"error_description="caveats""#));
+    }
+}
@@ -0,0 +1,147 @@
+//! A MessagePack encoding of almonds, enabled by the `msgpack` feature.
+//!
+//! An almond is encoded as a four element array of the hash (bin), the
+//! generation (uint), the type (bin) and an array of the caveats (bin). The
+//! hash is computed over the same caveat bytes as every other format, so an
+//! almond can be converted between formats without the key.
+//!
+//! ```
+//! # use almonds::Almond;
+//! let mut almond = Almond::create(b"secret", 1, b"login".to_vec());
+//! almond.add_caveat(b"user", Some(b"erikj"));
+//!
+//! let encoded = almond.serialize_msgpack();
+//! let parsed = Almond::parse_msgpack_and_validate(b"secret", &encoded).unwrap();
+//! assert_eq!(parsed.serialize_binary(), almond.serialize_binary());
+//! ```
+
+use rmp::{decode, encode};
+
+use almond::validate_parts;
+use {Almond, AlmondParseError};
+
+
+impl Almond {
+    /// Serialize into MessagePack.
+    pub fn serialize_msgpack(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        // Writing to a `Vec` can't fail.
+        encode::write_array_len(&mut buf, 4).expect("failed to write to Vec");
+        encode::write_bin(&mut buf, &self.hash().as_bytes()[..]).expect("failed to write to Vec");
+        encode::write_uint(&mut buf, self.generation() as u64).expect("failed to write to Vec");
+        encode::write_bin(&mut buf, self.almond_type()).expect("failed to write to Vec");
+
+        encode::write_array_len(&mut buf, self.caveats().count() as u32)
+            .expect("failed to write to Vec");
+        for caveat in self.caveats() {
+            encode::write_bin(&mut buf, caveat).expect("failed to write to Vec");
+        }
+
+        buf
+    }
+
+    /// Parse a MessagePack serialized Almond, and validate that the hashes
+    /// match.
+    pub fn parse_msgpack_and_validate(key: &[u8], input: &[u8])
+        -> Result<Almond, AlmondParseError>
+    {
+        let mut rest = input;
+
+        let len = try!(
+            decode::read_array_len(&mut rest).map_err(|_| AlmondParseError::InvalidAlmond)
+        );
+        if len != 4 {
+            return Err(AlmondParseError::InvalidAlmond);
+        }
+
+        let hash = try!(read_bin(&mut rest));
+        if hash.len() != 32 {
+            return Err(AlmondParseError::InvalidAlmond);
+        }
+
+        let generation: u32 = try!(
+            decode::read_int(&mut rest).map_err(|_| AlmondParseError::InvalidAlmond)
+        );
+        let almond_type = try!(read_bin(&mut rest));
+
+        let count = try!(
+            decode::read_array_len(&mut rest).map_err(|_| AlmondParseError::InvalidAlmond)
+        );
+
+        // Each caveat takes at least two bytes, which bounds the allocation.
+        if count as usize > rest.len() / 2 {
+            return Err(AlmondParseError::TooManyCaveats { count: count as u64 });
+        }
+
+        let mut caveats = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            caveats.push(try!(read_bin(&mut rest)));
+        }
+
+        if !rest.is_empty() {
+            return Err(AlmondParseError::InvalidAlmond);
+        }
+
+        validate_parts(key, hash, generation, almond_type, &caveats)
+    }
+}
+
+
+fn read_bin<'a>(input: &mut &'a [u8]) -> Result<&'a [u8], AlmondParseError> {
+    let len = try!(decode::read_bin_len(input).map_err(|_| AlmondParseError::InvalidAlmond));
+    if len as usize > input.len() {
+        return Err(AlmondParseError::InvalidAlmond);
+    }
+
+    let (data, rest) = input.split_at(len as usize);
+    *input = rest;
+    Ok(data)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use {Almond, AlmondParseError};
+
+    #[test]
+    fn round_trip() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 300, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let encoded = almond.serialize_msgpack();
+        assert_eq!(&encoded[..3], &b"\x94\xc4\x20"[..]);
+        assert_eq!(&encoded[35..], &b"\xcd\x01\x2c\xc4\x05login\x91\xc4\x0auser erikj"[..]);
+
+        let parsed = Almond::parse_msgpack_and_validate(key, &encoded).unwrap();
+        assert_eq!(parsed.serialize_binary(), almond.serialize_binary());
+
+        match Almond::parse_msgpack_and_validate(b"wrong_key", &encoded) {
+            Err(AlmondParseError::IncorrectHash) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        // Claims more caveats than could fit in the input.
+        let mut too_many = encoded[..45].to_vec();
+        too_many.extend_from_slice(b"\xdd\xff\xff\xff\xff");
+        match Almond::parse_msgpack_and_validate(key, &too_many) {
+            Err(AlmondParseError::TooManyCaveats { count: 0xFFFFFFFF }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        let inputs: &[&[u8]] = &[
+            b"",
+            &encoded[..encoded.len() - 1],
+            b"\x93\xc4\x00\x01\xc4\x00",
+        ];
+
+        for input in inputs {
+            match Almond::parse_msgpack_and_validate(key, input) {
+                Err(AlmondParseError::InvalidAlmond) => {}
+                res => panic!("unexpected result for {:?}: {:?}", input, res.map(|_| ())),
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Namespaced caveats, of the form `<namespace>.<key> <value>`.
+//!
+//! Reusable families of caveats can share a namespace, so that a single
+//! `NamespaceHandler` registered with `Verifier::handle_namespace` checks all
+//! of them without the verifier enumerating every key.
+//!
+//! ```
+//! # use almonds::{Almond, Verifier};
+//! use almonds::namespace::NamespaceHandler;
+//!
+//! /// Checks `limit.<resource> <max>` caveats against current usage.
+//! struct Limits {
+//!     uploads: u64,
+//! }
+//!
+//! impl NamespaceHandler for Limits {
+//!     fn check(&mut self, key: &[u8], value: Option<&[u8]>) -> Option<bool> {
+//!         let used = match key {
+//!             b"uploads" => self.uploads,
+//!             _ => return None,
+//!         };
+//!         let max = value
+//!             .and_then(|v| ::std::str::from_utf8(v).ok())
+//!             .and_then(|v| v.parse().ok());
+//!         Some(max.map_or(false, |max: u64| used < max))
+//!     }
+//! }
+//!
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_caveat(b"limit.uploads", Some(b"10"));
+//!
+//! let mut v = Verifier::new(&almond, 1, b"access");
+//! v.handle_namespace(b"limit", Limits { uploads: 3 });
+//! assert!(v.verify());
+//! ```
+
+
+/// The byte separating the namespace from the rest of a caveat key.
+pub const SEPARATOR : u8 = b'.';
+
+
+/// Checks the caveats in a namespace.
+///
+/// This is implemented for all closures of type
+/// `FnMut(&[u8], Option<&[u8]>) -> Option<bool>`.
+pub trait NamespaceHandler {
+    /// Check a caveat in the namespace. `key` has the namespace and
+    /// separator removed.
+    ///
+    /// Returns `Some(true)` to accept the caveat, `Some(false)` to reject it,
+    /// or `None` if the handler doesn't recognize it.
+    fn check(&mut self, key: &[u8], value: Option<&[u8]>) -> Option<bool>;
+}
+
+impl <F> NamespaceHandler for F where F: FnMut(&[u8], Option<&[u8]>) -> Option<bool> {
+    fn check(&mut self, key: &[u8], value: Option<&[u8]>) -> Option<bool> {
+        self(key, value)
+    }
+}
+
+
+/// If `key` is in `namespace`, returns the rest of the key.
+pub fn strip_namespace<'k>(namespace: &[u8], key: &'k [u8]) -> Option<&'k [u8]> {
+    let len = namespace.len();
+    if key.len() > len && key.starts_with(namespace) && key[len] == SEPARATOR {
+        Some(&key[len + 1..])
+    } else {
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip() {
+        assert_eq!(strip_namespace(b"time", b"time.expires"), Some(&b"expires"[..]));
+        assert_eq!(strip_namespace(b"time", b"time.a.b"), Some(&b"a.b"[..]));
+        assert_eq!(strip_namespace(b"time", b"time."), Some(&b""[..]));
+        assert_eq!(strip_namespace(b"time", b"time"), None);
+        assert_eq!(strip_namespace(b"time", b"timeout.x"), None);
+        assert_eq!(strip_namespace(b"time", b"expires"), None);
+    }
+}
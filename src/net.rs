@@ -0,0 +1,187 @@
+//! IP networks for restricting almonds to client addresses.
+//!
+//! Networks are encoded in CIDR notation, e.g. `10.0.0.0/8` or
+//! `2001:db8::/32`. A network containing a single address is encoded as just
+//! the address.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::{self, FromStr};
+
+use value::CaveatValue;
+
+
+/// An IPv4 or IPv6 network, i.e. an address and a prefix length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// Create a network, returning `None` if the prefix length is longer
+    /// than the address.
+    ///
+    /// Bits of `addr` beyond the prefix are ignored.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Option<IpNet> {
+        if prefix_len > max_prefix_len(&addr) {
+            return None;
+        }
+
+        Some(IpNet { addr: addr, prefix_len: prefix_len })
+    }
+
+    /// Get the address of the network
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Get the prefix length of the network
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns whether the network contains `addr`.
+    ///
+    /// IPv4 networks never contain IPv6 addresses, and vice versa.
+    ///
+    /// ```
+    /// # use almonds::net::IpNet;
+    /// let net: IpNet = "10.0.0.0/8".parse().unwrap();
+    /// assert!(net.contains("10.1.2.3".parse().unwrap()));
+    /// assert!(!net.contains("11.1.2.3".parse().unwrap()));
+    /// ```
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let (net, addr) = match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                (net.octets().to_vec(), addr.octets().to_vec())
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                (net.octets().to_vec(), addr.octets().to_vec())
+            }
+            _ => return false,
+        };
+
+        let prefix_len = self.prefix_len as usize;
+        let whole = prefix_len / 8;
+        if net[..whole] != addr[..whole] {
+            return false;
+        }
+
+        let bits = prefix_len % 8;
+        if bits == 0 {
+            return true;
+        }
+
+        let mask = !(0xFFu8 >> bits);
+        net[whole] & mask == addr[whole] & mask
+    }
+}
+
+impl From<IpAddr> for IpNet {
+    /// The network containing only `addr`.
+    fn from(addr: IpAddr) -> IpNet {
+        IpNet { addr: addr, prefix_len: max_prefix_len(&addr) }
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.prefix_len == max_prefix_len(&self.addr) {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix_len)
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<IpNet, ()> {
+        let mut parts = s.splitn(2, '/');
+        let addr: IpAddr = try!(parts.next().unwrap_or("").parse().map_err(|_| ()));
+
+        match parts.next() {
+            Some(prefix_len) => {
+                // Reject e.g. `+8` and `08`, so that each network has one
+                // encoding.
+                if prefix_len.starts_with('+')
+                    || (prefix_len.len() > 1 && prefix_len.starts_with('0'))
+                {
+                    return Err(());
+                }
+                let prefix_len = try!(prefix_len.parse().map_err(|_| ()));
+                IpNet::new(addr, prefix_len).ok_or(())
+            }
+            None => Ok(IpNet::from(addr)),
+        }
+    }
+}
+
+impl <'a> CaveatValue<'a> for IpNet {
+    fn encode_value(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.to_string().as_bytes());
+    }
+
+    fn decode_value(value: &'a [u8]) -> Option<IpNet> {
+        str::from_utf8(value).ok().and_then(|value| value.parse().ok())
+    }
+}
+
+fn max_prefix_len(addr: &IpAddr) -> u8 {
+    match *addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::IpNet;
+    use value::{encode, CaveatValue};
+
+    use std::net::IpAddr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn contains() {
+        let net: IpNet = "192.168.0.0/20".parse().unwrap();
+        assert!(net.contains(ip("192.168.0.1")));
+        assert!(net.contains(ip("192.168.15.255")));
+        assert!(!net.contains(ip("192.168.16.0")));
+        assert!(!net.contains(ip("::1")));
+
+        let net: IpNet = "2001:db8::/32".parse().unwrap();
+        assert!(net.contains(ip("2001:db8::1")));
+        assert!(!net.contains(ip("2001:db9::1")));
+        assert!(!net.contains(ip("127.0.0.1")));
+
+        let all: IpNet = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(ip("8.8.8.8")));
+
+        let single = IpNet::from(ip("10.0.0.1"));
+        assert!(single.contains(ip("10.0.0.1")));
+        assert!(!single.contains(ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn encoding() {
+        let net: IpNet = "10.0.0.0/8".parse().unwrap();
+        assert_eq!(encode(&net), b"10.0.0.0/8".to_vec());
+        assert_eq!(IpNet::decode_value(b"10.0.0.0/8"), Some(net));
+
+        assert_eq!(encode(&IpNet::from(ip("::1"))), b"::1".to_vec());
+        assert_eq!(IpNet::decode_value(b"::1"), Some(IpNet::from(ip("::1"))));
+
+        assert_eq!(IpNet::decode_value(b"10.0.0.0/33"), None);
+        assert_eq!(IpNet::decode_value(b"10.0.0.0/08"), None);
+        assert_eq!(IpNet::decode_value(b"10.0.0.0/+8"), None);
+        assert_eq!(IpNet::decode_value(b"10.0.0.0/"), None);
+        assert_eq!(IpNet::decode_value(b"example.com"), None);
+    }
+}
@@ -0,0 +1,121 @@
+//! Parallel validation and verification of many almonds, enabled by the
+//! `parallel` feature.
+
+use rayon::prelude::*;
+
+use {Almond, AlmondParseError, Verifier, VerifyError};
+
+
+quick_error! {
+    /// An error returned by `verify_many` for a single token.
+    #[derive(Debug)]
+    pub enum VerifyManyError {
+        /// The token could not be parsed or its hash did not match.
+        Parse(err: AlmondParseError) {
+            from()
+            cause(err)
+        }
+
+        /// The almond was valid but was rejected by the verifier.
+        Rejected(err: VerifyError) {
+            from()
+            cause(err)
+        }
+    }
+}
+
+
+/// Validate and verify a list of Base64 encoded almonds in parallel.
+///
+/// Each almond is validated with `key`, and then `policy` is invoked with a
+/// `Verifier` for the given `generation` and `almond_type` to add the
+/// predicates the almond must satisfy.
+///
+/// The result at index `i` corresponds to `tokens[i]`.
+///
+/// ```
+/// # use almonds::Almond;
+/// # use almonds::parallel::verify_many;
+/// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+/// almond.add_caveat(b"user", Some(b"erikj"));
+/// let token = almond.serialize_base64();
+///
+/// let results = verify_many(
+///     b"secret", &[token.as_bytes(), b"invalid"], 1, b"access",
+///     |v| { v.allow(b"user"); },
+/// );
+/// assert!(results[0].is_ok());
+/// assert!(results[1].is_err());
+/// ```
+pub fn verify_many<F>(
+    key: &[u8],
+    tokens: &[&[u8]],
+    generation: u32,
+    almond_type: &[u8],
+    policy: F,
+) -> Vec<Result<Almond, VerifyManyError>>
+    where F: Fn(&mut Verifier) + Sync
+{
+    tokens.par_iter().map(|token| {
+        let almond = try!(Almond::parse_base64_and_validate(key, token));
+
+        {
+            let mut v = Verifier::new(&almond, generation, almond_type);
+            policy(&mut v);
+            try!(v.verify_detailed());
+        }
+
+        Ok(almond)
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Almond;
+
+    #[test]
+    fn verify_many_in_order() {
+        let key = b"this_is_a_secret";
+
+        let tokens: Vec<String> = (0..100).map(|i| {
+            let mut almond = Almond::create(key, 1, b"login".to_vec());
+            let user = if i % 3 == 0 { "admin" } else { "erikj" };
+            almond.add_caveat(b"user", Some(user.as_bytes()));
+            almond.serialize_base64()
+        }).collect();
+        let token_refs: Vec<&[u8]> = tokens.iter().map(|t| t.as_bytes()).collect();
+
+        let results = verify_many(key, &token_refs, 1, b"login", |v| {
+            v.satisfies_exact(b"user", Some(b"erikj"));
+        });
+
+        assert_eq!(results.len(), 100);
+        for (i, result) in results.iter().enumerate() {
+            match *result {
+                Ok(ref almond) => {
+                    assert!(i % 3 != 0);
+                    assert_eq!(almond.serialize_base64(), tokens[i]);
+                }
+                Err(VerifyManyError::Rejected(_)) => assert!(i % 3 == 0),
+                Err(ref err) => panic!("unexpected error: {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn verify_many_wrong_key() {
+        let almond = Almond::create(b"other_key", 1, b"login".to_vec());
+        let token = almond.serialize_base64();
+
+        let results = verify_many(
+            b"this_is_a_secret", &[token.as_bytes()], 1, b"login", |_| {}
+        );
+
+        match results[0] {
+            Err(VerifyManyError::Parse(AlmondParseError::IncorrectHash)) => {}
+            _ => panic!("expected an IncorrectHash error"),
+        }
+    }
+}
@@ -0,0 +1,818 @@
+use {Almond, AlmondRef, Verifier, VerifyError};
+use clock::Clock;
+use ct::ct_eq;
+use expr::{Expression, ExprError};
+use registry::CaveatVerifier;
+use revocation::{RevocationChecker, UseStore};
+
+use rustc_serialize::json::{Json, ParserError};
+
+use std::time::Duration;
+
+
+enum Rule {
+    AcceptGeneration(u32),
+    AcceptType(Vec<u8>),
+    Allow(Vec<u8>),
+    Satisfies(Vec<u8>, Box<Fn(&[u8]) -> bool + Send + Sync>),
+    SatisfiesExact(Vec<u8>, Option<Vec<u8>>),
+    SatisfiesGeneral(Box<Fn(&[u8], Option<&[u8]>) -> bool + Send + Sync>),
+    RequirePresent(Vec<u8>),
+    Forbid(Vec<u8>),
+    RequireUnique(Vec<u8>),
+    RequireAllUnique,
+    RequireSealed,
+    SatisfiesAudience(Vec<u8>),
+    SatisfiesScope(String),
+    SatisfiesScopeAllowing(Vec<String>),
+    SatisfiesExpiry,
+    SatisfiesNotBefore,
+    SatisfiesMinEpoch(u64),
+    SatisfiesMaxCaveats,
+    SatisfiesAttenuationRecords,
+    Register(Box<CaveatVerifier + Send + Sync>),
+}
+
+
+quick_error! {
+    /// An error returned when a policy can't be loaded.
+    #[derive(Debug)]
+    pub enum PolicyError {
+        /// The policy wasn't valid JSON.
+        Json(err: ParserError) {
+            from()
+            display("invalid JSON in policy: {}", err)
+            cause(err)
+        }
+
+        /// A field of the policy is missing or has the wrong type.
+        InvalidField { name: String } {
+            display("policy field {:?} is missing or invalid", name)
+        }
+
+        /// The policy has a field that isn't understood.
+        UnknownField { name: String } {
+            display("unknown policy field {:?}", name)
+        }
+
+        /// A condition couldn't be parsed.
+        Expr(err: ExprError) {
+            from()
+            display("invalid condition in policy: {}", err)
+            cause(err)
+        }
+    }
+}
+
+
+/// A reusable set of predicates that can be checked against any almond.
+///
+/// A `Verifier` borrows a single almond, so its predicates have to be added
+/// again for every almond that is verified. A `VerifierPolicy` is instead
+/// built once, e.g. at startup, and then applied to each almond with
+/// `check`. The semantics are the same as adding the predicates to a
+/// `Verifier` in the same order.
+///
+/// ```
+/// # use almonds::{Almond, VerifierPolicy};
+/// let mut policy = VerifierPolicy::new(1, b"access".to_vec());
+/// policy.allow(b"user");
+///
+/// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+/// almond.add_caveat(b"user", Some(b"erikj"));
+/// assert!(policy.check(&almond));
+///
+/// almond.add_caveat(b"admin", None);
+/// assert!(!policy.check(&almond));
+/// ```
+pub struct VerifierPolicy {
+    generation: u32,
+    almond_type: Vec<u8>,
+    rules: Vec<Rule>,
+    clock: Option<Box<Clock + Send + Sync>>,
+    clock_skew: Duration,
+    revocation: Option<Box<RevocationChecker + Send + Sync>>,
+    use_store: Option<Box<UseStore + Send + Sync>>,
+}
+
+impl VerifierPolicy {
+    /// Create a new policy accepting almonds of the given generation and
+    /// type.
+    pub fn new(generation: u32, almond_type: Vec<u8>) -> VerifierPolicy {
+        VerifierPolicy {
+            generation: generation,
+            almond_type: almond_type,
+            rules: Vec::new(),
+            clock: None,
+            clock_skew: Duration::from_secs(0),
+            revocation: None,
+            use_store: None,
+        }
+    }
+
+    /// Load a policy from JSON, e.g. distributed from a central service.
+    ///
+    /// The policy is an object with the fields:
+    ///
+    /// - `generation` and `type`, which are required.
+    /// - `allow`, a list of caveat keys to accept with any value.
+    /// - `exact`, an object mapping caveat keys to their accepted value, which
+    ///   is a string, `null` for a caveat without a value, or a list of
+    ///   accepted strings.
+    /// - `conditions`, a list of expressions, as in `satisfies_expression`,
+    ///   for numeric and time comparisons.
+    /// - `required`, a list of caveat keys that must be present.
+    /// - `expiry` and `not_before`, which when `true` check the standard time
+    ///   caveats.
+    ///
+    /// Unknown fields are rejected, so that a misspelt field doesn't silently
+    /// change the policy.
+    ///
+    /// ```
+    /// # use almonds::{Almond, VerifierPolicy};
+    /// let policy = VerifierPolicy::from_json(r#"{
+    ///     "generation": 1,
+    ///     "type": "access",
+    ///     "allow": ["user"],
+    ///     "exact": {"role": ["reader", "writer"]},
+    ///     "conditions": ["uploads <= 10"],
+    ///     "required": ["user"]
+    /// }"#).unwrap();
+    ///
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// almond.add_caveat(b"role", Some(b"reader"));
+    /// almond.add_caveat(b"uploads", Some(b"5"));
+    /// assert!(policy.check(&almond));
+    /// ```
+    pub fn from_json(json: &str) -> Result<VerifierPolicy, PolicyError> {
+        let json = try!(Json::from_str(json));
+        let object = try!(json.as_object().ok_or(invalid("")));
+
+        let generation = try!(object.get("generation")
+            .and_then(|g| g.as_u64())
+            .and_then(|g| if g <= u32::max_value() as u64 { Some(g as u32) } else { None })
+            .ok_or(invalid("generation")));
+        let almond_type = try!(object.get("type")
+            .and_then(|t| t.as_string())
+            .ok_or(invalid("type")));
+
+        let mut policy = VerifierPolicy::new(generation, almond_type.as_bytes().to_vec());
+
+        for (name, value) in object {
+            match &name[..] {
+                "generation" | "type" => {}
+                "allow" => {
+                    for key in try!(strings(name, value)) {
+                        policy.allow(key.as_bytes());
+                    }
+                }
+                "exact" => {
+                    let exact = try!(value.as_object().ok_or(invalid(name)));
+                    for (key, value) in exact {
+                        match *value {
+                            Json::Null => {
+                                policy.satisfies_exact(key.as_bytes(), None);
+                            }
+                            Json::String(ref value) => {
+                                policy.satisfies_exact(key.as_bytes(), Some(value.as_bytes()));
+                            }
+                            Json::Array(_) => {
+                                let values: Vec<Vec<u8>> = try!(strings(name, value))
+                                    .into_iter()
+                                    .map(|v| v.as_bytes().to_vec())
+                                    .collect();
+                                policy.satisfies(key.as_bytes(), move |v| {
+                                    values.iter().any(|accepted| ct_eq(accepted, v))
+                                });
+                            }
+                            _ => return Err(invalid(name)),
+                        }
+                    }
+                }
+                "conditions" => {
+                    for expr in try!(strings(name, value)) {
+                        try!(policy.satisfies_expression(expr));
+                    }
+                }
+                "required" => {
+                    for key in try!(strings(name, value)) {
+                        policy.require_present(key.as_bytes());
+                    }
+                }
+                "expiry" => {
+                    if try!(value.as_boolean().ok_or(invalid(name))) {
+                        policy.satisfies_expiry();
+                    }
+                }
+                "not_before" => {
+                    if try!(value.as_boolean().ok_or(invalid(name))) {
+                        policy.satisfies_not_before();
+                    }
+                }
+                _ => return Err(PolicyError::UnknownField { name: name.clone() }),
+            }
+        }
+
+        Ok(policy)
+    }
+
+    /// See `Verifier::accept_generation`.
+    pub fn accept_generation(&mut self, generation: u32) -> &mut Self {
+        self.rules.push(Rule::AcceptGeneration(generation));
+        self
+    }
+
+    /// See `Verifier::accept_type`.
+    pub fn accept_type(&mut self, almond_type: &[u8]) -> &mut Self {
+        self.rules.push(Rule::AcceptType(almond_type.to_vec()));
+        self
+    }
+
+    /// See `Verifier::allow`.
+    pub fn allow(&mut self, key: &[u8]) -> &mut Self {
+        self.rules.push(Rule::Allow(key.to_vec()));
+        self
+    }
+
+    /// See `Verifier::satisfies`.
+    ///
+    /// As the policy may be shared between threads the predicate must be
+    /// `Fn` rather than `FnMut`.
+    pub fn satisfies<F>(&mut self, key: &[u8], predicate: F) -> &mut Self
+        where F: Fn(&[u8]) -> bool + Send + Sync + 'static
+    {
+        self.rules.push(Rule::Satisfies(key.to_vec(), Box::new(predicate)));
+        self
+    }
+
+    /// See `Verifier::satisfies_exact`.
+    pub fn satisfies_exact(&mut self, key: &[u8], value: Option<&[u8]>)
+        -> &mut Self
+    {
+        self.rules.push(
+            Rule::SatisfiesExact(key.to_vec(), value.map(|v| v.to_vec()))
+        );
+        self
+    }
+
+    /// See `Verifier::satisfies_general`.
+    pub fn satisfies_general<F>(&mut self, predicate: F) -> &mut Self
+        where F: Fn(&[u8], Option<&[u8]>) -> bool + Send + Sync + 'static
+    {
+        self.rules.push(Rule::SatisfiesGeneral(Box::new(predicate)));
+        self
+    }
+
+    /// See `Verifier::require_present`.
+    pub fn require_present(&mut self, key: &[u8]) -> &mut Self {
+        self.rules.push(Rule::RequirePresent(key.to_vec()));
+        self
+    }
+
+    /// See `Verifier::forbid`.
+    pub fn forbid(&mut self, key: &[u8]) -> &mut Self {
+        self.rules.push(Rule::Forbid(key.to_vec()));
+        self
+    }
+
+    /// See `Verifier::require_unique`.
+    pub fn require_unique(&mut self, key: &[u8]) -> &mut Self {
+        self.rules.push(Rule::RequireUnique(key.to_vec()));
+        self
+    }
+
+    /// See `Verifier::require_all_unique`.
+    pub fn require_all_unique(&mut self) -> &mut Self {
+        self.rules.push(Rule::RequireAllUnique);
+        self
+    }
+
+    /// See `Verifier::require_sealed`.
+    pub fn require_sealed(&mut self) -> &mut Self {
+        self.rules.push(Rule::RequireSealed);
+        self
+    }
+
+    /// See `Verifier::satisfies_audience`.
+    pub fn satisfies_audience(&mut self, audience: &[u8]) -> &mut Self {
+        self.rules.push(Rule::SatisfiesAudience(audience.to_vec()));
+        self
+    }
+
+    /// See `Verifier::satisfies_scope`.
+    pub fn satisfies_scope(&mut self, required: &str) -> &mut Self {
+        self.rules.push(Rule::SatisfiesScope(required.to_owned()));
+        self
+    }
+
+    /// See `Verifier::satisfies_scope_allowing`.
+    pub fn satisfies_scope_allowing(&mut self, allowed: &[&str]) -> &mut Self {
+        self.rules.push(Rule::SatisfiesScopeAllowing(
+            allowed.iter().map(|pattern| pattern.to_string()).collect()
+        ));
+        self
+    }
+
+    /// See `Verifier::satisfies_expiry`.
+    ///
+    /// The clock is read each time the policy is checked.
+    pub fn satisfies_expiry(&mut self) -> &mut Self {
+        self.rules.push(Rule::SatisfiesExpiry);
+        self
+    }
+
+    /// See `Verifier::satisfies_not_before`.
+    ///
+    /// The clock is read each time the policy is checked.
+    pub fn satisfies_not_before(&mut self) -> &mut Self {
+        self.rules.push(Rule::SatisfiesNotBefore);
+        self
+    }
+
+    /// See `Verifier::satisfies_min_epoch`.
+    pub fn satisfies_min_epoch(&mut self, min_epoch: u64) -> &mut Self {
+        self.rules.push(Rule::SatisfiesMinEpoch(min_epoch));
+        self
+    }
+
+    /// See `Verifier::satisfies_max_caveats`.
+    pub fn satisfies_max_caveats(&mut self) -> &mut Self {
+        self.rules.push(Rule::SatisfiesMaxCaveats);
+        self
+    }
+
+    /// See `Verifier::satisfies_attenuation_records`.
+    ///
+    /// The clock is read each time the policy is checked.
+    pub fn satisfies_attenuation_records(&mut self) -> &mut Self {
+        self.rules.push(Rule::SatisfiesAttenuationRecords);
+        self
+    }
+
+    /// See `Verifier::register`.
+    pub fn register<C>(&mut self, verifier: C) -> &mut Self
+        where C: CaveatVerifier + Send + Sync + 'static
+    {
+        self.rules.push(Rule::Register(Box::new(verifier)));
+        self
+    }
+
+    /// Parse an expression, e.g. loaded from configuration, and register it
+    /// with the policy. See the `expr` module for the syntax.
+    ///
+    /// Context values used by the expression are set on the verifier after
+    /// calling `apply`.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier, VerifierPolicy};
+    /// let mut policy = VerifierPolicy::new(1, b"access".to_vec());
+    /// policy.satisfies_expression(r#"user == ctx.user && scope startswith "repo:""#)
+    ///     .unwrap();
+    ///
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// almond.add_caveat(b"scope", Some(b"repo:read"));
+    ///
+    /// let mut v = Verifier::new(&almond, policy.generation(), policy.almond_type());
+    /// policy.apply(&mut v);
+    /// v.set_context_value("user", "erikj");
+    /// assert!(v.verify());
+    /// ```
+    pub fn satisfies_expression(&mut self, expr: &str) -> Result<&mut Self, ExprError> {
+        let expr: Expression = try!(expr.parse());
+        Ok(self.register(expr))
+    }
+
+    /// See `Verifier::set_clock`.
+    ///
+    /// Unlike on a `Verifier`, this can be called at any point.
+    pub fn set_clock<C>(&mut self, clock: C) -> &mut Self
+        where C: Clock + Send + Sync + 'static
+    {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// See `Verifier::set_clock_skew`.
+    ///
+    /// Unlike on a `Verifier`, this can be called at any point.
+    pub fn set_clock_skew(&mut self, skew: Duration) -> &mut Self {
+        self.clock_skew = skew;
+        self
+    }
+
+    /// See `Verifier::set_revocation_checker`.
+    pub fn set_revocation_checker<R>(&mut self, checker: R) -> &mut Self
+        where R: RevocationChecker + Send + Sync + 'static
+    {
+        self.revocation = Some(Box::new(checker));
+        self
+    }
+
+    /// See `Verifier::set_use_store`.
+    pub fn set_use_store<S>(&mut self, store: S) -> &mut Self
+        where S: UseStore + Send + Sync + 'static
+    {
+        self.use_store = Some(Box::new(store));
+        self
+    }
+
+    /// Get the generation accepted by this policy
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Get the almond type accepted by this policy
+    pub fn almond_type(&self) -> &[u8] {
+        &self.almond_type
+    }
+
+    /// Returns whether the almond satisfies the policy.
+    pub fn check(&self, almond: &Almond) -> bool {
+        let mut v = Verifier::new(almond, self.generation, &self.almond_type);
+        self.apply(&mut v);
+        v.verify()
+    }
+
+    /// Like `check`, but on failure returns the reason the almond was
+    /// rejected.
+    pub fn check_detailed(&self, almond: &Almond) -> Result<(), VerifyError> {
+        let mut v = Verifier::new(almond, self.generation, &self.almond_type);
+        self.apply(&mut v);
+        v.verify_detailed()
+    }
+
+    /// Returns whether the borrowed almond satisfies the policy.
+    pub fn check_ref(&self, almond: &AlmondRef) -> bool {
+        let mut v = Verifier::from_ref(almond, self.generation, &self.almond_type);
+        self.apply(&mut v);
+        v.verify()
+    }
+
+    /// Add the predicates of this policy to an existing verifier.
+    ///
+    /// This allows request specific predicates to be added on top of the
+    /// policy.
+    pub fn apply<'a>(&'a self, v: &mut Verifier<'a>) {
+        if let Some(ref clock) = self.clock {
+            v.set_clock(&**clock);
+        }
+        v.set_clock_skew(self.clock_skew);
+        if let Some(ref checker) = self.revocation {
+            v.set_revocation_checker(&**checker);
+        }
+        if let Some(ref store) = self.use_store {
+            v.set_use_store(&**store);
+        }
+
+        for rule in &self.rules {
+            match *rule {
+                Rule::AcceptGeneration(generation) => {
+                    v.accept_generation(generation);
+                }
+                Rule::AcceptType(ref almond_type) => {
+                    v.accept_type(almond_type);
+                }
+                Rule::Allow(ref key) => {
+                    v.allow(key);
+                }
+                Rule::Satisfies(ref key, ref predicate) => {
+                    v.satisfies(key, |val| predicate(val));
+                }
+                Rule::SatisfiesExact(ref key, ref value) => {
+                    v.satisfies_exact(key, value.as_ref().map(|v| &v[..]));
+                }
+                Rule::SatisfiesGeneral(ref predicate) => {
+                    v.satisfies_general(move |key, val| predicate(key, val));
+                }
+                Rule::RequirePresent(ref key) => {
+                    v.require_present(key);
+                }
+                Rule::Forbid(ref key) => {
+                    v.forbid(key);
+                }
+                Rule::RequireUnique(ref key) => {
+                    v.require_unique(key);
+                }
+                Rule::RequireAllUnique => {
+                    v.require_all_unique();
+                }
+                Rule::RequireSealed => {
+                    v.require_sealed();
+                }
+                Rule::SatisfiesAudience(ref audience) => {
+                    v.satisfies_audience(audience);
+                }
+                Rule::SatisfiesScope(ref required) => {
+                    v.satisfies_scope(required);
+                }
+                Rule::SatisfiesScopeAllowing(ref allowed) => {
+                    let allowed: Vec<&str> = allowed.iter().map(|p| &p[..]).collect();
+                    v.satisfies_scope_allowing(&allowed);
+                }
+                Rule::SatisfiesExpiry => {
+                    v.satisfies_expiry();
+                }
+                Rule::SatisfiesNotBefore => {
+                    v.satisfies_not_before();
+                }
+                Rule::SatisfiesMinEpoch(min_epoch) => {
+                    v.satisfies_min_epoch(min_epoch);
+                }
+                Rule::SatisfiesMaxCaveats => {
+                    v.satisfies_max_caveats();
+                }
+                Rule::SatisfiesAttenuationRecords => {
+                    v.satisfies_attenuation_records();
+                }
+                Rule::Register(ref verifier) => {
+                    v.register(Box::new(&**verifier));
+                }
+            }
+        }
+    }
+}
+
+
+fn invalid(name: &str) -> PolicyError {
+    PolicyError::InvalidField { name: name.to_owned() }
+}
+
+/// Get a JSON field that is a list of strings.
+fn strings<'j>(name: &str, value: &'j Json) -> Result<Vec<&'j str>, PolicyError> {
+    let values = try!(value.as_array().ok_or(invalid(name)));
+    values.iter().map(|v| v.as_string().ok_or(invalid(name))).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{PolicyError, VerifierPolicy};
+    use {Almond, AlmondRef, Verifier};
+    use clock::MockClock;
+
+    use std::str;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn check_many() {
+        let key = b"this_is_a_secret";
+
+        let mut policy = VerifierPolicy::new(1, b"login".to_vec());
+        policy.require_present(b"user");
+        policy.satisfies_exact(b"user", Some(b"erikj"));
+        policy.satisfies(
+            b"expires",
+            |val| str::from_utf8(val).ok().and_then(
+                    |val| val.parse::<u64>().ok()
+                ).map(
+                    |val| 1447720058 < val
+                ).unwrap_or(false)
+        );
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        assert!(policy.check(&almond));
+
+        almond.add_caveat(b"expires", Some(b"1500000000"));
+        assert!(policy.check(&almond));
+
+        let serialized = almond.serialize_binary();
+        let almond_ref = AlmondRef::parse_and_validate(key, &serialized).unwrap();
+        assert!(policy.check_ref(&almond_ref));
+
+        almond.add_caveat(b"expires", Some(b"1000000000"));
+        assert!(!policy.check(&almond));
+
+        policy.forbid(b"admin");
+        let mut admin = Almond::create(key, 1, b"login".to_vec());
+        admin.add_caveat(b"user", Some(b"erikj"));
+        admin.add_caveat(b"admin", None);
+        assert!(!policy.check(&admin));
+
+        let no_user = Almond::create(key, 1, b"login".to_vec());
+        assert!(!policy.check(&no_user));
+
+        let wrong_type = Almond::create(key, 1, b"access".to_vec());
+        assert!(!policy.check(&wrong_type));
+        assert!(policy.check_detailed(&wrong_type).is_err());
+    }
+
+    #[test]
+    fn satisfies_general() {
+        let mut policy = VerifierPolicy::new(1, b"login".to_vec());
+        policy.allow(b"user");
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"limit.uploads", Some(b"10"));
+        assert!(!policy.check(&almond));
+
+        policy.satisfies_general(|key, _| key.starts_with(b"limit."));
+        assert!(policy.check(&almond));
+    }
+
+    #[test]
+    fn require_unique() {
+        let mut policy = VerifierPolicy::new(1, b"login".to_vec());
+        policy.allow(b"expires");
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        almond.add_caveat(b"expires", Some(b"1500000000"));
+        almond.add_caveat(b"expires", Some(b"1600000000"));
+        assert!(policy.check(&almond));
+
+        policy.require_unique(b"expires");
+        assert!(!policy.check(&almond));
+    }
+
+    #[test]
+    fn accept_generation() {
+        let mut policy = VerifierPolicy::new(3, b"login".to_vec());
+        policy.allow(b"user");
+
+        let mut almond = Almond::create(b"this_is_a_secret", 4, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        assert!(!policy.check(&almond));
+
+        policy.accept_generation(4);
+        assert!(policy.check(&almond));
+    }
+
+    #[test]
+    fn accept_type() {
+        let mut policy = VerifierPolicy::new(1, b"login".to_vec());
+        policy.allow(b"user");
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"sso".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        assert!(!policy.check(&almond));
+
+        policy.accept_type(b"sso");
+        assert!(policy.check(&almond));
+    }
+
+    #[test]
+    fn time_caveats() {
+        let expires = UNIX_EPOCH + Duration::from_secs(1500000000);
+
+        let mut policy = VerifierPolicy::new(1, b"login".to_vec());
+        policy.satisfies_expiry();
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        almond.add_expiry(expires);
+        assert!(!policy.check(&almond));
+
+        policy.set_clock(MockClock::new(expires - Duration::from_secs(1)));
+        assert!(policy.check(&almond));
+
+        policy.satisfies_not_before();
+        almond.add_not_before(expires - Duration::from_secs(10));
+        assert!(policy.check(&almond));
+
+        almond.add_not_before(expires);
+        assert!(!policy.check(&almond));
+
+        policy.set_clock_skew(Duration::from_secs(1));
+        assert!(policy.check(&almond));
+    }
+
+    #[test]
+    fn min_epoch() {
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        almond.add_epoch(2);
+
+        let mut policy = VerifierPolicy::new(1, b"login".to_vec());
+        policy.satisfies_min_epoch(2);
+        assert!(policy.check(&almond));
+
+        let mut policy = VerifierPolicy::new(1, b"login".to_vec());
+        policy.satisfies_min_epoch(3);
+        assert!(!policy.check(&almond));
+    }
+
+    #[test]
+    fn revocation() {
+        use std::collections::HashSet;
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        let token_id = almond.add_token_id();
+
+        let mut revoked = HashSet::new();
+        revoked.insert(token_id);
+
+        let mut policy = VerifierPolicy::new(1, b"login".to_vec());
+        assert!(!policy.check(&almond));
+
+        policy.set_revocation_checker(HashSet::new());
+        assert!(policy.check(&almond));
+
+        policy.set_revocation_checker(revoked);
+        assert!(!policy.check(&almond));
+    }
+
+    #[test]
+    fn single_use() {
+        use std::collections::HashSet;
+        use std::sync::Mutex;
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"invite".to_vec());
+        almond.add_single_use();
+
+        let mut policy = VerifierPolicy::new(1, b"invite".to_vec());
+        assert!(!policy.check(&almond));
+
+        policy.set_use_store(Mutex::new(HashSet::new()));
+        assert!(policy.check(&almond));
+        assert!(!policy.check(&almond));
+    }
+
+    #[test]
+    fn expression() {
+        let mut policy = VerifierPolicy::new(1, b"login".to_vec());
+        policy.set_clock(MockClock::new(UNIX_EPOCH + Duration::from_secs(1000)));
+        policy.satisfies_expression("expires > @now && uploads <= 10").unwrap();
+        assert!(policy.satisfies_expression("expires >").is_err());
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        almond.add_caveat(b"expires", Some(b"2000"));
+        almond.add_caveat(b"uploads", Some(b"5"));
+        assert!(policy.check(&almond));
+
+        almond.add_caveat(b"expires", Some(b"500"));
+        assert!(!policy.check(&almond));
+    }
+
+    #[test]
+    fn from_json() {
+        let policy = VerifierPolicy::from_json(r#"{
+            "generation": 2,
+            "type": "login",
+            "allow": ["user"],
+            "exact": {"role": "admin", "guest": null, "org": ["a", "b"]},
+            "conditions": ["expires > @now"],
+            "required": ["expires"],
+            "not_before": true
+        }"#).unwrap();
+        assert_eq!(policy.generation(), 2);
+        assert_eq!(policy.almond_type(), b"login");
+
+        let mut almond = Almond::create(b"this_is_a_secret", 2, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"role", Some(b"admin"));
+        almond.add_caveat(b"guest", None);
+        almond.add_caveat(b"org", Some(b"b"));
+        assert!(!policy.check(&almond));
+
+        almond.add_caveat(b"expires", Some(b"99999999999"));
+        almond.add_caveat(b"not_before", Some(b"0"));
+        assert!(policy.check(&almond));
+
+        almond.add_caveat(b"role", Some(b"root"));
+        assert!(!policy.check(&almond));
+    }
+
+    #[test]
+    fn from_json_invalid() {
+        let field = |json: &str| match VerifierPolicy::from_json(json) {
+            Err(PolicyError::InvalidField { name }) => name,
+            Err(PolicyError::UnknownField { name }) => format!("unknown {}", name),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("policy loaded"),
+        };
+
+        assert_eq!(field(r#"{"type": "login"}"#), "generation");
+        assert_eq!(field(r#"{"generation": -1, "type": "login"}"#), "generation");
+        assert_eq!(field(r#"{"generation": 1}"#), "type");
+        assert_eq!(field(r#"{"generation": 1, "type": "a", "allow": "user"}"#), "allow");
+        assert_eq!(field(r#"{"generation": 1, "type": "a", "exact": {"a": 1}}"#), "exact");
+        assert_eq!(field(r#"{"generation": 1, "type": "a", "alow": []}"#), "unknown alow");
+
+        match VerifierPolicy::from_json("{") {
+            Err(PolicyError::Json(_)) => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+        match VerifierPolicy::from_json(r#"{"generation": 1, "type": "a", "conditions": ["a =="]}"#) {
+            Err(PolicyError::Expr(_)) => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+    }
+
+    #[test]
+    fn apply_to_verifier() {
+        let mut policy = VerifierPolicy::new(1, b"login".to_vec());
+        policy.allow(b"user");
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"ip", Some(b"127.0.0.1"));
+
+        let mut v = Verifier::new(&almond, policy.generation(), policy.almond_type());
+        policy.apply(&mut v);
+        assert!(!v.verify());
+
+        v.satisfies_exact(b"ip", Some(b"127.0.0.1"));
+        assert!(v.verify());
+    }
+}
@@ -0,0 +1,127 @@
+//! Predicates over caveat values, and combinators for building them.
+//!
+//! Calling `Verifier::satisfies` several times for the same key requires
+//! *all* of the predicates to accept the caveat. The combinators here allow
+//! other combinations to be expressed, and are used with
+//! `Verifier::satisfies_with`:
+//!
+//! ```
+//! # use almonds::{Almond, Verifier};
+//! use almonds::predicate::{any_of, not};
+//!
+//! let client_ip = b"10.0.0.7";
+//! let admin_vlan = true;
+//!
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_caveat(b"ip", Some(b"192.168.0.1"));
+//!
+//! let mut v = Verifier::new(&almond, 1, b"access");
+//! v.satisfies_with(b"ip", any_of(vec![
+//!     Box::new(|ip: &[u8]| ip == client_ip),
+//!     Box::new(|_: &[u8]| admin_vlan),
+//! ]));
+//! v.satisfies_with(b"ip", not(|ip: &[u8]| ip.is_empty()));
+//! assert!(v.verify());
+//! ```
+//!
+//! Note that closures passed to the combinators need their argument type
+//! annotated, i.e. `|value: &[u8]| ...`.
+
+
+/// A check against the value of a caveat.
+///
+/// This is implemented for all closures of type `FnMut(&[u8]) -> bool`.
+pub trait Predicate {
+    /// Returns whether the value is acceptable.
+    fn check(&mut self, value: &[u8]) -> bool;
+}
+
+impl <F> Predicate for F where F: FnMut(&[u8]) -> bool {
+    fn check(&mut self, value: &[u8]) -> bool {
+        self(value)
+    }
+}
+
+
+/// Accepts a value if any of the predicates accept it. See `any_of`.
+pub struct AnyOf<'p> {
+    predicates: Vec<Box<Predicate + 'p>>,
+}
+
+impl <'p> Predicate for AnyOf<'p> {
+    fn check(&mut self, value: &[u8]) -> bool {
+        self.predicates.iter_mut().any(|p| p.check(value))
+    }
+}
+
+/// Accepts a value if all of the predicates accept it. See `all_of`.
+pub struct AllOf<'p> {
+    predicates: Vec<Box<Predicate + 'p>>,
+}
+
+impl <'p> Predicate for AllOf<'p> {
+    fn check(&mut self, value: &[u8]) -> bool {
+        self.predicates.iter_mut().all(|p| p.check(value))
+    }
+}
+
+/// Inverts a predicate. See `not`.
+pub struct Not<P> {
+    inner: P,
+}
+
+impl <P: Predicate> Predicate for Not<P> {
+    fn check(&mut self, value: &[u8]) -> bool {
+        !self.inner.check(value)
+    }
+}
+
+
+/// Accepts a value if at least one of `predicates` accepts it. Predicates
+/// are evaluated in order, stopping at the first that accepts.
+///
+/// An empty list accepts nothing.
+pub fn any_of<'p>(predicates: Vec<Box<Predicate + 'p>>) -> AnyOf<'p> {
+    AnyOf { predicates: predicates }
+}
+
+/// Accepts a value if every one of `predicates` accepts it. Predicates are
+/// evaluated in order, stopping at the first that rejects.
+///
+/// An empty list accepts everything.
+pub fn all_of<'p>(predicates: Vec<Box<Predicate + 'p>>) -> AllOf<'p> {
+    AllOf { predicates: predicates }
+}
+
+/// Accepts a value if `predicate` rejects it.
+pub fn not<P: Predicate>(predicate: P) -> Not<P> {
+    Not { inner: predicate }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combinators() {
+        let mut p = any_of(vec![
+            Box::new(|v: &[u8]| v == b"a"),
+            Box::new(|v: &[u8]| v == b"b"),
+        ]);
+        assert!(p.check(b"a"));
+        assert!(p.check(b"b"));
+        assert!(!p.check(b"c"));
+
+        let mut p = all_of(vec![
+            Box::new(|v: &[u8]| v.starts_with(b"a")),
+            Box::new(not(|v: &[u8]| v.ends_with(b"z"))),
+        ]);
+        assert!(p.check(b"ab"));
+        assert!(!p.check(b"az"));
+        assert!(!p.check(b"bb"));
+
+        assert!(!any_of(vec![]).check(b"a"));
+        assert!(all_of(vec![]).check(b"a"));
+    }
+}
@@ -0,0 +1,79 @@
+//! A cache of compiled regexes for caveats that contain patterns.
+//!
+//! Compiling a regex is much more expensive than matching one, and the same
+//! few patterns tend to appear in every almond. A `RegexCache` is shared
+//! between requests, e.g. alongside a `VerifierPolicy`, and passed to
+//! `Verifier::satisfies_regex_caveat`.
+//!
+//! Requires the `regex` feature.
+
+use regex::{Regex, RegexBuilder};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+
+/// The limit on the compiled size of a pattern, to bound the cost of
+/// compiling a pathological caveat.
+const SIZE_LIMIT: usize = 1 << 20;
+
+
+/// A bounded cache from patterns to compiled regexes.
+///
+/// Patterns are anchored, so must match the whole of the candidate value.
+pub struct RegexCache {
+    capacity: usize,
+    compiled: Mutex<HashMap<String, Option<Regex>>>,
+}
+
+impl RegexCache {
+    /// Create a cache holding up to `capacity` patterns. When the cache is
+    /// full it is cleared.
+    pub fn new(capacity: usize) -> RegexCache {
+        RegexCache {
+            capacity: capacity,
+            compiled: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns whether `pattern` matches the whole of `candidate`, or `None`
+    /// if the pattern is invalid.
+    pub fn is_match(&self, pattern: &str, candidate: &str) -> Option<bool> {
+        let mut compiled = self.compiled.lock().unwrap();
+
+        if !compiled.contains_key(pattern) {
+            if compiled.len() >= self.capacity {
+                compiled.clear();
+            }
+
+            let regex = RegexBuilder::new(&format!("^(?:{})$", pattern))
+                .size_limit(SIZE_LIMIT)
+                .build()
+                .ok();
+            compiled.insert(pattern.to_owned(), regex);
+        }
+
+        compiled[pattern].as_ref().map(|regex| regex.is_match(candidate))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::RegexCache;
+
+    #[test]
+    fn is_match() {
+        let cache = RegexCache::new(2);
+
+        assert_eq!(cache.is_match("orders/[0-9]+", "orders/17"), Some(true));
+        assert_eq!(cache.is_match("orders/[0-9]+", "orders/17/items"), Some(false));
+        assert_eq!(cache.is_match("orders/[0-9]+", "x/orders/17"), Some(false));
+        assert_eq!(cache.is_match("orders/(", "orders/("), None);
+
+        // Evicts the previous patterns
+        assert_eq!(cache.is_match("users/.*", "users/erikj"), Some(true));
+        assert_eq!(cache.compiled.lock().unwrap().len(), 1);
+        assert_eq!(cache.is_match("orders/[0-9]+", "orders/17"), Some(true));
+    }
+}
@@ -0,0 +1,78 @@
+//! Pluggable caveat verifiers.
+//!
+//! A `CaveatVerifier` checks a family of caveats, e.g. geographic or billing
+//! restrictions, so that it can be shipped as a library and registered with
+//! `Verifier::register` alongside any others.
+//!
+//! ```
+//! # use almonds::{Almond, Verifier};
+//! use almonds::registry::{CaveatVerifier, VerifyContext};
+//!
+//! /// Checks `country` caveats against the country of the request.
+//! struct Geo;
+//!
+//! impl CaveatVerifier for Geo {
+//!     fn check(&self, key: &[u8], value: Option<&[u8]>, ctx: &VerifyContext) -> Option<bool> {
+//!         if key != b"country" {
+//!             return None;
+//!         }
+//!         let country = ctx.get("country").map(|c| c.as_bytes());
+//!         Some(value.is_some() && value == country)
+//!     }
+//! }
+//!
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! almond.add_caveat(b"country", Some(b"GB"));
+//!
+//! let mut v = Verifier::new(&almond, 1, b"access");
+//! v.register(Box::new(Geo));
+//! v.set_context_value("country", "GB");
+//! assert!(v.verify());
+//! ```
+
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+
+/// Checks caveats, given the context the almond is being verified in.
+pub trait CaveatVerifier {
+    /// Check a caveat.
+    ///
+    /// Returns `Some(true)` to accept the caveat, `Some(false)` to reject it,
+    /// or `None` if the caveat isn't one this verifier checks.
+    fn check(&self, key: &[u8], value: Option<&[u8]>, ctx: &VerifyContext) -> Option<bool>;
+}
+
+impl <'c, C: CaveatVerifier + ?Sized> CaveatVerifier for &'c C {
+    fn check(&self, key: &[u8], value: Option<&[u8]>, ctx: &VerifyContext) -> Option<bool> {
+        (**self).check(key, value, ctx)
+    }
+}
+
+
+/// The context an almond is being verified in, passed to each
+/// `CaveatVerifier`.
+pub struct VerifyContext<'c> {
+    now: SystemTime,
+    values: &'c BTreeMap<String, String>,
+}
+
+impl <'c> VerifyContext<'c> {
+    /// Create a new context.
+    pub fn new(now: SystemTime, values: &'c BTreeMap<String, String>) -> VerifyContext<'c> {
+        VerifyContext {
+            now: now,
+            values: values,
+        }
+    }
+
+    /// The current time, as given by the verifier's clock.
+    pub fn now(&self) -> SystemTime {
+        self.now
+    }
+
+    /// Get a value set with `Verifier::set_context_value`.
+    pub fn get(&self, name: &str) -> Option<&'c str> {
+        self.values.get(name).map(|v| &v[..])
+    }
+}
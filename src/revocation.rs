@@ -0,0 +1,89 @@
+//! Revoking individual almonds.
+//!
+//! Almonds are stateless, so the only way to revoke one before it expires is
+//! to remember that it has been revoked. Almonds with a token id, added by
+//! `Almond::add_token_id`, can be revoked by a `RevocationChecker` set on the
+//! `Verifier`.
+//!
+//! Similarly, almonds with a single use caveat, added by
+//! `Almond::add_single_use`, are burnt by a `UseStore` the first time they
+//! are successfully verified.
+//!
+//! ```
+//! # use std::collections::HashSet;
+//! # use almonds::{Almond, Verifier};
+//! let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+//! let token_id = almond.add_token_id();
+//!
+//! let mut revoked = HashSet::new();
+//! {
+//!     let mut v = Verifier::new(&almond, 1, b"access");
+//!     v.set_revocation_checker(&revoked);
+//!     assert!(v.verify());
+//! }
+//!
+//! revoked.insert(token_id);
+//!
+//! let mut v = Verifier::new(&almond, 1, b"access");
+//! v.set_revocation_checker(&revoked);
+//! assert!(!v.verify());
+//! ```
+
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Mutex;
+
+
+/// Checks whether a token id has been revoked.
+///
+/// Implementations must be safe to call concurrently, e.g. by querying a
+/// shared denylist.
+pub trait RevocationChecker {
+    /// Returns whether the almond with the given token id has been revoked.
+    fn is_revoked(&self, token_id: &[u8]) -> bool;
+}
+
+impl <'r, R: RevocationChecker + ?Sized> RevocationChecker for &'r R {
+    fn is_revoked(&self, token_id: &[u8]) -> bool {
+        (**self).is_revoked(token_id)
+    }
+}
+
+impl RevocationChecker for HashSet<Vec<u8>> {
+    fn is_revoked(&self, token_id: &[u8]) -> bool {
+        self.contains(token_id)
+    }
+}
+
+impl RevocationChecker for BTreeSet<Vec<u8>> {
+    fn is_revoked(&self, token_id: &[u8]) -> bool {
+        self.contains(token_id)
+    }
+}
+
+
+/// Records which single use almonds have been used.
+///
+/// As the store is only consulted after every other check has passed, an
+/// almond that fails verification is not burnt.
+pub trait UseStore {
+    /// Mark the almond with the given single use id as used, returning
+    /// `false` if it had already been used.
+    ///
+    /// This must be atomic, so that concurrent requests can't both use the
+    /// same almond.
+    fn consume(&self, token_id: &[u8]) -> bool;
+}
+
+impl <'s, S: UseStore + ?Sized> UseStore for &'s S {
+    fn consume(&self, token_id: &[u8]) -> bool {
+        (**self).consume(token_id)
+    }
+}
+
+/// An in memory store. Used ids are never forgotten, so single use almonds
+/// should also expire.
+impl UseStore for Mutex<HashSet<Vec<u8>>> {
+    fn consume(&self, token_id: &[u8]) -> bool {
+        self.lock().unwrap().insert(token_id.to_vec())
+    }
+}
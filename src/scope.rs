@@ -0,0 +1,68 @@
+//! Hierarchical scopes with wildcards.
+//!
+//! A scope is a list of segments separated by colons, e.g. `repo:read`. In a
+//! pattern, a `*` segment matches any single segment, or if it is the last
+//! segment then any one or more remaining segments. So `repo:*` matches
+//! `repo:read` and `repo:read:issues`, but not `repo` or `user:read`.
+//!
+//! Scopes are added to an almond with `Almond::add_scope`, and checked with
+//! `Verifier::satisfies_scope` or `Verifier::satisfies_scope_allowing`.
+
+
+/// Returns whether `pattern` matches `scope`.
+///
+/// A `*` segment in `scope` is only matched by a `*` segment in `pattern`, so
+/// a scope with a wildcard is only matched by a pattern at least as broad.
+///
+/// ```
+/// # use almonds::scope::matches;
+/// assert!(matches("repo:*", "repo:read"));
+/// assert!(matches("repo:*", "repo:read:issues"));
+/// assert!(matches("*:read", "repo:read"));
+/// assert!(!matches("repo:*", "repo"));
+/// assert!(!matches("repo:read", "repo:*"));
+/// ```
+pub fn matches(pattern: &str, scope: &str) -> bool {
+    let mut pattern = pattern.split(':').peekable();
+    let mut scope = scope.split(':');
+
+    while let Some(expected) = pattern.next() {
+        let segment = match scope.next() {
+            Some(segment) => segment,
+            None => return false,
+        };
+
+        if expected == "*" {
+            // A trailing wildcard matches everything that's left.
+            if pattern.peek().is_none() {
+                return true;
+            }
+        } else if expected != segment {
+            return false;
+        }
+    }
+
+    scope.next().is_none()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("repo:read", "repo:read"));
+        assert!(!matches("repo:read", "repo:write"));
+        assert!(!matches("repo:read", "repo:read:issues"));
+        assert!(!matches("repo:read:issues", "repo:read"));
+
+        assert!(matches("*", "repo"));
+        assert!(matches("*", "repo:read"));
+        assert!(matches("repo:*", "repo:*"));
+        assert!(matches("repo:*:issues", "repo:read:issues"));
+        assert!(!matches("repo:*:issues", "repo:read:pulls"));
+        assert!(!matches("repo:*:issues", "repo:read"));
+        assert!(!matches("user:*", "repo:read"));
+    }
+}
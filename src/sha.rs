@@ -0,0 +1,89 @@
+//! The SHA-256 and HMAC-SHA256 implementations used for the hash chain and
+//! digests.
+//!
+//! By default these use `rust-crypto`. With the `fast-sha2` feature they use
+//! the `sha2` crate instead, which detects and uses the SHA extensions
+//! (SHA-NI) at runtime. Both produce the same output.
+
+pub use self::imp::*;
+
+
+#[cfg(feature = "fast-sha2")]
+mod imp {
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+
+    /// Compute the HMAC-SHA256 of `data` with the given key.
+    pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(data);
+
+        let mut result = [0u8; 32];
+        result.copy_from_slice(&mac.finalize().into_bytes());
+        result
+    }
+
+    /// Compute the SHA-256 digest of `data`.
+    pub fn sha256(data: &[u8]) -> [u8; 32] {
+        let mut result = [0u8; 32];
+        result.copy_from_slice(&Sha256::digest(data));
+        result
+    }
+}
+
+
+#[cfg(not(feature = "fast-sha2"))]
+mod imp {
+    use crypto::digest::Digest;
+    use crypto::hmac::Hmac;
+    use crypto::mac::Mac;
+    use crypto::sha2::Sha256;
+
+    /// Compute the HMAC-SHA256 of `data` with the given key.
+    pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut mac = Hmac::new(Sha256::new(), key);
+        mac.input(data);
+
+        let mut result = [0u8; 32];
+        mac.raw_result(&mut result);
+        result
+    }
+
+    /// Compute the SHA-256 digest of `data`.
+    pub fn sha256(data: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.input(data);
+
+        let mut result = [0u8; 32];
+        hasher.result(&mut result);
+        result
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::hex::ToHex;
+
+    use super::*;
+
+    #[test]
+    fn vectors() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?").to_hex(),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        // A key longer than the block size is hashed first.
+        assert_eq!(
+            hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First").to_hex(),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+
+        assert_eq!(
+            sha256(b"abc").to_hex(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
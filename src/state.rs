@@ -0,0 +1,226 @@
+//! Opaque, tamper-proof state tokens, e.g. pagination cursors or resume
+//! tokens handed to clients.
+//!
+//! A `StateToken` serializes the state as JSON into a caveat of an almond
+//! that expires after a fixed lifetime, and encrypts the whole almond with
+//! `seal_base64`. Clients can neither read nor modify the state, and tokens
+//! are rejected once they have expired.
+//!
+//! ```
+//! use std::time::Duration;
+//! use almonds::state::StateToken;
+//!
+//! let cursors = StateToken::new(b"secret", b"cursor", Duration::from_secs(3600));
+//!
+//! // The last item returned and the page size.
+//! let token = cursors.encode(&("item-1234".to_owned(), 50u32)).unwrap();
+//!
+//! let (after, limit): (String, u32) = cursors.decode(token.as_bytes()).unwrap();
+//! assert_eq!(after, "item-1234");
+//! assert_eq!(limit, 50);
+//! ```
+//!
+//! Tokens for different purposes should use different almond types, so that
+//! one can't be used in place of another.
+
+use rustc_serialize::{Decodable, Encodable};
+use rustc_serialize::json::{self, DecoderError, EncoderError};
+
+use std::time::Duration;
+
+use clock::{Clock, SystemClock};
+use {Almond, AlmondParseError, Verifier, VerifyError};
+
+
+/// The key of the caveat holding the JSON serialized state.
+pub const STATE_KEY : &'static [u8] = b"state";
+
+/// The generation of almonds minted by a `StateToken`.
+const STATE_GENERATION : u32 = 1;
+
+
+/// Packs state into encrypted, expiring tokens, see the module
+/// documentation.
+pub struct StateToken {
+    secret: Vec<u8>,
+    almond_type: Vec<u8>,
+    lifetime: Duration,
+    clock: Box<Clock + Send + Sync>,
+}
+
+impl StateToken {
+    /// Create tokens of the given almond type that are valid for `lifetime`
+    /// after they were encoded.
+    pub fn new(secret: &[u8], almond_type: &[u8], lifetime: Duration) -> StateToken {
+        StateToken {
+            secret: secret.to_vec(),
+            almond_type: almond_type.to_vec(),
+            lifetime: lifetime,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    /// Set the clock used for both the expiry of new tokens and checking it.
+    /// Defaults to the `SystemClock`.
+    pub fn set_clock<C>(&mut self, clock: C) -> &mut Self
+        where C: Clock + Send + Sync + 'static
+    {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Encode the state into a token.
+    ///
+    /// The token is URL safe Base64, see `Almond::seal_base64`.
+    pub fn encode<T: Encodable>(&self, state: &T) -> Result<String, StateError> {
+        let encoded = try!(json::encode(state));
+
+        let mut almond = Almond::create(&self.secret, STATE_GENERATION, self.almond_type.clone());
+        almond.add_expiry(self.clock.now() + self.lifetime);
+        almond.add_caveat(STATE_KEY, Some(encoded.as_bytes()));
+
+        Ok(almond.seal_base64(&self.secret))
+    }
+
+    /// Decode the state from a token, checking that it was encoded by a
+    /// `StateToken` with the same secret and almond type and has not
+    /// expired.
+    pub fn decode<T: Decodable>(&self, token: &[u8]) -> Result<T, StateError> {
+        let almond = try!(Almond::open_and_validate(&self.secret, token));
+
+        {
+            let mut v = Verifier::new(&almond, STATE_GENERATION, &self.almond_type);
+            v.set_clock(&*self.clock);
+            v.require_present(STATE_KEY);
+            v.require_unique(STATE_KEY);
+            v.allow(STATE_KEY);
+            v.satisfies_expiry();
+            try!(v.verify_detailed());
+        }
+
+        // The verifier checked the caveat is present, so this only fails if
+        // it is not UTF-8.
+        let encoded = try!(almond.caveat_str(STATE_KEY).ok_or(StateError::InvalidUtf8));
+        Ok(try!(json::decode(encoded)))
+    }
+}
+
+
+quick_error! {
+    /// An error returned by `StateToken`.
+    #[derive(Debug)]
+    pub enum StateError {
+        /// The state could not be serialized.
+        Encode(err: EncoderError) {
+            from()
+            display("failed to encode state: {}", err)
+            cause(err)
+        }
+
+        /// The token could not be decrypted, or the almond could not be
+        /// parsed or its hash did not match.
+        Parse(err: AlmondParseError) {
+            from()
+            display("invalid token: {}", err)
+            cause(err)
+        }
+
+        /// The almond was valid but has expired, or was not minted by a
+        /// `StateToken` of the same type.
+        Rejected(err: VerifyError) {
+            from()
+            display("token rejected: {}", err)
+            cause(err)
+        }
+
+        /// The state is not valid UTF-8.
+        InvalidUtf8 {
+            display("state is not valid UTF-8")
+        }
+
+        /// The state could not be deserialized as the requested type.
+        Decode(err: DecoderError) {
+            from()
+            display("failed to decode state: {}", err)
+            cause(err)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use clock::MockClock;
+    use {Almond, AlmondParseError, VerifyError};
+
+    #[test]
+    fn state_tokens() {
+        let key = b"this_is_a_secret";
+        let now = UNIX_EPOCH + Duration::from_secs(1500000000);
+
+        let mut tokens = StateToken::new(key, b"cursor", Duration::from_secs(60));
+        tokens.set_clock(MockClock::new(now));
+
+        let state = vec![("after".to_owned(), 1234u64)];
+        let token = tokens.encode(&state).unwrap();
+        assert!(!token.contains("after"));
+        assert_eq!(tokens.decode::<Vec<(String, u64)>>(token.as_bytes()).unwrap(), state);
+
+        match tokens.decode::<String>(token.as_bytes()) {
+            Err(StateError::Decode(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let mut tampered = token.clone().into_bytes();
+        let mid = tampered.len() / 2;
+        tampered[mid] = if tampered[mid] == b'A' { b'B' } else { b'A' };
+        match tokens.decode::<Vec<(String, u64)>>(&tampered) {
+            Err(StateError::Parse(AlmondParseError::Decryption)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let other = StateToken::new(b"other", b"cursor", Duration::from_secs(60));
+        match other.decode::<Vec<(String, u64)>>(token.as_bytes()) {
+            Err(StateError::Parse(AlmondParseError::Decryption)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let mut resume = StateToken::new(key, b"resume", Duration::from_secs(60));
+        resume.set_clock(MockClock::new(now));
+        match resume.decode::<Vec<(String, u64)>>(token.as_bytes()) {
+            Err(StateError::Rejected(VerifyError::WrongType { .. })) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // A sealed almond without any state.
+        let empty = Almond::create(key, 1, b"cursor".to_vec()).seal_base64(key);
+        match tokens.decode::<Vec<(String, u64)>>(empty.as_bytes()) {
+            Err(StateError::Rejected(VerifyError::MissingCaveats { .. })) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn expiry() {
+        let now = UNIX_EPOCH + Duration::from_secs(1500000000);
+        let encoder = {
+            let mut tokens = StateToken::new(b"this_is_a_secret", b"cursor", Duration::from_secs(60));
+            tokens.set_clock(MockClock::new(now));
+            tokens
+        };
+        let token = encoder.encode(&42u32).unwrap();
+
+        let mut decoder = StateToken::new(b"this_is_a_secret", b"cursor", Duration::from_secs(60));
+        decoder.set_clock(MockClock::new(now + Duration::from_secs(59)));
+        assert_eq!(decoder.decode::<u32>(token.as_bytes()).unwrap(), 42);
+
+        decoder.set_clock(MockClock::new(now + Duration::from_secs(61)));
+        match decoder.decode::<u32>(token.as_bytes()) {
+            Err(StateError::Rejected(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Spans and events emitted with the `tracing` feature.
+//!
+//! Without the feature these are all no-ops, so callers don't need to be
+//! conditionally compiled. Caveat values and hashes are never recorded.
+
+pub use self::imp::*;
+
+
+#[cfg(feature = "tracing")]
+mod imp {
+    use tracing::span::EnteredSpan;
+
+    use {Almond, AlmondParseError, VerifyError};
+
+    /// Returned by the span functions, and exits the span when dropped.
+    pub type Entered = EnteredSpan;
+
+    /// Record that an almond was created.
+    pub fn created(almond: &Almond) {
+        debug!(
+            almond_type = %String::from_utf8_lossy(almond.almond_type()),
+            generation = almond.generation(),
+            "created almond"
+        );
+    }
+
+    /// Enter a span covering parsing an almond.
+    pub fn parse_span() -> Entered {
+        debug_span!("almond.parse").entered()
+    }
+
+    /// Record the result of parsing and validating an almond.
+    pub fn parsed(res: &Result<Almond, AlmondParseError>) {
+        match *res {
+            Ok(ref almond) => debug!(
+                almond_type = %String::from_utf8_lossy(almond.almond_type()),
+                generation = almond.generation(),
+                caveats = almond.caveats().count(),
+                "parsed almond"
+            ),
+            Err(ref err) => info!(error = %err, "failed to parse almond"),
+        }
+    }
+
+    /// Enter a span covering verifying an almond.
+    pub fn verify_span(almond_type: &[u8], generation: u32, caveats: usize) -> Entered {
+        debug_span!(
+            "almond.verify",
+            almond_type = %String::from_utf8_lossy(almond_type),
+            generation = generation,
+            caveats = caveats
+        ).entered()
+    }
+
+    /// Record the result of verifying an almond.
+    pub fn verified(res: &Result<(), VerifyError>) {
+        match *res {
+            Ok(()) => debug!("verified almond"),
+            Err(ref err) => info!(error = %err, "rejected almond"),
+        }
+    }
+}
+
+
+#[cfg(not(feature = "tracing"))]
+mod imp {
+    use {Almond, AlmondParseError, VerifyError};
+
+    pub struct Entered;
+
+    #[inline]
+    pub fn created(_: &Almond) {}
+
+    #[inline]
+    pub fn parse_span() -> Entered {
+        Entered
+    }
+
+    #[inline]
+    pub fn parsed(_: &Result<Almond, AlmondParseError>) {}
+
+    #[inline]
+    pub fn verify_span(_: &[u8], _: u32, _: usize) -> Entered {
+        Entered
+    }
+
+    #[inline]
+    pub fn verified(_: &Result<(), VerifyError>) {}
+}
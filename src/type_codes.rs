@@ -0,0 +1,118 @@
+//! Numeric codes for almond types.
+//!
+//! Every almond carries its type, so replacing `login` with a one byte code
+//! saves bytes on every token. A `TypeRegistry` maps codes to type names,
+//! which are still what is hashed and what the rest of the API uses, so the
+//! codes only affect the serialization produced by
+//! `Almond::serialize_with_type_code`.
+//!
+//! In the serialization the `FLAG_TYPE_CODE` flag is set in the v2 header,
+//! and the length prefixed type is replaced by the code as a varint.
+//!
+//! ```
+//! # use almonds::Almond;
+//! use almonds::type_codes::TypeRegistry;
+//!
+//! let mut types = TypeRegistry::new();
+//! types.register(1, b"login".to_vec()).unwrap();
+//!
+//! let mut almond = Almond::create(b"secret", 1, b"login".to_vec());
+//! almond.add_caveat(b"user", Some(b"erikj"));
+//!
+//! let serialized = almond.serialize_with_type_code(&types);
+//! let parsed = Almond::parse_and_validate_with_type_codes(b"secret", &serialized, &types)
+//!     .unwrap();
+//! assert_eq!(parsed.almond_type(), b"login");
+//! assert_eq!(types.name(1), Some(&b"login"[..]));
+//! ```
+
+use std::collections::BTreeMap;
+
+
+quick_error! {
+    /// An error returned when a type can't be registered.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum TypeRegistryError {
+        /// The code is already registered.
+        DuplicateCode { code: u16 } {
+            display("type code {} is already registered", code)
+        }
+
+        /// The type name is already registered.
+        DuplicateName { name: Vec<u8> } {
+            display("type {:?} is already registered", String::from_utf8_lossy(name))
+        }
+    }
+}
+
+
+/// A mapping between almond types and numeric codes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TypeRegistry {
+    names: BTreeMap<u16, Vec<u8>>,
+    codes: BTreeMap<Vec<u8>, u16>,
+}
+
+impl TypeRegistry {
+    /// Create an empty registry.
+    pub fn new() -> TypeRegistry {
+        TypeRegistry::default()
+    }
+
+    /// Register a code for the type `name`.
+    ///
+    /// Codes below 128 take a single byte on the wire, and those below 16384
+    /// two.
+    pub fn register(&mut self, code: u16, name: Vec<u8>)
+        -> Result<&mut Self, TypeRegistryError>
+    {
+        if self.names.contains_key(&code) {
+            return Err(TypeRegistryError::DuplicateCode { code: code });
+        }
+        if self.codes.contains_key(&name) {
+            return Err(TypeRegistryError::DuplicateName { name: name });
+        }
+
+        self.codes.insert(name.clone(), code);
+        self.names.insert(code, name);
+        Ok(self)
+    }
+
+    /// Get the code of a type, if it is registered.
+    pub fn code(&self, name: &[u8]) -> Option<u16> {
+        self.codes.get(name).cloned()
+    }
+
+    /// Get the name of the type with the given code.
+    pub fn name(&self, code: u16) -> Option<&[u8]> {
+        self.names.get(&code).map(|name| &name[..])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register() {
+        let mut types = TypeRegistry::new();
+        types.register(1, b"login".to_vec()).unwrap();
+        types.register(300, b"access".to_vec()).unwrap();
+
+        assert_eq!(types.code(b"login"), Some(1));
+        assert_eq!(types.code(b"access"), Some(300));
+        assert_eq!(types.code(b"other"), None);
+        assert_eq!(types.name(300), Some(&b"access"[..]));
+        assert_eq!(types.name(2), None);
+
+        assert_eq!(
+            types.register(1, b"other".to_vec()).err(),
+            Some(TypeRegistryError::DuplicateCode { code: 1 })
+        );
+        assert_eq!(
+            types.register(2, b"login".to_vec()).err(),
+            Some(TypeRegistryError::DuplicateName { name: b"login".to_vec() })
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Encoding of typed values into caveat values.
+//!
+//! Caveats are bytes, so every application needs to agree on how e.g.
+//! timestamps are written. The `CaveatValue` trait defines a standard
+//! encoding for common types, which is used by `Almond::add_caveat_typed`,
+//! `Almond::caveat_typed` and `Verifier::satisfies_typed`.
+//!
+//! | Type          | Encoding                                      |
+//! |---------------|-----------------------------------------------|
+//! | `u64`         | Decimal, e.g. `1500000000`                    |
+//! | `bool`        | `true` or `false`                             |
+//! | `&str`        | UTF-8                                         |
+//! | `String`      | UTF-8                                         |
+//! | `IpAddr`      | Textual, e.g. `10.0.0.1` or `::1`             |
+//! | `IpNet`       | CIDR, e.g. `10.0.0.0/8`, see `net`            |
+//! | `SystemTime`  | Whole seconds since the UNIX epoch, in decimal |
+
+use std::net::IpAddr;
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+
+/// A type that can be stored as the value of a caveat.
+///
+/// The lifetime allows borrowed types, such as `&str`, to be decoded without
+/// copying.
+pub trait CaveatValue<'a>: Sized {
+    /// Append the encoded value to `buf`.
+    fn encode_value(&self, buf: &mut Vec<u8>);
+
+    /// Decode a value, returning `None` if it is not validly encoded.
+    fn decode_value(value: &'a [u8]) -> Option<Self>;
+}
+
+impl <'a> CaveatValue<'a> for u64 {
+    fn encode_value(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.to_string().as_bytes());
+    }
+
+    fn decode_value(value: &'a [u8]) -> Option<u64> {
+        // `parse` accepts a leading `+` and leading zeros, which would allow
+        // several encodings of the same value.
+        match value.first() {
+            Some(&b'+') => return None,
+            Some(&b'0') if value.len() > 1 => return None,
+            _ => {}
+        }
+        str::from_utf8(value).ok().and_then(|value| value.parse().ok())
+    }
+}
+
+impl <'a> CaveatValue<'a> for bool {
+    fn encode_value(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(if *self { b"true" } else { b"false" });
+    }
+
+    fn decode_value(value: &'a [u8]) -> Option<bool> {
+        match value {
+            b"true" => Some(true),
+            b"false" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl <'a> CaveatValue<'a> for &'a str {
+    fn encode_value(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode_value(value: &'a [u8]) -> Option<&'a str> {
+        str::from_utf8(value).ok()
+    }
+}
+
+impl <'a> CaveatValue<'a> for String {
+    fn encode_value(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode_value(value: &'a [u8]) -> Option<String> {
+        str::from_utf8(value).ok().map(|value| value.to_owned())
+    }
+}
+
+impl <'a> CaveatValue<'a> for IpAddr {
+    fn encode_value(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.to_string().as_bytes());
+    }
+
+    fn decode_value(value: &'a [u8]) -> Option<IpAddr> {
+        str::from_utf8(value).ok().and_then(|value| value.parse().ok())
+    }
+}
+
+impl <'a> CaveatValue<'a> for SystemTime {
+    /// Times before the UNIX epoch are encoded as the epoch, and sub-second
+    /// precision is truncated.
+    fn encode_value(&self, buf: &mut Vec<u8>) {
+        let secs = self.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        secs.encode_value(buf);
+    }
+
+    /// Times too far in the future to be represented are invalid.
+    fn decode_value(value: &'a [u8]) -> Option<SystemTime> {
+        u64::decode_value(value)
+            .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+    }
+}
+
+
+/// Encode a value into a new buffer.
+pub fn encode<'a, T: CaveatValue<'a>>(value: &T) -> Vec<u8> {
+    let mut buf = Vec::new();
+    value.encode_value(&mut buf);
+    buf
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::IpAddr;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(encode(&1500000000u64), b"1500000000");
+        assert_eq!(u64::decode_value(b"1500000000"), Some(1500000000));
+        assert_eq!(u64::decode_value(b"+15"), None);
+        assert_eq!(u64::decode_value(b"-15"), None);
+        assert_eq!(u64::decode_value(b"007"), None);
+        assert_eq!(u64::decode_value(b"0"), Some(0));
+
+        assert_eq!(encode(&true), b"true");
+        assert_eq!(bool::decode_value(b"false"), Some(false));
+        assert_eq!(bool::decode_value(b"yes"), None);
+
+        assert_eq!(<&str>::decode_value(b"erikj"), Some("erikj"));
+        assert_eq!(<&str>::decode_value(b"\xff"), None);
+
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        assert_eq!(encode(&ip), b"10.0.0.1");
+        assert_eq!(IpAddr::decode_value(b"10.0.0.1"), Some(ip));
+
+        let time = UNIX_EPOCH + Duration::from_secs(1500000000);
+        assert_eq!(encode(&time), b"1500000000");
+        assert_eq!(SystemTime::decode_value(b"1500000000"), Some(time));
+        assert_eq!(
+            encode(&(time + Duration::from_millis(999))),
+            b"1500000000"
+        );
+
+        let max = u64::max_value().to_string();
+        assert_eq!(u64::decode_value(max.as_bytes()), Some(u64::max_value()));
+        assert_eq!(SystemTime::decode_value(max.as_bytes()), None);
+    }
+}
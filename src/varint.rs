@@ -0,0 +1,93 @@
+//! Unsigned LEB128 variable length integers, as used by the v2 format.
+
+
+/// The maximum number of bytes in an encoded `u64`.
+pub const MAX_LEN: usize = 10;
+
+
+/// Append the encoding of `value` to `buf`.
+pub fn encode(mut value: u64, buf: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Write the encoding of `value` to the start of `buf`, returning the number
+/// of bytes written.
+pub fn encode_to_slice(mut value: u64, buf: &mut [u8; MAX_LEN]) -> usize {
+    let mut len = 0;
+    while value >= 0x80 {
+        buf[len] = (value as u8) | 0x80;
+        value >>= 7;
+        len += 1;
+    }
+    buf[len] = value as u8;
+    len + 1
+}
+
+/// The number of bytes `encode` would write for `value`.
+pub fn encoded_len(mut value: u64) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+/// Decode a value from the start of `input`, returning the value and the
+/// number of bytes read.
+///
+/// Returns `None` if the input is truncated, overflows a `u64`, or is not
+/// minimally encoded.
+pub fn decode(input: &[u8]) -> Option<(u64, usize)> {
+    let mut value: u64 = 0;
+
+    for (idx, byte) in input.iter().enumerate().take(MAX_LEN) {
+        let bits = (*byte & 0x7f) as u64;
+
+        if idx == MAX_LEN - 1 && bits > 1 {
+            return None;
+        }
+
+        value |= bits << (7 * idx);
+
+        if *byte & 0x80 == 0 {
+            // A trailing zero byte means the value had a shorter encoding.
+            if idx > 0 && *byte == 0 {
+                return None;
+            }
+            return Some((value, idx + 1));
+        }
+    }
+
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for &value in &[0, 1, 127, 128, 255, 300, 16384, ::std::u64::MAX] {
+            let mut buf = Vec::new();
+            encode(value, &mut buf);
+            assert_eq!(buf.len(), encoded_len(value));
+            assert_eq!(decode(&buf), Some((value, buf.len())));
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decode(b""), None);
+        assert_eq!(decode(&[0x80]), None);
+        assert_eq!(decode(&[0x80, 0x00]), None);
+        assert_eq!(decode(&[0xff; 10]), None);
+        assert_eq!(decode(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]), None);
+        assert_eq!(decode(&[0x05, 0xff]), Some((5, 1)));
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use {Almond, AlmondParseError, Verifier, VerifyError};
+
+
+/// An immutable, validated almond that is cheap to clone and can be shared
+/// between threads.
+///
+/// This can only be produced by validating an almond, so e.g. a web
+/// framework can store it in the extensions of a request and handlers know
+/// the hash has been checked. It dereferences to an `Almond`, and cloning
+/// only clones an `Arc`.
+///
+/// ```
+/// # use almonds::{Almond, VerifiedAlmond};
+/// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+/// almond.add_caveat(b"user", Some(b"erikj"));
+/// let serialized = almond.serialize_binary();
+///
+/// let verified = VerifiedAlmond::parse_and_validate(b"secret", &serialized).unwrap();
+/// let shared = verified.clone();
+///
+/// let user = std::thread::spawn(move || shared.caveat_str(b"user").map(String::from));
+/// assert_eq!(user.join().unwrap(), Some("erikj".to_owned()));
+/// assert_eq!(verified.almond_type(), b"access");
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct VerifiedAlmond {
+    inner: Arc<Almond>,
+}
+
+impl VerifiedAlmond {
+    /// Parse a binary serialized Almond, and validate that the hashes match.
+    ///
+    /// *Note: This expects a binary serialization rather than base64*
+    pub fn parse_and_validate(key: &[u8], input: &[u8])
+        -> Result<VerifiedAlmond, AlmondParseError>
+    {
+        Almond::parse_and_validate(key, input).map(from_validated)
+    }
+
+    /// Parse a Base64 serialized Almond, and validate that the hashes match.
+    /// See `Almond::parse_base64_and_validate`.
+    pub fn parse_base64_and_validate(key: &[u8], input: &[u8])
+        -> Result<VerifiedAlmond, AlmondParseError>
+    {
+        Almond::parse_base64_and_validate(key, input).map(from_validated)
+    }
+
+    /// Copy the almond, e.g. to add caveats to it.
+    pub fn to_almond(&self) -> Almond {
+        (*self.inner).clone()
+    }
+}
+
+impl Deref for VerifiedAlmond {
+    type Target = Almond;
+
+    fn deref(&self) -> &Almond {
+        &self.inner
+    }
+}
+
+impl fmt::Debug for VerifiedAlmond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+quick_error! {
+    /// An error returned by `verify_token`.
+    #[derive(Debug)]
+    pub enum TokenError {
+        /// The token could not be parsed or its hash did not match.
+        Parse(err: AlmondParseError) {
+            from()
+            display("invalid almond: {}", err)
+            cause(err)
+        }
+
+        /// The almond was valid but was rejected by the verifier.
+        Rejected(err: VerifyError) {
+            from()
+            display("almond rejected: {}", err)
+            cause(err)
+        }
+    }
+}
+
+
+/// Validate a Base64 encoded almond with `key` and verify it, in one step.
+///
+/// `policy` is invoked with a `Verifier` for the given `generation` and
+/// `almond_type` to add the predicates the almond must satisfy. The almond
+/// is only returned if it is both valid and accepted, so the result of the
+/// verifier can't be ignored by mistake.
+///
+/// ```
+/// # use almonds::{verify_token, Almond, TokenError};
+/// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+/// almond.add_caveat(b"user", Some(b"erikj"));
+/// let token = almond.serialize_base64();
+///
+/// let verified = verify_token(b"secret", token.as_bytes(), 1, b"access", |v| {
+///     v.allow(b"user");
+/// }).unwrap();
+/// assert_eq!(verified.caveat_str(b"user"), Some("erikj"));
+///
+/// match verify_token(b"secret", token.as_bytes(), 1, b"access", |_| {}) {
+///     Err(TokenError::Rejected(_)) => {}
+///     _ => panic!("almond should have been rejected"),
+/// }
+/// ```
+pub fn verify_token<F>(
+    key: &[u8],
+    encoded: &[u8],
+    generation: u32,
+    almond_type: &[u8],
+    policy: F,
+) -> Result<VerifiedAlmond, TokenError>
+    where F: FnOnce(&mut Verifier)
+{
+    let almond = try!(Almond::parse_base64_and_validate(key, encoded));
+
+    {
+        let mut v = Verifier::new(&almond, generation, almond_type);
+        policy(&mut v);
+        try!(v.verify_detailed());
+    }
+
+    Ok(from_validated(almond))
+}
+
+/// Wrap an almond whose hash has been validated.
+pub fn from_validated(almond: Almond) -> VerifiedAlmond {
+    VerifiedAlmond { inner: Arc::new(almond) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {AlmondParseError, VerifyError};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn verified() {
+        assert_send_sync::<VerifiedAlmond>();
+
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let verified = VerifiedAlmond::parse_and_validate(key, &almond.serialize_binary()).unwrap();
+        assert_eq!(*verified, almond);
+        assert_eq!(verified.clone(), verified);
+        assert_eq!(verified.to_almond(), almond);
+        assert_eq!(format!("{:?}", verified), format!("{:?}", almond));
+
+        let base64 = almond.serialize_base64();
+        let parsed = VerifiedAlmond::parse_base64_and_validate(key, base64.as_bytes()).unwrap();
+        assert_eq!(parsed, verified);
+
+        match VerifiedAlmond::parse_and_validate(b"other", &almond.serialize_binary()) {
+            Err(AlmondParseError::IncorrectHash) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn verify_tokens() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let token = almond.serialize_base64();
+
+        let verified = verify_token(key, token.as_bytes(), 1, b"login", |v| {
+            v.satisfies_exact(b"user", Some(b"erikj"));
+        }).unwrap();
+        assert_eq!(*verified, almond);
+
+        match verify_token(key, b"invalid", 1, b"login", |_| {}) {
+            Err(TokenError::Parse(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match verify_token(b"other", token.as_bytes(), 1, b"login", |v| { v.allow(b"user"); }) {
+            Err(TokenError::Parse(AlmondParseError::IncorrectHash)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match verify_token(key, token.as_bytes(), 2, b"login", |v| { v.allow(b"user"); }) {
+            Err(TokenError::Rejected(VerifyError::WrongGeneration { .. })) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -1,4 +1,35 @@
-use Almond;
+use {Almond, AlmondRef, Caveat, ATTENUATION_KEY, AUDIENCE_KEY, CLIENT_IP_KEY, EPOCH_KEY, EXPIRES_KEY,
+     MAX_CAVEATS_KEY, NOT_BEFORE_KEY, SCOPE_KEY, SEALED_KEY, SINGLE_USE_KEY, TOKEN_ID_KEY};
+use attenuation::AttenuationRecord;
+use audit::AuditRecord;
+use binding::{self, CHANNEL_KEY, CONTENT_KEY};
+use claims::{AlmondCaveats, CaveatList, ClaimsError};
+use http::{self, RequestInfo};
+use namespace::{self, NamespaceHandler};
+use net::IpNet;
+use clock::{Clock, SystemClock};
+use crypt;
+use ct::ct_eq;
+use metrics::{self, Observer};
+use predicate::Predicate;
+use registry::{CaveatVerifier, VerifyContext};
+use revocation::{RevocationChecker, UseStore};
+use scope;
+use trace;
+use value::CaveatValue;
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::str;
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "glob")]
+use glob::{MatchOptions, Pattern};
+#[cfg(feature = "regex")]
+use regex::Regex;
+#[cfg(feature = "regex")]
+use regex_cache::RegexCache;
 
 
 struct DeconstructedCaveatEntry<'a> {
@@ -7,6 +38,15 @@ struct DeconstructedCaveatEntry<'a> {
     pub accepted: Option<bool>,
 }
 
+/// A time based predicate, which is checked against the verifier's clock
+/// when verifying rather than when it is added.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TimeCheck {
+    Expiry,
+    NotBefore,
+    AttenuationRecords,
+}
+
 
 /// The verifier takes an almond and checks if it satisfies a list of
 /// predicates.
@@ -21,42 +61,280 @@ struct DeconstructedCaveatEntry<'a> {
 /// not all predicates must have matched a caveat.
 pub struct Verifier<'a> {
     caveats: Vec<DeconstructedCaveatEntry<'a>>,
-    reject: bool,
+    generation: u32,
+    almond_type: &'a [u8],
+    expected_generations: Vec<u32>,
+    expected_types: Vec<Vec<u8>>,
+    general: Vec<Box<Fn(&[u8], Option<&[u8]>) -> bool + 'a>>,
+    registered: Vec<Box<CaveatVerifier + 'a>>,
+    context_values: BTreeMap<String, String>,
+    required: Vec<Vec<u8>>,
+    forbidden: Vec<Vec<u8>>,
+    unique: Vec<Vec<u8>>,
+    all_unique: bool,
+    require_sealed: bool,
+    clock: Box<Clock + 'a>,
+    clock_skew: Duration,
+    time_checks: Vec<TimeCheck>,
+    revocation: Option<Box<RevocationChecker + 'a>>,
+    use_store: Option<Box<UseStore + 'a>>,
+    used: Cell<bool>,
+    observer: Option<Box<Observer + 'a>>,
 }
 
 impl <'a> Verifier<'a> {
     /// Create a new instance to verify the given caveat.
-    pub fn new(almond: &'a Almond, generation: u8, almond_type: &[u8])
+    pub fn new(almond: &'a Almond, generation: u32, almond_type: &[u8])
         -> Verifier<'a>
     {
-        let caveats = almond.caveats().iter()
-            .map(
-                |caveat| {
-                    let mut it = caveat.splitn(2, |c| *c == b' ');
+        Verifier::from_parts(
+            almond.iter_caveats(),
+            almond.generation(),
+            almond.almond_type(),
+            generation,
+            almond_type,
+        )
+    }
+
+    /// Create a new instance that accepts any of the given generations.
+    ///
+    /// This is useful during a migration between generations. Use
+    /// `generation` to check which generation is being verified.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 4, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    ///
+    /// let mut v = Verifier::new_with_generations(&almond, 3..5, b"access");
+    /// v.allow(b"user");
+    /// assert!(v.verify());
+    /// ```
+    pub fn new_with_generations<G>(almond: &'a Almond, generations: G, almond_type: &[u8])
+        -> Verifier<'a>
+        where G: IntoIterator<Item = u32>
+    {
+        let mut generations = generations.into_iter();
+        let first = generations.next().expect("no generations given");
+
+        let mut v = Verifier::new(almond, first, almond_type);
+        for generation in generations {
+            v.accept_generation(generation);
+        }
+        v
+    }
+
+    /// Create a new instance that accepts any of the given types.
+    ///
+    /// This is useful when several types of almond share the same caveats.
+    /// Use `almond_type` to check which type is being verified.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"sso".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    ///
+    /// let mut v = Verifier::new_with_types(&almond, 1, &[b"login", b"sso"]);
+    /// v.allow(b"user");
+    /// assert!(v.verify());
+    /// ```
+    pub fn new_with_types(almond: &'a Almond, generation: u32, almond_types: &[&[u8]])
+        -> Verifier<'a>
+    {
+        let (first, rest) = almond_types.split_first().expect("no types given");
+
+        let mut v = Verifier::new(almond, generation, first);
+        for almond_type in rest {
+            v.accept_type(almond_type);
+        }
+        v
+    }
 
-                    // By definition this must have at least one item
-                    let key = it.next().expect(
-                        "`split` returned zero results."
-                    );
-                    let value = it.next();
+    /// Create a new instance to verify the given borrowed almond.
+    pub fn from_ref(almond: &AlmondRef<'a>, generation: u32, almond_type: &[u8])
+        -> Verifier<'a>
+    {
+        Verifier::from_parts(
+            almond.caveats().map(Caveat::parse),
+            almond.generation(),
+            almond.almond_type(),
+            generation,
+            almond_type,
+        )
+    }
 
+    fn from_parts<I>(
+        caveats: I,
+        almond_generation: u32,
+        almond_type: &'a [u8],
+        expected_generation: u32,
+        expected_type: &[u8],
+    ) -> Verifier<'a>
+        where I: Iterator<Item = Caveat<'a>>
+    {
+        let mut caveats: Vec<DeconstructedCaveatEntry<'a>> = caveats
+            .map(
+                |caveat| {
                     DeconstructedCaveatEntry {
-                        key: key,
-                        value: value,
+                        key: caveat.key(),
+                        value: caveat.value(),
                         accepted: None,
                     }
                 }
             )
             .collect();
 
+        // The seal is accepted if it's the last caveat, see `Almond::seal`.
+        let len = caveats.len();
+        for (idx, item) in caveats.iter_mut().enumerate() {
+            if item.key == SEALED_KEY && item.value.is_none() {
+                item.accepted = Some(idx + 1 == len);
+            }
+        }
+
         Verifier {
             caveats: caveats,
-            reject:
-                almond.generation() != generation
-                || almond.almond_type() != almond_type
+            generation: almond_generation,
+            almond_type: almond_type,
+            expected_generations: vec![expected_generation],
+            expected_types: vec![expected_type.to_vec()],
+            general: Vec::new(),
+            registered: Vec::new(),
+            context_values: BTreeMap::new(),
+            required: Vec::new(),
+            forbidden: Vec::new(),
+            unique: Vec::new(),
+            all_unique: false,
+            require_sealed: false,
+            clock: Box::new(SystemClock),
+            clock_skew: Duration::from_secs(0),
+            time_checks: Vec::new(),
+            revocation: None,
+            use_store: None,
+            used: Cell::new(false),
+            observer: None,
         }
     }
 
+    /// Also accept almonds with the given generation.
+    pub fn accept_generation(&mut self, generation: u32) -> &mut Self {
+        if !self.expected_generations.contains(&generation) {
+            self.expected_generations.push(generation);
+        }
+        self
+    }
+
+    /// Also accept almonds with the given type.
+    pub fn accept_type(&mut self, almond_type: &[u8]) -> &mut Self {
+        if !self.expected_types.iter().any(|t| &t[..] == almond_type) {
+            self.expected_types.push(almond_type.to_vec());
+        }
+        self
+    }
+
+    /// Set the clock used by time based predicates such as
+    /// `satisfies_expiry`. Defaults to `SystemClock`.
+    ///
+    /// The clock is read when verifying, so this can be called before or
+    /// after adding the predicates.
+    pub fn set_clock<C: Clock + 'a>(&mut self, clock: C) -> &mut Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Set the observer notified if the almond is rejected, in place of the
+    /// global one set with `metrics::set_global_observer`.
+    pub fn observe<O: Observer + 'a>(&mut self, observer: O) -> &mut Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Set how far the clock may be wrong by. Time based predicates are
+    /// relaxed by this amount, so e.g. an almond is still accepted by
+    /// `satisfies_expiry` up to `skew` after it expires. Defaults to zero.
+    ///
+    /// Like the clock, the skew is only used when verifying.
+    ///
+    /// ```
+    /// # use std::time::{Duration, SystemTime};
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_expiry(SystemTime::now() - Duration::from_secs(5));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.set_clock_skew(Duration::from_secs(30));
+    /// v.satisfies_expiry();
+    /// assert!(v.verify());
+    /// ```
+    pub fn set_clock_skew(&mut self, skew: Duration) -> &mut Self {
+        self.clock_skew = skew;
+        self
+    }
+
+    /// Set a checker that is consulted for the id of every `TOKEN_ID_KEY`
+    /// caveat when verifying. If any have been revoked the almond is
+    /// rejected.
+    ///
+    /// This also accepts `TOKEN_ID_KEY` caveats, as they don't restrict the
+    /// almond otherwise. Almonds without a token id can't be revoked, use
+    /// `require_present(TOKEN_ID_KEY)` to reject them.
+    pub fn set_revocation_checker<R>(&mut self, checker: R) -> &mut Self
+        where R: RevocationChecker + 'a
+    {
+        self.revocation = Some(Box::new(checker));
+        self.allow(TOKEN_ID_KEY)
+    }
+
+    /// Set the store used to burn single use almonds.
+    ///
+    /// Once every other check has passed, the id of the `SINGLE_USE_KEY`
+    /// caveat is consumed from the store. If it had already been used the
+    /// almond is rejected. The id is only consumed once per verifier, so
+    /// e.g. calling `verify` and then `verify_into` on the same verifier
+    /// accepts the almond both times, but any other verifier rejects it.
+    ///
+    /// This also accepts `SINGLE_USE_KEY` caveats, which are otherwise
+    /// unmatched, and requires the almond to have exactly one. As anyone can
+    /// add caveats, an almond with several could otherwise be used to burn
+    /// the ids of other almonds.
+    ///
+    /// ```
+    /// # use std::collections::HashSet;
+    /// # use std::sync::Mutex;
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"reset_password".to_vec());
+    /// almond.add_single_use();
+    ///
+    /// let store = Mutex::new(HashSet::new());
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"reset_password");
+    /// v.set_use_store(&store);
+    /// assert!(v.verify());
+    /// assert!(v.verify());
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"reset_password");
+    /// v.set_use_store(&store);
+    /// assert!(!v.verify());
+    /// ```
+    pub fn set_use_store<S>(&mut self, store: S) -> &mut Self
+        where S: UseStore + 'a
+    {
+        self.use_store = Some(Box::new(store));
+        self.allow(SINGLE_USE_KEY)
+            .require_present(SINGLE_USE_KEY)
+            .require_unique(SINGLE_USE_KEY)
+    }
+
+    /// Get the type of the almond being verified.
+    pub fn almond_type(&self) -> &'a [u8] {
+        self.almond_type
+    }
+
+    /// Get the generation of the almond being verified.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
     /// Allow all caveats with the given key, irrespective of their values.
     ///
     /// This should generally only be used if the value will be pulled out of
@@ -101,7 +379,7 @@ impl <'a> Verifier<'a> {
     ///    b"expires",
     ///    |val| {
     ///         str::from_utf8(val).ok().and_then(
-    ///             |val| val.parse().ok()
+    ///             |val| val.parse::<u64>().ok()
     ///         ).map(
     ///             |val| 1447720058 < val   // Where `1447720058` is 'now'
     ///         ).unwrap_or(false)
@@ -109,13 +387,21 @@ impl <'a> Verifier<'a> {
     /// );
     /// assert!(v.verify());
     /// ```
-    pub fn satisfies<F>(&mut self, key: &[u8], mut predicate: F) -> &mut Self
+    pub fn satisfies<F>(&mut self, key: &[u8], predicate: F) -> &mut Self
         where F: FnMut(&[u8]) -> bool
+    {
+        self.satisfies_with(key, predicate)
+    }
+
+    /// Like `satisfies`, but takes any `Predicate`. This allows predicates to
+    /// be built with the combinators in the `predicate` module.
+    pub fn satisfies_with<P>(&mut self, key: &[u8], mut predicate: P) -> &mut Self
+        where P: Predicate
     {
         for item in &mut self.caveats {
             if item.key == key {
                 item.accepted = if let Some(val) = item.value {
-                    let res = predicate(val);
+                    let res = predicate.check(val);
                     Some(res && item.accepted.unwrap_or(true))
                 } else {
                     None
@@ -141,61 +427,2147 @@ impl <'a> Verifier<'a> {
     pub fn satisfies_exact(&mut self, key: &[u8], value: Option<&[u8]>) {
         for item in &mut self.caveats {
             if item.key == key {
-                let res = item.value.map(|x| &*x) == value;
+                // The expected value may be a secret, e.g. a session ID.
+                let res = match (item.value, value) {
+                    (Some(a), Some(b)) => ct_eq(a, b),
+                    (None, None) => true,
+                    _ => false,
+                };
                 item.accepted = Some(res && item.accepted.unwrap_or(true));
             }
         }
     }
 
-    /// Returns whether the almond satisfies the given conditions and whether
-    /// all caveats have been accepted by at least one condition.
+    /// Accepts caveats with the given key whose value is a prefix of
+    /// `value`, and rejects the rest.
     ///
-    /// Always returns false if the almond does not have match specified
-    /// `almond_type` and `generation`.
-    pub fn verify(&self) -> bool {
-        !self.reject && self.caveats.iter().all(
-            |item| item.accepted.unwrap_or(false)
-        )
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"path", Some(b"/api/v1/"));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_prefix(b"path", b"/api/v1/users");
+    /// assert!(v.verify());
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_prefix(b"path", b"/api/v2/users");
+    /// assert!(!v.verify());
+    /// ```
+    pub fn satisfies_prefix(&mut self, key: &[u8], value: &[u8]) -> &mut Self {
+        self.satisfies(key, |prefix| value.starts_with(prefix))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::Verifier;
-    use Almond;
+    /// Accepts caveats with the given key whose value is a glob pattern
+    /// matching `candidate`, and rejects the rest.
+    ///
+    /// `*` and `?` don't match `/`, so `orders/*/items` matches
+    /// `orders/17/items` but not `orders/17/refunds/items`. Caveats whose
+    /// value is not a valid pattern are rejected.
+    ///
+    /// Requires the `glob` feature.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"resource", Some(b"orders/*/items"));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_glob(b"resource", "orders/17/items");
+    /// assert!(v.verify());
+    /// ```
+    #[cfg(feature = "glob")]
+    pub fn satisfies_glob(&mut self, key: &[u8], candidate: &str) -> &mut Self {
+        let options = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
 
-    use std::str;
+        self.satisfies_typed(key, |pattern: &str| {
+            Pattern::new(pattern)
+                .map(|pattern| pattern.matches_with(candidate, options))
+                .unwrap_or(false)
+        })
+    }
 
-    #[test]
-    fn verify_test() {
-        let key = b"this_is_a_secret";
-        let mut almond = Almond::create(key, 1, b"login".to_vec());
-        almond.add_caveat(b"user", Some(b"erikj"));
-        almond.add_caveat(b"guest", None);
+    /// Accepts caveats with the given key whose value matches `regex`, and
+    /// rejects the rest. Caveats whose value is not valid UTF-8 are
+    /// rejected.
+    ///
+    /// *Note: The regex is not anchored, so use `^` and `$` to match the
+    /// whole value.*
+    ///
+    /// Requires the `regex` feature.
+    #[cfg(feature = "regex")]
+    pub fn satisfies_regex(&mut self, key: &[u8], regex: &Regex) -> &mut Self {
+        self.satisfies_typed(key, |value: &str| regex.is_match(value))
+    }
 
-        let mut v = Verifier::new(&almond, 1, b"login");
-        v.allow(b"user");
-        assert!(!v.verify());
+    /// Accepts caveats with the given key whose value is a regex matching the
+    /// whole of `candidate`, and rejects the rest. Compiled patterns are
+    /// kept in `cache`. Caveats whose value is not a valid pattern are
+    /// rejected.
+    ///
+    /// Requires the `regex` feature.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// # use almonds::regex_cache::RegexCache;
+    /// let cache = RegexCache::new(100);
+    ///
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"resource", Some(b"orders/[0-9]+"));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_regex_caveat(b"resource", "orders/17", &cache);
+    /// assert!(v.verify());
+    /// ```
+    #[cfg(feature = "regex")]
+    pub fn satisfies_regex_caveat(&mut self, key: &[u8], candidate: &str, cache: &RegexCache)
+        -> &mut Self
+    {
+        self.satisfies_typed(key, |pattern: &str| {
+            cache.is_match(pattern, candidate).unwrap_or(false)
+        })
+    }
 
-        v.satisfies_exact(b"guest", None);
-        assert!(v.verify());
+    /// Accepts `CLIENT_IP_KEY` caveats whose network contains `addr`, and
+    /// rejects the rest.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_ip_restriction("10.0.0.0/8".parse().unwrap());
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_client_ip("10.1.2.3".parse().unwrap());
+    /// assert!(v.verify());
+    /// ```
+    pub fn satisfies_client_ip(&mut self, addr: IpAddr) -> &mut Self {
+        self.satisfies_typed(CLIENT_IP_KEY, |network: IpNet| network.contains(addr))
+    }
 
-        v.satisfies_exact(b"user", Some(b"noterikj"));
-        assert!(!v.verify());
+    /// Accepts the caveats added by `http::confine_to_request` if they match
+    /// `request`, and rejects them otherwise.
+    ///
+    /// Almonds with a host caveat are rejected if the request has no host.
+    pub fn satisfies_http_request(&mut self, request: &RequestInfo) -> &mut Self {
+        self.satisfies_exact(http::METHOD_KEY, Some(request.method.as_bytes()));
+        self.satisfies_exact(http::PATH_KEY, Some(request.path.as_bytes()));
+        self.satisfies(http::HOST_KEY, |host| {
+            request.host.map_or(false, |h| h.as_bytes().eq_ignore_ascii_case(host))
+        })
     }
 
-    #[test]
-    fn verify_test_gen_type() {
-        let key = b"this_is_a_secret";
-        let mut almond = Almond::create(key, 1, b"login".to_vec());
-        almond.add_caveat(b"user", Some(b"erikj"));
+    /// Accepts `binding::CONTENT_KEY` caveats matching the digest of the
+    /// request body, and rejects the rest.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// # use almonds::binding::content_digest;
+    /// let mut almond = Almond::create(b"secret", 1, b"upload".to_vec());
+    /// almond.add_content_binding(&content_digest(b"hello"));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"upload");
+    /// v.satisfies_content(&content_digest(b"hello"));
+    /// assert!(v.verify());
+    /// ```
+    pub fn satisfies_content(&mut self, digest: &[u8; 32]) -> &mut Self {
+        self.satisfies_exact(CONTENT_KEY, Some(&binding::encode_digest(digest)));
+        self
+    }
 
-        let mut v = Verifier::new(&almond, 2, b"login");
-        v.allow(b"user");
-        assert!(!v.verify());
+    /// Accepts `binding::CHANNEL_KEY` caveats matching keying material
+    /// exported from the current TLS connection, and rejects the rest.
+    pub fn satisfies_channel(&mut self, exporter: &[u8]) -> &mut Self {
+        let digest = binding::channel_digest(exporter);
+        self.satisfies_exact(CHANNEL_KEY, Some(&binding::encode_digest(&digest)));
+        self
+    }
 
-        let mut v = Verifier::new(&almond, 1, b"notlogin");
-        v.allow(b"user");
+    /// Accepts `AUDIENCE_KEY` caveats naming the given service, and rejects
+    /// the rest.
+    ///
+    /// Almonds without an audience are accepted by every service. Use
+    /// `require_present(AUDIENCE_KEY)` to reject them.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_audience(b"billing");
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_audience(b"billing");
+    /// assert!(v.verify());
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_audience(b"search");
+    /// assert!(!v.verify());
+    /// ```
+    pub fn satisfies_audience(&mut self, audience: &[u8]) -> &mut Self {
+        self.satisfies_exact(AUDIENCE_KEY, Some(audience));
+        self
+    }
+
+    /// Accepts `SCOPE_KEY` caveats whose scope includes `required`, and
+    /// rejects the rest. This is used to check that an almond may perform a
+    /// specific operation.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_scope("repo:*");
+    ///
+    /// {
+    ///     let mut v = Verifier::new(&almond, 1, b"access");
+    ///     v.satisfies_scope("repo:read");
+    ///     assert!(v.verify());
+    /// }
+    ///
+    /// almond.add_scope("repo:write");
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_scope("repo:read");
+    /// assert!(!v.verify());
+    /// ```
+    pub fn satisfies_scope(&mut self, required: &str) -> &mut Self {
+        self.satisfies_typed(SCOPE_KEY, |granted: &str| scope::matches(granted, required))
+    }
+
+    /// Accepts `SCOPE_KEY` caveats whose scope is matched by at least one of
+    /// the `allowed` patterns, and rejects the rest. This is used to check
+    /// that an almond isn't broader than an endpoint permits.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_scope("repo:read");
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_scope_allowing(&["repo:*", "user:read"]);
+    /// assert!(v.verify());
+    /// ```
+    pub fn satisfies_scope_allowing(&mut self, allowed: &[&str]) -> &mut Self {
+        self.satisfies_typed(SCOPE_KEY, |granted: &str| {
+            allowed.iter().any(|pattern| scope::matches(pattern, granted))
+        })
+    }
+
+    /// Passes every caveat in the namespace `ns`, i.e. whose key is of the
+    /// form `<ns>.<key>`, to `handler`. See the `namespace` module.
+    ///
+    /// Caveats the handler accepts are accepted, and those it rejects are
+    /// rejected. Caveats it doesn't recognize are left to other predicates.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"device.os", Some(b"linux"));
+    /// almond.add_caveat(b"device.model", Some(b"laptop"));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.handle_namespace(b"device", |key: &[u8], value: Option<&[u8]>| {
+    ///     match key {
+    ///         b"os" => Some(value == Some(b"linux")),
+    ///         b"model" => Some(true),
+    ///         _ => None,
+    ///     }
+    /// });
+    /// assert!(v.verify());
+    /// ```
+    pub fn handle_namespace<H>(&mut self, ns: &[u8], mut handler: H) -> &mut Self
+        where H: NamespaceHandler
+    {
+        for item in &mut self.caveats {
+            let key = match namespace::strip_namespace(ns, item.key) {
+                Some(key) => key,
+                None => continue,
+            };
+
+            if let Some(res) = handler.check(key, item.value) {
+                item.accepted = Some(res && item.accepted.unwrap_or(true));
+            }
+        }
+
+        self
+    }
+
+    /// Require that the almond has at least one caveat with the given key.
+    ///
+    /// This does not accept the caveat, so it still needs to be matched by
+    /// another predicate.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let almond = Almond::create(b"secret", 1, b"access".to_vec());
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.allow(b"user");
+    /// assert!(v.verify());
+    ///
+    /// v.require_present(b"user");
+    /// assert!(!v.verify());
+    /// ```
+    pub fn require_present(&mut self, key: &[u8]) -> &mut Self {
+        self.required.push(key.to_vec());
+        self
+    }
+
+    /// Reject the almond if it has any caveat with the given key, even if the
+    /// caveat is accepted by another predicate.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier, VerifyError};
+    /// let mut almond = Almond::create(b"secret", 1, b"refresh".to_vec());
+    /// almond.add_caveat(b"admin", None);
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"refresh");
+    /// v.forbid(b"admin");
+    /// assert_eq!(
+    ///     v.verify_detailed(),
+    ///     Err(VerifyError::ForbiddenCaveats { keys: vec![b"admin".to_vec()] })
+    /// );
+    /// ```
+    pub fn forbid(&mut self, key: &[u8]) -> &mut Self {
+        self.forbidden.push(key.to_vec());
+        self
+    }
+
+    /// Reject the almond if it has more than one caveat with the given key.
+    ///
+    /// By default an almond may have several caveats with the same key, in
+    /// which case each of them must be accepted. For caveats where a second
+    /// copy would be surprising, e.g. `expires`, this rejects the almond
+    /// outright instead.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"expires", Some(b"1500000000"));
+    /// almond.add_caveat(b"expires", Some(b"1600000000"));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.allow(b"expires");
+    /// assert!(v.verify());
+    ///
+    /// v.require_unique(b"expires");
+    /// assert!(!v.verify());
+    /// ```
+    pub fn require_unique(&mut self, key: &[u8]) -> &mut Self {
+        self.unique.push(key.to_vec());
+        self
+    }
+
+    /// Reject the almond if it has more than one caveat with the same key,
+    /// for any key. See `require_unique`.
+    pub fn require_all_unique(&mut self) -> &mut Self {
+        self.all_unique = true;
+        self
+    }
+
+    /// Reject the almond unless it has been sealed with `Almond::seal`.
+    ///
+    /// The seal caveat is always accepted when it is the last caveat, so
+    /// this only needs to be called if the almond must be sealed.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    ///
+    /// let mut sealed = Almond::create(b"secret", 1, b"access".to_vec());
+    /// sealed.add_caveat(b"user", Some(b"erikj"));
+    /// sealed.seal();
+    ///
+    /// for &(almond, accepted) in &[(&almond, false), (&sealed, true)] {
+    ///     let mut v = Verifier::new(almond, 1, b"access");
+    ///     v.allow(b"user");
+    ///     v.require_sealed();
+    ///     assert_eq!(v.verify(), accepted);
+    /// }
+    /// ```
+    pub fn require_sealed(&mut self) -> &mut Self {
+        self.require_sealed = true;
+        self
+    }
+
+    /// Returns the distinct keys that appear in more than one caveat.
+    fn duplicate_keys(&self) -> Vec<Vec<u8>> {
+        let mut duplicates: Vec<Vec<u8>> = Vec::new();
+
+        for (idx, item) in self.caveats.iter().enumerate() {
+            let is_checked = self.all_unique
+                || self.unique.iter().any(|key| &key[..] == item.key);
+
+            if is_checked
+                && self.caveats[..idx].iter().any(|other| other.key == item.key)
+                && !duplicates.iter().any(|key| &key[..] == item.key)
+            {
+                duplicates.push(item.key.to_vec());
+            }
+        }
+
+        duplicates
+    }
+
+    /// Adds a fallback predicate that is consulted for every caveat that no
+    /// other predicate matched, and is called with the caveat's key and
+    /// value.
+    ///
+    /// This is useful for families of caveats that can't be enumerated up
+    /// front. Fallbacks are only evaluated by `verify`, so it does not matter
+    /// whether they are added before or after the other predicates. If there
+    /// are several fallbacks then a caveat is accepted if any of them return
+    /// `true`.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// almond.add_caveat(b"limit.uploads", Some(b"10"));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.allow(b"user");
+    /// v.satisfies_general(|key, value| {
+    ///     key.starts_with(b"limit.") && value.is_some()
+    /// });
+    /// assert!(v.verify());
+    /// ```
+    pub fn satisfies_general<F>(&mut self, predicate: F) -> &mut Self
+        where F: Fn(&[u8], Option<&[u8]>) -> bool + 'a
+    {
+        self.general.push(Box::new(predicate));
+        self
+    }
+
+    /// Returns whether a caveat that matched no predicate is accepted by a
+    /// fallback predicate.
+    fn accepted_by_general(&self, item: &DeconstructedCaveatEntry) -> bool {
+        self.general.iter().any(|predicate| predicate(item.key, item.value))
+    }
+
+    /// Registers a `CaveatVerifier`, see the `registry` module.
+    ///
+    /// Registered verifiers are consulted for every caveat by `verify`, so
+    /// it does not matter whether they are registered before or after the
+    /// other predicates. A caveat rejected by any registered verifier is
+    /// rejected, even if it was accepted elsewhere.
+    pub fn register(&mut self, verifier: Box<CaveatVerifier + 'a>) -> &mut Self {
+        self.registered.push(verifier);
+        self
+    }
+
+    /// Sets a value in the `VerifyContext` passed to registered verifiers.
+    pub fn set_context_value(&mut self, name: &str, value: &str) -> &mut Self {
+        self.context_values.insert(name.to_owned(), value.to_owned());
+        self
+    }
+
+    /// Returns whether the caveat is accepted, rejected or unmatched, taking
+    /// into account registered verifiers and fallback predicates.
+    fn caveat_status(&self, item: &DeconstructedCaveatEntry, ctx: &VerifyContext)
+        -> Option<bool>
+    {
+        let mut accepted = item.accepted;
+        for check in &self.time_checks {
+            match self.time_status(*check, item, ctx.now()) {
+                Some(false) => return Some(false),
+                Some(true) => accepted = accepted.or(Some(true)),
+                None => {}
+            }
+        }
+        for verifier in &self.registered {
+            match verifier.check(item.key, item.value, ctx) {
+                Some(false) => return Some(false),
+                Some(true) => accepted = accepted.or(Some(true)),
+                None => {}
+            }
+        }
+
+        if accepted.is_none() && self.accepted_by_general(item) {
+            accepted = Some(true);
+        }
+
+        accepted
+    }
+
+    /// Like `satisfies`, but the value is decoded with `CaveatValue` before
+    /// being passed to the predicate. Caveats whose value can't be decoded are
+    /// rejected.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat_typed(b"uploads", 10u64);
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_typed(b"uploads", |limit: u64| limit <= 100);
+    /// assert!(v.verify());
+    /// ```
+    pub fn satisfies_typed<T, F>(&mut self, key: &[u8], mut predicate: F) -> &mut Self
+        where T: CaveatValue<'a>, F: FnMut(T) -> bool
+    {
+        for item in &mut self.caveats {
+            if item.key == key {
+                item.accepted = if let Some(val) = item.value {
+                    let res = T::decode_value(val).map(&mut predicate)
+                        .unwrap_or(false);
+                    Some(res && item.accepted.unwrap_or(true))
+                } else {
+                    None
+                };
+            }
+        }
+
+        self
+    }
+
+    /// Accepts `EXPIRES_KEY` caveats whose time is after the current time,
+    /// as given by the verifier's clock, and rejects the rest.
+    ///
+    /// ```
+    /// # use std::time::{Duration, SystemTime};
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_expiry(SystemTime::now() + Duration::from_secs(3600));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_expiry();
+    /// assert!(v.verify());
+    /// ```
+    pub fn satisfies_expiry(&mut self) -> &mut Self {
+        self.add_time_check(TimeCheck::Expiry)
+    }
+
+    /// Accepts `NOT_BEFORE_KEY` caveats whose time is at or before the
+    /// current time, as given by the verifier's clock, and rejects the rest.
+    ///
+    /// ```
+    /// # use std::time::{Duration, SystemTime};
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_not_before(SystemTime::now() + Duration::from_secs(3600));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_not_before();
+    /// assert!(!v.verify());
+    /// ```
+    pub fn satisfies_not_before(&mut self) -> &mut Self {
+        self.add_time_check(TimeCheck::NotBefore)
+    }
+
+    /// Accepts `EPOCH_KEY` caveats whose epoch is at least `min_epoch`, and
+    /// rejects the rest.
+    ///
+    /// Almonds without an epoch are not affected, use
+    /// `require_present(EPOCH_KEY)` to reject them.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_epoch(3);
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_min_epoch(4);
+    /// assert!(!v.verify());
+    /// ```
+    pub fn satisfies_min_epoch(&mut self, min_epoch: u64) -> &mut Self {
+        self.satisfies_typed(EPOCH_KEY, |epoch: u64| epoch >= min_epoch)
+    }
+
+    /// Accepts `MAX_CAVEATS_KEY` caveats if the almond has at most that many
+    /// caveats, and rejects the rest.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_max_caveats(2);
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// almond.add_caveat(b"device", Some(b"phone"));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.allow(b"user");
+    /// v.allow(b"device");
+    /// v.satisfies_max_caveats();
+    /// assert!(!v.verify());
+    /// ```
+    pub fn satisfies_max_caveats(&mut self) -> &mut Self {
+        let count = self.caveats.len() as u64;
+        self.satisfies_typed(MAX_CAVEATS_KEY, |max: u64| count <= max)
+    }
+
+    /// Accepts caveats with the given key whose value, added with
+    /// `Almond::add_encrypted_caveat`, decrypts with `secret` to a value
+    /// accepted by the predicate. Values that don't decrypt are rejected.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_encrypted_caveat(b"secret", b"user_id", b"1234");
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_encrypted(b"secret", b"user_id", |user_id| user_id == b"1234");
+    /// assert!(v.verify());
+    /// ```
+    pub fn satisfies_encrypted<F>(&mut self, secret: &[u8], key: &[u8], mut predicate: F)
+        -> &mut Self
+        where F: FnMut(&[u8]) -> bool
+    {
+        let enc_key = crypt::caveat_key(secret, self.generation, self.almond_type);
+        let caveat_key = key.to_vec();
+        self.satisfies(key, move |value| {
+            crypt::decrypt_caveat(&enc_key, &caveat_key, value)
+                .map(|plaintext| predicate(&plaintext))
+                .unwrap_or(false)
+        })
+    }
+
+    /// Accepts well formed `ATTENUATION_KEY` caveats whose time is at or
+    /// before the current time, as given by the verifier's clock, and
+    /// rejects the rest.
+    ///
+    /// Use `attenuation_chain` to get the records.
+    pub fn satisfies_attenuation_records(&mut self) -> &mut Self {
+        self.add_time_check(TimeCheck::AttenuationRecords)
+    }
+
+    fn add_time_check(&mut self, check: TimeCheck) -> &mut Self {
+        if !self.time_checks.contains(&check) {
+            self.time_checks.push(check);
+        }
+        self
+    }
+
+    /// Check a caveat against a time based predicate, returning `None` if
+    /// the predicate doesn't apply to it.
+    fn time_status(&self, check: TimeCheck, item: &DeconstructedCaveatEntry, now: SystemTime)
+        -> Option<bool>
+    {
+        let value = match item.value {
+            Some(value) => value,
+            None => return None,
+        };
+
+        // Times too far in the future to be relaxed by the skew never expire,
+        // and a clock too far in the future is after every time.
+        let skew = self.clock_skew;
+        let skewed_now = now.checked_add(skew);
+
+        match check {
+            TimeCheck::Expiry if item.key == EXPIRES_KEY => Some(
+                SystemTime::decode_value(value).map_or(false, |expires| {
+                    expires.checked_add(skew).map_or(true, |expires| now < expires)
+                })
+            ),
+            TimeCheck::NotBefore if item.key == NOT_BEFORE_KEY => Some(
+                SystemTime::decode_value(value).map_or(false, |not_before| {
+                    skewed_now.map_or(true, |now| not_before <= now)
+                })
+            ),
+            TimeCheck::AttenuationRecords if item.key == ATTENUATION_KEY => Some(
+                AttenuationRecord::decode_value(value).map_or(false, |record| {
+                    skewed_now.map_or(true, |now| record.timestamp <= now)
+                })
+            ),
+            _ => None,
+        }
+    }
+
+    /// Returns the well formed attenuation records of the almond, in the
+    /// order they were added, i.e. the chain of who attenuated it.
+    ///
+    /// *Note: Only trust this once the almond has been verified.*
+    pub fn attenuation_chain(&self) -> Vec<AttenuationRecord> {
+        self.caveats.iter()
+            .filter(|item| item.key == ATTENUATION_KEY)
+            .filter_map(|item| item.value.and_then(AttenuationRecord::decode_value))
+            .collect()
+    }
+
+    /// Like `satisfies`, but with a string key and a predicate over string
+    /// values. Caveats whose value is not valid UTF-8 are rejected.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat_str("user", Some("erikj"));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_str("user", |user| user.starts_with("erik"));
+    /// assert!(v.verify());
+    /// ```
+    pub fn satisfies_str<F>(&mut self, key: &str, mut predicate: F) -> &mut Self
+        where F: FnMut(&str) -> bool
+    {
+        self.satisfies(key.as_bytes(), |val| {
+            str::from_utf8(val).map(|val| predicate(val)).unwrap_or(false)
+        })
+    }
+
+    /// Like `satisfies_exact`, but with a string key and value.
+    pub fn satisfies_str_exact(&mut self, key: &str, value: Option<&str>)
+        -> &mut Self
+    {
+        self.satisfies_exact(key.as_bytes(), value.map(|v| v.as_bytes()));
+        self
+    }
+
+    /// Returns the key and value of every caveat that has not been matched by
+    /// any predicate so far, including fallbacks added with
+    /// `satisfies_general`.
+    ///
+    /// Any such caveats cause verification to fail, so this is useful for
+    /// logging which unrecognized caveat an almond was rejected for.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// almond.add_caveat(b"device", Some(b"phone"));
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.allow(b"user");
+    /// assert_eq!(
+    ///     v.unmatched_caveats(),
+    ///     vec![(&b"device"[..], Some(&b"phone"[..]))]
+    /// );
+    /// ```
+    pub fn unmatched_caveats(&self) -> Vec<(&'a [u8], Option<&'a [u8]>)> {
+        let ctx = VerifyContext::new(self.clock.now(), &self.context_values);
+
+        self.caveats.iter()
+            .filter(|item| self.caveat_status(item, &ctx).is_none())
+            .map(|item| (item.key, item.value))
+            .collect()
+    }
+
+    /// Returns whether the almond satisfies the given conditions and whether
+    /// all caveats have been accepted by at least one condition.
+    ///
+    /// Always returns false if the almond does not have match specified
+    /// `almond_type` and `generation`.
+    pub fn verify(&self) -> bool {
+        self.verify_detailed().is_ok()
+    }
+
+    /// Like `verify`, but on failure returns the reason the almond was
+    /// rejected.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier, VerifyError};
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// almond.add_caveat(b"user", Some(b"erikj"));
+    /// almond.add_caveat(b"admin", None);
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.allow(b"user");
+    /// assert_eq!(
+    ///     v.verify_detailed(),
+    ///     Err(VerifyError::Caveats {
+    ///         unmatched: vec![b"admin".to_vec()],
+    ///         rejected: vec![],
+    ///     })
+    /// );
+    /// ```
+    pub fn verify_detailed(&self) -> Result<(), VerifyError> {
+        let _span = trace::verify_span(self.almond_type, self.generation, self.caveats.len());
+
+        let res = self.check();
+        trace::verified(&res);
+
+        if let Err(ref err) = res {
+            match self.observer {
+                Some(ref observer) => observer.on_verify_rejected(err),
+                None => if let Some(observer) = metrics::global_observer() {
+                    observer.on_verify_rejected(err)
+                },
+            }
+        }
+
+        res
+    }
+
+    fn check(&self) -> Result<(), VerifyError> {
+        if !self.expected_generations.contains(&self.generation) {
+            return Err(VerifyError::WrongGeneration {
+                expected: self.expected_generations.clone(),
+                actual: self.generation,
+            });
+        }
+
+        if !self.expected_types.iter().any(|t| &t[..] == self.almond_type) {
+            return Err(VerifyError::WrongType {
+                expected: self.expected_types.clone(),
+                actual: self.almond_type.to_vec(),
+            });
+        }
+
+        let missing: Vec<Vec<u8>> = self.required.iter()
+            .filter(|key| !self.caveats.iter().any(|item| item.key == &key[..]))
+            .cloned()
+            .collect();
+
+        if !missing.is_empty() {
+            return Err(VerifyError::MissingCaveats { keys: missing });
+        }
+
+        let forbidden: Vec<Vec<u8>> = self.forbidden.iter()
+            .filter(|key| self.caveats.iter().any(|item| item.key == &key[..]))
+            .cloned()
+            .collect();
+
+        if !forbidden.is_empty() {
+            return Err(VerifyError::ForbiddenCaveats { keys: forbidden });
+        }
+
+        let duplicates = self.duplicate_keys();
+        if !duplicates.is_empty() {
+            return Err(VerifyError::DuplicateCaveats { keys: duplicates });
+        }
+
+        if self.require_sealed {
+            let sealed = self.caveats.last().map_or(false, |item| {
+                item.key == SEALED_KEY && item.value.is_none()
+            });
+            if !sealed {
+                return Err(VerifyError::NotSealed);
+            }
+        }
+
+        let ctx = VerifyContext::new(self.clock.now(), &self.context_values);
+
+        let mut unmatched = Vec::new();
+        let mut rejected = Vec::new();
+
+        for item in &self.caveats {
+            match self.caveat_status(item, &ctx) {
+                Some(true) => {}
+                Some(false) => rejected.push(item.key.to_vec()),
+                None => unmatched.push(item.key.to_vec()),
+            }
+        }
+
+        if !unmatched.is_empty() || !rejected.is_empty() {
+            return Err(VerifyError::Caveats {
+                unmatched: unmatched,
+                rejected: rejected,
+            });
+        }
+
+        if let Some(ref checker) = self.revocation {
+            for item in &self.caveats {
+                if item.key != TOKEN_ID_KEY {
+                    continue;
+                }
+
+                let token_id = item.value.unwrap_or(b"");
+                if checker.is_revoked(token_id) {
+                    return Err(VerifyError::Revoked { token_id: token_id.to_vec() });
+                }
+            }
+        }
+
+        if let Some(ref store) = self.use_store {
+            if self.used.get() {
+                return Ok(());
+            }
+
+            // We checked there is exactly one when checking the required and
+            // unique caveats.
+            let token_id = self.caveats.iter()
+                .find(|item| item.key == SINGLE_USE_KEY)
+                .and_then(|item| item.value)
+                .unwrap_or(b"");
+            if !store.consume(token_id) {
+                return Err(VerifyError::AlreadyUsed { token_id: token_id.to_vec() });
+            }
+            self.used.set(true);
+        }
+
+        Ok(())
+    }
+
+    /// Verify the almond, and if it is accepted extract its caveats as
+    /// claims.
+    ///
+    /// This ensures the values used downstream are the ones the verifier
+    /// checked.
+    ///
+    /// ```
+    /// # use almonds::{Almond, Verifier};
+    /// use almonds::claims::{AlmondCaveats, CaveatList, ClaimsError};
+    ///
+    /// struct User(String);
+    ///
+    /// impl AlmondCaveats for User {
+    ///     fn add_to(&self, almond: &mut Almond) {
+    ///         almond.add_caveat_typed(b"user", &self.0[..]);
+    ///     }
+    ///
+    ///     fn extract(caveats: &CaveatList) -> Result<User, ClaimsError> {
+    ///         match caveats.claim("user") {
+    ///             Ok(Some(user)) => Ok(User(user)),
+    ///             Ok(None) => Err(ClaimsError::Missing { key: "user".to_owned() }),
+    ///             Err(err) => Err(err),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let mut almond = Almond::create(b"secret", 1, b"access".to_vec());
+    /// User("erikj".to_owned()).add_to(&mut almond);
+    ///
+    /// let mut v = Verifier::new(&almond, 1, b"access");
+    /// v.satisfies_exact(b"user", Some(b"erikj"));
+    /// let user: User = v.verify_into().unwrap();
+    /// assert_eq!(user.0, "erikj");
+    /// ```
+    pub fn verify_into<T: AlmondCaveats>(&self) -> Result<T, VerifyError> {
+        try!(self.verify_detailed());
+
+        let mut caveats = CaveatList::new();
+        for item in &self.caveats {
+            caveats.push(item.key, item.value);
+        }
+
+        Ok(try!(T::extract(&caveats)))
+    }
+
+    /// Like `verify_detailed`, but returns a record of the verification
+    /// suitable for audit logs. See the `audit` module.
+    pub fn verify_with_audit(&self) -> AuditRecord {
+        AuditRecord::new(
+            self.almond_type, self.generation, self.caveats.len(), self.verify_detailed()
+        )
+    }
+}
+
+
+quick_error! {
+    /// The reason an almond failed verification.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum VerifyError {
+        /// The almond has a different generation than expected.
+        WrongGeneration { expected: Vec<u32>, actual: u32 } {
+            display("almond has generation {}, expected one of {:?}", actual, expected)
+        }
+
+        /// The almond has a different type than expected.
+        WrongType { expected: Vec<Vec<u8>>, actual: Vec<u8> } {
+            display(
+                "almond has type {:?}, expected one of {:?}",
+                String::from_utf8_lossy(actual),
+                expected.iter().map(|t| String::from_utf8_lossy(t)).collect::<Vec<_>>()
+            )
+        }
+
+        /// The almond lacks caveats required by `require_present`.
+        MissingCaveats { keys: Vec<Vec<u8>> } {
+            display(
+                "almond is missing required caveats {:?}",
+                keys.iter().map(|k| String::from_utf8_lossy(k)).collect::<Vec<_>>()
+            )
+        }
+
+        /// The almond has caveats forbidden by `forbid`.
+        ForbiddenCaveats { keys: Vec<Vec<u8>> } {
+            display(
+                "almond has forbidden caveats {:?}",
+                keys.iter().map(|k| String::from_utf8_lossy(k)).collect::<Vec<_>>()
+            )
+        }
+
+        /// The almond has several caveats with a key that must be unique, see
+        /// `require_unique`.
+        DuplicateCaveats { keys: Vec<Vec<u8>> } {
+            display(
+                "almond has duplicate caveats {:?}",
+                keys.iter().map(|k| String::from_utf8_lossy(k)).collect::<Vec<_>>()
+            )
+        }
+
+        /// The almond wasn't sealed, see `require_sealed`.
+        NotSealed {
+            display("almond is not sealed")
+        }
+
+        /// The almond's token id has been revoked.
+        Revoked { token_id: Vec<u8> } {
+            display("almond has been revoked (id {:?})", String::from_utf8_lossy(token_id))
+        }
+
+        /// The almond is single use and has already been used.
+        AlreadyUsed { token_id: Vec<u8> } {
+            display("almond has already been used (id {:?})", String::from_utf8_lossy(token_id))
+        }
+
+        /// Some caveats were not accepted. `unmatched` lists the keys of
+        /// caveats that no predicate matched, and `rejected` the keys of
+        /// caveats that a predicate rejected.
+        Caveats { unmatched: Vec<Vec<u8>>, rejected: Vec<Vec<u8>> } {
+            display(
+                "caveats not accepted (unmatched: {:?}, rejected: {:?})",
+                unmatched.iter().map(|k| String::from_utf8_lossy(k)).collect::<Vec<_>>(),
+                rejected.iter().map(|k| String::from_utf8_lossy(k)).collect::<Vec<_>>()
+            )
+        }
+
+        /// The almond was accepted, but its claims couldn't be extracted, see
+        /// `verify_into`.
+        Claims(err: ClaimsError) {
+            from()
+            display("invalid claims: {}", err)
+            cause(err)
+        }
+    }
+}
+
+impl VerifyError {
+    /// A short name for the kind of error, suitable for use as a metrics
+    /// label.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            VerifyError::WrongGeneration { .. } => "wrong_generation",
+            VerifyError::WrongType { .. } => "wrong_type",
+            VerifyError::MissingCaveats { .. } => "missing_caveats",
+            VerifyError::ForbiddenCaveats { .. } => "forbidden_caveats",
+            VerifyError::DuplicateCaveats { .. } => "duplicate_caveats",
+            VerifyError::NotSealed => "not_sealed",
+            VerifyError::Revoked { .. } => "revoked",
+            VerifyError::AlreadyUsed { .. } => "already_used",
+            VerifyError::Caveats { .. } => "caveats",
+            VerifyError::Claims(_) => "claims",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Verifier, VerifyError};
+    use {Almond, AlmondRef, AUDIENCE_KEY, EPOCH_KEY, EXPIRES_KEY, NOT_BEFORE_KEY, SCOPE_KEY,
+         SINGLE_USE_KEY};
+    use clock::MockClock;
+    use predicate::{any_of, not};
+
+    use std::str;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn verify_test() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"guest", None);
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.allow(b"user");
+        assert!(!v.verify());
+
+        v.satisfies_exact(b"guest", None);
+        assert!(v.verify());
+
+        v.satisfies_exact(b"user", Some(b"noterikj"));
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn verify_test_gen_type() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let mut v = Verifier::new(&almond, 2, b"login");
+        v.allow(b"user");
+        assert!(!v.verify());
+
+        let mut v = Verifier::new(&almond, 1, b"notlogin");
+        v.allow(b"user");
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn accept_generations() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 4, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let mut v = Verifier::new(&almond, 3, b"login");
+        v.allow(b"user");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::WrongGeneration { expected: vec![3], actual: 4 })
+        );
+
+        v.accept_generation(4);
+        assert_eq!(v.generation(), 4);
+        assert!(v.verify());
+
+        let mut v = Verifier::new_with_generations(&almond, vec![3, 4], b"login");
+        v.allow(b"user");
+        assert!(v.verify());
+
+        let mut v = Verifier::new_with_generations(&almond, 5..7, b"login");
+        v.allow(b"user");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::WrongGeneration { expected: vec![5, 6], actual: 4 })
+        );
+    }
+
+    #[test]
+    fn accept_types() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"sso".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.allow(b"user");
+        assert!(!v.verify());
+
+        v.accept_type(b"sso");
+        assert_eq!(v.almond_type(), b"sso");
+        assert!(v.verify());
+
+        let mut v = Verifier::new_with_types(&almond, 1, &[b"login", b"access"]);
+        v.allow(b"user");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::WrongType {
+                expected: vec![b"login".to_vec(), b"access".to_vec()],
+                actual: b"sso".to_vec(),
+            })
+        );
+    }
+
+    #[test]
+    fn satisfies_expiry() {
+        let key = b"this_is_a_secret";
+        let expires = UNIX_EPOCH + Duration::from_secs(1500000000);
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_expiry(expires);
+        almond.add_expiry(expires + Duration::from_secs(60));
+        assert_eq!(almond.caveat_value(EXPIRES_KEY), Some(&b"1500000000"[..]));
+        assert_eq!(almond.expiry(), Some(expires));
+
+        let clock = MockClock::new(expires - Duration::from_secs(1));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.set_clock(&clock);
+        v.satisfies_expiry();
+        assert!(v.verify());
+
+        clock.set(expires);
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.set_clock(&clock);
+        v.satisfies_expiry();
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats {
+                unmatched: vec![],
+                rejected: vec![b"expires".to_vec()],
+            })
+        );
+
+        let mut malformed = Almond::create(key, 1, b"login".to_vec());
+        malformed.add_caveat(EXPIRES_KEY, Some(b"soon"));
+
+        let mut v = Verifier::new(&malformed, 1, b"login");
+        v.satisfies_expiry();
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn satisfies_min_epoch() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_epoch(7);
+        assert_eq!(almond.caveat_value(EPOCH_KEY), Some(&b"7"[..]));
+        assert_eq!(almond.epoch(), Some(7));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_min_epoch(7);
+        assert!(v.verify());
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_min_epoch(8);
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats {
+                unmatched: vec![],
+                rejected: vec![b"epoch".to_vec()],
+            })
+        );
+
+        let unversioned = Almond::create(key, 1, b"login".to_vec());
+
+        let mut v = Verifier::new(&unversioned, 1, b"login");
+        v.satisfies_min_epoch(8);
+        assert!(v.verify());
+
+        let mut v = Verifier::new(&unversioned, 1, b"login");
+        v.satisfies_min_epoch(8);
+        v.require_present(EPOCH_KEY);
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn satisfies_not_before() {
+        let key = b"this_is_a_secret";
+        let not_before = UNIX_EPOCH + Duration::from_secs(1500000000);
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_not_before(not_before - Duration::from_secs(60));
+        almond.add_not_before(not_before);
+        assert_eq!(almond.caveat_value(NOT_BEFORE_KEY), Some(&b"1499999940"[..]));
+        assert_eq!(almond.not_before(), Some(not_before));
+
+        let clock = MockClock::new(not_before - Duration::from_secs(1));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.set_clock(&clock);
+        v.satisfies_not_before();
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats {
+                unmatched: vec![],
+                rejected: vec![b"not_before".to_vec()],
+            })
+        );
+
+        clock.set(not_before);
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.set_clock(&clock);
+        v.satisfies_not_before();
+        assert!(v.verify());
+    }
+
+    #[test]
+    fn clock_skew() {
+        let key = b"this_is_a_secret";
+        let time = UNIX_EPOCH + Duration::from_secs(1500000000);
+        let skew = Duration::from_secs(30);
+
+        let mut expired = Almond::create(key, 1, b"login".to_vec());
+        expired.add_expiry(time - Duration::from_secs(10));
+
+        let mut early = Almond::create(key, 1, b"login".to_vec());
+        early.add_not_before(time + Duration::from_secs(10));
+
+        let clock = MockClock::new(time);
+
+        for almond in &[&expired, &early] {
+            let mut v = Verifier::new(almond, 1, b"login");
+            v.set_clock(&clock);
+            v.satisfies_expiry();
+            v.satisfies_not_before();
+            assert!(!v.verify());
+
+            let mut v = Verifier::new(almond, 1, b"login");
+            v.set_clock(&clock);
+            v.set_clock_skew(skew);
+            v.satisfies_expiry();
+            v.satisfies_not_before();
+            assert!(v.verify());
+        }
+
+        clock.advance(skew);
+
+        let mut v = Verifier::new(&expired, 1, b"login");
+        v.set_clock(&clock);
+        v.set_clock_skew(skew);
+        v.satisfies_expiry();
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn clock_read_when_verifying() {
+        let key = b"this_is_a_secret";
+        let expires = UNIX_EPOCH + Duration::from_secs(1500000000);
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_expiry(expires);
+        almond.add_not_before(expires - Duration::from_secs(60));
+
+        // The clock and skew can be set after adding the predicates.
+        let clock = MockClock::new(expires);
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_expiry();
+        v.satisfies_not_before();
+        v.set_clock(&clock);
+        assert!(!v.verify());
+
+        v.set_clock_skew(Duration::from_secs(30));
+        assert!(v.verify());
+
+        // The clock is read every time the almond is verified.
+        clock.advance(Duration::from_secs(30));
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn clock_skew_overflow() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(EXPIRES_KEY, Some(b"9223372036854775807"));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.set_clock_skew(Duration::from_secs(30));
+        v.satisfies_expiry();
+        assert!(v.verify());
+    }
+
+    #[test]
+    fn satisfies_prefix() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"path", Some(b"/api/"));
+        almond.add_caveat(b"path", Some(b"/api/v1/"));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_prefix(b"path", b"/api/v1/rooms");
+        assert!(v.verify());
+
+        // The caveat is the prefix, not the value being checked.
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_prefix(b"path", b"/api");
+        assert!(!v.verify());
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_prefix(b"path", b"/api/v2/rooms");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats {
+                unmatched: vec![],
+                rejected: vec![b"path".to_vec()],
+            })
+        );
+    }
+
+    #[cfg(feature = "glob")]
+    #[test]
+    fn satisfies_glob() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"resource", Some(b"orders/*/items"));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_glob(b"resource", "orders/17/items");
+        assert!(v.verify());
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_glob(b"resource", "orders/17/refunds/items");
+        assert!(!v.verify());
+
+        let mut invalid = Almond::create(key, 1, b"login".to_vec());
+        invalid.add_caveat(b"resource", Some(b"orders/[/items"));
+
+        let mut v = Verifier::new(&invalid, 1, b"login");
+        v.satisfies_glob(b"resource", "orders/[/items");
+        assert!(!v.verify());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn satisfies_regex() {
+        use regex::Regex;
+        use regex_cache::RegexCache;
+
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"@erikj:example.com"));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_regex(b"user", &Regex::new(r"^@[a-z]+:example\.com$").unwrap());
+        assert!(v.verify());
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_regex(b"user", &Regex::new(r"^@[a-z]+:matrix\.org$").unwrap());
+        assert!(!v.verify());
+
+        let cache = RegexCache::new(10);
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"resource", Some(b"orders/[0-9]+"));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_regex_caveat(b"resource", "orders/17", &cache);
+        assert!(v.verify());
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_regex_caveat(b"resource", "orders/17/items", &cache);
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn satisfies_client_ip() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_ip_restriction("10.0.0.0/8".parse().unwrap());
+        almond.add_ip_restriction("10.1.0.0/16".parse().unwrap());
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_client_ip("10.1.2.3".parse().unwrap());
+        assert!(v.verify());
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_client_ip("10.2.0.1".parse().unwrap());
+        assert!(!v.verify());
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_client_ip("::1".parse().unwrap());
+        assert!(!v.verify());
+
+        let mut malformed = Almond::create(key, 1, b"login".to_vec());
+        malformed.add_caveat(b"client_ip", Some(b"office"));
+
+        let mut v = Verifier::new(&malformed, 1, b"login");
+        v.satisfies_client_ip("10.1.2.3".parse().unwrap());
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn satisfies_http_request() {
+        use http::{confine_to_request, RequestInfo, HOST_KEY};
+
+        let key = b"this_is_a_secret";
+        let request = RequestInfo::new("GET", "/media/abc", Some("Example.com"));
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        confine_to_request(&mut almond, &request);
+        assert_eq!(almond.caveat_value(HOST_KEY), Some(&b"example.com"[..]));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_http_request(&RequestInfo::new("GET", "/media/abc", Some("EXAMPLE.com")));
+        assert!(v.verify());
+
+        for other in &[
+            RequestInfo::new("POST", "/media/abc", Some("example.com")),
+            RequestInfo::new("GET", "/media/abcd", Some("example.com")),
+            RequestInfo::new("GET", "/media/abc", Some("example.org")),
+            RequestInfo::new("GET", "/media/abc", None),
+        ] {
+            let mut v = Verifier::new(&almond, 1, b"login");
+            v.satisfies_http_request(other);
+            assert!(!v.verify(), "{:?}", other);
+        }
+
+        let mut no_host = Almond::create(key, 1, b"login".to_vec());
+        confine_to_request(&mut no_host, &RequestInfo::new("GET", "/media/abc", None));
+
+        let mut v = Verifier::new(&no_host, 1, b"login");
+        v.satisfies_http_request(&request);
+        assert!(v.verify());
+    }
+
+    #[test]
+    fn satisfies_content() {
+        use binding::content_digest;
+
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"upload".to_vec());
+        almond.add_content_binding(&content_digest(b"some content"));
+
+        let mut v = Verifier::new(&almond, 1, b"upload");
+        v.satisfies_content(&content_digest(b"some content"));
+        assert!(v.verify());
+
+        let mut v = Verifier::new(&almond, 1, b"upload");
+        v.satisfies_content(&content_digest(b"other content"));
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats {
+                unmatched: vec![],
+                rejected: vec![b"content_sha256".to_vec()],
+            })
+        );
+    }
+
+    #[test]
+    fn satisfies_channel() {
+        let key = b"this_is_a_secret";
+        let exporter = [7u8; 32];
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_channel_binding(&exporter);
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_channel(&exporter);
+        assert!(v.verify());
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_channel(&[8u8; 32]);
+        assert!(!v.verify());
+
+        // Without a check the binding is unmatched.
+        let v = Verifier::new(&almond, 1, b"login");
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn revocation() {
+        use std::collections::HashSet;
+        use TOKEN_ID_KEY;
+
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        let token_id = almond.add_token_id();
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        assert_eq!(token_id.len(), 32);
+        assert_eq!(almond.token_id(), Some(&token_id[..]));
+
+        let mut other = Almond::create(key, 1, b"login".to_vec());
+        assert!(other.add_token_id() != token_id);
+
+        let mut revoked = HashSet::new();
+        revoked.insert(b"some other id".to_vec());
+
+        {
+            let mut v = Verifier::new(&almond, 1, b"login");
+            v.allow(b"user");
+            assert!(!v.verify());
+
+            v.set_revocation_checker(&revoked);
+            assert!(v.verify());
+        }
+
+        revoked.insert(token_id.clone());
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.allow(b"user");
+        v.set_revocation_checker(&revoked);
+        assert_eq!(v.verify_detailed(), Err(VerifyError::Revoked { token_id: token_id }));
+
+        // Revocation is only checked once the caveats have been accepted.
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.set_revocation_checker(&revoked);
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats {
+                unmatched: vec![b"user".to_vec()],
+                rejected: vec![],
+            })
+        );
+
+        let no_id = Almond::create(key, 1, b"login".to_vec());
+        let mut v = Verifier::new(&no_id, 1, b"login");
+        v.set_revocation_checker(&revoked);
+        assert!(v.verify());
+
+        v.require_present(TOKEN_ID_KEY);
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn single_use() {
+        use std::collections::HashSet;
+        use std::sync::Mutex;
+
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"invite".to_vec());
+        let token_id = almond.add_single_use();
+        almond.add_caveat(b"room", Some(b"!abc"));
+
+        let store = Mutex::new(HashSet::new());
+
+        // Without a store the almond is rejected.
+        let mut v = Verifier::new(&almond, 1, b"invite");
+        v.allow(b"room");
+        assert!(!v.verify());
+
+        // Failing verification doesn't use up the almond.
+        let mut v = Verifier::new(&almond, 1, b"invite");
+        v.set_use_store(&store);
+        v.satisfies_exact(b"room", Some(b"!def"));
+        assert!(!v.verify());
+
+        // The same verifier keeps accepting the almond, e.g. to audit it.
+        let mut v = Verifier::new(&almond, 1, b"invite");
+        v.set_use_store(&store);
+        v.allow(b"room");
+        assert!(v.verify());
+        assert!(v.verify_with_audit().accepted());
+        assert!(v.verify());
+
+        let mut v = Verifier::new(&almond, 1, b"invite");
+        v.set_use_store(&store);
+        v.allow(b"room");
+        assert_eq!(v.verify_detailed(), Err(VerifyError::AlreadyUsed { token_id: token_id }));
+    }
+
+    #[test]
+    fn single_use_appended_ids() {
+        use std::collections::HashSet;
+        use std::sync::Mutex;
+
+        let key = b"this_is_a_secret";
+
+        let mut victim = Almond::create(key, 1, b"invite".to_vec());
+        let victim_id = victim.add_single_use();
+
+        let store = Mutex::new(HashSet::new());
+
+        // Appending the id of another almond to a single use almond doesn't
+        // burn it, and neither id is consumed.
+        let mut almond = Almond::create(key, 1, b"invite".to_vec());
+        let token_id = almond.add_single_use();
+        almond.add_caveat(SINGLE_USE_KEY, Some(&victim_id));
+        let mut v = Verifier::new(&almond, 1, b"invite");
+        v.set_use_store(&store);
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::DuplicateCaveats { keys: vec![SINGLE_USE_KEY.to_vec()] })
+        );
+        assert!(store.lock().unwrap().is_empty());
+
+        // Almonds without a single use caveat are rejected.
+        let no_id = Almond::create(key, 1, b"invite".to_vec());
+        let mut v = Verifier::new(&no_id, 1, b"invite");
+        v.set_use_store(&store);
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::MissingCaveats { keys: vec![SINGLE_USE_KEY.to_vec()] })
+        );
+
+        let mut v = Verifier::new(&victim, 1, b"invite");
+        v.set_use_store(&store);
+        assert!(v.verify());
+
+        // Duplicates of the almond's own id are rejected too.
+        let mut almond = Almond::create(key, 1, b"invite".to_vec());
+        almond.add_caveat(SINGLE_USE_KEY, Some(&token_id));
+        almond.add_caveat(SINGLE_USE_KEY, Some(&token_id));
+        let mut v = Verifier::new(&almond, 1, b"invite");
+        v.set_use_store(&store);
+        assert!(!v.verify());
+        assert!(!store.lock().unwrap().contains(&token_id));
+    }
+
+    #[test]
+    fn satisfies_audience() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_audience(b"billing");
+        assert_eq!(almond.caveat_value(AUDIENCE_KEY), Some(&b"billing"[..]));
+
+        {
+            let mut v = Verifier::new(&almond, 1, b"login");
+            v.satisfies_audience(b"billing");
+            assert!(v.verify());
+        }
+
+        // Adding a second audience can only narrow the almond.
+        almond.add_audience(b"search");
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_audience(b"billing");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats {
+                unmatched: vec![],
+                rejected: vec![b"audience".to_vec()],
+            })
+        );
+
+        let no_audience = Almond::create(key, 1, b"login".to_vec());
+
+        let mut v = Verifier::new(&no_audience, 1, b"login");
+        v.satisfies_audience(b"billing");
+        assert!(v.verify());
+
+        v.require_present(AUDIENCE_KEY);
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn satisfies_scope() {
+        let key = b"this_is_a_secret";
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_scope("repo:*");
+        assert_eq!(almond.caveat_value(SCOPE_KEY), Some(&b"repo:*"[..]));
+
+        {
+            let mut v = Verifier::new(&almond, 1, b"login");
+            v.satisfies_scope("repo:read:issues");
+            assert!(v.verify());
+
+            let mut v = Verifier::new(&almond, 1, b"login");
+            v.satisfies_scope("user:read");
+            assert!(!v.verify());
+
+            // `repo:*` is broader than the endpoint allows.
+            let mut v = Verifier::new(&almond, 1, b"login");
+            v.satisfies_scope_allowing(&["repo:read", "user:read"]);
+            assert!(!v.verify());
+
+            let mut v = Verifier::new(&almond, 1, b"login");
+            v.satisfies_scope_allowing(&["*"]);
+            assert!(v.verify());
+        }
+
+        almond.add_scope("repo:read");
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_scope("repo:write");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats {
+                unmatched: vec![],
+                rejected: vec![b"scope".to_vec()],
+            })
+        );
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_scope("repo:read");
+        assert!(v.verify());
+    }
+
+    #[test]
+    fn verify_detailed() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"guest", None);
+        almond.add_caveat(b"ip", Some(b"127.0.0.1"));
+
+        let v = Verifier::new(&almond, 2, b"login");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::WrongGeneration { expected: vec![2], actual: 1 })
+        );
+
+        let v = Verifier::new(&almond, 1, b"access");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::WrongType {
+                expected: vec![b"access".to_vec()],
+                actual: b"login".to_vec(),
+            })
+        );
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_exact(b"user", Some(b"noterikj"));
+        v.allow(b"guest");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats {
+                unmatched: vec![b"ip".to_vec()],
+                rejected: vec![b"user".to_vec()],
+            })
+        );
+    }
+
+    #[test]
+    fn unmatched_caveats() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"guest", None);
+        almond.add_caveat(b"ip", Some(b"127.0.0.1"));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        assert_eq!(v.unmatched_caveats().len(), 3);
+
+        v.satisfies_exact(b"user", Some(b"noterikj"));
+        assert_eq!(
+            v.unmatched_caveats(),
+            vec![(&b"guest"[..], None), (&b"ip"[..], Some(&b"127.0.0.1"[..]))]
+        );
+
+        v.allow(b"guest");
+        v.allow(b"ip");
+        assert!(v.unmatched_caveats().is_empty());
+    }
+
+    #[test]
+    fn satisfies_with_combinators() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"ip", Some(b"10.0.0.1"));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_with(b"ip", any_of(vec![
+            Box::new(|ip: &[u8]| ip == b"127.0.0.1"),
+            Box::new(|ip: &[u8]| ip.starts_with(b"10.")),
+        ]));
+        assert!(v.verify());
+
+        v.satisfies_with(b"ip", not(|ip: &[u8]| ip.starts_with(b"10.")));
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn satisfies_general() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"limit.uploads", Some(b"10"));
+        almond.add_caveat(b"limit.downloads", None);
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_general(|key, value| {
+            key.starts_with(b"limit.") && value.is_some()
+        });
+        v.satisfies_exact(b"user", Some(b"erikj"));
+        assert!(!v.verify());
+        assert_eq!(v.unmatched_caveats(), vec![(&b"limit.downloads"[..], None)]);
+
+        v.satisfies_general(|key, _| key == b"limit.downloads");
+        assert!(v.verify());
+
+        // Fallbacks do not override predicates that rejected the caveat.
+        v.satisfies_exact(b"user", Some(b"noterikj"));
+        v.satisfies_general(|_, _| true);
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn handle_namespace() {
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"access".to_vec());
+        almond.add_caveat(b"device.os", Some(b"linux"));
+        almond.add_caveat(b"device.model", Some(b"laptop"));
+        almond.add_caveat(b"device", Some(b"phone"));
+
+        let handler = |key: &[u8], value: Option<&[u8]>| {
+            match key {
+                b"os" => Some(value == Some(b"linux")),
+                _ => None,
+            }
+        };
+
+        // Unrecognized caveats in the namespace are left unmatched.
+        let mut v = Verifier::new(&almond, 1, b"access");
+        v.handle_namespace(b"device", handler);
+        v.allow(b"device");
+        assert_eq!(v.unmatched_caveats(), vec![(&b"device.model"[..], Some(&b"laptop"[..]))]);
+
+        v.allow(b"device.model");
+        assert!(v.verify());
+
+        let mut v = Verifier::new(&almond, 1, b"access");
+        v.handle_namespace(b"device", |_: &[u8], _: Option<&[u8]>| Some(false));
+        v.allow(b"device");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats {
+                unmatched: vec![],
+                rejected: vec![b"device.os".to_vec(), b"device.model".to_vec()],
+            })
+        );
+    }
+
+    #[test]
+    fn register() {
+        use registry::{CaveatVerifier, VerifyContext};
+
+        struct Device;
+
+        impl CaveatVerifier for Device {
+            fn check(&self, key: &[u8], value: Option<&[u8]>, ctx: &VerifyContext)
+                -> Option<bool>
+            {
+                if key == b"device" {
+                    Some(value.is_some() && value == ctx.get("device").map(|d| d.as_bytes()))
+                } else {
+                    None
+                }
+            }
+        }
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"access".to_vec());
+        almond.add_caveat(b"device", Some(b"phone"));
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        let mut v = Verifier::new(&almond, 1, b"access");
+        v.register(Box::new(Device));
+        v.set_context_value("device", "phone");
+        assert_eq!(v.unmatched_caveats(), vec![(&b"user"[..], Some(&b"erikj"[..]))]);
+
+        v.allow(b"user");
+        assert!(v.verify());
+
+        // A rejection overrides acceptance by other predicates.
+        v.set_context_value("device", "laptop");
+        v.allow(b"device");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats {
+                unmatched: vec![],
+                rejected: vec![b"device".to_vec()],
+            })
+        );
+    }
+
+    #[test]
+    fn verify_into() {
+        use claims::{AlmondCaveats, CaveatList, ClaimsError};
+
+        #[derive(Debug, PartialEq)]
+        struct Uploads(u64);
+
+        impl AlmondCaveats for Uploads {
+            fn add_to(&self, almond: &mut Almond) {
+                almond.add_caveat_typed(b"uploads", self.0);
+            }
+
+            fn extract(caveats: &CaveatList) -> Result<Uploads, ClaimsError> {
+                let uploads = try!(caveats.claim("uploads"));
+                Ok(Uploads(uploads.unwrap_or(0)))
+            }
+        }
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"access".to_vec());
+        almond.add_caveat(b"uploads", Some(b"5"));
+
+        {
+            let mut v = Verifier::new(&almond, 1, b"access");
+            assert_eq!(
+                v.verify_into::<Uploads>(),
+                Err(VerifyError::Caveats { unmatched: vec![b"uploads".to_vec()], rejected: vec![] })
+            );
+
+            v.allow(b"uploads");
+            assert_eq!(v.verify_into(), Ok(Uploads(5)));
+        }
+
+        almond.add_caveat(b"uploads", Some(b"10"));
+        let mut v = Verifier::new(&almond, 1, b"access");
+        v.allow(b"uploads");
+        assert_eq!(
+            v.verify_into::<Uploads>(),
+            Err(VerifyError::Claims(ClaimsError::Duplicate { key: "uploads".to_owned() }))
+        );
+    }
+
+    #[test]
+    fn sealed() {
+        use SEALED_KEY;
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"access".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.seal();
+
+        {
+            let mut v = Verifier::new(&almond, 1, b"access");
+            v.allow(b"user");
+            v.require_sealed();
+            assert_eq!(v.verify_detailed(), Ok(()));
+        }
+
+        // A seal that isn't the last caveat is rejected.
+        almond.add_caveat(b"device", Some(b"phone"));
+        let mut v = Verifier::new(&almond, 1, b"access");
+        v.allow(b"user");
+        v.allow(b"device");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats { unmatched: vec![], rejected: vec![SEALED_KEY.to_vec()] })
+        );
+
+        v.require_sealed();
+        assert_eq!(v.verify_detailed(), Err(VerifyError::NotSealed));
+
+        // A caveat that is merely named `sealed` isn't a seal.
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"access".to_vec());
+        almond.add_caveat(b"sealed", None);
+        let mut v = Verifier::new(&almond, 1, b"access");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats { unmatched: vec![b"sealed".to_vec()], rejected: vec![] })
+        );
+        v.require_sealed();
+        assert_eq!(v.verify_detailed(), Err(VerifyError::NotSealed));
+    }
+
+    #[test]
+    fn max_caveats() {
+        use MAX_CAVEATS_KEY;
+
+        let mut almond = Almond::create(b"this_is_a_secret", 1, b"access".to_vec());
+        almond.add_max_caveats(3);
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_max_caveats(5);
+        assert_eq!(almond.max_caveats(), Some(3));
+
+        {
+            let mut v = Verifier::new(&almond, 1, b"access");
+            v.allow(b"user");
+            v.satisfies_max_caveats();
+            assert!(v.verify());
+        }
+
+        // Both limits apply, so the tighter one rejects the almond.
+        almond.add_caveat(b"device", Some(b"phone"));
+        let mut v = Verifier::new(&almond, 1, b"access");
+        v.allow(b"user");
+        v.allow(b"device");
+        v.satisfies_max_caveats();
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats { unmatched: vec![], rejected: vec![MAX_CAVEATS_KEY.to_vec()] })
+        );
+    }
+
+    #[test]
+    fn require_present() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"guest", None);
+
+        {
+            let mut v = Verifier::new(&almond, 1, b"login");
+            v.require_present(b"user");
+            v.require_present(b"guest");
+            v.allow(b"user");
+            v.allow(b"guest");
+            assert_eq!(
+                v.verify_detailed(),
+                Err(VerifyError::MissingCaveats { keys: vec![b"user".to_vec()] })
+            );
+        }
+
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.require_present(b"user");
+        assert!(!v.verify());
+
+        v.allow(b"user");
+        v.allow(b"guest");
+        assert!(v.verify());
+    }
+
+    #[test]
+    fn forbid() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"refresh".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+
+        {
+            let mut v = Verifier::new(&almond, 1, b"refresh");
+            v.allow(b"user");
+            v.forbid(b"admin");
+            assert!(v.verify());
+        }
+
+        almond.add_caveat(b"admin", None);
+
+        let mut v = Verifier::new(&almond, 1, b"refresh");
+        v.allow(b"user");
+        v.allow(b"admin");
+        v.forbid(b"admin");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::ForbiddenCaveats { keys: vec![b"admin".to_vec()] })
+        );
+    }
+
+    #[test]
+    fn require_unique() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        almond.add_caveat(b"expires", Some(b"1500000000"));
+        almond.add_caveat(b"ip", Some(b"10.0.0.1"));
+        almond.add_caveat(b"expires", Some(b"1600000000"));
+        almond.add_caveat(b"ip", Some(b"10.0.0.2"));
+        almond.add_caveat(b"expires", Some(b"1700000000"));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.allow(b"user");
+        v.allow(b"expires");
+        v.allow(b"ip");
+        assert!(v.verify());
+
+        v.require_unique(b"user");
+        assert!(v.verify());
+
+        v.require_unique(b"expires");
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::DuplicateCaveats { keys: vec![b"expires".to_vec()] })
+        );
+
+        v.require_all_unique();
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::DuplicateCaveats {
+                keys: vec![b"expires".to_vec(), b"ip".to_vec()],
+            })
+        );
+    }
+
+    #[test]
+    fn str_api() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat_str("user", Some("erikj"));
+        almond.add_caveat_str("guest", None);
+        almond.add_caveat(b"blob", Some(b"\xff"));
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_str_exact("user", Some("erikj"));
+        v.satisfies_str_exact("guest", None);
+        v.satisfies_str("blob", |_| true);
+        assert_eq!(
+            v.verify_detailed(),
+            Err(VerifyError::Caveats {
+                unmatched: vec![],
+                rejected: vec![b"blob".to_vec()],
+            })
+        );
+    }
+
+    #[test]
+    fn satisfies_typed() {
+        use std::net::IpAddr;
+
+        let key = b"this_is_a_secret";
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat_typed(b"ip", ip);
+        almond.add_caveat_typed(b"expires", 1500000000u64);
+        almond.add_caveat_typed(b"admin", false);
+
+        let mut v = Verifier::new(&almond, 1, b"login");
+        v.satisfies_typed(b"ip", |value: IpAddr| value == ip);
+        v.satisfies_typed(b"expires", |value: u64| 1447720058 < value);
+        v.satisfies_typed(b"admin", |value: bool| !value);
+        assert!(v.verify());
+
+        v.satisfies_typed(b"ip", |_: u64| true);
+        assert!(!v.verify());
+    }
+
+    #[test]
+    fn verify_ref() {
+        let key = b"this_is_a_secret";
+        let mut almond = Almond::create(key, 1, b"login".to_vec());
+        almond.add_caveat(b"user", Some(b"erikj"));
+        let serialized = almond.serialize_binary();
+
+        let almond_ref = AlmondRef::parse_and_validate(key, &serialized).unwrap();
+
+        let mut v = Verifier::from_ref(&almond_ref, 1, b"login");
+        v.satisfies_exact(b"user", Some(b"erikj"));
+        assert!(v.verify());
+
+        let mut v = Verifier::from_ref(&almond_ref, 1, b"notlogin");
+        v.satisfies_exact(b"user", Some(b"erikj"));
         assert!(!v.verify());
     }
 
@@ -210,7 +2582,7 @@ mod tests {
         v.satisfies(
             b"expires",
             |val| str::from_utf8(val).ok().and_then(
-                    |val| val.parse().ok()
+                    |val| val.parse::<u64>().ok()
                 ).map(
                     |val| now < val
                 ).unwrap_or(false)